@@ -1,16 +1,9 @@
-use std::{
-    error::Error,
-    io::{Read, Write},
-    env,
-    net::{SocketAddr, TcpStream},
-};
+use std::{env, error::Error, net::SocketAddr};
 
-use utils::Server;
+use utils::{proxy, server::Conn, Server};
 
-fn handler(mut conn: TcpStream) -> Result<(), Box<dyn Error>> {
-    let mut buf = Vec::with_capacity(1024);
-    conn.read_to_end(&mut buf)?;
-    conn.write_all(&buf)?;
+fn handler(conn: Conn) -> Result<(), Box<dyn Error>> {
+    proxy::echo(conn)?;
     Ok(())
 }
 
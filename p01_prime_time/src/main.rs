@@ -1,20 +1,17 @@
-use std::{
-    borrow::Cow,
-    env,
-    error::Error,
-    io::{self, BufRead, BufReader, Write},
-    net::{SocketAddr, TcpStream},
-};
+use std::{env, net::SocketAddr};
 
 use utils::{
-    json::{self, Value},
-    Server,
+    json::{
+        self,
+        schema::{Kind, Schema},
+        Value,
+    },
+    server::{Reply, Server},
 };
 
-fn write_error(s: &mut TcpStream) -> io::Result<()> {
-    s.write_all(b"{\"error\": \"malformed request\"}")?;
-    Ok(())
-}
+const MALFORMED: &str = "{\"error\": \"malformed request\"}";
+
+const MAX_REQUEST_LEN: usize = 1 << 20;
 
 fn is_prime(n: i64) -> bool {
     if n < 2 {
@@ -22,7 +19,7 @@ fn is_prime(n: i64) -> bool {
     }
     let n = n as u64;
     for i in 2..=((n as f64).sqrt() as u64) {
-        dbg!(i);
+        utils::log_trace!("trying divisor"; n = n, i = i);
         if n.rem_euclid(i) == 0 {
             return false;
         }
@@ -30,63 +27,39 @@ fn is_prime(n: i64) -> bool {
     true
 }
 
-fn handle(mut s: TcpStream) -> Result<(), Box<dyn Error>> {
-    let mut reader = BufReader::new(s.try_clone()?);
-    let mut req_buf = Vec::new();
-    let mut res_buf = Vec::new();
-    loop {
-        req_buf.clear();
-        res_buf.clear();
-        match reader.read_until(b'\n', &mut req_buf) {
-            Ok(read) if read == 0 => {
-                write_error(&mut s)?;
-                break;
-            }
-            res => res,
-        }?;
-        let req = match json::parse_json(&req_buf) {
-            Ok(v) => v,
-            Err(e) => {
-                utils::log_err!("Failed parsing json {:?}", e);
-                write_error(&mut s)?;
-                break;
-            }
-        };
-        let arg = match req
-            .object()
-            .and_then(|o| Some((o.get("method")?.string()?, o.get("prime")?.int()?)))
-        {
-            Some((method, arg)) if method == "isPrime" => *arg,
-            _ => {
-                utils::log_info!("Non conforming payloads");
-                write_error(&mut s)?;
-                break;
-            }
-        };
-        json::serialize_json(
-            &Value::Object(
-                [
-                    (
-                        Cow::Borrowed("method"),
-                        Value::String(Cow::Borrowed("isPrime")),
-                    ),
-                    (Cow::Borrowed("prime"), Value::Bool(is_prime(arg))),
-                ]
-                .into_iter()
-                .collect(),
-            ),
-            &mut res_buf,
-        );
-        res_buf.push(b'\n');
-        s.write_all(&res_buf)?;
+fn respond(schema: &Schema, line: &str) -> Reply {
+    let req = match json::from_str(line) {
+        Ok(req) => req,
+        Err(e) => {
+            utils::log_err!("Failed parsing json {:?}", e);
+            return Reply::Close(Some(MALFORMED.to_owned()));
+        }
+    };
+    if let Err(violations) = schema.validate(&req) {
+        let violations: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+        utils::log_info!("Non conforming payload: {}", violations.join(", "));
+        return Reply::Close(Some(MALFORMED.to_owned()));
     }
-    Ok(())
+    // Numbers that aren't integers can't be prime
+    let prime = match req.get_path("prime").unwrap().as_i64() {
+        Ok(n) => is_prime(n),
+        Err(_) => false,
+    };
+    Reply::Line(json::to_string(
+        &[("method", Value::from("isPrime")), ("prime", prime.into())]
+            .into_iter()
+            .collect(),
+    ))
 }
 
 fn main() {
     let port = env::var("PORT").unwrap().parse().unwrap();
     let addr = SocketAddr::from(([127, 0, 0, 1], port));
 
-    let server = Server::new(handle).unwrap();
+    let schema = Schema::new()
+        .required("method", Kind::String)
+        .one_of(&["isPrime"])
+        .required("prime", Kind::Number);
+    let server = Server::lines(MAX_REQUEST_LEN, move |line| respond(&schema, line)).unwrap();
     server.listen(addr).unwrap();
 }
@@ -0,0 +1,100 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use utils::json::{self, scan, Map, Value};
+
+const ITERATIONS: u32 = 200_000;
+
+fn bench<F: FnMut()>(name: &str, mut f: F) -> Duration {
+    // Warm up caches and the allocator
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let per_iter = start.elapsed() / ITERATIONS;
+    println!("{:<40} {:>8?}/iter", name, per_iter);
+    per_iter
+}
+
+fn build_lookup_hashmap(keys: &[&'static str]) {
+    let mut m: HashMap<Cow<str>, Value> = HashMap::new();
+    for k in keys {
+        m.insert(Cow::Borrowed(*k), Value::Int(1));
+    }
+    for k in keys {
+        black_box(m.get(*k));
+    }
+    black_box(m);
+}
+
+fn build_lookup_map(keys: &[&'static str]) {
+    let mut m = Map::new();
+    for k in keys {
+        m.insert(*k, Value::Int(1));
+    }
+    for k in keys {
+        black_box(m.get(*k));
+    }
+    black_box(m);
+}
+
+fn main() {
+    let keys = ["method", "number", "id", "queue"];
+    let hashmap = bench("object with 4 keys: HashMap", || {
+        build_lookup_hashmap(black_box(&keys))
+    });
+    let map = bench("object with 4 keys: Map", || {
+        build_lookup_map(black_box(&keys))
+    });
+    println!(
+        "speedup x{:.2}\n",
+        hashmap.as_secs_f64() / map.as_secs_f64()
+    );
+
+    let request = br#"{"method": "isPrime", "number": 1234567}"#;
+    bench("parse prime time request", || {
+        black_box(json::parse_json(black_box(request)).unwrap());
+    });
+    let job = br#"{"request": "put", "queue": "queue1", "job": {"title": "example-job", "tags": ["a", "b"]}, "pri": 123}"#;
+    bench("parse job centre request", || {
+        black_box(json::parse_json(black_box(job)).unwrap());
+    });
+
+    let mut reused = Value::Null(());
+    bench("parse_into job centre request", || {
+        json::parse_into(black_box(job), &mut reused).unwrap();
+    });
+
+    let long_string = format!("\"{}\\n{}\"", "x".repeat(4096), "y".repeat(4096));
+    let long_string = long_string.as_bytes();
+    bench("find string end: portable", || {
+        black_box(scan::find_quote_or_backslash_portable(
+            black_box(long_string),
+            1,
+        ));
+    });
+    bench("find string end: dispatched", || {
+        black_box(scan::find_quote_or_backslash(black_box(long_string), 1));
+    });
+    bench("parse 8KiB string", || {
+        black_box(json::parse_json(black_box(long_string)).unwrap());
+    });
+    let indented = format!("[{}1]", "\n                ".repeat(512));
+    let indented = indented.as_bytes();
+    bench("skip whitespace: portable", || {
+        black_box(scan::skip_whitespace_portable(black_box(indented), 1));
+    });
+    bench("skip whitespace: dispatched", || {
+        black_box(scan::skip_whitespace(black_box(indented), 1));
+    });
+    bench("parse indented array", || {
+        black_box(json::parse_json(black_box(indented)).unwrap());
+    });
+}
@@ -0,0 +1,166 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+const MIN_CLASS: usize = 256;
+const CLASSES: usize = 9;
+/// Buffers bigger than this aren't pooled
+pub const MAX_POOLED: usize = MIN_CLASS << (CLASSES - 1);
+
+static GLOBAL: BufPool = BufPool::new(256);
+
+/// Pool of `Vec<u8>` buffers in power of two size classes from 256 bytes to
+/// 64KiB, so connections don't allocate fresh buffers each time
+#[derive(Debug)]
+pub struct BufPool {
+    classes: [Mutex<Vec<Vec<u8>>>; CLASSES],
+    max_per_class: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl BufPool {
+    /// Pool keeping at most `max_per_class` idle buffers of each size class
+    pub const fn new(max_per_class: usize) -> Self {
+        Self {
+            classes: [const { Mutex::new(Vec::new()) }; CLASSES],
+            max_per_class,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Pool shared by the whole process
+    pub fn global() -> &'static BufPool {
+        &GLOBAL
+    }
+
+    /// Smallest class holding `len` bytes
+    fn class_for(len: usize) -> Option<usize> {
+        let size = len.max(MIN_CLASS).checked_next_power_of_two()?;
+        let class = (size / MIN_CLASS).trailing_zeros() as usize;
+        (class < CLASSES).then_some(class)
+    }
+
+    /// Empty buffer with a capacity of at least `capacity`, returned to the
+    /// pool when dropped
+    pub fn get(&self, capacity: usize) -> PooledBuf<'_> {
+        let buf = match Self::class_for(capacity) {
+            Some(class) => {
+                let pooled = self.classes[class]
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .pop();
+                match pooled {
+                    Some(buf) => {
+                        self.hits.fetch_add(1, Ordering::Relaxed);
+                        buf
+                    }
+                    None => {
+                        self.misses.fetch_add(1, Ordering::Relaxed);
+                        Vec::with_capacity(MIN_CLASS << class)
+                    }
+                }
+            }
+            None => Vec::with_capacity(capacity),
+        };
+        PooledBuf { buf, pool: self }
+    }
+
+    fn put(&self, mut buf: Vec<u8>) {
+        // A buffer goes in the biggest class it can serve
+        let capacity = buf.capacity();
+        if !(MIN_CLASS..MAX_POOLED * 2).contains(&capacity) {
+            return;
+        }
+        let class = ((capacity / MIN_CLASS).ilog2() as usize).min(CLASSES - 1);
+        let mut idle = self.classes[class]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if idle.len() < self.max_per_class {
+            buf.clear();
+            idle.push(buf);
+        }
+    }
+
+    /// Buffers handed out from the pool and freshly allocated
+    pub fn stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Buffer from a `BufPool`, derefs to its `Vec<u8>`
+#[derive(Debug)]
+pub struct PooledBuf<'p> {
+    buf: Vec<u8>,
+    pool: &'p BufPool,
+}
+
+impl PooledBuf<'_> {
+    /// Takes the buffer out of the pool for good
+    pub fn into_vec(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf)
+    }
+}
+
+impl Deref for PooledBuf<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuf<'_> {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buf));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BufPool, MAX_POOLED};
+
+    #[test]
+    fn test_reuse() {
+        let pool = BufPool::new(2);
+        let mut a = pool.get(1000);
+        assert!(a.capacity() >= 1024);
+        a.extend_from_slice(b"data");
+        let ptr = a.as_ptr() as usize;
+        drop(a);
+        let b = pool.get(600);
+        assert_eq!(b.as_ptr() as usize, ptr);
+        assert!(b.is_empty());
+        assert_eq!(pool.stats(), (1, 1));
+        // Smaller classes don't take bigger buffers
+        let c = pool.get(100);
+        assert!(c.capacity() < 1024);
+        assert_eq!(pool.stats(), (1, 2));
+
+        // Beyond the biggest class
+        let big = pool.get(MAX_POOLED + 1);
+        assert!(big.capacity() > MAX_POOLED);
+        drop(big);
+        assert_eq!(pool.stats(), (1, 2));
+
+        let bufs: Vec<_> = (0..3).map(|_| pool.get(300)).collect();
+        drop(bufs);
+        assert_eq!(pool.classes[1].lock().unwrap().len(), 2);
+        assert_eq!(pool.get(10).into_vec().capacity(), 256);
+        assert_eq!(BufPool::class_for(usize::MAX), None);
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex, MutexGuard, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct Waiters {
+    channels: Vec<mpsc::Sender<()>>,
+    callbacks: Vec<Callback>,
+    children: Vec<Weak<Node>>,
+}
+
+#[derive(Default)]
+struct Node {
+    cancelled: AtomicBool,
+    waiters: Mutex<Waiters>,
+    cond: Condvar,
+}
+
+impl Node {
+    fn lock(&self) -> MutexGuard<'_, Waiters> {
+        self.waiters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn cancel(&self) {
+        let waiters = {
+            let mut waiters = self.lock();
+            if self.cancelled.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            std::mem::take(&mut *waiters)
+        };
+        self.cond.notify_all();
+        for channel in waiters.channels {
+            let _ = channel.send(());
+        }
+        for callback in waiters.callbacks {
+            callback();
+        }
+        for child in waiters.children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+/// Shared flag telling threads to stop what they are doing. Cancelling a
+/// token cancels its children, but not its parent.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    node: Arc<Node>,
+}
+
+impl std::fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.node.cancel()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.node.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Token cancelled with this one, or on its own
+    pub fn child(&self) -> CancelToken {
+        let child = CancelToken::new();
+        let mut waiters = self.node.lock();
+        if self.is_cancelled() {
+            child.node.cancelled.store(true, Ordering::SeqCst);
+        } else {
+            waiters.children.retain(|c| c.strong_count() > 0);
+            waiters.children.push(Arc::downgrade(&child.node));
+        }
+        child
+    }
+
+    /// Channel receiving a message on cancellation, to `select` it with
+    /// others through `recv_timeout` loops
+    pub fn cancelled_channel(&self) -> mpsc::Receiver<()> {
+        let (tx, rx) = mpsc::channel();
+        let mut waiters = self.node.lock();
+        if self.is_cancelled() {
+            let _ = tx.send(());
+        } else {
+            waiters.channels.push(tx);
+        }
+        rx
+    }
+
+    /// Calls `f` on cancellation, from the cancelling thread, or right away if
+    /// already cancelled. Useful to unblock calls which can't watch the token.
+    pub fn on_cancel<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let mut waiters = self.node.lock();
+        if self.is_cancelled() {
+            drop(waiters);
+            f();
+        } else {
+            waiters.callbacks.push(Box::new(f));
+        }
+    }
+
+    pub fn wait(&self) {
+        let mut waiters = self.node.lock();
+        while !self.is_cancelled() {
+            waiters = self
+                .node
+                .cond
+                .wait(waiters)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Waits for cancellation for at most `timeout`, returning whether the
+    /// token is cancelled
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut waiters = self.node.lock();
+        while !self.is_cancelled() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            waiters = self
+                .node
+                .cond
+                .wait_timeout(waiters, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::CancelToken;
+
+    #[test]
+    fn test_children() {
+        let root = CancelToken::new();
+        let child = root.child();
+        let grandchild = child.child();
+        let other = root.child();
+        let rx = grandchild.cancelled_channel();
+        let called = Arc::new(AtomicBool::new(false));
+        let flag = called.clone();
+        grandchild.on_cancel(move || flag.store(true, Ordering::SeqCst));
+
+        child.cancel();
+        assert!(child.is_cancelled() && grandchild.is_cancelled());
+        assert!(!root.is_cancelled() && !other.is_cancelled());
+        assert_eq!(rx.try_recv(), Ok(()));
+        assert!(called.load(Ordering::SeqCst));
+
+        root.cancel();
+        assert!(other.is_cancelled());
+        assert!(root.child().is_cancelled());
+        assert_eq!(root.cancelled_channel().try_recv(), Ok(()));
+    }
+
+    #[test]
+    fn test_wait() {
+        let token = CancelToken::new();
+        assert!(!token.wait_timeout(Duration::from_millis(1)));
+        let waiter = {
+            let token = token.child();
+            thread::spawn(move || token.wait())
+        };
+        thread::sleep(Duration::from_millis(10));
+        token.cancel();
+        waiter.join().unwrap();
+        assert!(token.wait_timeout(Duration::ZERO));
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+/// What a send does when the channel is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Waits for the receiver to make room
+    Block,
+    /// Drops the oldest queued item to make room
+    DropOldest,
+    /// Drops the item sent
+    DropNewest,
+}
+
+/// Counters of a channel since its creation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub sent: u64,
+    pub received: u64,
+    /// Items dropped by the overflow policy
+    pub dropped: u64,
+    /// Sends which found the channel full
+    pub full: u64,
+    /// Largest number of items queued at once
+    pub high_water: usize,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver: bool,
+    stats: Stats,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    overflow: Overflow,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Channel holding at most `capacity` items, using the error types of
+/// `std::sync::mpsc`
+pub fn bounded<T>(capacity: usize, overflow: Overflow) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0);
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(capacity),
+            senders: 1,
+            receiver: true,
+            stats: Stats::default(),
+        }),
+        capacity,
+        overflow,
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+#[derive(Debug)]
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Fails only if the receiver is gone. Items dropped by the overflow
+    /// policy count as sent.
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        match self.send_until(item, None) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Disconnected(item)) | Err(TrySendError::Full(item)) => {
+                Err(SendError(item))
+            }
+        }
+    }
+
+    /// Like `send`, but gives up with `Full` after waiting `timeout` for room
+    pub fn send_timeout(&self, item: T, timeout: Duration) -> Result<(), TrySendError<T>> {
+        self.send_until(item, Some(Instant::now() + timeout))
+    }
+
+    pub fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.send_until(item, Some(Instant::now()))
+    }
+
+    fn send_until(&self, item: T, deadline: Option<Instant>) -> Result<(), TrySendError<T>> {
+        let shared = &*self.shared;
+        let mut state = shared.lock();
+        let mut counted_full = false;
+        loop {
+            if !state.receiver {
+                return Err(TrySendError::Disconnected(item));
+            }
+            if state.queue.len() < shared.capacity {
+                break;
+            }
+            if !counted_full {
+                state.stats.full += 1;
+                counted_full = true;
+            }
+            match shared.overflow {
+                Overflow::DropOldest => {
+                    state.queue.pop_front();
+                    state.stats.dropped += 1;
+                    break;
+                }
+                Overflow::DropNewest => {
+                    state.stats.sent += 1;
+                    state.stats.dropped += 1;
+                    return Ok(());
+                }
+                Overflow::Block => {}
+            }
+            state = match deadline {
+                None => shared
+                    .not_full
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(TrySendError::Full(item));
+                    }
+                    let res = shared.not_full.wait_timeout(state, deadline - now);
+                    res.unwrap_or_else(|e| e.into_inner()).0
+                }
+            };
+        }
+        state.queue.push_back(item);
+        state.stats.sent += 1;
+        state.stats.high_water = state.stats.high_water.max(state.queue.len());
+        shared.not_empty.notify_one();
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.shared.lock().stats
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for an item, failing once the channel is empty and all the
+    /// senders are gone
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(Instant::now() + timeout))
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.recv_until(Some(Instant::now())).map_err(|e| match e {
+            RecvTimeoutError::Timeout => TryRecvError::Empty,
+            RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+        })
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let shared = &*self.shared;
+        let mut state = shared.lock();
+        loop {
+            if let Some(item) = state.queue.pop_front() {
+                state.stats.received += 1;
+                shared.not_full.notify_one();
+                return Ok(item);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            state = match deadline {
+                None => shared
+                    .not_empty
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    let res = shared.not_empty.wait_timeout(state, deadline - now);
+                    res.unwrap_or_else(|e| e.into_inner()).0
+                }
+            };
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.shared.lock().stats
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(|| self.recv().ok())
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiver = false;
+        self.shared.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::mpsc::{RecvTimeoutError, TryRecvError, TrySendError},
+        thread,
+        time::Duration,
+    };
+
+    use super::{bounded, Overflow, Stats};
+
+    #[test]
+    fn test_overflow_policies() {
+        for (overflow, expected) in [
+            (Overflow::DropOldest, vec![3, 4]),
+            (Overflow::DropNewest, vec![1, 2]),
+        ] {
+            let (tx, rx) = bounded(2, overflow);
+            for i in 1..=4 {
+                tx.send(i).unwrap();
+            }
+            drop(tx);
+            assert_eq!(rx.iter().collect::<Vec<_>>(), expected);
+            assert_eq!(
+                rx.stats(),
+                Stats {
+                    sent: 4,
+                    received: 2,
+                    dropped: 2,
+                    full: 2,
+                    high_water: 2
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_block() {
+        let (tx, rx) = bounded(1, Overflow::Block);
+        tx.send(1).unwrap();
+        assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+        assert_eq!(
+            tx.send_timeout(2, Duration::from_millis(5)),
+            Err(TrySendError::Full(2))
+        );
+        let sender = tx.clone();
+        let t = thread::spawn(move || sender.send(2).unwrap());
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Ok(2));
+        t.join().unwrap();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(1)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        drop(tx);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (tx, rx) = bounded(1, Overflow::Block);
+        tx.send(1).unwrap();
+        let t = thread::spawn(move || tx.send(2));
+        drop(rx);
+        assert!(t.join().unwrap().is_err());
+    }
+}
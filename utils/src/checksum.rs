@@ -0,0 +1,111 @@
+/// Wrapping sum of bytes. Pestcontrol frames end with the byte making the
+/// sum of the whole frame 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteSum(u8);
+
+impl ByteSum {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.0 = bytes.iter().fold(self.0, |sum, &b| sum.wrapping_add(b));
+    }
+
+    pub fn sum(&self) -> u8 {
+        self.0
+    }
+
+    /// Byte to append for the sum to be 0
+    pub fn complement(&self) -> u8 {
+        self.0.wrapping_neg()
+    }
+}
+
+pub fn byte_sum(bytes: &[u8]) -> u8 {
+    let mut sum = ByteSum::new();
+    sum.update(bytes);
+    sum.sum()
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 of zlib, png and ethernet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self(!0)
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = CRC32_TABLE[((self.0 ^ b as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{byte_sum, crc32, ByteSum, Crc32};
+
+    #[test]
+    fn test_byte_sum() {
+        // Hello message of the protohackers problem, checksum included
+        let hello = b"\x50\x00\x00\x00\x19\x00\x00\x00\x0bpestcontrol\x00\x00\x00\x01";
+        let mut sum = ByteSum::new();
+        sum.update(&hello[..10]);
+        sum.update(&hello[10..]);
+        let mut frame = hello.to_vec();
+        frame.push(sum.complement());
+        assert_eq!(frame.last(), Some(&0xce));
+        assert_eq!(byte_sum(&frame), 0);
+        assert_eq!(byte_sum(&[0xff, 0x02]), 1);
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let mut crc = Crc32::new();
+        for chunk in b"The quick brown fox jumps over the lazy dog".chunks(5) {
+            crc.update(chunk);
+        }
+        assert_eq!(crc.finish(), 0x414f_a339);
+    }
+}
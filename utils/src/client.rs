@@ -0,0 +1,275 @@
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    log_debug,
+    retry::{self, with_backoff},
+    wire::{Reader, Writer},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Rounds of connection attempts, each trying all the resolved addresses
+    pub attempts: u32,
+    pub connect_timeout: Duration,
+    /// Delay after the first failed round, doubling after each one
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            connect_timeout: Duration::from_secs(5),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnInfo {
+    pub peer: SocketAddr,
+    /// Round which succeeded, from 1
+    pub attempts: u32,
+    pub elapsed: Duration,
+}
+
+impl RetryPolicy {
+    fn backoff(&self) -> retry::Policy {
+        retry::Policy {
+            attempts: self.attempts,
+            initial_backoff: self.initial_backoff,
+            max_backoff: self.max_backoff,
+            // Resolution failures are often transient, and of no particular kind
+            retryable: |e| e.kind() != io::ErrorKind::InvalidInput,
+        }
+    }
+}
+
+/// Connects to `addr`, resolved again before each round of attempts, retrying
+/// with jittered exponential backoff
+pub fn connect<A: ToSocketAddrs>(
+    addr: A,
+    policy: &RetryPolicy,
+) -> io::Result<(TcpStream, ConnInfo)> {
+    let start = Instant::now();
+    with_backoff(&policy.backoff(), |attempt| {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no address resolved");
+        let addrs = addr.to_socket_addrs().inspect_err(|e| {
+            log_debug!("resolving upstream: {}", e; attempt = attempt);
+        })?;
+        for peer in addrs {
+            match TcpStream::connect_timeout(&peer, policy.connect_timeout) {
+                Ok(stream) => {
+                    let info = ConnInfo {
+                        peer,
+                        attempts: attempt,
+                        elapsed: start.elapsed(),
+                    };
+                    return Ok((stream, info));
+                }
+                Err(e) => {
+                    log_debug!("connecting upstream: {}", e; peer = peer, attempt = attempt);
+                    last_err = e;
+                }
+            }
+        }
+        Err(last_err)
+    })
+}
+
+/// SOCKS5 proxy to connect through
+#[derive(Debug, Clone)]
+pub struct Socks5<A> {
+    pub proxy: A,
+    /// User name and password
+    pub auth: Option<(String, String)>,
+}
+
+/// Connects to `host:port` through a SOCKS5 proxy, itself connected to with
+/// `connect`. Host names are resolved by the proxy. The handshake must finish
+/// within the connect timeout of the policy.
+pub fn connect_via<A: ToSocketAddrs>(
+    socks: &Socks5<A>,
+    host: &str,
+    port: u16,
+    policy: &RetryPolicy,
+) -> io::Result<(TcpStream, ConnInfo)> {
+    let (mut stream, info) = connect(&socks.proxy, policy)?;
+    stream.set_read_timeout(Some(policy.connect_timeout))?;
+    stream.set_write_timeout(Some(policy.connect_timeout))?;
+    socks5_handshake(&mut stream, socks.auth.as_ref(), host, port)?;
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    log_debug!("Connected through socks proxy"; proxy = info.peer, host = host, port = port);
+    Ok((stream, info))
+}
+
+fn socks_error(kind: io::ErrorKind, msg: &str) -> io::Error {
+    io::Error::new(kind, format!("socks proxy: {}", msg))
+}
+
+fn socks5_handshake<S: Read + Write>(
+    stream: &mut S,
+    auth: Option<&(String, String)>,
+    host: &str,
+    port: u16,
+) -> io::Result<()> {
+    let mut w = Writer::new(Vec::new());
+    // No authentication, and user/password if we have some
+    match auth {
+        Some(_) => w.write_bytes(&[5, 2, 0, 2])?,
+        None => w.write_bytes(&[5, 1, 0])?,
+    }
+    stream.write_all(w.get_ref())?;
+    let mut r = Reader::new(&mut *stream);
+    let reply = r.read_bytes(2)?;
+    match (reply[1], auth) {
+        (0, _) => {}
+        (2, Some((user, password))) => {
+            let mut w = Writer::new(vec![1]);
+            w.write_str(user)?;
+            w.write_str(password)?;
+            stream.write_all(w.get_ref())?;
+            let reply = Reader::new(&mut *stream).read_bytes(2)?;
+            if reply[1] != 0 {
+                return Err(socks_error(
+                    io::ErrorKind::PermissionDenied,
+                    "authentication failed",
+                ));
+            }
+        }
+        _ => {
+            return Err(socks_error(
+                io::ErrorKind::PermissionDenied,
+                "no acceptable authentication method",
+            ))
+        }
+    }
+
+    let mut w = Writer::new(vec![5, 1, 0]);
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            w.write_u8(1)?;
+            w.write_bytes(&ip.octets())?;
+        }
+        Ok(IpAddr::V6(ip)) => {
+            w.write_u8(4)?;
+            w.write_bytes(&ip.octets())?;
+        }
+        Err(_) => {
+            w.write_u8(3)?;
+            w.write_str(host)?;
+        }
+    }
+    w.write_u16(port)?;
+    stream.write_all(w.get_ref())?;
+
+    let mut r = Reader::new(&mut *stream);
+    let reply = r.read_bytes(4)?;
+    if reply[1] != 0 {
+        let msg = match reply[1] {
+            1 => "general failure",
+            2 => "connection not allowed by ruleset",
+            3 => "network unreachable",
+            4 => "host unreachable",
+            5 => "connection refused",
+            6 => "TTL expired",
+            7 => "command not supported",
+            8 => "address type not supported",
+            _ => "unknown error",
+        };
+        return Err(socks_error(io::ErrorKind::ConnectionRefused, msg));
+    }
+    // Address the proxy bound, not needed
+    let len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => r.read_u8()? as usize,
+        _ => {
+            return Err(socks_error(
+                io::ErrorKind::InvalidData,
+                "invalid address type",
+            ))
+        }
+    };
+    r.read_bytes(len + 2)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{connect, connect_via, RetryPolicy, Socks5};
+
+    #[test]
+    fn test_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (_, info) = connect(("localhost", port), &RetryPolicy::default()).unwrap();
+        assert_eq!(info.attempts, 1);
+        assert_eq!(info.peer, listener.local_addr().unwrap());
+
+        drop(listener);
+        let policy = RetryPolicy {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            ..RetryPolicy::default()
+        };
+        let start = Instant::now();
+        connect(("127.0.0.1", port), &policy).unwrap_err();
+        // Waited 5 to 10ms, then 10 to 20ms
+        assert!(start.elapsed() >= Duration::from_millis(15));
+        // Not retried
+        let start = Instant::now();
+        connect("not an address", &policy).unwrap_err();
+        assert!(start.elapsed() < Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_socks5() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let expect = |conn: &mut TcpStream, bytes: &[u8]| {
+                let mut buf = vec![0; bytes.len()];
+                conn.read_exact(&mut buf).unwrap();
+                assert_eq!(buf, bytes);
+            };
+            expect(&mut conn, &[5, 2, 0, 2]);
+            conn.write_all(&[5, 2]).unwrap();
+            expect(&mut conn, b"\x01\x04user\x06secret");
+            conn.write_all(&[1, 0]).unwrap();
+            expect(&mut conn, b"\x05\x01\x00\x03\x0dupstream.test\x00\x07");
+            conn.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 80]).unwrap();
+            // Echoes as the upstream would
+            let mut buf = [0; 4];
+            conn.read_exact(&mut buf).unwrap();
+            conn.write_all(&buf).unwrap();
+        });
+        let socks = Socks5 {
+            proxy,
+            auth: Some(("user".to_owned(), "secret".to_owned())),
+        };
+        let (mut stream, info) =
+            connect_via(&socks, "upstream.test", 7, &RetryPolicy::default()).unwrap();
+        assert_eq!(info.peer, proxy);
+        stream.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Source of time of timers and protocol state machines, mocked in tests
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep_until(&self, deadline: Instant);
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) {
+        thread::sleep(deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// Clock only moving when told to. Sleeping threads wake up once it is
+/// advanced past their deadline.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<Instant>,
+    cond: Condvar,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            now: Mutex::new(Instant::now()),
+            cond: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Instant> {
+        self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn advance(&self, d: Duration) {
+        *self.lock() += d;
+        self.cond.notify_all();
+    }
+
+    /// Moves the clock to `t`, if it is later than now
+    pub fn set(&self, t: Instant) {
+        let mut now = self.lock();
+        *now = (*now).max(t);
+        self.cond.notify_all();
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.lock()
+    }
+
+    fn sleep_until(&self, deadline: Instant) {
+        let mut now = self.lock();
+        while *now < deadline {
+            now = self.cond.wait(now).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use super::{Clock, MockClock};
+
+    #[test]
+    fn test_mock_sleep() {
+        let clock = Arc::new(MockClock::new());
+        let start = clock.now();
+        let sleeper = {
+            let clock = clock.clone();
+            thread::spawn(move || {
+                clock.sleep_until(start + Duration::from_secs(60));
+                clock.now()
+            })
+        };
+        clock.advance(Duration::from_secs(30));
+        thread::sleep(Duration::from_millis(5));
+        assert!(!sleeper.is_finished());
+        clock.set(start + Duration::from_secs(90));
+        assert_eq!(sleeper.join().unwrap(), start + Duration::from_secs(90));
+        // Never goes back
+        clock.set(start);
+        assert_eq!(clock.now(), start + Duration::from_secs(90));
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    io::{self, Read, Write},
+    ops::Deref,
+};
+
+use crate::bufpool::{BufPool, PooledBuf};
+
+/// Bytes received but not decoded yet
+#[derive(Debug)]
+pub struct BytesBuf {
+    buf: PooledBuf<'static>,
+    start: usize,
+}
+
+impl Default for BytesBuf {
+    fn default() -> Self {
+        Self {
+            buf: BufPool::global().get(0),
+            start: 0,
+        }
+    }
+}
+
+impl BytesBuf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops the first `n` bytes, once they've been decoded
+    pub fn advance(&mut self, n: usize) {
+        assert!(n <= self.len(), "advancing past the end of the buffer");
+        self.start += n;
+        if self.start == self.buf.len() {
+            self.clear();
+        }
+    }
+
+    /// Removes and returns the first `n` bytes
+    pub fn split_to(&mut self, n: usize) -> Vec<u8> {
+        let bytes = self[..n].to_vec();
+        self.advance(n);
+        bytes
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.start = 0;
+    }
+
+    /// Appends the result of a single read call, returning its size
+    pub fn read_from<R: Read>(&mut self, r: &mut R) -> io::Result<usize> {
+        if self.start > 0 && self.start >= self.buf.len() / 2 {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+        let len = self.buf.len();
+        self.buf.resize(len + 4096, 0);
+        let res = loop {
+            match r.read(&mut self.buf[len..]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                res => break res,
+            }
+        };
+        self.buf.truncate(len + *res.as_ref().unwrap_or(&0));
+        res
+    }
+}
+
+impl Deref for BytesBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[self.start..]
+    }
+}
+
+/// Turns bytes into messages and back. `decode` returns `Ok(None)` until the
+/// buffer holds a whole message, and consumes the bytes of the messages it
+/// returns.
+pub trait Codec {
+    /// Messages received
+    type In;
+    /// Messages sent
+    type Out;
+
+    fn decode(&mut self, buf: &mut BytesBuf) -> io::Result<Option<Self::In>>;
+    fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>);
+}
+
+/// Stream of typed messages over a byte stream
+#[derive(Debug)]
+pub struct Framed<S, C> {
+    stream: S,
+    codec: C,
+    read_buf: BytesBuf,
+    write_buf: PooledBuf<'static>,
+}
+
+impl<S: Read + Write, C: Codec> Framed<S, C> {
+    pub fn new(stream: S, codec: C) -> Self {
+        Self {
+            stream,
+            codec,
+            read_buf: BytesBuf::new(),
+            write_buf: BufPool::global().get(0),
+        }
+    }
+
+    /// Next message, or `None` if the stream ends between messages
+    pub fn recv(&mut self) -> io::Result<Option<C::In>> {
+        loop {
+            if let Some(msg) = self.codec.decode(&mut self.read_buf)? {
+                return Ok(Some(msg));
+            }
+            if self.read_buf.read_from(&mut self.stream)? == 0 {
+                if self.read_buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "stream ended inside of a message",
+                ));
+            }
+        }
+    }
+
+    pub fn send(&mut self, msg: C::Out) -> io::Result<()> {
+        self.write_buf.clear();
+        self.codec.encode(msg, &mut self.write_buf);
+        self.stream.write_all(&self.write_buf)
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+}
+
+/// Newline terminated lines, erroring on lines longer than `max_len`
+#[derive(Debug, Clone)]
+pub struct LineCodec {
+    pub max_len: usize,
+}
+
+impl Codec for LineCodec {
+    type In = Vec<u8>;
+    type Out = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut BytesBuf) -> io::Result<Option<Vec<u8>>> {
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(i) if i <= self.max_len => {
+                let line = buf.split_to(i);
+                buf.advance(1);
+                Ok(Some(line))
+            }
+            None if buf.len() <= self.max_len => Ok(None),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "line too long")),
+        }
+    }
+
+    fn encode(&mut self, mut msg: Vec<u8>, buf: &mut Vec<u8>) {
+        msg.push(b'\n');
+        buf.append(&mut msg);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+
+    use super::{BytesBuf, Framed, LineCodec};
+
+    /// Input given 3 bytes at a time, and output collected
+    struct Pipe<'a> {
+        input: &'a [u8],
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.input.len()).min(3);
+            self.input.read(&mut buf[..n])
+        }
+    }
+
+    impl Write for Pipe<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_framed_lines() {
+        let pipe = Pipe {
+            input: b"hello\nworld\n",
+            output: Vec::new(),
+        };
+        let mut framed = Framed::new(pipe, LineCodec { max_len: 10 });
+        while let Some(mut line) = framed.recv().unwrap() {
+            line.reverse();
+            framed.send(line).unwrap();
+        }
+        assert_eq!(framed.get_ref().output, b"olleh\ndlrow\n");
+
+        let pipe = Pipe {
+            input: b"ok\nincomplete",
+            output: Vec::new(),
+        };
+        let mut framed = Framed::new(pipe, LineCodec { max_len: 10 });
+        assert_eq!(framed.recv().unwrap().unwrap(), b"ok");
+        let err = framed.recv().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let pipe = Pipe {
+            input: b"much too long\n",
+            output: Vec::new(),
+        };
+        let err = Framed::new(pipe, LineCodec { max_len: 10 })
+            .recv()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_bytes_buf() {
+        let mut buf = BytesBuf::new();
+        buf.extend_from_slice(b"abcdef");
+        assert_eq!(buf.split_to(2), b"ab");
+        buf.advance(1);
+        assert_eq!(&buf[..], b"def");
+        let n = buf.read_from(&mut &b"gh"[..]).unwrap();
+        assert_eq!(n, 2);
+        assert_eq!(&buf[..], b"defgh");
+        buf.advance(5);
+        assert!(buf.is_empty());
+    }
+}
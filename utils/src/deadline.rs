@@ -0,0 +1,195 @@
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use crate::metrics::Counted;
+
+/// Streams with per call timeouts
+pub trait Timeouts {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Timeouts for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+impl<S: Timeouts> Timeouts for Counted<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_write_timeout(timeout)
+    }
+}
+
+fn exceeded() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "deadline exceeded")
+}
+
+/// Time left before `deadline`, `None` without deadline. Errors with
+/// `TimedOut` once it passed.
+fn remaining(deadline: Option<Instant>) -> io::Result<Option<Duration>> {
+    match deadline {
+        None => Ok(None),
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(left) if !left.is_zero() => Ok(Some(left)),
+            _ => Err(exceeded()),
+        },
+    }
+}
+
+/// Operations timing out with `TimedOut` report the deadline, whatever the
+/// platform returns for socket timeouts
+fn map_timeout<T>(res: io::Result<T>, deadline: Option<Instant>) -> io::Result<T> {
+    res.map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut if deadline.is_some() => exceeded(),
+        _ => e,
+    })
+}
+
+/// Reader failing with `TimedOut` once an absolute deadline passes, however
+/// many reads it took to get there
+#[derive(Debug)]
+pub struct DeadlineReader<R> {
+    inner: R,
+    deadline: Option<Instant>,
+}
+
+impl<R: Read + Timeouts> DeadlineReader<R> {
+    pub fn new(inner: R, deadline: Option<Instant>) -> Self {
+        Self { inner, deadline }
+    }
+
+    /// Sets a deadline `timeout` from now
+    pub fn expire_in(&mut self, timeout: Duration) {
+        self.deadline = Some(Instant::now() + timeout);
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Timeouts> Read for DeadlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.set_read_timeout(remaining(self.deadline)?)?;
+        map_timeout(self.inner.read(buf), self.deadline)
+    }
+}
+
+/// Writer failing with `TimedOut` once an absolute deadline passes
+#[derive(Debug)]
+pub struct DeadlineWriter<W> {
+    inner: W,
+    deadline: Option<Instant>,
+}
+
+impl<W: Write + Timeouts> DeadlineWriter<W> {
+    pub fn new(inner: W, deadline: Option<Instant>) -> Self {
+        Self { inner, deadline }
+    }
+
+    /// Sets a deadline `timeout` from now
+    pub fn expire_in(&mut self, timeout: Duration) {
+        self.deadline = Some(Instant::now() + timeout);
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write + Timeouts> Write for DeadlineWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.set_write_timeout(remaining(self.deadline)?)?;
+        map_timeout(self.inner.write(buf), self.deadline)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.set_write_timeout(remaining(self.deadline)?)?;
+        map_timeout(self.inner.flush(), self.deadline)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::DeadlineReader;
+
+    #[test]
+    fn test_deadline_over_partial_reads() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (conn, _) = listener.accept().unwrap();
+
+        // Trickles a byte every 20ms, each read is fast but the whole is not
+        let writer = thread::spawn(move || {
+            for _ in 0..20 {
+                if client.write_all(b"x").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        let start = Instant::now();
+        let mut reader = DeadlineReader::new(conn, None);
+        reader.expire_in(Duration::from_millis(100));
+        let mut buf = [0; 20];
+        let err = reader.read_exact(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_millis(300));
+
+        reader.set_deadline(None);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Mutex,
+    time::{Duration, Instant},
+    vec,
+};
+
+use crate::{
+    lru::LruCache,
+    retry::{self, with_backoff},
+};
+
+const CACHE_SIZE: usize = 1024;
+
+type Lookup = dyn Fn(&str, u16) -> io::Result<Vec<SocketAddr>> + Send + Sync;
+
+#[derive(Debug, Clone)]
+enum Cached {
+    Found(Vec<SocketAddr>),
+    Failed(io::ErrorKind, String),
+}
+
+/// Resolves host names through the system resolver, caching the answers for
+/// `ttl` and the failures for `negative_ttl`, as the system resolver doesn't
+/// tell the TTLs of the records. Only the most recently used names are kept.
+pub struct Resolver {
+    ttl: Duration,
+    negative_ttl: Duration,
+    cache: Mutex<LruCache<(String, u16), (Cached, Instant)>>,
+    lookup: Box<Lookup>,
+    retry: retry::Policy,
+}
+
+impl Resolver {
+    pub fn new(ttl: Duration, negative_ttl: Duration) -> Self {
+        Self::with_lookup(ttl, negative_ttl, |host, port| {
+            Ok((host, port).to_socket_addrs()?.collect())
+        })
+    }
+
+    /// Resolver using `lookup` rather than the system resolver
+    pub fn with_lookup<F>(ttl: Duration, negative_ttl: Duration, lookup: F) -> Self
+    where
+        F: Fn(&str, u16) -> io::Result<Vec<SocketAddr>> + Send + Sync + 'static,
+    {
+        Self {
+            ttl,
+            negative_ttl,
+            cache: Mutex::new(LruCache::new(CACHE_SIZE).with_metrics("dns_cache")),
+            lookup: Box::new(lookup),
+            retry: retry::Policy {
+                attempts: 1,
+                ..retry::Policy::default()
+            },
+        }
+    }
+
+    /// Retries failed lookups following `policy` before caching the failure
+    pub fn retry(mut self, policy: retry::Policy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    pub fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        self.resolve_at(host, port, Instant::now())
+    }
+
+    fn resolve_at(&self, host: &str, port: u16, now: Instant) -> io::Result<Vec<SocketAddr>> {
+        let key = (host.to_owned(), port);
+        let cached = self
+            .cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+            .cloned();
+        let answer = match cached {
+            Some((answer, expiry)) if now < expiry => answer,
+            _ => {
+                // Not holding the lock, lookups can be slow
+                let (answer, ttl) = match with_backoff(&self.retry, |_| (self.lookup)(host, port)) {
+                    Ok(addrs) if !addrs.is_empty() => (Cached::Found(addrs), self.ttl),
+                    Ok(_) => (
+                        Cached::Failed(io::ErrorKind::NotFound, "no address found".to_owned()),
+                        self.negative_ttl,
+                    ),
+                    Err(e) => (Cached::Failed(e.kind(), e.to_string()), self.negative_ttl),
+                };
+                let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+                cache.insert(key, (answer.clone(), now + ttl));
+                answer
+            }
+        };
+        match answer {
+            Cached::Found(addrs) => Ok(addrs),
+            Cached::Failed(kind, msg) => Err(io::Error::new(kind, msg)),
+        }
+    }
+
+    /// Address resolved through the cache, for `client::connect` or anything
+    /// else taking `ToSocketAddrs`
+    pub fn addr<'r>(&'r self, host: &'r str, port: u16) -> CachedAddr<'r> {
+        CachedAddr {
+            resolver: self,
+            host,
+            port,
+        }
+    }
+
+    /// Forgets everything cached
+    pub fn clear(&self) {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct CachedAddr<'r> {
+    resolver: &'r Resolver,
+    host: &'r str,
+    port: u16,
+}
+
+impl ToSocketAddrs for CachedAddr<'_> {
+    type Iter = vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        Ok(self.resolver.resolve(self.host, self.port)?.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        net::{SocketAddr, ToSocketAddrs},
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use super::Resolver;
+    use crate::retry;
+
+    #[test]
+    fn test_cache() {
+        let lookups = Arc::new(AtomicU32::new(0));
+        let counter = lookups.clone();
+        let resolver = Resolver::with_lookup(
+            Duration::from_secs(60),
+            Duration::from_secs(5),
+            move |host, port| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                match host {
+                    "upstream" => Ok(vec![SocketAddr::from(([10, 0, 0, 1], port))]),
+                    _ => Err(io::Error::new(io::ErrorKind::NotFound, "unknown host")),
+                }
+            },
+        );
+        let now = Instant::now();
+        let addrs = resolver.resolve_at("upstream", 16963, now).unwrap();
+        assert_eq!(addrs, vec!["10.0.0.1:16963".parse().unwrap()]);
+        resolver
+            .resolve_at("upstream", 16963, now + Duration::from_secs(59))
+            .unwrap();
+        assert_eq!(lookups.load(Ordering::Relaxed), 1);
+        resolver
+            .resolve_at("upstream", 16963, now + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(lookups.load(Ordering::Relaxed), 2);
+
+        let err = resolver.resolve_at("other", 1, now).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "unknown host");
+        resolver
+            .resolve_at("other", 1, now + Duration::from_secs(4))
+            .unwrap_err();
+        assert_eq!(lookups.load(Ordering::Relaxed), 3);
+        resolver
+            .resolve_at("other", 1, now + Duration::from_secs(5))
+            .unwrap_err();
+        assert_eq!(lookups.load(Ordering::Relaxed), 4);
+
+        let addrs: Vec<_> = resolver
+            .addr("upstream", 1)
+            .to_socket_addrs()
+            .unwrap()
+            .collect();
+        assert_eq!(addrs.len(), 1);
+    }
+
+    #[test]
+    fn test_system_resolver() {
+        let resolver = Resolver::new(Duration::from_secs(60), Duration::from_secs(5));
+        let addrs = resolver.resolve("127.0.0.1", 80).unwrap();
+        assert_eq!(addrs, vec!["127.0.0.1:80".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_retry() {
+        let lookups = Arc::new(AtomicU32::new(0));
+        let counter = lookups.clone();
+        let resolver = Resolver::with_lookup(
+            Duration::from_secs(60),
+            Duration::from_secs(5),
+            move |_, port| match counter.fetch_add(1, Ordering::Relaxed) {
+                0 => Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
+                _ => Ok(vec![SocketAddr::from(([10, 0, 0, 1], port))]),
+            },
+        )
+        .retry(retry::Policy {
+            attempts: 2,
+            initial_backoff: Duration::from_millis(1),
+            ..retry::Policy::default()
+        });
+        resolver.resolve("upstream", 1).unwrap();
+        assert_eq!(lookups.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,286 @@
+use std::io::{self, Read, Write};
+
+use crate::bufpool::{BufPool, PooledBuf};
+
+/// Reads newline terminated lines, without buffering more than `max_len`
+/// bytes when the peer never sends the newline
+#[derive(Debug)]
+pub struct LineReader<R> {
+    inner: R,
+    buf: PooledBuf<'static>,
+    start: usize,
+    end: usize,
+    /// Bytes of buf[start..end] already searched for a newline
+    scanned: usize,
+    max_len: usize,
+}
+
+impl<R: Read> LineReader<R> {
+    pub fn new(inner: R, max_len: usize) -> Self {
+        Self {
+            inner,
+            buf: BufPool::global().get(0),
+            start: 0,
+            end: 0,
+            scanned: 0,
+            max_len,
+        }
+    }
+
+    /// Next line, without its `\n`, or `None` at the end of the stream. A
+    /// trailing incomplete line is dropped.
+    /// Errors with `InvalidData` if the line is longer than `max_len`.
+    pub fn read_line(&mut self) -> io::Result<Option<&[u8]>> {
+        loop {
+            let pending = &self.buf[self.start + self.scanned..self.end];
+            if let Some(i) = pending.iter().position(|&b| b == b'\n') {
+                let line_start = self.start;
+                let line_end = self.start + self.scanned + i;
+                self.start = line_end + 1;
+                self.scanned = 0;
+                if line_end - line_start > self.max_len {
+                    return Err(too_long());
+                }
+                return Ok(Some(&self.buf[line_start..line_end]));
+            }
+            self.scanned = self.end - self.start;
+            if self.scanned > self.max_len {
+                return Err(too_long());
+            }
+            if self.start > 0 {
+                self.buf.copy_within(self.start..self.end, 0);
+                self.end -= self.start;
+                self.start = 0;
+            }
+            if self.end == self.buf.len() {
+                // +1 for the newline
+                let len = (self.buf.len() * 2).max(1024).min(self.max_len + 1);
+                self.buf.resize(len.max(self.end + 1), 0);
+            }
+            let n = match self.inner.read(&mut self.buf[self.end..]) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                return Ok(None);
+            }
+            self.end += n;
+        }
+    }
+
+    /// Bytes read from the inner reader but not returned as lines yet
+    pub fn buffered(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "line too long")
+}
+
+/// Big endian length prefix of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prefix {
+    U16,
+    U32,
+}
+
+impl Prefix {
+    pub fn size(self) -> usize {
+        match self {
+            Prefix::U16 => 2,
+            Prefix::U32 => 4,
+        }
+    }
+
+    pub fn max_len(self) -> usize {
+        match self {
+            Prefix::U16 => u16::MAX as usize,
+            Prefix::U32 => u32::MAX as usize,
+        }
+    }
+}
+
+/// Reads length prefixed frames, rejecting the ones longer than `max_len`
+/// before allocating for them
+#[derive(Debug)]
+pub struct FrameReader<R> {
+    inner: R,
+    prefix: Prefix,
+    max_len: usize,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R, prefix: Prefix, max_len: usize) -> Self {
+        Self {
+            inner,
+            prefix,
+            max_len,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Payload of the next frame, or `None` if the stream ends between frames.
+    /// Errors with `UnexpectedEof` if it ends inside of one.
+    pub fn read_frame(&mut self) -> io::Result<Option<&[u8]>> {
+        let mut prefix = [0; 4];
+        let prefix = &mut prefix[..self.prefix.size()];
+        match fill(&mut self.inner, prefix)? {
+            0 => return Ok(None),
+            n if n < prefix.len() => return Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => {}
+        }
+        let len = prefix.iter().fold(0, |len, &b| len << 8 | b as usize);
+        if len > self.max_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"));
+        }
+        self.buf.resize(len, 0);
+        self.inner.read_exact(&mut self.buf)?;
+        Ok(Some(&self.buf))
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+/// Reads until `buf` is full or the end of the stream, returning the bytes read
+fn fill<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Writes `payload` preceded by its length, with a single write call so
+/// frames aren't split in small packets
+pub fn write_frame<W: Write>(w: &mut W, prefix: Prefix, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > prefix.max_len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "frame too long for its length prefix",
+        ));
+    }
+    let mut frame = Vec::with_capacity(prefix.size() + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[4 - prefix.size()..]);
+    frame.extend_from_slice(payload);
+    w.write_all(&frame)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read};
+
+    use super::{write_frame, FrameReader, LineReader, Prefix};
+
+    /// Returns at most `chunk` bytes per read
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn lines(data: &[u8], chunk: usize, max_len: usize) -> io::Result<Vec<Vec<u8>>> {
+        let mut reader = LineReader::new(Chunked { data, chunk }, max_len);
+        let mut lines = Vec::new();
+        while let Some(line) = reader.read_line()? {
+            lines.push(line.to_vec());
+        }
+        Ok(lines)
+    }
+
+    #[test]
+    fn test_split_lines() {
+        let data = b"hello\n\nworld, a longer line\nlast\nincomplete";
+        for chunk in [1, 2, 3, 7, 100] {
+            assert_eq!(
+                lines(data, chunk, 32).unwrap(),
+                vec![
+                    b"hello".to_vec(),
+                    b"".to_vec(),
+                    b"world, a longer line".to_vec(),
+                    b"last".to_vec(),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_len() {
+        assert_eq!(lines(b"12345\n", 3, 5).unwrap().len(), 1);
+        for chunk in [1, 4, 100] {
+            let err = lines(b"ok\n123456\n", chunk, 5).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        // Never buffers much more than the max length without a newline
+        let mut reader = LineReader::new(io::repeat(b'a'), 2000);
+        reader.read_line().unwrap_err();
+        assert!(reader.buf.len() <= 2001);
+    }
+
+    #[test]
+    fn test_frames() {
+        for prefix in [Prefix::U16, Prefix::U32] {
+            let mut data = Vec::new();
+            write_frame(&mut data, prefix, b"hello").unwrap();
+            write_frame(&mut data, prefix, b"").unwrap();
+            write_frame(&mut data, prefix, &[7; 300]).unwrap();
+            assert_eq!(data.len(), 3 * prefix.size() + 305);
+            let mut reader = FrameReader::new(
+                Chunked {
+                    data: &data,
+                    chunk: 3,
+                },
+                prefix,
+                300,
+            );
+            assert_eq!(reader.read_frame().unwrap(), Some(&b"hello"[..]));
+            assert_eq!(reader.read_frame().unwrap(), Some(&b""[..]));
+            assert_eq!(reader.read_frame().unwrap(), Some(&[7; 300][..]));
+            assert_eq!(reader.read_frame().unwrap(), None);
+
+            let mut reader = FrameReader::new(&data[..], prefix, 299);
+            reader.read_frame().unwrap();
+            reader.read_frame().unwrap();
+            let err = reader.read_frame().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+            for truncated in [1, prefix.size() + 2] {
+                let mut reader = FrameReader::new(&data[..truncated], prefix, 300);
+                let err = reader.read_frame().unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+            }
+        }
+        assert_eq!(Prefix::U16.max_len(), 65535);
+        let err = write_frame(&mut Vec::new(), Prefix::U16, &[0; 65536]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::timer::{Timer, TimerHandle};
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Writes a heartbeat frame to a connection at a fixed interval, from a
+/// `Timer` thread. The writer is shared with the connection, which must write
+/// through the same mutex so that frames aren't interleaved.
+/// Stops when dropped, or at the first write failure.
+pub struct Heartbeat<W> {
+    timer: Arc<Timer>,
+    writer: Arc<Mutex<W>>,
+    frame: Arc<Mutex<Vec<u8>>>,
+    error: Arc<Mutex<Option<io::ErrorKind>>>,
+    handle: Option<TimerHandle>,
+}
+
+impl<W: Write + Send + 'static> Heartbeat<W> {
+    /// Sends `frame` every `interval`, a zero interval sending nothing
+    pub fn start(
+        timer: Arc<Timer>,
+        writer: Arc<Mutex<W>>,
+        interval: Duration,
+        frame: &[u8],
+    ) -> Self {
+        let mut heartbeat = Self {
+            timer,
+            writer,
+            frame: Arc::new(Mutex::new(frame.to_vec())),
+            error: Arc::default(),
+            handle: None,
+        };
+        heartbeat.set_interval(interval);
+        heartbeat
+    }
+
+    /// Restarts the heartbeat with a new interval counted from now, or stops
+    /// it if the interval is zero
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.cancel();
+        if interval.is_zero() || self.error().is_some() {
+            return;
+        }
+        let (writer, frame, error) = (self.writer.clone(), self.frame.clone(), self.error.clone());
+        self.handle = Some(self.timer.every(interval, move || {
+            let mut error = lock(&error);
+            if error.is_some() {
+                return;
+            }
+            let mut writer = lock(&writer);
+            let frame = lock(&frame);
+            if let Err(e) = writer.write_all(&frame).and_then(|()| writer.flush()) {
+                *error = Some(e.kind());
+            }
+        }));
+    }
+
+    /// Replaces the frame sent, from the next heartbeat on
+    pub fn set_frame(&self, frame: &[u8]) {
+        *lock(&self.frame) = frame.to_vec();
+    }
+
+    pub fn cancel(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.cancel();
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.handle.is_some() && self.error().is_none()
+    }
+
+    /// Kind of the write error which stopped the heartbeat
+    pub fn error(&self) -> Option<io::ErrorKind> {
+        *lock(&self.error)
+    }
+}
+
+impl<W> Drop for Heartbeat<W> {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Write},
+        sync::{mpsc, Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::Heartbeat;
+    use crate::{clock::MockClock, timer::Timer};
+
+    /// Fails writes after `limit` bytes
+    struct Limited {
+        written: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written.len() + buf.len() > self.limit {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let ms = Duration::from_millis;
+        let clock = Arc::new(MockClock::new());
+        let timer = Arc::new(Timer::with_clock(ms(1), clock.clone()));
+        // Moves the clock forward and waits for the timers due by then to run,
+        // which run before the one scheduled last
+        let step = |d| {
+            let (tx, rx) = mpsc::channel();
+            timer.after(d, move || tx.send(()).unwrap());
+            clock.advance(d);
+            rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        };
+        let writer = Arc::new(Mutex::new(Limited {
+            written: Vec::new(),
+            limit: 6,
+        }));
+        let written = || writer.lock().unwrap().written.clone();
+        let mut heartbeat = Heartbeat::start(timer.clone(), writer.clone(), ms(2), b"A");
+        step(ms(1));
+        assert_eq!(written(), b"");
+        step(ms(1));
+        step(ms(2));
+        assert_eq!(written(), b"AA");
+        heartbeat.set_frame(b"BB");
+        step(ms(2));
+        assert_eq!(written(), b"AABB");
+        heartbeat.set_interval(Duration::ZERO);
+        assert!(!heartbeat.is_running());
+        step(ms(10));
+        assert_eq!(written(), b"AABB");
+
+        heartbeat.set_interval(ms(2));
+        step(ms(2));
+        assert_eq!(written(), b"AABBBB");
+        step(ms(2));
+        assert_eq!(heartbeat.error(), Some(io::ErrorKind::BrokenPipe));
+        assert!(!heartbeat.is_running());
+        assert_eq!(written().len(), 6);
+    }
+}
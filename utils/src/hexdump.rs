@@ -0,0 +1,82 @@
+use std::fmt;
+
+/// Hex dump of `bytes`, 16 per line, with their offset and ASCII. Only the
+/// first 256 bytes are shown unless changed with `max_len`.
+pub fn hexdump(bytes: &[u8]) -> HexDump<'_> {
+    HexDump {
+        bytes,
+        max_len: 256,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HexDump<'a> {
+    bytes: &'a [u8],
+    max_len: usize,
+}
+
+impl HexDump<'_> {
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+}
+
+impl fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = &self.bytes[..self.bytes.len().min(self.max_len)];
+        for (i, line) in shown.chunks(16).enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:08x} ", i * 16)?;
+            for j in 0..16 {
+                if j == 8 {
+                    write!(f, " ")?;
+                }
+                match line.get(j) {
+                    Some(b) => write!(f, " {:02x}", b)?,
+                    None => write!(f, "   ")?,
+                }
+            }
+            write!(f, "  |")?;
+            for &b in line {
+                let c = if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                };
+                write!(f, "{}", c)?;
+            }
+            write!(f, "|")?;
+        }
+        if shown.len() < self.bytes.len() {
+            if !shown.is_empty() {
+                writeln!(f)?;
+            }
+            write!(f, "... {} more bytes", self.bytes.len() - shown.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::hexdump;
+
+    #[test]
+    fn test_hexdump() {
+        let data: Vec<u8> = b"hello\n".iter().copied().chain(0..20).collect();
+        assert_eq!(
+            hexdump(&data).to_string(),
+            "00000000  68 65 6c 6c 6f 0a 00 01  02 03 04 05 06 07 08 09  |hello...........|\n\
+             00000010  0a 0b 0c 0d 0e 0f 10 11  12 13                    |..........|"
+        );
+        assert_eq!(
+            hexdump(&data).max_len(4).to_string(),
+            "00000000  68 65 6c 6c                                       |hell|\n... 22 more bytes"
+        );
+        assert_eq!(hexdump(&data).max_len(0).to_string(), "... 26 more bytes");
+        assert_eq!(hexdump(b"").to_string(), "");
+    }
+}
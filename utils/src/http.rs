@@ -0,0 +1,431 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Read, Write},
+    time::Duration,
+};
+
+use crate::{
+    client::{self, RetryPolicy},
+    logging, metrics,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    /// Header names are lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+const MAX_HEAD: u64 = 8192;
+const MAX_BODY: usize = 1 << 20;
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads a request, with its body if it has a `Content-Length`. Errors with
+/// `InvalidData` on malformed or too big requests.
+pub fn read_request<R: BufRead>(r: &mut R) -> io::Result<Request> {
+    let mut head = r.take(MAX_HEAD);
+    let mut line = String::new();
+    head.read_line(&mut line)?;
+    let mut parts = line.trim_end().split(' ');
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid("malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(invalid("unsupported HTTP version"));
+    }
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_owned(), Some(query.to_owned())),
+        None => (target.to_owned(), None),
+    };
+    let method = method.to_owned();
+    let mut request = Request {
+        method,
+        path,
+        query,
+        headers: read_headers(&mut head)?,
+        body: Vec::new(),
+    };
+    if let Some(len) = request.header("content-length") {
+        let len: usize = len.parse().map_err(|_| invalid("invalid content length"))?;
+        if len > MAX_BODY {
+            return Err(invalid("request body too big"));
+        }
+        request.body.resize(len, 0);
+        r.read_exact(&mut request.body)?;
+    }
+    Ok(request)
+}
+
+/// Headers up to the empty line ending them, with lowercased names
+fn read_headers<R: BufRead>(head: &mut R) -> io::Result<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if head.read_line(&mut line)? == 0 || !line.ends_with('\n') {
+            return Err(invalid("head too long or truncated"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("malformed header"))?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_owned(), content_type.to_owned())],
+            body: body.into(),
+        }
+    }
+
+    pub fn text(body: impl Into<Vec<u8>>) -> Self {
+        Self::new(200, "text/plain; charset=utf-8", body)
+    }
+
+    pub fn json(body: impl Into<Vec<u8>>) -> Self {
+        Self::new(200, "application/json", body)
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", reason(status))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Writes the response, closing the connection after it
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            let _ = write!(head, "{}: {}\r\n", name, value);
+        }
+        let _ = write!(
+            head,
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.body.len()
+        );
+        let mut out = head.into_bytes();
+        out.extend_from_slice(&self.body);
+        w.write_all(&out)?;
+        w.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+const MAX_RESPONSE_BODY: usize = 16 << 20;
+
+/// Sends a request to an `http://` url, on a new connection closed after the
+/// response. Errors with `Unsupported` for other schemes.
+pub fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> io::Result<Response> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "only http:// urls are supported",
+        )
+    })?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let addr = match host.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => host.to_owned(),
+        _ => format!("{}:80", host),
+    };
+    let policy = RetryPolicy {
+        attempts: 1,
+        ..RetryPolicy::default()
+    };
+    let (stream, _) = client::connect(addr.as_str(), &policy)?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, host);
+    for (name, value) in headers {
+        let _ = write!(head, "{}: {}\r\n", name, value);
+    }
+    if !body.is_empty() || method == "POST" || method == "PUT" {
+        let _ = write!(head, "Content-Length: {}\r\n", body.len());
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    let mut out = head.into_bytes();
+    out.extend_from_slice(body);
+    (&stream).write_all(&out)?;
+    read_response(&mut BufReader::new(stream))
+}
+
+pub fn get(url: &str) -> io::Result<Response> {
+    request("GET", url, &[], b"")
+}
+
+pub fn post(url: &str, content_type: &str, body: &[u8]) -> io::Result<Response> {
+    request("POST", url, &[("Content-Type", content_type)], body)
+}
+
+/// Reads a response, with a body delimited by its length, chunks, or the end
+/// of the stream
+pub fn read_response<R: BufRead>(r: &mut R) -> io::Result<Response> {
+    let mut head = r.take(MAX_HEAD);
+    let mut line = String::new();
+    head.read_line(&mut line)?;
+    let mut parts = line.trim_end().splitn(3, ' ');
+    let status = match (parts.next(), parts.next()) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/1.") => status.parse().ok(),
+        _ => None,
+    };
+    let status = status.ok_or_else(|| invalid("malformed status line"))?;
+    let mut response = Response {
+        status,
+        headers: read_headers(&mut head)?,
+        body: Vec::new(),
+    };
+    let chunked = response
+        .header("transfer-encoding")
+        .is_some_and(|te| te.eq_ignore_ascii_case("chunked"));
+    if chunked {
+        response.body = read_chunked(r)?;
+    } else if let Some(len) = response.header("content-length") {
+        let len: usize = len.parse().map_err(|_| invalid("invalid content length"))?;
+        if len > MAX_RESPONSE_BODY {
+            return Err(invalid("response body too big"));
+        }
+        response.body.resize(len, 0);
+        r.read_exact(&mut response.body)?;
+    } else {
+        r.take(MAX_RESPONSE_BODY as u64 + 1)
+            .read_to_end(&mut response.body)?;
+        if response.body.len() > MAX_RESPONSE_BODY {
+            return Err(invalid("response body too big"));
+        }
+    }
+    Ok(response)
+}
+
+fn read_chunked<R: BufRead>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        r.take(1024).read_line(&mut line)?;
+        let size = line.trim_end().split(';').next().unwrap_or_default();
+        let size =
+            usize::from_str_radix(size.trim(), 16).map_err(|_| invalid("invalid chunk size"))?;
+        if size == 0 {
+            // Trailers
+            read_headers(&mut r.take(MAX_HEAD))?;
+            return Ok(body);
+        }
+        if size > MAX_RESPONSE_BODY - body.len() {
+            return Err(invalid("response body too big"));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        r.read_exact(&mut body[start..])?;
+        let mut crlf = [0; 2];
+        r.read_exact(&mut crlf)?;
+        if crlf != *b"\r\n" {
+            return Err(invalid("chunk not followed by CRLF"));
+        }
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// Routes requests by exact method and path, answering a request per
+/// connection
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<(String, String, Box<Handler>)>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route<F>(mut self, method: &str, path: &str, handler: F) -> Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes
+            .push((method.to_owned(), path.to_owned(), Box::new(handler)));
+        self
+    }
+
+    /// `/metrics` in the Prometheus format, `/healthz`, and `/debug` with the
+    /// recent log history
+    pub fn observability() -> Self {
+        Self::new()
+            .route("GET", "/metrics", |_| {
+                Response::new(200, "text/plain; version=0.0.4", metrics::render())
+            })
+            .route("GET", "/healthz", |_| Response::text("ok\n"))
+            .route("GET", "/debug", |_| {
+                let mut page = String::new();
+                for entry in logging::recent(None) {
+                    let level = entry.level.map_or("-", |l| l.as_str());
+                    let _ = writeln!(page, "{} {} {}", entry.timestamp.utc(), level, entry.text);
+                }
+                Response::text(page)
+            })
+    }
+
+    pub fn respond(&self, request: &Request) -> Response {
+        let mut path_found = false;
+        for (method, path, handler) in &self.routes {
+            if *path == request.path {
+                if *method == request.method {
+                    return handler(request);
+                }
+                path_found = true;
+            }
+        }
+        Response::status(if path_found { 405 } else { 404 })
+    }
+
+    /// Answers the request on `stream`
+    pub fn handle<S: Read + Write>(&self, stream: S) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let response = match read_request(&mut reader) {
+            Ok(request) => self.respond(&request),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => Response::status(400),
+            Err(e) => return Err(e),
+        };
+        response.write_to(reader.get_mut())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{BufReader, Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::{get, post, read_request, read_response, Response, Router};
+    use crate::testing::duplex;
+
+    #[test]
+    fn test_read_request() {
+        let raw = b"POST /jobs?queue=a HTTP/1.1\r\nHost: x\r\nContent-Length: 4\r\n\r\nbodyrest";
+        let request = read_request(&mut &raw[..]).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/jobs");
+        assert_eq!(request.query.as_deref(), Some("queue=a"));
+        assert_eq!(request.header("HOST"), Some("x"));
+        assert_eq!(request.body, b"body");
+
+        assert!(read_request(&mut &b"garbage\r\n\r\n"[..]).is_err());
+        let long = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(10000));
+        assert!(read_request(&mut long.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_router() {
+        let router = Router::observability().route("GET", "/hello", |r| {
+            Response::text(format!("hello {}", r.query.as_deref().unwrap_or("")))
+        });
+        let get = |request: &[u8]| {
+            let (mut client, server) = duplex();
+            client.write_all(request).unwrap();
+            router.handle(server).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get(b"GET /hello?you HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 9\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello you"));
+        assert!(get(b"GET /healthz HTTP/1.0\r\n\r\n").ends_with("ok\n"));
+        assert!(get(b"GET /metrics HTTP/1.1\r\n\r\n").contains("conn_bytes_count"));
+        assert!(get(b"POST /hello HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+        assert!(get(b"GET /nope HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert!(get(b"nonsense\r\n\r\n").starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn test_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            for response in [
+                &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                   5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nTrailer: x\r\n\r\n"[..],
+                b"HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok",
+            ] {
+                let (conn, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(conn);
+                let request = read_request(&mut reader).unwrap();
+                reader.get_mut().write_all(response).unwrap();
+                if request.method == "POST" {
+                    assert_eq!(request.body, b"{}");
+                    assert_eq!(request.header("content-type"), Some("application/json"));
+                }
+            }
+        });
+        let response = get(&format!("http://{}/results", addr)).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"hello, world");
+        let response = post(&format!("http://{}", addr), "application/json", b"{}").unwrap();
+        assert_eq!((response.status, &response.body[..]), (201, &b"ok"[..]));
+        server.join().unwrap();
+
+        // Body until the end of the stream
+        let raw = b"HTTP/1.0 200 OK\r\nServer: x\r\n\r\nall of it";
+        assert_eq!(read_response(&mut &raw[..]).unwrap().body, b"all of it");
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    1\r\na\r\nffffffffffffffff\r\n";
+        let err = read_response(&mut &raw[..]).unwrap_err();
+        assert_eq!(err.to_string(), "response body too big");
+        assert!(get("https://example.com").is_err());
+    }
+}
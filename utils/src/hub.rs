@@ -0,0 +1,255 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// What happens when a message is published to a subscriber whose queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumer {
+    /// The oldest queued message is dropped
+    DropOldest,
+    /// The new message isn't queued for this subscriber
+    DropNewest,
+    /// The subscriber is removed, its receiver returns `None` once drained
+    Disconnect,
+}
+
+#[derive(Debug)]
+struct Queue<T> {
+    messages: VecDeque<T>,
+    /// Messages dropped since the last receive
+    dropped: u64,
+    disconnected: bool,
+}
+
+type SharedQueue<T> = (Mutex<Queue<T>>, Condvar);
+
+#[derive(Debug)]
+struct Inner<T> {
+    subscribers: HashMap<u64, Arc<SharedQueue<T>>>,
+    next_id: u64,
+}
+
+/// Fans out published messages to all the current subscribers, each with its
+/// own bounded queue
+#[derive(Debug)]
+pub struct Hub<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+    capacity: usize,
+    policy: SlowConsumer,
+}
+
+impl<T> Clone for Hub<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            capacity: self.capacity,
+            policy: self.policy,
+        }
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl<T: Clone> Hub<T> {
+    pub fn new(capacity: usize, policy: SlowConsumer) -> Self {
+        assert!(capacity > 0);
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                subscribers: HashMap::new(),
+                next_id: 0,
+            })),
+            capacity,
+            policy,
+        }
+    }
+
+    pub fn subscribe(&self) -> Subscriber<T> {
+        let mut inner = lock(&self.inner);
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let queue = Arc::new((
+            Mutex::new(Queue {
+                messages: VecDeque::new(),
+                dropped: 0,
+                disconnected: false,
+            }),
+            Condvar::new(),
+        ));
+        inner.subscribers.insert(id, queue.clone());
+        Subscriber {
+            id,
+            queue,
+            hub: self.inner.clone(),
+        }
+    }
+
+    pub fn subscribers(&self) -> usize {
+        lock(&self.inner).subscribers.len()
+    }
+
+    /// Queues `msg` for every subscriber, returning how many got it
+    pub fn publish(&self, msg: T) -> usize {
+        self.publish_filtered(msg, |_| true)
+    }
+
+    /// Queues `msg` for the subscribers `to` returns true for, such as all of
+    /// them but the sender
+    pub fn publish_filtered<F>(&self, msg: T, mut to: F) -> usize
+    where
+        F: FnMut(u64) -> bool,
+    {
+        let mut inner = lock(&self.inner);
+        let mut delivered = 0;
+        inner.subscribers.retain(|&id, queue| {
+            if !to(id) {
+                return true;
+            }
+            let (queue, cond) = &**queue;
+            let mut queue = lock(queue);
+            if queue.messages.len() >= self.capacity {
+                queue.dropped += 1;
+                match self.policy {
+                    SlowConsumer::DropOldest => {
+                        queue.messages.pop_front();
+                    }
+                    SlowConsumer::DropNewest => return true,
+                    SlowConsumer::Disconnect => {
+                        queue.disconnected = true;
+                        cond.notify_all();
+                        return false;
+                    }
+                }
+            }
+            queue.messages.push_back(msg.clone());
+            delivered += 1;
+            cond.notify_all();
+            true
+        });
+        delivered
+    }
+}
+
+/// Receiving end of a subscription, unsubscribed when dropped
+#[derive(Debug)]
+pub struct Subscriber<T> {
+    id: u64,
+    queue: Arc<SharedQueue<T>>,
+    hub: Arc<Mutex<Inner<T>>>,
+}
+
+impl<T> Subscriber<T> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Next message without waiting
+    pub fn try_recv(&self) -> Option<T> {
+        lock(&self.queue.0).messages.pop_front()
+    }
+
+    /// Waits for the next message, `None` if disconnected as a slow consumer
+    pub fn recv(&self) -> Option<T> {
+        let (queue, cond) = &*self.queue;
+        let mut queue = lock(queue);
+        loop {
+            if let Some(msg) = queue.messages.pop_front() {
+                return Some(msg);
+            }
+            if queue.disconnected {
+                return None;
+            }
+            queue = cond.wait(queue).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Waits for the next message for at most `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let (queue, cond) = &*self.queue;
+        let mut queue = lock(queue);
+        loop {
+            if let Some(msg) = queue.messages.pop_front() {
+                return Some(msg);
+            }
+            let now = Instant::now();
+            if queue.disconnected || now >= deadline {
+                return None;
+            }
+            queue = cond
+                .wait_timeout(queue, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Number of messages dropped because the queue was full, since the last
+    /// call
+    pub fn take_dropped(&self) -> u64 {
+        std::mem::take(&mut lock(&self.queue.0).dropped)
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        lock(&self.queue.0).disconnected
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        lock(&self.hub).subscribers.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    use super::{Hub, SlowConsumer};
+
+    #[test]
+    fn test_fan_out() {
+        let hub = Hub::new(8, SlowConsumer::DropOldest);
+        let a = hub.subscribe();
+        let b = hub.subscribe();
+        assert_eq!(hub.publish("hello"), 2);
+        assert_eq!(hub.publish_filtered("not to a", |id| id != a.id()), 1);
+        assert_eq!(a.try_recv(), Some("hello"));
+        assert_eq!(a.try_recv(), None);
+        assert_eq!(b.recv(), Some("hello"));
+        assert_eq!(b.recv(), Some("not to a"));
+        drop(b);
+        assert_eq!(hub.subscribers(), 1);
+
+        let publisher = hub.clone();
+        let t = thread::spawn(move || publisher.publish("from thread"));
+        assert_eq!(a.recv_timeout(Duration::from_secs(5)), Some("from thread"));
+        t.join().unwrap();
+        assert_eq!(a.recv_timeout(Duration::from_millis(1)), None);
+    }
+
+    #[test]
+    fn test_slow_consumers() {
+        for (policy, expected) in [
+            (SlowConsumer::DropOldest, vec![2, 3]),
+            (SlowConsumer::DropNewest, vec![1, 2]),
+            (SlowConsumer::Disconnect, vec![1, 2]),
+        ] {
+            let hub = Hub::new(2, policy);
+            let sub = hub.subscribe();
+            for i in 1..=3 {
+                hub.publish(i);
+            }
+            assert_eq!(sub.take_dropped(), 1);
+            let received: Vec<_> = std::iter::from_fn(|| sub.try_recv()).collect();
+            assert_eq!(received, expected);
+            assert_eq!(sub.is_disconnected(), policy == SlowConsumer::Disconnect);
+            if policy == SlowConsumer::Disconnect {
+                assert_eq!(hub.subscribers(), 0);
+                assert_eq!(sub.recv(), None);
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Unique increasing ids, usable in statics
+#[derive(Debug, Default)]
+pub struct IdGen {
+    next: AtomicU64,
+}
+
+impl IdGen {
+    pub const fn new() -> Self {
+        Self::starting_at(0)
+    }
+
+    pub const fn starting_at(first: u64) -> Self {
+        Self {
+            next: AtomicU64::new(first),
+        }
+    }
+
+    /// Panics when the ids run out rather than reusing one
+    pub fn next_id(&self) -> u64 {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        assert!(id != u64::MAX, "ran out of ids");
+        id
+    }
+
+    /// Id the next call to `next_id` returns
+    pub fn peek(&self) -> u64 {
+        self.next.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, thread};
+
+    use super::IdGen;
+
+    #[test]
+    fn test_unique() {
+        static IDS: IdGen = IdGen::starting_at(1);
+        let ids: Vec<u64> = thread::scope(|s| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| (0..1000).map(|_| IDS.next_id()).collect::<Vec<_>>()))
+                .collect();
+            threads
+                .into_iter()
+                .flat_map(|t| {
+                    let ids = t.join().unwrap();
+                    assert!(ids.windows(2).all(|w| w[0] < w[1]));
+                    ids
+                })
+                .collect()
+        });
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), 4000);
+        assert_eq!(ids.iter().min(), Some(&1));
+        assert_eq!(IDS.peek(), 4001);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+#[derive(Debug)]
+struct Strings {
+    set: HashSet<Arc<str>>,
+    /// Size at which strings nobody else holds are dropped
+    next_purge: usize,
+}
+
+/// Shares a single `Arc<str>` between equal strings. Unlike `json::KeyInterner`
+/// strings are freed once only the interner holds them, which is checked when
+/// the set doubled in size since the last check.
+#[derive(Debug)]
+pub struct Interner {
+    strings: RwLock<Strings>,
+}
+
+const MIN_PURGE: usize = 1024;
+
+impl Default for Interner {
+    fn default() -> Self {
+        Self {
+            strings: RwLock::new(Strings {
+                set: HashSet::new(),
+                next_purge: MIN_PURGE,
+            }),
+        }
+    }
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process wide interner, shared by all connections
+    pub fn global() -> &'static Interner {
+        static GLOBAL: OnceLock<Interner> = OnceLock::new();
+        GLOBAL.get_or_init(Interner::new)
+    }
+
+    pub fn intern(&self, s: &str) -> Arc<str> {
+        if let Some(s) = self
+            .strings
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .set
+            .get(s)
+        {
+            return s.clone();
+        }
+        let mut strings = self.strings.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(s) = strings.set.get(s) {
+            return s.clone();
+        }
+        if strings.set.len() >= strings.next_purge {
+            strings.set.retain(|s| Arc::strong_count(s) > 1);
+            strings.next_purge = (strings.set.len() * 2).max(MIN_PURGE);
+        }
+        let s: Arc<str> = s.into();
+        strings.set.insert(s.clone());
+        s
+    }
+
+    /// Drops the strings only the interner holds, returning how many
+    pub fn purge(&self) -> usize {
+        let mut strings = self.strings.write().unwrap_or_else(|e| e.into_inner());
+        let before = strings.set.len();
+        strings.set.retain(|s| Arc::strong_count(s) > 1);
+        before - strings.set.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .set
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{Interner, MIN_PURGE};
+
+    #[test]
+    fn test_intern() {
+        let interner = Interner::new();
+        let a = interner.intern("RE05BKG");
+        let b = interner.intern(&String::from("RE05BKG"));
+        assert!(Arc::ptr_eq(&a, &b));
+        interner.intern("dropped");
+        assert_eq!(interner.len(), 2);
+        assert_eq!(interner.purge(), 1);
+        assert!(Arc::ptr_eq(&a, &interner.intern("RE05BKG")));
+    }
+
+    #[test]
+    fn test_bounded_by_live_strings() {
+        let interner = Interner::new();
+        let kept: Vec<_> = (0..10).map(|i| interner.intern(&i.to_string())).collect();
+        for i in 0..10 * MIN_PURGE {
+            interner.intern(&format!("tmp{}", i));
+        }
+        assert!(interner.len() <= MIN_PURGE + 1);
+        for s in &kept {
+            assert_eq!(Arc::strong_count(s), 2);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    ReverseBits,
+    Xor(u8),
+    XorPos,
+    Add(u8),
+    AddPos,
+}
+
+/// Cipher of the insecure sockets layer, a list of operations applied to each
+/// byte of a stream depending on its position in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cipher {
+    ops: Vec<Op>,
+}
+
+impl Cipher {
+    pub fn new(ops: Vec<Op>) -> Self {
+        Self { ops }
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Parses the spec at the start of `buf`, returning the cipher and the
+    /// length of the spec, with its terminating 0, or `None` if it is
+    /// incomplete. Errors with `InvalidData` on unknown operations.
+    pub fn parse(buf: &[u8]) -> io::Result<Option<(Cipher, usize)>> {
+        let mut ops = Vec::new();
+        let mut i = 0;
+        loop {
+            let Some(&code) = buf.get(i) else {
+                return Ok(None);
+            };
+            let arg = buf.get(i + 1).copied();
+            let op = match (code, arg) {
+                (0, _) => return Ok(Some((Cipher { ops }, i + 1))),
+                (1, _) => Op::ReverseBits,
+                (3, _) => Op::XorPos,
+                (5, _) => Op::AddPos,
+                (2 | 4, None) => return Ok(None),
+                (2, Some(n)) => Op::Xor(n),
+                (4, Some(n)) => Op::Add(n),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown cipher operation {:#04x}", code),
+                    ))
+                }
+            };
+            i += match op {
+                Op::Xor(_) | Op::Add(_) => 2,
+                _ => 1,
+            };
+            ops.push(op);
+        }
+    }
+
+    /// Reads a spec byte by byte, so nothing after it is consumed
+    pub fn read_from<R: Read>(r: &mut R, max_len: usize) -> io::Result<Cipher> {
+        let mut spec = Vec::new();
+        loop {
+            if let Some((cipher, _)) = Self::parse(&spec)? {
+                return Ok(cipher);
+            }
+            if spec.len() >= max_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "cipher spec too long",
+                ));
+            }
+            let mut byte = [0];
+            r.read_exact(&mut byte)?;
+            spec.push(byte[0]);
+        }
+    }
+
+    pub fn encode(&self, byte: u8, pos: u64) -> u8 {
+        self.ops.iter().fold(byte, |b, op| match *op {
+            Op::ReverseBits => b.reverse_bits(),
+            Op::Xor(n) => b ^ n,
+            Op::XorPos => b ^ pos as u8,
+            Op::Add(n) => b.wrapping_add(n),
+            Op::AddPos => b.wrapping_add(pos as u8),
+        })
+    }
+
+    pub fn decode(&self, byte: u8, pos: u64) -> u8 {
+        self.ops.iter().rev().fold(byte, |b, op| match *op {
+            Op::ReverseBits => b.reverse_bits(),
+            Op::Xor(n) => b ^ n,
+            Op::XorPos => b ^ pos as u8,
+            Op::Add(n) => b.wrapping_sub(n),
+            Op::AddPos => b.wrapping_sub(pos as u8),
+        })
+    }
+
+    /// Whether the cipher leaves every byte as is. Operations only depend on
+    /// the position modulo 256.
+    pub fn is_identity(&self) -> bool {
+        (0..256).all(|pos| (0..=255).all(|b| self.encode(b, pos) == b))
+    }
+}
+
+/// Decodes what is read from the inner reader
+#[derive(Debug)]
+pub struct Reader<R> {
+    inner: R,
+    cipher: Cipher,
+    pos: u64,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R, cipher: Cipher) -> Self {
+        Self {
+            inner,
+            cipher,
+            pos: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        for b in &mut buf[..n] {
+            *b = self.cipher.decode(*b, self.pos);
+            self.pos += 1;
+        }
+        Ok(n)
+    }
+}
+
+/// Encodes what is written to the inner writer. Writes are written entirely,
+/// so the position of the peer doesn't drift from ours on short writes.
+#[derive(Debug)]
+pub struct Writer<W> {
+    inner: W,
+    cipher: Cipher,
+    pos: u64,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W, cipher: Cipher) -> Self {
+        Self {
+            inner,
+            cipher,
+            pos: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.clear();
+        let pos = self.pos;
+        let cipher = &self.cipher;
+        self.buf
+            .extend(buf.iter().zip(pos..).map(|(&b, p)| cipher.encode(b, p)));
+        self.inner.write_all(&self.buf)?;
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use super::{Cipher, Op, Reader, Writer};
+
+    #[test]
+    fn test_spec_examples() {
+        let (cipher, len) = Cipher::parse(&[0x02, 0x01, 0x01, 0x00, 0xff])
+            .unwrap()
+            .unwrap();
+        assert_eq!(cipher.ops(), [Op::Xor(1), Op::ReverseBits]);
+        assert_eq!(len, 4);
+        let mut out = Writer::new(Vec::new(), cipher);
+        out.write_all(b"hello").unwrap();
+        assert_eq!(out.get_ref(), &[0x96, 0x26, 0xb6, 0xb6, 0x76]);
+
+        let cipher = Cipher::parse(&[0x05, 0x05, 0x00]).unwrap().unwrap().0;
+        let mut out = Writer::new(Vec::new(), cipher.clone());
+        out.write_all(b"he").unwrap();
+        out.write_all(b"llo").unwrap();
+        assert_eq!(out.get_ref(), &[0x68, 0x67, 0x70, 0x72, 0x77]);
+        let mut decoded = String::new();
+        Reader::new(&out.get_ref()[..], cipher)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello");
+
+        assert_eq!(Cipher::parse(&[0x02]).unwrap(), None);
+        assert!(Cipher::parse(&[0x06, 0x00]).is_err());
+        let mut spec = &[0x03, 0x00, 0x42][..];
+        assert_eq!(
+            Cipher::read_from(&mut spec, 80).unwrap().ops(),
+            [Op::XorPos]
+        );
+        assert_eq!(spec, [0x42]);
+    }
+
+    #[test]
+    fn test_identity() {
+        let identity = [
+            vec![],
+            vec![Op::Xor(0)],
+            vec![Op::Xor(0xa0), Op::Xor(0x0b), Op::Xor(0xab)],
+            vec![Op::ReverseBits, Op::ReverseBits],
+            vec![Op::XorPos, Op::XorPos],
+            vec![Op::Add(200), Op::Add(56)],
+        ];
+        for ops in identity {
+            assert!(Cipher::new(ops).is_identity());
+        }
+        assert!(!Cipher::new(vec![Op::XorPos]).is_identity());
+        assert!(!Cipher::new(vec![Op::AddPos, Op::XorPos]).is_identity());
+    }
+}
@@ -1,5 +1,43 @@
 use core::fmt;
-use std::{borrow::Cow, collections::HashMap, io::Write, str};
+use std::{
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    io::Write,
+    mem, str,
+};
+
+#[cfg(any(test, feature = "testing"))]
+pub mod arbitrary;
+mod array;
+pub mod cbor;
+mod diff;
+pub mod events;
+mod intern;
+mod map;
+pub mod msgpack;
+mod options;
+pub mod patch;
+mod raw;
+mod reader;
+pub mod scan;
+pub mod schema;
+mod span;
+mod writer;
+
+pub use array::{array_elements, ArrayElements};
+pub use diff::{diff, Difference};
+pub use intern::KeyInterner;
+use map::Visited;
+pub use map::{Entry, Map, VacantEntry};
+pub use options::{DuplicateKeys, ParserOptions};
+pub use raw::RawValue;
+pub use reader::{
+    parse_json_from_reader, parse_json_from_reader_interned, parse_json_from_reader_with,
+    ReadError, MAX_DOCUMENT_LEN,
+};
+pub use span::{Span, SpanTree};
+pub use writer::JsonWriter;
 
 #[derive(Debug)]
 pub struct Error {
@@ -16,12 +54,73 @@ impl fmt::Display for Error {
     }
 }
 
+impl std::error::Error for Error {}
+
+/// Why a `Value` couldn't be converted to the requested number type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberError {
+    NotANumber,
+    /// A float with a fractional part, or not finite
+    Fractional,
+    /// Outside of the target type's range, or not exactly representable in it
+    OutOfRange,
+}
+
+impl fmt::Display for NumberError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NumberError::NotANumber => "value isn't a number",
+            NumberError::Fractional => "number isn't an integer",
+            NumberError::OutOfRange => "number is out of range",
+        })
+    }
+}
+
+impl std::error::Error for NumberError {}
+
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
 #[derive(Debug)]
-struct Cursor {
+struct Cursor<'k> {
     pos: usize,
+    max_depth: usize,
+    /// Turn strings that aren't utf8 into `Value::Bytes` instead of failing
+    allow_bytes: bool,
+    /// Replace unpaired surrogates in `\u` escapes with U+FFFD instead of failing
+    lossy_surrogates: bool,
+    /// Accept the JSON5 extensions, see `parse_json5`
+    json5: bool,
+    duplicate_keys: DuplicateKeys,
+    /// Object values under these keys are kept as `Value::Raw`
+    raw_keys: &'k [&'k str],
 }
 
-impl Cursor {
+impl Default for Cursor<'_> {
+    fn default() -> Self {
+        Self {
+            pos: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            allow_bytes: false,
+            lossy_surrogates: false,
+            json5: false,
+            duplicate_keys: DuplicateKeys::Last,
+            raw_keys: &[],
+        }
+    }
+}
+
+impl Cursor<'_> {
+    fn with_options(opts: &ParserOptions) -> Self {
+        Self {
+            max_depth: opts.max_depth,
+            allow_bytes: opts.allow_bytes,
+            lossy_surrogates: opts.lossy_surrogates,
+            json5: opts.json5,
+            duplicate_keys: opts.duplicate_keys,
+            ..Default::default()
+        }
+    }
+
     fn current(&self, buf: &[u8]) -> Option<u8> {
         buf.get(self.pos).copied()
     }
@@ -31,57 +130,70 @@ impl Cursor {
     }
 
     fn next_token(&mut self, buf: &[u8]) -> u8 {
-        loop {
-            if self.pos >= buf.len() {
-                break;
-            }
-            match self.current(buf).unwrap() {
-                b'{'
-                | b'}'
-                | b'['
-                | b']'
-                | b'"'
-                | b':'
-                | b','
-                | b'0'..=b'9'
-                | b't'
-                | b'f'
-                | b'n' => return self.current(buf).unwrap(),
-                b' ' | b'\n' | b'\t' | b'\r' => self.pos += 1,
-                _ => return 0,
-            }
+        self.pos = scan::skip_whitespace(buf, self.pos);
+        match self.current(buf).unwrap_or(0) {
+            c @ (b'{'
+            | b'}'
+            | b'['
+            | b']'
+            | b'"'
+            | b':'
+            | b','
+            | b'-'
+            | b'0'..=b'9'
+            | b't'
+            | b'f'
+            | b'n') => c,
+            c @ (b'\'' | b'a'..=b'z' | b'A'..=b'Z' | b'_' | b'$') if self.json5 => c,
+            _ => 0,
         }
-        0
+    }
+
+    /// After a `,`, whether the container is closed right away (JSON5 only)
+    fn trailing_comma(&mut self, buf: &[u8], close: u8) -> bool {
+        self.json5 && self.next_token(buf) == close
     }
 
     fn consume_str<'a>(&mut self, buf: &'a [u8]) -> Result<(&'a [u8], bool), Error> {
-        if self.current(buf) != Some(b'"') {
-            return Err(Error {
-                pos: self.pos,
-                msg: "Couldn't parse string. Missed first \"",
-            });
-        }
+        let quote = match self.current(buf) {
+            Some(b'"') => b'"',
+            Some(b'\'') if self.json5 => b'\'',
+            _ => {
+                return Err(Error {
+                    pos: self.pos,
+                    msg: "Couldn't parse string. Missed first \"",
+                })
+            }
+        };
         self.pos += 1;
-        let mut escape = false;
         let mut escaped = false;
         let span_start = self.pos;
         loop {
-            match self.current(buf) {
-                Some(b'\\') if !escape => {
-                    escape = true;
-                    escaped = true
+            let next = if quote == b'"' {
+                scan::find_quote_or_backslash(buf, self.pos)
+            } else {
+                buf.get(self.pos..)
+                    .and_then(|rest| rest.iter().position(|&c| c == quote || c == b'\\'))
+                    .map(|i| self.pos + i)
+            };
+            match next {
+                Some(i) if buf[i] == b'\\' => {
+                    escaped = true;
+                    // Skip the escaped character
+                    self.pos = i + 2;
+                }
+                Some(i) => {
+                    self.pos = i;
+                    break;
                 }
-                Some(b'"') if !escape => break,
                 None => {
+                    self.pos = buf.len();
                     return Err(Error {
                         pos: self.pos,
                         msg: "Unexpected data end while parsing string",
-                    })
+                    });
                 }
-                _ => escape = false,
-            };
-
-            self.advance();
+            }
         }
         let span_end = self.pos;
 
@@ -92,27 +204,65 @@ impl Cursor {
     fn consume_number<'a>(&mut self, buf: &'a [u8]) -> Result<(&'a [u8], bool), Error> {
         let span_start = self.pos;
         let mut float = false;
-        if !(b'0'..=b'9').contains(&self.current(buf).unwrap_or(0)) {
+        if self.current(buf) == Some(b'-') {
+            self.advance();
+        }
+        self.consume_digits(buf)?;
+        if self.current(buf) == Some(b'.') {
+            float = true;
+            self.advance();
+            self.consume_digits(buf)?;
+        }
+        if let Some(b'e' | b'E') = self.current(buf) {
+            float = true;
+            self.advance();
+            if let Some(b'+' | b'-') = self.current(buf) {
+                self.advance();
+            }
+            self.consume_digits(buf)?;
+        }
+        let span_end = self.pos;
+        Ok((&buf[span_start..span_end], float))
+    }
+
+    fn consume_digits(&mut self, buf: &[u8]) -> Result<(), Error> {
+        if !self.current(buf).unwrap_or(0).is_ascii_digit() {
             return Err(Error {
                 pos: self.pos,
-                msg: "Couldn't parse number. Missed first digit",
+                msg: "Couldn't parse number. Missed digit",
             });
         }
-        loop {
-            match self.current(buf) {
-                None => break,
-                Some(t) => match t {
-                    b'0'..=b'9' => {}
-                    b'.' => {
-                        float = true;
-                    }
-                    _ => break,
-                },
+        while self.current(buf).unwrap_or(0).is_ascii_digit() {
+            self.advance();
+        }
+        Ok(())
+    }
+
+    /// Unquoted JSON5 object key
+    fn consume_ident<'a>(&mut self, buf: &'a [u8]) -> Result<&'a str, Error> {
+        let span_start = self.pos;
+        while let Some(b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'$') = self.current(buf) {
+            self.advance();
+        }
+        match &buf[span_start..self.pos] {
+            [b'a'..=b'z' | b'A'..=b'Z' | b'_' | b'$', ..] => {
+                Ok(str::from_utf8(&buf[span_start..self.pos]).unwrap())
             }
+            _ => Err(Error {
+                pos: self.pos,
+                msg: "Couldn't parse unquoted key",
+            }),
+        }
+    }
+
+    /// Digits of a JSON5 `0x` prefixed number
+    fn consume_hex<'a>(&mut self, buf: &'a [u8]) -> &'a [u8] {
+        self.pos += 2;
+        let span_start = self.pos;
+        while self.current(buf).is_some_and(|c| c.is_ascii_hexdigit()) {
             self.advance();
         }
-        let span_end = self.pos;
-        Ok((&buf[span_start..span_end], float))
+        &buf[span_start..self.pos]
     }
 
     fn consume_lit(&mut self, buf: &[u8], lit: &[u8]) -> Result<(), Error> {
@@ -169,39 +319,420 @@ macro_rules! accessors {
 
 accessors!([
     (string, String, &Cow<'_, str>),
+    (bytes, Bytes, &Cow<'_, [u8]>),
+    (raw, Raw, &RawValue<'_>),
     (int, Int, &i64),
     (float, Float, &f64),
     (bool, Bool, &bool),
     (null, Null, &()),
-    (array, Array, &Vec<Value<'_>>),
-    (object, Object, &HashMap<Cow<'a, str>, Value<'a>>),
+    (array, Array, &Array<'a>),
+    (object, Object, &Map<'a>),
 ]);
 
-#[derive(Debug, PartialEq)]
+/// Storage behind `Value::Array`. A `Vec` by default, or a boxed slice with
+/// the `boxed-arrays` feature, which drops the spare capacity of the arrays
+/// kept around at the cost of a reallocation when one is built or grown.
+#[cfg(not(feature = "boxed-arrays"))]
+pub type Array<'a> = Vec<Value<'a>>;
+#[cfg(feature = "boxed-arrays")]
+pub type Array<'a> = Box<[Value<'a>]>;
+
+#[cfg(not(feature = "boxed-arrays"))]
+pub(crate) fn array_into_vec(array: Array<'_>) -> Vec<Value<'_>> {
+    array
+}
+
+#[cfg(feature = "boxed-arrays")]
+pub(crate) fn array_into_vec(array: Array<'_>) -> Vec<Value<'_>> {
+    array.into_vec()
+}
+
+/// Runs `f` on the elements of `array` as a vector
+#[cfg(not(feature = "boxed-arrays"))]
+pub(crate) fn edit_array<'a, R>(
+    array: &mut Array<'a>,
+    f: impl FnOnce(&mut Vec<Value<'a>>) -> R,
+) -> R {
+    f(array)
+}
+
+#[cfg(feature = "boxed-arrays")]
+pub(crate) fn edit_array<'a, R>(
+    array: &mut Array<'a>,
+    f: impl FnOnce(&mut Vec<Value<'a>>) -> R,
+) -> R {
+    let mut vec = mem::take(array).into_vec();
+    let res = f(&mut vec);
+    *array = vec.into_boxed_slice();
+    res
+}
+
+#[derive(Debug, Clone)]
 pub enum Value<'a> {
     String(Cow<'a, str>),
+    /// String content that isn't valid utf8, see `parse_json_bytes`
+    Bytes(Cow<'a, [u8]>),
     Float(f64),
     Int(i64),
     Bool(bool),
     Null(()),
-    Array(Vec<Value<'a>>),
-    Object(HashMap<Cow<'a, str>, Value<'a>>),
+    Array(Array<'a>),
+    Object(Map<'a>),
+    /// Unparsed JSON text, see `parse_json_raw_keys`
+    Raw(RawValue<'a>),
+}
+
+macro_rules! from_impls {
+    ([
+        $( ( $type:ty, $variant:tt ) ,)*
+    ]) => {
+        $(
+            impl<'a> From<$type> for Value<'a> {
+                fn from(v: $type) -> Self {
+                    Value::$variant(v.into())
+                }
+            }
+        )*
+    };
+}
+
+from_impls!([
+    (i64, Int),
+    (f64, Float),
+    (bool, Bool),
+    ((), Null),
+    (&'a str, String),
+    (String, String),
+    (Cow<'a, str>, String),
+    (&'a [u8], Bytes),
+    (Vec<u8>, Bytes),
+    (Vec<Value<'a>>, Array),
+    (Map<'a>, Object),
+    (HashMap<Cow<'a, str>, Value<'a>>, Object),
+]);
+
+impl<'a, K> FromIterator<(K, Value<'a>)> for Value<'a>
+where
+    K: Into<Cow<'a, str>>,
+{
+    fn from_iter<T: IntoIterator<Item = (K, Value<'a>)>>(iter: T) -> Self {
+        Value::Object(iter.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+}
+
+/// Floats are compared and hashed by bit pattern so that `Value` can be `Eq`:
+/// `NaN` is equal to itself and `0.0` differs from `-0.0`.
+impl PartialEq for Value<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        use Value::*;
+        match (self, other) {
+            (String(a), String(b)) => a == b,
+            (Bytes(a), Bytes(b)) => a == b,
+            (Float(a), Float(b)) => a.to_bits() == b.to_bits(),
+            (Int(a), Int(b)) => a == b,
+            (Bool(a), Bool(b)) => a == b,
+            (Null(()), Null(())) => true,
+            (Array(a), Array(b)) => a == b,
+            (Object(a), Object(b)) => a == b,
+            (Raw(a), Raw(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value<'_> {}
+
+impl Hash for Value<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use Value::*;
+        mem::discriminant(self).hash(state);
+        match self {
+            String(v) => v.hash(state),
+            Bytes(v) => v.hash(state),
+            Float(v) => v.to_bits().hash(state),
+            Int(v) => v.hash(state),
+            Bool(v) => v.hash(state),
+            Null(()) => {}
+            Array(v) => v.hash(state),
+            Object(v) => {
+                // Map iteration order is unspecified, so entries are combined
+                // with a commutative operation
+                let mut sum: u64 = 0;
+                for entry in v {
+                    let mut h = DefaultHasher::new();
+                    entry.hash(&mut h);
+                    sum = sum.wrapping_add(h.finish());
+                }
+                v.len().hash(state);
+                sum.hash(state);
+            }
+            Raw(v) => v.hash(state),
+        }
+    }
+}
+
+impl<'a> Value<'a> {
+    /// Follows a dot separated path of keys through nested objects
+    pub fn get_path(&self, path: &str) -> Option<&Value<'a>> {
+        path.split('.')
+            .try_fold(self, |val, key| val.object()?.get(key))
+    }
+
+    pub fn get_str(&self, path: &str) -> Option<&str> {
+        self.get_path(path)?.string().map(|s| s.as_ref())
+    }
+
+    pub fn get_i64(&self, path: &str) -> Option<i64> {
+        self.get_path(path)?.int().copied()
+    }
+
+    pub fn get_bool(&self, path: &str) -> Option<bool> {
+        self.get_path(path)?.bool().copied()
+    }
+
+    pub fn get_array(&self, path: &str) -> Option<&Array<'_>> {
+        self.get_path(path)?.array()
+    }
+
+    /// Ints are accepted if they convert to a float exactly
+    pub fn as_f64(&self) -> Result<f64, NumberError> {
+        match *self {
+            Value::Float(v) => Ok(v),
+            Value::Int(v) if v as f64 as i128 == v as i128 => Ok(v as f64),
+            Value::Int(_) => Err(NumberError::OutOfRange),
+            _ => Err(NumberError::NotANumber),
+        }
+    }
+
+    /// Floats are accepted if they hold an integer, like `3.0`
+    pub fn as_i64(&self) -> Result<i64, NumberError> {
+        match *self {
+            Value::Int(v) => Ok(v),
+            Value::Float(v) => {
+                let v = float_to_integer(v)?;
+                i64::try_from(v).map_err(|_| NumberError::OutOfRange)
+            }
+            _ => Err(NumberError::NotANumber),
+        }
+    }
+
+    pub fn as_u64(&self) -> Result<u64, NumberError> {
+        match *self {
+            Value::Int(v) => u64::try_from(v).map_err(|_| NumberError::OutOfRange),
+            Value::Float(v) => {
+                let v = float_to_integer(v)?;
+                u64::try_from(v).map_err(|_| NumberError::OutOfRange)
+            }
+            _ => Err(NumberError::NotANumber),
+        }
+    }
+
+    /// Moves the value out, leaving `Null` in its place
+    pub fn take(&mut self) -> Value<'a> {
+        mem::replace(self, Value::Null(()))
+    }
+
+    pub fn into_string(self) -> Option<Cow<'a, str>> {
+        match self {
+            Value::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn into_array(self) -> Option<Array<'a>> {
+        match self {
+            Value::Array(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn into_object(self) -> Option<Map<'a>> {
+        match self {
+            Value::Object(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Entry of `key` if the value is an object
+    pub fn entry<K: Into<Cow<'a, str>>>(&mut self, key: K) -> Option<Entry<'_, 'a>> {
+        match self {
+            Value::Object(o) => Some(o.entry(key)),
+            _ => None,
+        }
+    }
+
+    /// Detaches the value from the buffer it was parsed from
+    pub fn into_owned(self) -> Value<'static> {
+        match self {
+            Value::String(v) => Value::String(Cow::Owned(v.into_owned())),
+            Value::Bytes(v) => Value::Bytes(Cow::Owned(v.into_owned())),
+            Value::Float(v) => Value::Float(v),
+            Value::Int(v) => Value::Int(v),
+            Value::Bool(v) => Value::Bool(v),
+            Value::Null(()) => Value::Null(()),
+            Value::Array(v) => Value::Array(
+                array_into_vec(v)
+                    .into_iter()
+                    .map(Value::into_owned)
+                    .collect(),
+            ),
+            Value::Object(v) => Value::Object(
+                v.into_iter()
+                    .map(|(k, v)| (Cow::Owned(k.into_owned()), v.into_owned()))
+                    .collect(),
+            ),
+            Value::Raw(v) => Value::Raw(v.into_owned()),
+        }
+    }
+}
+
+fn float_to_integer(v: f64) -> Result<i128, NumberError> {
+    if !v.is_finite() || v.fract() != 0.0 {
+        return Err(NumberError::Fractional);
+    }
+    // Saturates, which only matters far outside of the i64 and u64 ranges
+    Ok(v as i128)
 }
 
 pub fn parse_json(buf: &[u8]) -> Result<Value<'_>, Error> {
-    let mut cursor = Cursor { pos: 0 };
+    parse_json_with(buf, &ParserOptions::default())
+}
+
+pub fn parse_json_with<'a>(buf: &'a [u8], opts: &ParserOptions) -> Result<Value<'a>, Error> {
+    if buf.len() > opts.max_size {
+        return Err(Error {
+            pos: opts.max_size,
+            msg: "Message exceeds the maximum size",
+        });
+    }
+    _parse_json(buf, &mut Cursor::with_options(opts))
+}
+
+/// Like `parse_json`, also returning where each value was found in `buf`
+pub fn parse_json_spanned(buf: &[u8]) -> Result<(Value<'_>, SpanTree<'_>), Error> {
+    let mut cursor = Cursor::default();
+    let (value, spans) = parse_value(buf, &mut cursor, true)?;
+    Ok((value, spans.expect("spans weren't tracked")))
+}
+
+/// Like `parse_json`, but values of object fields named in `keys` are only
+/// validated and kept verbatim as `Value::Raw`, at any depth
+pub fn parse_json_raw_keys<'a>(buf: &'a [u8], keys: &[&str]) -> Result<Value<'a>, Error> {
+    let mut cursor = Cursor {
+        raw_keys: keys,
+        ..Default::default()
+    };
     _parse_json(buf, &mut cursor)
 }
 
-fn _parse_json<'a>(buf: &'a [u8], cursor: &mut Cursor) -> Result<Value<'a>, Error> {
-    Ok(match cursor.next_token(buf) {
-        b'"' => Value::String(parse_str(buf, cursor)?),
-        b'0'..=b'9' => parse_number(buf, cursor)?,
-        b'n' => Value::Null(parse_null(buf, cursor)?),
-        b't' => Value::Bool(parse_true(buf, cursor)?),
-        b'f' => Value::Bool(parse_false(buf, cursor)?),
-        b'{' => Value::Object(parse_object(buf, cursor)?),
-        b'[' => Value::Array(parse_array(buf, cursor)?),
+/// Like `parse_json`, but strings that aren't valid utf8 are kept as
+/// `Value::Bytes` instead of failing the whole message
+pub fn parse_json_bytes(buf: &[u8]) -> Result<Value<'_>, Error> {
+    let opts = ParserOptions {
+        allow_bytes: true,
+        ..Default::default()
+    };
+    parse_json_with(buf, &opts)
+}
+
+/// Parses `buf` into `dest`, reusing the strings, arrays and objects already
+/// allocated in it. Meant for connections decoding one message after the other
+/// into the same value.
+pub fn parse_into(buf: &[u8], dest: &mut Value<'static>) -> Result<(), Error> {
+    let mut cursor = Cursor::default();
+    parse_value_into(buf, &mut cursor, 0, dest)
+}
+
+/// Recursion is bounded by `check_depth` here
+fn parse_value_into(
+    buf: &[u8],
+    cursor: &mut Cursor,
+    depth: usize,
+    dest: &mut Value<'static>,
+) -> Result<(), Error> {
+    match cursor.next_token(buf) {
+        b'"' => match parse_string_value(buf, cursor)? {
+            Value::String(s) => match dest {
+                Value::String(Cow::Owned(d)) => {
+                    d.clear();
+                    d.push_str(&s);
+                }
+                _ => *dest = Value::String(Cow::Owned(s.into_owned())),
+            },
+            v => *dest = v.into_owned(),
+        },
+        b'-' | b'0'..=b'9' => *dest = parse_number(buf, cursor)?.into_owned(),
+        b'n' => *dest = Value::Null(parse_null(buf, cursor)?),
+        b't' => *dest = Value::Bool(parse_true(buf, cursor)?),
+        b'f' => *dest = Value::Bool(parse_false(buf, cursor)?),
+        b'[' => {
+            check_depth(depth, cursor)?;
+            cursor.advance();
+            let mut array = match mem::replace(dest, Value::Null(())) {
+                Value::Array(array) => array_into_vec(array),
+                _ => Vec::new(),
+            };
+            let mut len = 0;
+            if cursor.next_token(buf) == b']' {
+                cursor.advance();
+            } else {
+                loop {
+                    if len == array.len() {
+                        array.push(Value::Null(()));
+                    }
+                    parse_value_into(buf, cursor, depth + 1, &mut array[len])?;
+                    len += 1;
+                    match cursor.next_token(buf) {
+                        b',' => cursor.advance(),
+                        b']' => {
+                            cursor.advance();
+                            break;
+                        }
+                        _ => {
+                            return Err(Error {
+                                pos: cursor.pos,
+                                msg: "Unexpected token when parsing array",
+                            })
+                        }
+                    }
+                }
+            }
+            array.truncate(len);
+            *dest = Value::from(array);
+        }
+        b'{' => {
+            check_depth(depth, cursor)?;
+            cursor.advance();
+            let mut obj = match mem::replace(dest, Value::Null(())) {
+                Value::Object(obj) => obj,
+                _ => Map::new(),
+            };
+            let mut visited = Visited::default();
+            if cursor.next_token(buf) == b'}' {
+                cursor.advance();
+            } else {
+                loop {
+                    let key = parse_key(buf, cursor)?;
+                    let slot = obj.reuse_slot(&key, &mut visited);
+                    parse_value_into(buf, cursor, depth + 1, slot)?;
+                    match cursor.next_token(buf) {
+                        b',' => cursor.advance(),
+                        b'}' => {
+                            cursor.advance();
+                            break;
+                        }
+                        _ => {
+                            return Err(Error {
+                                pos: cursor.pos,
+                                msg: "Unexpected token when parsing object",
+                            })
+                        }
+                    }
+                }
+            }
+            obj.drop_unvisited(&visited);
+            *dest = Value::Object(obj);
+        }
         0 => {
             return Err(Error {
                 pos: cursor.pos,
@@ -214,10 +745,341 @@ fn _parse_json<'a>(buf: &'a [u8], cursor: &mut Cursor) -> Result<Value<'a>, Erro
                 msg: "Unexpected token while parsing message",
             })
         }
+    }
+    Ok(())
+}
+
+/// Like `parse_json`, but `\u` escapes of unpaired surrogates decode to
+/// U+FFFD instead of failing
+pub fn parse_json_lossy(buf: &[u8]) -> Result<Value<'_>, Error> {
+    let opts = ParserOptions {
+        lossy_surrogates: true,
+        ..Default::default()
+    };
+    parse_json_with(buf, &opts)
+}
+
+/// Like `parse_json`, also accepting the JSON5 extensions handy in hand
+/// written files: unquoted keys, single quoted strings, hexadecimal integers
+/// and trailing commas
+pub fn parse_json5(buf: &[u8]) -> Result<Value<'_>, Error> {
+    let opts = ParserOptions {
+        json5: true,
+        ..Default::default()
+    };
+    parse_json_with(buf, &opts)
+}
+
+pub fn from_str(s: &str) -> Result<Value<'_>, Error> {
+    parse_json(s.as_bytes())
+}
+
+#[derive(Debug)]
+enum Frame<'a> {
+    Array(Vec<Value<'a>>),
+    /// The object being built and the key of the value being parsed
+    Object(Map<'a>, Cow<'a, str>),
+}
+
+/// Parses with an explicit stack rather than recursion, so that nesting is
+/// bounded by `Cursor::max_depth` instead of the thread stack
+fn _parse_json<'a>(buf: &'a [u8], cursor: &mut Cursor) -> Result<Value<'a>, Error> {
+    Ok(parse_value(buf, cursor, false)?.0)
+}
+
+/// When `track_spans` is set, also builds a `SpanTree` alongside the value
+fn parse_value<'a>(
+    buf: &'a [u8],
+    cursor: &mut Cursor,
+    track_spans: bool,
+) -> Result<(Value<'a>, Option<SpanTree<'a>>), Error> {
+    let mut stack: Vec<Frame<'a>> = Vec::new();
+    let mut span_stack: Vec<SpanTree<'a>> = Vec::new();
+    'value: loop {
+        let token = cursor.next_token(buf);
+        let start = cursor.pos;
+        let span = |cursor: &Cursor| Span {
+            start,
+            end: cursor.pos,
+        };
+        let raw = match stack.last() {
+            Some(Frame::Object(_, key)) => cursor.raw_keys.contains(&key.as_ref()),
+            _ => false,
+        };
+        // Containers push a frame and move on to their first element
+        let mut value = match token {
+            _ if raw => {
+                skip_value(buf, cursor)?;
+                let text = str::from_utf8(&buf[start..cursor.pos]).map_err(|_| Error {
+                    pos: cursor.pos,
+                    msg: "Raw value wasn't utf8 encoded",
+                })?;
+                Value::Raw(RawValue(Cow::Borrowed(text)))
+            }
+            b'"' | b'\'' => parse_string_value(buf, cursor)?,
+            b'-' | b'0'..=b'9' => parse_number(buf, cursor)?,
+            b'n' => Value::Null(parse_null(buf, cursor)?),
+            b't' => Value::Bool(parse_true(buf, cursor)?),
+            b'f' => Value::Bool(parse_false(buf, cursor)?),
+            b'[' => {
+                check_depth(stack.len(), cursor)?;
+                cursor.advance();
+                if cursor.next_token(buf) == b']' {
+                    cursor.advance();
+                    Value::Array(Array::default())
+                } else {
+                    stack.push(Frame::Array(Vec::new()));
+                    if track_spans {
+                        span_stack.push(SpanTree::Array(span(cursor), Vec::new()));
+                    }
+                    continue;
+                }
+            }
+            b'{' => {
+                check_depth(stack.len(), cursor)?;
+                cursor.advance();
+                if cursor.next_token(buf) == b'}' {
+                    cursor.advance();
+                    Value::Object(Map::new())
+                } else {
+                    let key = parse_key(buf, cursor)?;
+                    stack.push(Frame::Object(Map::new(), key));
+                    if track_spans {
+                        span_stack.push(SpanTree::Object(span(cursor), HashMap::new()));
+                    }
+                    continue;
+                }
+            }
+            0 => {
+                return Err(Error {
+                    pos: cursor.pos,
+                    msg: "Unexpected message end",
+                })
+            }
+            _ => {
+                return Err(Error {
+                    pos: cursor.pos,
+                    msg: "Unexpected token while parsing message",
+                })
+            }
+        };
+        let mut tree = track_spans.then(|| match token {
+            b'[' => SpanTree::Array(span(cursor), Vec::new()),
+            b'{' => SpanTree::Object(span(cursor), HashMap::new()),
+            _ => SpanTree::Leaf(span(cursor)),
+        });
+        // Attach the value to its parent, closing every container ending here
+        loop {
+            match stack.last_mut() {
+                None => return Ok((value, tree)),
+                Some(Frame::Array(array)) => {
+                    array.push(value);
+                    if let (Some(t), Some(SpanTree::Array(_, children))) =
+                        (tree.take(), span_stack.last_mut())
+                    {
+                        children.push(t);
+                    }
+                    match cursor.next_token(buf) {
+                        b',' => {
+                            cursor.advance();
+                            if !cursor.trailing_comma(buf, b']') {
+                                continue 'value;
+                            }
+                            cursor.advance();
+                        }
+                        b']' => cursor.advance(),
+                        _ => {
+                            return Err(Error {
+                                pos: cursor.pos,
+                                msg: "Unexpected token when parsing array",
+                            })
+                        }
+                    }
+                }
+                Some(Frame::Object(obj, key)) => {
+                    if let (Some(t), Some(SpanTree::Object(_, children))) =
+                        (tree.take(), span_stack.last_mut())
+                    {
+                        children.insert(key.clone(), t);
+                    }
+                    match cursor.duplicate_keys {
+                        DuplicateKeys::Last => {
+                            obj.insert(mem::take(key), value);
+                        }
+                        _ if !obj.contains_key(key) => {
+                            obj.insert(mem::take(key), value);
+                        }
+                        DuplicateKeys::First => {}
+                        DuplicateKeys::Reject => {
+                            return Err(Error {
+                                pos: cursor.pos,
+                                msg: "Duplicate object key",
+                            })
+                        }
+                    }
+                    match cursor.next_token(buf) {
+                        b',' => {
+                            cursor.advance();
+                            if !cursor.trailing_comma(buf, b'}') {
+                                *key = parse_key(buf, cursor)?;
+                                continue 'value;
+                            }
+                            cursor.advance();
+                        }
+                        b'}' => cursor.advance(),
+                        _ => {
+                            return Err(Error {
+                                pos: cursor.pos,
+                                msg: "Unexpected token when parsing object",
+                            })
+                        }
+                    }
+                }
+            }
+            value = match stack.pop() {
+                Some(Frame::Array(array)) => Value::from(array),
+                Some(Frame::Object(obj, _)) => Value::Object(obj),
+                None => unreachable!(),
+            };
+            tree = span_stack.pop().map(|mut t| {
+                t.set_end(cursor.pos);
+                t
+            });
+        }
+    }
+}
+
+/// Validates a value without building it
+fn skip_value(buf: &[u8], cursor: &mut Cursor) -> Result<(), Error> {
+    // Closing bracket of each open container
+    let mut stack: Vec<u8> = Vec::new();
+    'value: loop {
+        match cursor.next_token(buf) {
+            b'"' | b'\'' => {
+                parse_string_value(buf, cursor)?;
+            }
+            b'-' | b'0'..=b'9' => {
+                parse_number(buf, cursor)?;
+            }
+            b'n' => parse_null(buf, cursor)?,
+            b't' => {
+                parse_true(buf, cursor)?;
+            }
+            b'f' => {
+                parse_false(buf, cursor)?;
+            }
+            open @ (b'[' | b'{') => {
+                check_depth(stack.len(), cursor)?;
+                cursor.advance();
+                let close = if open == b'[' { b']' } else { b'}' };
+                if cursor.next_token(buf) == close {
+                    cursor.advance();
+                } else {
+                    if close == b'}' {
+                        parse_key(buf, cursor)?;
+                    }
+                    stack.push(close);
+                    continue;
+                }
+            }
+            0 => {
+                return Err(Error {
+                    pos: cursor.pos,
+                    msg: "Unexpected message end",
+                })
+            }
+            _ => {
+                return Err(Error {
+                    pos: cursor.pos,
+                    msg: "Unexpected token while parsing message",
+                })
+            }
+        }
+        while let Some(&close) = stack.last() {
+            match cursor.next_token(buf) {
+                b',' => {
+                    cursor.advance();
+                    if !cursor.trailing_comma(buf, close) {
+                        if close == b'}' {
+                            parse_key(buf, cursor)?;
+                        }
+                        continue 'value;
+                    }
+                    cursor.advance();
+                    stack.pop();
+                }
+                c if c == close => {
+                    cursor.advance();
+                    stack.pop();
+                }
+                _ => {
+                    return Err(Error {
+                        pos: cursor.pos,
+                        msg: "Unexpected token when parsing container",
+                    })
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+fn check_depth(depth: usize, cursor: &Cursor) -> Result<(), Error> {
+    if depth >= cursor.max_depth {
+        return Err(Error {
+            pos: cursor.pos,
+            msg: "Maximum nesting depth exceeded",
+        });
+    }
+    Ok(())
+}
+
+/// Parses an object key and the following separator
+fn parse_key<'a>(buf: &'a [u8], cursor: &mut Cursor) -> Result<Cow<'a, str>, Error> {
+    let key = match cursor.next_token(buf) {
+        b'"' | b'\'' => parse_str(buf, cursor)?,
+        _ if cursor.json5 => Cow::Borrowed(cursor.consume_ident(buf)?),
+        _ => parse_str(buf, cursor)?,
+    };
+    if cursor.next_token(buf) != b':' {
+        return Err(Error {
+            pos: cursor.pos,
+            msg: "Unexpcted object key value separator",
+        });
+    }
+    cursor.advance();
+    Ok(key)
+}
+
+fn parse_str<'a>(buf: &'a [u8], cursor: &mut Cursor) -> Result<Cow<'a, str>, Error> {
+    let err = |cursor: &Cursor| Error {
+        pos: cursor.pos,
+        msg: "String wasn't utf8 encoded",
+    };
+    Ok(match parse_str_bytes(buf, cursor)? {
+        Cow::Borrowed(s) => Cow::Borrowed(str::from_utf8(s).map_err(|_| err(cursor))?),
+        Cow::Owned(s) => Cow::Owned(String::from_utf8(s).map_err(|_| err(cursor))?),
     })
 }
 
-fn parse_str<'a, 'b>(buf: &'a [u8], cursor: &'b mut Cursor) -> Result<Cow<'a, str>, Error> {
+fn parse_string_value<'a>(buf: &'a [u8], cursor: &mut Cursor) -> Result<Value<'a>, Error> {
+    if !cursor.allow_bytes {
+        return Ok(Value::String(parse_str(buf, cursor)?));
+    }
+    Ok(match parse_str_bytes(buf, cursor)? {
+        Cow::Borrowed(s) => match str::from_utf8(s) {
+            Ok(s) => Value::String(Cow::Borrowed(s)),
+            Err(_) => Value::Bytes(Cow::Borrowed(s)),
+        },
+        Cow::Owned(s) => match String::from_utf8(s) {
+            Ok(s) => Value::String(Cow::Owned(s)),
+            Err(e) => Value::Bytes(Cow::Owned(e.into_bytes())),
+        },
+    })
+}
+
+/// Parses and unescapes a string without checking its encoding
+fn parse_str_bytes<'a>(buf: &'a [u8], cursor: &mut Cursor) -> Result<Cow<'a, [u8]>, Error> {
     let (s, escaped) = cursor.consume_str(buf)?;
     Ok(if escaped {
         let mut next_char_escaped = false;
@@ -240,17 +1102,16 @@ fn parse_str<'a, 'b>(buf: &'a [u8], cursor: &'b mut Cursor) -> Result<Cow<'a, st
             next_char_escaped = false;
             match c {
                 b'"' | b'\\' | b'/' => unescaped.push(c),
+                b'\'' if cursor.json5 => unescaped.push(c),
                 b'b' => unescaped.push(0x08),  // backspace
                 b'f' => unescaped.push(0xC),   // formfeed
                 b'n' => unescaped.push(b'\n'), // linefeed
                 b'r' => unescaped.push(b'\r'), // carriage return
                 b't' => unescaped.push(b'\t'), // tab
                 b'u' => {
-                    return Err(Error {
-                        pos: cursor.pos,
-                        msg: "Hex character not handled",
-                    })
-                } // hex digit
+                    let c = parse_unicode_escape(s, &mut pos, cursor)?;
+                    unescaped.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
                 _ => {
                     return Err(Error {
                         pos: cursor.pos,
@@ -259,19 +1120,69 @@ fn parse_str<'a, 'b>(buf: &'a [u8], cursor: &'b mut Cursor) -> Result<Cow<'a, st
                 }
             }
         }
-        Cow::Owned(String::from_utf8(unescaped).map_err(|_| Error {
-            pos: cursor.pos,
-            msg: "String wasn't utf8 encoded",
-        })?)
+        Cow::Owned(unescaped)
     } else {
-        Cow::Borrowed(str::from_utf8(s).map_err(|_| Error {
-            pos: cursor.pos,
-            msg: "String wasn't utf8 encoded",
-        })?)
+        Cow::Borrowed(s)
     })
 }
 
-fn parse_number<'a, 'b>(buf: &'a [u8], cursor: &'b mut Cursor) -> Result<Value<'a>, Error> {
+/// Decodes the code point of a `\uXXXX` escape, `pos` being right after the `u`.
+/// Surrogate pairs are written as two consecutive escapes.
+fn parse_unicode_escape(s: &[u8], pos: &mut usize, cursor: &Cursor) -> Result<char, Error> {
+    let hex = |pos: usize| -> Result<u32, Error> {
+        let digits = s
+            .get(pos..pos + 4)
+            .and_then(|d| str::from_utf8(d).ok())
+            .filter(|d| d.bytes().all(|c| c.is_ascii_hexdigit()));
+        digits
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or(Error {
+                pos: cursor.pos,
+                msg: "Invalid unicode escape",
+            })
+    };
+    let lone_surrogate = || {
+        if cursor.lossy_surrogates {
+            Ok(char::REPLACEMENT_CHARACTER)
+        } else {
+            Err(Error {
+                pos: cursor.pos,
+                msg: "Unpaired surrogate in unicode escape",
+            })
+        }
+    };
+    let first = hex(*pos)?;
+    *pos += 4;
+    match first {
+        0xD800..=0xDBFF => {
+            if s.get(*pos..*pos + 2) != Some(b"\\u") {
+                return lone_surrogate();
+            }
+            let second = hex(*pos + 2)?;
+            if !(0xDC00..=0xDFFF).contains(&second) {
+                // Leave the second escape to be decoded on its own
+                return lone_surrogate();
+            }
+            *pos += 6;
+            let c = 0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00);
+            Ok(char::from_u32(c).unwrap())
+        }
+        0xDC00..=0xDFFF => lone_surrogate(),
+        c => Ok(char::from_u32(c).unwrap()),
+    }
+}
+
+fn parse_number<'a>(buf: &'a [u8], cursor: &mut Cursor) -> Result<Value<'a>, Error> {
+    if cursor.json5 && matches!(buf.get(cursor.pos..cursor.pos + 2), Some(b"0x" | b"0X")) {
+        let digits = cursor.consume_hex(buf);
+        let digits = str::from_utf8(digits).unwrap();
+        return Ok(Value::Int(i64::from_str_radix(digits, 16).map_err(
+            |_| Error {
+                pos: cursor.pos,
+                msg: "Wasn't able to parse hexadecimal number",
+            },
+        )?));
+    }
     let (s, float) = cursor.consume_number(buf)?;
     let num_str = str::from_utf8(s).map_err(|_| Error {
         pos: cursor.pos,
@@ -290,117 +1201,128 @@ fn parse_number<'a, 'b>(buf: &'a [u8], cursor: &'b mut Cursor) -> Result<Value<'
     })
 }
 
-fn parse_null<'a, 'b>(buf: &'a [u8], cursor: &'b mut Cursor) -> Result<(), Error> {
+fn parse_null(buf: &[u8], cursor: &mut Cursor) -> Result<(), Error> {
     cursor.consume_null(buf)
 }
 
-fn parse_true<'a, 'b>(buf: &'a [u8], cursor: &'b mut Cursor) -> Result<bool, Error> {
+fn parse_true(buf: &[u8], cursor: &mut Cursor) -> Result<bool, Error> {
     cursor.consume_true(buf)?;
     Ok(true)
 }
 
-fn parse_false<'a, 'b>(buf: &'a [u8], cursor: &'b mut Cursor) -> Result<bool, Error> {
+fn parse_false(buf: &[u8], cursor: &mut Cursor) -> Result<bool, Error> {
     cursor.consume_false(buf)?;
     Ok(false)
 }
 
-fn parse_array<'a, 'b>(buf: &'a [u8], cursor: &'b mut Cursor) -> Result<Vec<Value<'a>>, Error> {
-    cursor.advance();
-    let mut array = Vec::new();
-    loop {
-        match cursor.next_token(buf) {
-            b']' => break,
-            _ => {
-                array.push(_parse_json(buf, cursor)?);
-                match cursor.next_token(buf) {
-                    b',' => cursor.advance(),
-                    b']' => break,
-                    _ => {
-                        return Err(Error {
-                            pos: cursor.pos,
-                            msg: "Unexpected token when parsing array",
-                        })
-                    }
-                }
-            }
-        }
-    }
-    cursor.advance();
-    Ok(array)
+pub fn serialize_json(val: &Value, buf: &mut Vec<u8>) {
+    serialize(val, buf, false)
 }
 
-fn parse_object<'a, 'b>(
-    buf: &'a [u8],
-    cursor: &'b mut Cursor,
-) -> Result<HashMap<Cow<'a, str>, Value<'a>>, Error> {
-    cursor.advance();
-    let mut obj = HashMap::new();
-    loop {
-        match cursor.next_token(buf) {
-            b'}' => break,
-            _ => {
-                let key = parse_str(buf, cursor)?;
-                if cursor.next_token(buf) != b':' {
-                    return Err(Error {
-                        pos: cursor.pos,
-                        msg: "Unexpcted object key value separator",
-                    });
-                }
-                cursor.advance();
-                let value = _parse_json(buf, cursor)?;
-                obj.insert(key, value);
-                match cursor.next_token(buf) {
-                    b',' => cursor.advance(),
-                    b'}' => break,
-                    _ => {
-                        return Err(Error {
-                            pos: cursor.pos,
-                            msg: "Unexpected token when parsing object",
-                        })
-                    }
-                }
-            }
-        }
-    }
-    cursor.advance();
-    Ok(obj)
+/// Like `serialize_json`, but every non ASCII character is written as a
+/// `\uXXXX` escape, for transports that don't preserve utf8.
+/// `Value::Bytes` content that isn't utf8 is replaced lossily.
+pub fn serialize_json_ascii(val: &Value, buf: &mut Vec<u8>) {
+    serialize(val, buf, true)
 }
 
-pub fn serialize_json(val: &Value, buf: &mut Vec<u8>) {
+fn serialize(val: &Value, buf: &mut Vec<u8>, ascii: bool) {
     match val {
         Value::Int(v) => write!(buf, "{}", v).unwrap(),
         Value::Bool(v) => write!(buf, "{}", v).unwrap(),
-        Value::Float(v) => write!(buf, "{}", v).unwrap(),
+        // Debug keeps a fractional part or an exponent, so floats parse back as floats
+        Value::Float(v) => write!(buf, "{:?}", v).unwrap(),
         Value::Null(()) => buf.extend_from_slice(b"null"),
+        Value::String(v) if ascii => serialize_str_ascii(v, buf),
         Value::String(v) => serialize_str(v, buf),
-        Value::Object(v) => serialize_object(v, buf),
-        Value::Array(v) => serialize_array(v, buf),
+        Value::Bytes(v) if ascii => serialize_str_ascii(&String::from_utf8_lossy(v), buf),
+        Value::Bytes(v) => serialize_bytes(v, buf),
+        Value::Object(v) => serialize_object(v, buf, ascii),
+        Value::Array(v) => serialize_array(v, buf, ascii),
+        // Outside of strings JSON text is ASCII already
+        Value::Raw(v) if ascii => {
+            for c in v.get().chars() {
+                escape_non_ascii(c, buf);
+            }
+        }
+        Value::Raw(v) => buf.extend_from_slice(v.get().as_bytes()),
     }
 }
 
-fn serialize_str(s: &Cow<str>, buf: &mut Vec<u8>) {
+pub fn to_vec(val: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    serialize_json(val, &mut buf);
+    buf
+}
+
+/// `Value::Bytes` content that isn't utf8 is replaced lossily
+pub fn to_string(val: &Value) -> String {
+    match String::from_utf8(to_vec(val)) {
+        Ok(s) => s,
+        Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+    }
+}
+
+fn serialize_str(s: &str, buf: &mut Vec<u8>) {
+    serialize_bytes(s.as_bytes(), buf)
+}
+
+/// Bytes are written verbatim apart from the escapes, so non utf8 content
+/// round trips through `parse_json_bytes`
+fn serialize_bytes(s: &[u8], buf: &mut Vec<u8>) {
     buf.extend_from_slice(b"\"");
-    for c in s.bytes() {
-        let s;
-        buf.extend_from_slice(match c {
-            b'"' => b"\\\"",
-            b'\\' => b"\\\\",
-            b'/' => b"/",
-            b'\n' => b"\\n",
-            b'\r' => b"\\r",
-            b'\t' => b"\\t",
-            0x08 => b"\\b",
-            0x0C => b"\\f",
-            _ => {
-                s = [c];
-                &s
-            }
-        });
+    for &c in s {
+        escape_byte(c, buf);
     }
     buf.extend_from_slice(b"\"");
 }
 
-fn serialize_object(o: &HashMap<Cow<str>, Value>, buf: &mut Vec<u8>) {
+fn serialize_str_ascii(s: &str, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(b"\"");
+    for c in s.chars() {
+        if c.is_ascii() {
+            escape_byte(c as u8, buf);
+        } else {
+            escape_non_ascii(c, buf);
+        }
+    }
+    buf.extend_from_slice(b"\"");
+}
+
+fn escape_byte(c: u8, buf: &mut Vec<u8>) {
+    let s;
+    buf.extend_from_slice(match c {
+        b'"' => b"\\\"",
+        b'\\' => b"\\\\",
+        b'/' => b"/",
+        b'\n' => b"\\n",
+        b'\r' => b"\\r",
+        b'\t' => b"\\t",
+        0x08 => b"\\b",
+        0x0C => b"\\f",
+        0x00..=0x1F => {
+            write!(buf, "\\u{:04x}", c).unwrap();
+            return;
+        }
+        _ => {
+            s = [c];
+            &s
+        }
+    });
+}
+
+/// Writes ASCII characters as is
+fn escape_non_ascii(c: char, buf: &mut Vec<u8>) {
+    if c.is_ascii() {
+        buf.push(c as u8);
+        return;
+    }
+    for unit in c.encode_utf16(&mut [0; 2]) {
+        write!(buf, "\\u{:04x}", unit).unwrap();
+    }
+}
+
+fn serialize_object(o: &Map, buf: &mut Vec<u8>, ascii: bool) {
     buf.extend_from_slice(b"{");
     let mut first = true;
     for (key, val) in o {
@@ -408,14 +1330,18 @@ fn serialize_object(o: &HashMap<Cow<str>, Value>, buf: &mut Vec<u8>) {
             buf.extend_from_slice(b", ");
         }
         first = false;
-        serialize_str(key, buf);
+        if ascii {
+            serialize_str_ascii(key, buf);
+        } else {
+            serialize_str(key, buf);
+        }
         buf.extend_from_slice(b": ");
-        serialize_json(val, buf);
+        serialize(val, buf, ascii);
     }
     buf.extend_from_slice(b"}");
 }
 
-fn serialize_array(a: &Vec<Value>, buf: &mut Vec<u8>) {
+fn serialize_array(a: &[Value], buf: &mut Vec<u8>, ascii: bool) {
     buf.extend_from_slice(b"[");
     let mut first = true;
     for val in a {
@@ -423,16 +1349,21 @@ fn serialize_array(a: &Vec<Value>, buf: &mut Vec<u8>) {
             buf.extend_from_slice(b", ");
         }
         first = false;
-        serialize_json(val, buf);
+        serialize(val, buf, ascii);
     }
     buf.extend_from_slice(b"]");
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod test {
-    use std::{borrow::Cow, collections::HashMap, str};
+    use std::{borrow::Cow, collections::HashSet, str};
 
-    use super::{parse_json, serialize_json, Error, Value};
+    use super::{
+        from_str, parse_json, parse_json_bytes, parse_json_raw_keys, parse_json_spanned,
+        serialize_json, to_string, to_vec, Array, Error, Map, RawValue, Span, Value,
+        DEFAULT_MAX_DEPTH,
+    };
 
     #[test]
     fn test_parse_simple_values() {
@@ -458,8 +1389,8 @@ mod test {
                 Value::Object(
                     [
                         (Cow::Borrowed("a"), Value::Null(())),
-                        (Cow::Borrowed("b"), Value::Array(Vec::new())),
-                        (Cow::Borrowed("c"), Value::Object(HashMap::new())),
+                        (Cow::Borrowed("b"), Value::Array(Array::default())),
+                        (Cow::Borrowed("c"), Value::Object(Map::new())),
                     ]
                     .into_iter()
                     .collect(),
@@ -481,7 +1412,7 @@ mod test {
             ),
             (
                 b"[\"\", 3.14, 314]",
-                Value::Array(vec![
+                Value::from(vec![
                     Value::String("".into()),
                     Value::Float(3.14),
                     Value::Int(314),
@@ -510,12 +1441,253 @@ mod test {
             b"",
             b"{1: 1}",
             b"{1: [}}",
+            b"[1,]",
+            b"{\"a\": 1,}",
+            b"[1 2]",
+            b"[-]",
+            b"[1.]",
+            b"[1e]",
+            b"[.5]",
         ];
         for input in inputs {
             parse_json(input).unwrap_err();
         }
     }
 
+    #[test]
+    fn test_parse_unicode_escapes() {
+        // Cases from https://github.com/nst/JSONTestSuite
+        let valid: &[(&[u8], &str)] = &[
+            (br#"["\u0060\u012a\u12AB"]"#, "\u{60}\u{12a}\u{12ab}"),
+            (br#"["\uD801\udc37"]"#, "\u{10437}"),
+            (br#"["\ud83d\ude39\ud83d\udc8d"]"#, "\u{1f639}\u{1f48d}"),
+            (br#"["\u0000"]"#, "\0"),
+            (br#"["\uFFFE"]"#, "\u{fffe}"),
+            (br#"["a\u002Fb\n"]"#, "a/b\n"),
+        ];
+        for (input, expected) in valid {
+            let expected = Value::from(vec![Value::from(*expected)]);
+            assert_eq!(parse_json(input).unwrap(), expected);
+            assert_eq!(super::parse_json_lossy(input).unwrap(), expected);
+        }
+
+        let invalid: &[&[u8]] = &[
+            br#"["\uqqqq"]"#,
+            br#"["\u00A"]"#,
+            br#"["\uD834\uDd"]"#,
+            br#"["\uD800\"]"#,
+            br#"["\u"]"#,
+        ];
+        for input in invalid {
+            parse_json(input).unwrap_err();
+            super::parse_json_lossy(input).unwrap_err();
+        }
+
+        let unpaired: &[(&[u8], &str)] = &[
+            (br#"["\uDADA"]"#, "\u{fffd}"),
+            (br#"["\uDFAA"]"#, "\u{fffd}"),
+            (br#"["\uDd1ea"]"#, "\u{fffd}a"),
+            (br#"["\uDd1e\uD834"]"#, "\u{fffd}\u{fffd}"),
+            (br#"["\uD800\n"]"#, "\u{fffd}\n"),
+            (br#"["\uD800\u0041"]"#, "\u{fffd}A"),
+            (br#"["\uD800\uD800\uDC00"]"#, "\u{fffd}\u{10000}"),
+        ];
+        for (input, expected) in unpaired {
+            parse_json(input).unwrap_err();
+            assert_eq!(
+                super::parse_json_lossy(input).unwrap(),
+                Value::from(vec![Value::from(*expected)])
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_json5() {
+        let input = br#"{
+            name: 'it\'s "quoted"',
+            $id_2: 0x1F,
+            "list": [1, 'two', {nested: true,},],
+        }"#;
+        assert_eq!(
+            super::parse_json5(input).unwrap(),
+            super::from_str(
+                r#"{"name": "it's \"quoted\"", "$id_2": 31, "list": [1, "two", {"nested": true}]}"#
+            )
+            .unwrap()
+        );
+        parse_json(input).unwrap_err();
+        let invalid = [
+            b"[1,,]".as_ref(),
+            b"[,]",
+            b"{a: 1,,}",
+            b"{1a: 1}",
+            b"{a b: 1}",
+            b"'unterminated",
+            b"0x",
+            b"0xFFFFFFFFFFFFFFFFF",
+            b"['a\\']",
+        ];
+        for input in invalid {
+            super::parse_json5(input).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_parse_into_reuses_allocations() {
+        let mut value = Value::Null(());
+        super::parse_into(
+            br#"{"method": "put", "tags": ["a", "b", "c"], "job": {"id": 1}}"#,
+            &mut value,
+        )
+        .unwrap();
+        let tags_ptr = value.get_array("tags").unwrap().as_ptr() as usize;
+        let method_ptr = value.get_str("method").unwrap().as_ptr();
+
+        super::parse_into(
+            br#"{"tags": ["d"], "method": "get", "other": null}"#,
+            &mut value,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            super::parse_json(br#"{"method": "get", "tags": ["d"], "other": null}"#).unwrap()
+        );
+        assert_eq!(value.get_array("tags").unwrap().as_ptr() as usize, tags_ptr);
+        assert_eq!(value.get_str("method").unwrap().as_ptr(), method_ptr);
+
+        // Duplicated keys and objects spilling over to a HashMap
+        let big: String = (0..20).map(|i| format!("\"k{}\": {},", i, i)).collect();
+        let big = format!("{{\"k0\": 1, {}\"k0\": 2}}", big);
+        super::parse_into(big.as_bytes(), &mut value).unwrap();
+        assert_eq!(value, super::parse_json(big.as_bytes()).unwrap());
+        // Leftovers of a spilled map are dropped too
+        super::parse_into(b"{\"a\": 1}", &mut value).unwrap();
+        assert_eq!(value, super::parse_json(b"{\"a\": 1}").unwrap());
+        super::parse_into(big.as_bytes(), &mut value).unwrap();
+        let smaller: String = (0..10).map(|i| format!("\"j{}\": {},", i, i)).collect();
+        let smaller = format!("{{{}\"k3\": 0}}", smaller);
+        super::parse_into(smaller.as_bytes(), &mut value).unwrap();
+        assert_eq!(value, super::parse_json(smaller.as_bytes()).unwrap());
+        super::parse_into(b"[1, {}, 2]", &mut value).unwrap();
+        assert_eq!(value, super::parse_json(b"[1, {}, 2]").unwrap());
+
+        super::parse_into(b"{\"a\": [1,", &mut value).unwrap_err();
+        super::parse_into(&[b'['; 200], &mut value).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_with_options() {
+        use super::{parse_json_with, DuplicateKeys, ParserOptions};
+
+        let input = br#"{"a": 1, "b": [{"a": 2}], "a": 3}"#;
+        let parse = |opts: ParserOptions| parse_json_with(input, &opts);
+        assert_eq!(parse(Default::default()).unwrap().get_i64("a"), Some(3));
+        let first = ParserOptions {
+            duplicate_keys: DuplicateKeys::First,
+            ..Default::default()
+        };
+        assert_eq!(parse(first).unwrap().get_i64("a"), Some(1));
+        let reject = ParserOptions {
+            duplicate_keys: DuplicateKeys::Reject,
+            ..Default::default()
+        };
+        parse(reject).unwrap_err();
+
+        let limits = ParserOptions {
+            max_size: input.len(),
+            max_depth: 3,
+            ..Default::default()
+        };
+        parse(limits.clone()).unwrap();
+        parse(ParserOptions {
+            max_size: input.len() - 1,
+            ..limits.clone()
+        })
+        .unwrap_err();
+        parse(ParserOptions {
+            max_depth: 2,
+            ..limits
+        })
+        .unwrap_err();
+
+        let json5 = ParserOptions {
+            json5: true,
+            allow_bytes: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_json_with(b"{a: '\xFF',}", &json5)
+                .unwrap()
+                .get_path("a"),
+            Some(&Value::Bytes(b"\xFF".as_ref().into()))
+        );
+    }
+
+    #[test]
+    fn test_parse_depth_limit() {
+        let nested = |depth| {
+            let mut s = "[".repeat(depth);
+            s.push_str(&"]".repeat(depth));
+            s
+        };
+        parse_json(nested(DEFAULT_MAX_DEPTH).as_bytes()).expect("parsing failed");
+        parse_json(nested(DEFAULT_MAX_DEPTH + 1).as_bytes()).unwrap_err();
+        // Would overflow the stack of a recursive parser
+        parse_json("{\"a\": [".repeat(1_000_000).as_bytes()).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_spanned() {
+        let input = b"{\"req\": {\"method\": \"isPrime\", \"args\": [1, []]}, \"n\": 12}";
+        let (val, spans) = parse_json_spanned(input).expect("parsing failed");
+        assert_eq!(val, parse_json(input).unwrap());
+        let text = |span: Span| str::from_utf8(&input[span.range()]).unwrap();
+        assert_eq!(text(spans.span()), str::from_utf8(input).unwrap());
+        assert_eq!(
+            text(spans.get_path("req.method").unwrap().span()),
+            "\"isPrime\""
+        );
+        assert_eq!(text(spans.get_path("n").unwrap().span()), "12");
+        let args = spans.get_path("req.args").unwrap();
+        assert_eq!(text(args.span()), "[1, []]");
+        assert_eq!(text(args.index(1).unwrap().span()), "[]");
+        assert!(spans.get_path("req.missing").is_none());
+    }
+
+    #[test]
+    fn test_parse_raw_keys() {
+        let input =
+            b"{\"id\": 1, \"job\": {\"title\":  [1,2, {\"job\": 3}] }, \"q\": [{\"job\": \"x\"}]}";
+        let val = parse_json_raw_keys(input, &["job"]).expect("parsing failed");
+        assert_eq!(val.get_i64("id"), Some(1));
+        let job = val.get_path("job").unwrap().raw().unwrap();
+        assert_eq!(job.get(), "{\"title\":  [1,2, {\"job\": 3}] }");
+        assert_eq!(job.parse().unwrap().get_array("title").unwrap().len(), 3);
+        let nested = &val.get_array("q").unwrap()[0];
+        assert_eq!(
+            nested.get_path("job").unwrap().raw().unwrap().get(),
+            "\"x\""
+        );
+        // Raw values are echoed back verbatim
+        let serialized = to_string(&Value::from_iter([(
+            "job",
+            val.get_path("job").unwrap().clone(),
+        )]));
+        assert_eq!(serialized, "{\"job\": {\"title\":  [1,2, {\"job\": 3}] }}");
+
+        parse_json_raw_keys(b"{\"job\": [1, }", &["job"]).unwrap_err();
+        parse_json_raw_keys(b"{\"job\": {\"a\" 1}}", &["job"]).unwrap_err();
+    }
+
+    #[test]
+    fn test_raw_value_from_json() {
+        let raw = RawValue::from_json(" {\"a\": [1, 2]}\n").unwrap();
+        assert_eq!(raw.get(), "{\"a\": [1, 2]}");
+        RawValue::from_json("[1] [2]").unwrap_err();
+        RawValue::from_json("[1").unwrap_err();
+        RawValue::from_json("").unwrap_err();
+    }
+
     #[test]
     fn test_serialize_simples_values() {
         let cases = [
@@ -529,6 +1701,12 @@ mod test {
             (Value::String("foo".into()), "\"foo\""),
             (Value::String("\"".into()), "\"\\\"\""),
             (Value::String("\\".into()), "\"\\\\\""),
+            (Value::String("\0".into()), "\"\\u0000\""),
+            (
+                Value::String("a\0b\x01\x1f".into()),
+                "\"a\\u0000b\\u0001\\u001f\"",
+            ),
+            (Value::String("\n\t".into()), "\"\\n\\t\""),
             (
                 Value::Object(
                     [(Cow::Borrowed("a"), Value::Null(()))]
@@ -537,7 +1715,7 @@ mod test {
                 ),
                 "{\"a\": null}",
             ),
-            (Value::Array(vec![Value::Null(())]), "[null]"),
+            (Value::from(vec![Value::Null(())]), "[null]"),
         ];
 
         for (input, expected) in cases {
@@ -548,19 +1726,186 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_serialize_ascii() {
+        let val = Value::from(vec![
+            Value::from("é€😀\n\"a"),
+            [("clé", Value::Int(1))].into_iter().collect(),
+            Value::Bytes(b"\xFFz".as_ref().into()),
+            Value::Raw(RawValue::from_json("{\"ü\": \"ü\"}").unwrap()),
+        ]);
+        let mut buf = Vec::new();
+        super::serialize_json_ascii(&val, &mut buf);
+        assert!(buf.is_ascii());
+        assert_eq!(
+            str::from_utf8(&buf).unwrap(),
+            r#"["\u00e9\u20ac\ud83d\ude00\n\"a", {"cl\u00e9": 1}, "\ufffdz", {"\u00fc": "\u00fc"}]"#
+        );
+        let parsed = parse_json(&buf).unwrap();
+        assert_eq!(parsed.array().unwrap()[0], val.array().unwrap()[0]);
+        assert_eq!(parsed.array().unwrap()[1], val.array().unwrap()[1]);
+    }
+
+    #[test]
+    fn test_convenience_functions() {
+        let val = from_str("[1, \"a\", null]").expect("parsing failed");
+        assert_eq!(to_string(&val), "[1, \"a\", null]");
+        assert_eq!(to_vec(&val), b"[1, \"a\", null]");
+        from_str("[1,").unwrap_err();
+    }
+
+    #[test]
+    fn test_from_conversions() {
+        let val: Value = [
+            ("a", Value::from(1)),
+            ("b", 2.5.into()),
+            ("c", true.into()),
+            ("d", ().into()),
+            ("e", "s".into()),
+            ("f", String::from("t").into()),
+            ("g", vec![Value::from(1), "x".into()].into()),
+        ]
+        .into_iter()
+        .collect();
+        let expected = parse_json(
+            b"{\"a\": 1, \"b\": 2.5, \"c\": true, \"d\": null, \"e\": \"s\", \"f\": \"t\", \"g\": [1, \"x\"]}",
+        )
+        .unwrap();
+        assert_eq!(val, expected);
+    }
+
+    #[test]
+    fn test_take_and_into() {
+        let mut req = from_str(r#"{"job": {"title": "x"}, "tags": ["a"], "queue": "q"}"#)
+            .unwrap()
+            .into_object()
+            .unwrap();
+        let queue = req.get_mut("queue").unwrap().take().into_string().unwrap();
+        assert!(matches!(queue, Cow::Borrowed("q")));
+        assert_eq!(req.get("queue"), Some(&Value::Null(())));
+        let tags = req.remove("tags").unwrap().into_array().unwrap();
+        assert_eq!(tags[..], [Value::from("a")]);
+        let job = req.remove("job").unwrap().into_object().unwrap();
+        assert_eq!(job.get("title"), Some(&Value::from("x")));
+        assert!(Value::Int(1).into_string().is_none());
+        assert!(Value::Int(1).into_array().is_none());
+        assert!(Value::Null(()).into_object().is_none());
+    }
+
+    #[test]
+    fn test_value_entry() {
+        let mut stats = Value::Object(Default::default());
+        for method in ["put", "get", "put"] {
+            let counts = stats
+                .entry("counts")
+                .unwrap()
+                .or_insert_with(|| Value::Object(Default::default()));
+            counts
+                .entry(method)
+                .unwrap()
+                .and_modify(|n| *n = Value::Int(n.int().unwrap() + 1))
+                .or_insert(Value::Int(1));
+        }
+        assert_eq!(stats.get_i64("counts.put"), Some(2));
+        assert_eq!(stats.get_i64("counts.get"), Some(1));
+        assert!(Value::Int(1).entry("a").is_none());
+    }
+
+    #[test]
+    fn test_number_coercion() {
+        use super::NumberError;
+
+        assert_eq!(Value::Int(3).as_f64(), Ok(3.0));
+        assert_eq!(Value::Float(3.5).as_f64(), Ok(3.5));
+        assert_eq!(Value::Int(i64::MAX).as_f64(), Err(NumberError::OutOfRange));
+        assert_eq!(Value::Int(1 << 53).as_f64(), Ok(9007199254740992.0));
+        assert_eq!(Value::from("3").as_f64(), Err(NumberError::NotANumber));
+
+        assert_eq!(Value::Float(3.0).as_i64(), Ok(3));
+        assert_eq!(Value::Float(-3.0).as_i64(), Ok(-3));
+        assert_eq!(Value::Float(3.5).as_i64(), Err(NumberError::Fractional));
+        assert_eq!(
+            Value::Float(f64::NAN).as_i64(),
+            Err(NumberError::Fractional)
+        );
+        assert_eq!(Value::Float(1e19).as_i64(), Err(NumberError::OutOfRange));
+        assert_eq!(Value::Null(()).as_i64(), Err(NumberError::NotANumber));
+
+        assert_eq!(Value::Float(1e19).as_u64(), Ok(10_000_000_000_000_000_000));
+        assert_eq!(Value::Int(-1).as_u64(), Err(NumberError::OutOfRange));
+        assert_eq!(Value::Float(-1.0).as_u64(), Err(NumberError::OutOfRange));
+        assert_eq!(Value::Float(1e20).as_u64(), Err(NumberError::OutOfRange));
+    }
+
+    #[test]
+    fn test_path_getters() {
+        let val = parse_json(
+            b"{\"request\": {\"method\": \"isPrime\", \"args\": [1], \"n\": 7, \"ok\": true}}",
+        )
+        .unwrap();
+        assert_eq!(val.get_str("request.method"), Some("isPrime"));
+        assert_eq!(val.get_i64("request.n"), Some(7));
+        assert_eq!(val.get_bool("request.ok"), Some(true));
+        assert_eq!(
+            val.get_array("request.args").map(|a| &a[..]),
+            Some(&[Value::Int(1)][..])
+        );
+        assert_eq!(val.get_i64("request.method"), None);
+        assert_eq!(val.get_str("request.missing"), None);
+        assert_eq!(val.get_str("request.method.deeper"), None);
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        let input = b"[\"ok\", \"\xff\xfe\", \"a\\n\xc3\"]";
+        parse_json(input).unwrap_err();
+        let val = parse_json_bytes(input).expect("parsing failed");
+        assert_eq!(
+            val,
+            Value::from(vec![
+                Value::String("ok".into()),
+                Value::Bytes(b"\xff\xfe".as_ref().into()),
+                Value::Bytes(b"a\n\xc3".as_ref().into()),
+            ])
+        );
+        let serialized = to_vec(&val);
+        assert_eq!(serialized, b"[\"ok\", \"\xff\xfe\", \"a\\n\xc3\"]");
+        assert_eq!(parse_json_bytes(&serialized).unwrap(), val);
+        // Keys still have to be valid utf8
+        parse_json_bytes(b"{\"\xff\": 1}").unwrap_err();
+    }
+
+    #[test]
+    fn test_eq_hash() {
+        let values = [
+            parse_json(b"{\"a\": 1, \"b\": 2.5, \"c\": null}").unwrap(),
+            parse_json(b"{\"c\": null, \"b\": 2.5, \"a\": 1}").unwrap(),
+            parse_json(b"{\"c\": null, \"b\": 2.5, \"a\": 2}").unwrap(),
+            Value::Float(f64::NAN),
+            Value::Float(f64::NAN),
+            Value::Float(0.0),
+            Value::Float(-0.0),
+            Value::Int(0),
+        ];
+        let set: HashSet<_> = values.iter().collect();
+        assert_eq!(set.len(), 6);
+        assert_eq!(Value::Float(f64::NAN), Value::Float(f64::NAN));
+        assert_ne!(Value::Float(0.0), Value::Float(-0.0));
+    }
+
     #[test]
     fn test_serialize_deserialize() {
         let inputs = [
             Value::Object(
                 [
                     (Cow::Borrowed("a"), Value::Null(())),
-                    (Cow::Borrowed("b"), Value::Object(HashMap::new())),
-                    (Cow::Borrowed("c"), Value::Array(Vec::new())),
+                    (Cow::Borrowed("b"), Value::Object(Map::new())),
+                    (Cow::Borrowed("c"), Value::Array(Array::default())),
                 ]
                 .into_iter()
                 .collect(),
             ),
-            Value::Array(vec![
+            Value::from(vec![
                 Value::String("".into()),
                 Value::Float(3.14),
                 Value::Int(314),
@@ -0,0 +1,188 @@
+//! Random `Value` generation for property tests.
+//! Generated values only use what plain JSON can represent: no `Bytes`, `Raw`
+//! or non finite floats.
+
+use std::borrow::Cow;
+
+use super::{Map, Value};
+
+/// xorshift64*, good enough to explore inputs and reproducible from a seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must never be zero
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15 | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in `0..n`, `n` must not be zero
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    pub fn chance(&mut self, one_in: u64) -> bool {
+        self.below(one_in) == 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Generator {
+    rng: Rng,
+    max_depth: usize,
+    max_len: usize,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            max_depth: 4,
+            max_len: 8,
+        }
+    }
+
+    /// Nesting of arrays and objects
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Length of strings, arrays and objects
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn value(&mut self) -> Value<'static> {
+        self.value_at(0)
+    }
+
+    fn value_at(&mut self, depth: usize) -> Value<'static> {
+        let kinds = if depth < self.max_depth { 8 } else { 6 };
+        match self.rng.below(kinds) {
+            0 => Value::Null(()),
+            1 => Value::Bool(self.rng.chance(2)),
+            2 => Value::Int(self.int()),
+            3 => Value::Float(self.float()),
+            4 | 5 => Value::String(Cow::Owned(self.string())),
+            6 => {
+                let len = self.len();
+                Value::Array((0..len).map(|_| self.value_at(depth + 1)).collect())
+            }
+            _ => {
+                let len = self.len();
+                let mut obj = Map::new();
+                for _ in 0..len {
+                    obj.insert(self.string(), self.value_at(depth + 1));
+                }
+                Value::Object(obj)
+            }
+        }
+    }
+
+    fn len(&mut self) -> usize {
+        self.rng.below(self.max_len as u64 + 1) as usize
+    }
+
+    fn int(&mut self) -> i64 {
+        match self.rng.below(4) {
+            0 => [0, 1, -1, i64::MAX, i64::MIN][self.rng.below(5) as usize],
+            1 => self.rng.below(100) as i64 - 50,
+            _ => self.rng.next_u64() as i64 >> self.rng.below(64),
+        }
+    }
+
+    fn float(&mut self) -> f64 {
+        match self.rng.below(4) {
+            0 => [
+                0.0,
+                -0.0,
+                1.0,
+                0.5,
+                1e300,
+                -1e-300,
+                f64::MIN_POSITIVE,
+                f64::MAX,
+            ][self.rng.below(8) as usize],
+            1 => (self.rng.below(2000) as f64 - 1000.0) / 8.0,
+            _ => loop {
+                let v = f64::from_bits(self.rng.next_u64());
+                if v.is_finite() {
+                    break v;
+                }
+            },
+        }
+    }
+
+    fn string(&mut self) -> String {
+        const SPECIAL: [char; 10] = ['"', '\\', '/', '\n', '\t', '\0', '\u{1f}', 'é', '€', '😀'];
+        let len = self.len();
+        (0..len)
+            .map(|_| match self.rng.below(3) {
+                0 => SPECIAL[self.rng.below(SPECIAL.len() as u64) as usize],
+                1 => char::from_u32(self.rng.below(0x11_0000) as u32).unwrap_or('?'),
+                _ => (b'a' + self.rng.below(26) as u8) as char,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Generator;
+    use crate::json::{cbor, msgpack, parse_into, parse_json, to_vec, Value};
+
+    const CASES: u64 = 500;
+
+    #[test]
+    fn test_json_round_trip() {
+        for seed in 0..CASES {
+            let val = Generator::new(seed).value();
+            let text = to_vec(&val);
+            assert_eq!(
+                parse_json(&text).unwrap_or_else(|e| panic!("seed {}: {}", seed, e)),
+                val,
+                "seed {}: {}",
+                seed,
+                String::from_utf8_lossy(&text)
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_into_round_trip() {
+        let mut dest = Value::Null(());
+        for seed in 0..CASES {
+            let val = Generator::new(seed).max_depth(2).value();
+            parse_into(&to_vec(&val), &mut dest).unwrap();
+            assert_eq!(dest, val, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        for seed in 0..CASES {
+            let val = Generator::new(seed).value();
+            assert_eq!(
+                cbor::decode(&cbor::encode(&val)).unwrap(),
+                val,
+                "seed {}",
+                seed
+            );
+            assert_eq!(
+                msgpack::decode(&msgpack::encode(&val)).unwrap(),
+                val,
+                "seed {}",
+                seed
+            );
+        }
+    }
+}
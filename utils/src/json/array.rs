@@ -0,0 +1,98 @@
+use super::{_parse_json, Cursor, Error, Value, DEFAULT_MAX_DEPTH};
+
+/// Iterator over the elements of a top level array, see `array_elements`
+#[derive(Debug)]
+pub struct ArrayElements<'a> {
+    buf: &'a [u8],
+    cursor: Cursor<'static>,
+    done: bool,
+}
+
+/// Parses the elements of the array in `buf` one at a time, so only one
+/// element is held in memory at once.
+/// Iteration stops after the first error.
+pub fn array_elements(buf: &[u8]) -> Result<ArrayElements<'_>, Error> {
+    let mut cursor = Cursor {
+        // The enclosing array counts towards the depth
+        max_depth: DEFAULT_MAX_DEPTH - 1,
+        ..Default::default()
+    };
+    if cursor.next_token(buf) != b'[' {
+        return Err(Error {
+            pos: cursor.pos,
+            msg: "Expected an array",
+        });
+    }
+    cursor.advance();
+    let done = cursor.next_token(buf) == b']';
+    if done {
+        cursor.advance();
+    }
+    Ok(ArrayElements { buf, cursor, done })
+}
+
+impl<'a> ArrayElements<'a> {
+    fn next_element(&mut self) -> Result<Value<'a>, Error> {
+        let val = _parse_json(self.buf, &mut self.cursor)?;
+        match self.cursor.next_token(self.buf) {
+            b',' => self.cursor.advance(),
+            b']' => {
+                self.cursor.advance();
+                self.done = true;
+            }
+            _ => {
+                return Err(Error {
+                    pos: self.cursor.pos,
+                    msg: "Unexpected token when parsing array",
+                })
+            }
+        }
+        Ok(val)
+    }
+}
+
+impl<'a> Iterator for ArrayElements<'a> {
+    type Item = Result<Value<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.next_element();
+        if res.is_err() {
+            self.done = true;
+        }
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::array_elements;
+    use crate::json::Value;
+
+    #[test]
+    fn test_array_elements() {
+        let elements: Vec<Value> = array_elements(b" [1, {\"a\": [2]}, \"x\"] ")
+            .expect("not an array")
+            .collect::<Result<_, _>>()
+            .expect("parsing failed");
+        assert_eq!(elements.len(), 3);
+        assert_eq!(
+            elements[1].get_array("a").map(|a| &a[..]),
+            Some(&[Value::Int(2)][..])
+        );
+        assert_eq!(array_elements(b"[]").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_array_elements_errors() {
+        array_elements(b"{}").unwrap_err();
+        let mut elements = array_elements(b"[1, 2 3]").unwrap();
+        assert_eq!(elements.next().unwrap().unwrap(), Value::Int(1));
+        elements.next().unwrap().unwrap_err();
+        assert!(elements.next().is_none());
+        let res: Vec<_> = array_elements(b"[1, 2").unwrap().collect();
+        assert!(res[1].is_err());
+    }
+}
@@ -0,0 +1,291 @@
+//! CBOR (RFC 8949) encoding of `Value`.
+//! Only definite length items are decoded, tags are skipped and integers must
+//! fit an `i64`.
+
+use std::{borrow::Cow, str};
+
+use super::{Error, Map, Value, DEFAULT_MAX_DEPTH};
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+const SIMPLE: u8 = 7;
+
+pub fn encode(val: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_into(val, &mut buf);
+    buf
+}
+
+pub fn encode_into(val: &Value, buf: &mut Vec<u8>) {
+    match val {
+        Value::Int(v) if *v >= 0 => write_head(UNSIGNED, *v as u64, buf),
+        Value::Int(v) => write_head(NEGATIVE, !*v as u64, buf),
+        Value::Float(v) => {
+            buf.push(SIMPLE << 5 | 27);
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+        Value::Bool(false) => buf.push(SIMPLE << 5 | 20),
+        Value::Bool(true) => buf.push(SIMPLE << 5 | 21),
+        Value::Null(()) => buf.push(SIMPLE << 5 | 22),
+        Value::String(v) => {
+            write_head(TEXT, v.len() as u64, buf);
+            buf.extend_from_slice(v.as_bytes());
+        }
+        Value::Bytes(v) => {
+            write_head(BYTES, v.len() as u64, buf);
+            buf.extend_from_slice(v);
+        }
+        Value::Array(v) => {
+            write_head(ARRAY, v.len() as u64, buf);
+            for e in v {
+                encode_into(e, buf);
+            }
+        }
+        Value::Object(v) => {
+            write_head(MAP, v.len() as u64, buf);
+            for (k, e) in v {
+                write_head(TEXT, k.len() as u64, buf);
+                buf.extend_from_slice(k.as_bytes());
+                encode_into(e, buf);
+            }
+        }
+        Value::Raw(v) => match v.parse() {
+            Ok(v) => encode_into(&v, buf),
+            Err(_) => encode_into(&Value::from(v.get()), buf),
+        },
+    }
+}
+
+fn write_head(major: u8, n: u64, buf: &mut Vec<u8>) {
+    let major = major << 5;
+    match n {
+        0..=23 => buf.push(major | n as u8),
+        24..=0xFF => buf.extend_from_slice(&[major | 24, n as u8]),
+        0x100..=0xFFFF => {
+            buf.push(major | 25);
+            buf.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            buf.push(major | 26);
+            buf.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+/// Decodes exactly one item, strings are borrowed from `buf`
+pub fn decode(buf: &[u8]) -> Result<Value<'_>, Error> {
+    let mut decoder = Decoder { buf, pos: 0 };
+    let val = decoder.value(0)?;
+    if decoder.pos != buf.len() {
+        return Err(decoder.err("Trailing data after CBOR item"));
+    }
+    Ok(val)
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn err(&self, msg: &'static str) -> Error {
+        Error { msg, pos: self.pos }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| self.err("Unexpected data end while decoding CBOR"))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Major type and argument of the next item
+    fn head(&mut self) -> Result<(u8, u8, u64), Error> {
+        let initial = self.take(1)?[0];
+        let info = initial & 0x1F;
+        let n = match info {
+            0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            31 => return Err(self.err("Indefinite length CBOR items aren't supported")),
+            _ => return Err(self.err("Reserved CBOR additional information")),
+        };
+        Ok((initial >> 5, info, n))
+    }
+
+    fn len(&self, n: u64) -> Result<usize, Error> {
+        // Every item takes at least a byte, which bounds preallocations
+        match usize::try_from(n) {
+            Ok(n) if n <= self.buf.len() - self.pos => Ok(n),
+            _ => Err(self.err("CBOR length larger than the input")),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value<'a>, Error> {
+        let (major, info, n) = self.head()?;
+        Ok(match major {
+            UNSIGNED => Value::Int(
+                i64::try_from(n).map_err(|_| self.err("CBOR integer doesn't fit an i64"))?,
+            ),
+            NEGATIVE => Value::Int(
+                !i64::try_from(n).map_err(|_| self.err("CBOR integer doesn't fit an i64"))?,
+            ),
+            BYTES => {
+                let len = self.len(n)?;
+                Value::Bytes(Cow::Borrowed(self.take(len)?))
+            }
+            TEXT => {
+                let len = self.len(n)?;
+                let text = str::from_utf8(self.take(len)?)
+                    .map_err(|_| self.err("CBOR text wasn't utf8 encoded"))?;
+                Value::String(Cow::Borrowed(text))
+            }
+            ARRAY => {
+                self.check_depth(depth)?;
+                let len = self.len(n)?;
+                let mut array = Vec::with_capacity(len);
+                for _ in 0..len {
+                    array.push(self.value(depth + 1)?);
+                }
+                Value::from(array)
+            }
+            MAP => {
+                self.check_depth(depth)?;
+                let len = self.len(n)?;
+                let mut obj = Map::with_capacity(len);
+                for _ in 0..len {
+                    let key = match self.value(depth + 1)? {
+                        Value::String(k) => k,
+                        _ => return Err(self.err("CBOR map key isn't text")),
+                    };
+                    obj.insert(key, self.value(depth + 1)?);
+                }
+                Value::Object(obj)
+            }
+            TAG => {
+                self.check_depth(depth)?;
+                self.value(depth + 1)?
+            }
+            _ => match info {
+                20 => Value::Bool(false),
+                21 => Value::Bool(true),
+                22 | 23 => Value::Null(()),
+                25 => Value::Float(f16_to_f64(n as u16)),
+                26 => Value::Float(f32::from_bits(n as u32) as f64),
+                27 => Value::Float(f64::from_bits(n)),
+                _ => return Err(self.err("Unsupported CBOR simple value")),
+            },
+        })
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<(), Error> {
+        if depth >= DEFAULT_MAX_DEPTH {
+            return Err(self.err("Maximum nesting depth exceeded"));
+        }
+        Ok(())
+    }
+}
+
+fn f16_to_f64(half: u16) -> f64 {
+    let exp = (half >> 10) & 0x1F;
+    let mant = (half & 0x3FF) as f64;
+    let val = match exp {
+        0 => mant * 2f64.powi(-24),
+        31 if mant == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mant + 1024.0) * 2f64.powi(exp as i32 - 25),
+    };
+    if half & 0x8000 != 0 {
+        -val
+    } else {
+        val
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode};
+    use crate::json::{from_str, Value};
+
+    #[test]
+    fn test_cbor_round_trip() {
+        let val = from_str(
+            r#"{"method": "put", "ids": [0, 23, 24, 255, 256, 65536, 4294967296], "job": {"pri": 1.5, "ok": true, "none": null}}"#,
+        )
+        .unwrap();
+        let mut val = val.into_owned();
+        if let Value::Object(obj) = &mut val {
+            obj.insert("bytes", Value::Bytes(vec![0xFF, 0].into()));
+            obj.insert("neg", Value::Int(i64::MIN));
+        }
+        assert_eq!(decode(&encode(&val)).unwrap(), val);
+    }
+
+    #[test]
+    fn test_cbor_rfc_examples() {
+        // From RFC 8949 appendix A
+        let cases: &[(&[u8], Value)] = &[
+            (&[0x17], Value::Int(23)),
+            (&[0x18, 0x64], Value::Int(100)),
+            (&[0x39, 0x03, 0xe7], Value::Int(-1000)),
+            (&[0xf9, 0x3c, 0x00], Value::Float(1.0)),
+            (&[0xf9, 0xc4, 0x00], Value::Float(-4.0)),
+            (&[0xfa, 0x47, 0xc3, 0x50, 0x00], Value::Float(100000.0)),
+            (&[0xf6], Value::Null(())),
+            (&[0x64, 0x49, 0x45, 0x54, 0x46], Value::from("IETF")),
+            (
+                &[0x82, 0x01, 0x82, 0x02, 0x03],
+                Value::from(vec![
+                    Value::Int(1),
+                    Value::from(vec![Value::Int(2), Value::Int(3)]),
+                ]),
+            ),
+            (
+                &[0xa1, 0x61, 0x61, 0x01],
+                [("a", Value::Int(1))].into_iter().collect(),
+            ),
+            (
+                &[0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0],
+                Value::Int(1363896240),
+            ),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(&decode(bytes).unwrap(), expected);
+        }
+        assert_eq!(encode(&Value::Int(-1000)), [0x39, 0x03, 0xe7]);
+    }
+
+    #[test]
+    fn test_cbor_decode_errors() {
+        let inputs: &[&[u8]] = &[
+            &[],
+            &[0x18],
+            &[0x62, 0x61],
+            &[0x9f, 0x01, 0xff],
+            &[0xa1, 0x01, 0x01],
+            &[0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            &[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            &[0x01, 0x01],
+            &[0x62, 0xff, 0xfe],
+        ];
+        for input in inputs {
+            decode(input).unwrap_err();
+        }
+        decode(&[0x81; 200]).unwrap_err();
+    }
+}
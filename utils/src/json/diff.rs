@@ -0,0 +1,138 @@
+use std::fmt;
+
+use super::{patch::escape_token, to_string, Value};
+
+/// A path where two values differ. `None` means the path is missing on that side.
+/// Paths are JSON Pointers, the empty path being the root.
+#[derive(Debug, PartialEq)]
+pub struct Difference<'v, 'a> {
+    pub path: String,
+    pub left: Option<&'v Value<'a>>,
+    pub right: Option<&'v Value<'a>>,
+}
+
+impl fmt::Display for Difference<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = |v: Option<&Value>| v.map_or_else(|| "<missing>".to_owned(), to_string);
+        let path = if self.path.is_empty() {
+            "<root>"
+        } else {
+            &self.path
+        };
+        write!(f, "{}: {} != {}", path, side(self.left), side(self.right))
+    }
+}
+
+pub fn diff<'v, 'a>(left: &'v Value<'a>, right: &'v Value<'a>) -> Vec<Difference<'v, 'a>> {
+    let mut diffs = Vec::new();
+    diff_at(left, right, &mut String::new(), &mut diffs);
+    diffs
+}
+
+fn diff_at<'v, 'a>(
+    left: &'v Value<'a>,
+    right: &'v Value<'a>,
+    path: &mut String,
+    diffs: &mut Vec<Difference<'v, 'a>>,
+) {
+    if left == right {
+        return;
+    }
+    let prefix_len = path.len();
+    match (left, right) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys: Vec<_> = a
+                .keys()
+                .chain(b.keys().filter(|k| !a.contains_key(*k)))
+                .collect();
+            keys.sort();
+            for key in keys {
+                path.push('/');
+                path.push_str(&escape_token(key));
+                match (a.get(key), b.get(key)) {
+                    (Some(x), Some(y)) => diff_at(x, y, path, diffs),
+                    (x, y) => diffs.push(Difference {
+                        path: path.clone(),
+                        left: x,
+                        right: y,
+                    }),
+                }
+                path.truncate(prefix_len);
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            for i in 0..a.len().max(b.len()) {
+                path.push_str(&format!("/{}", i));
+                match (a.get(i), b.get(i)) {
+                    (Some(x), Some(y)) => diff_at(x, y, path, diffs),
+                    (x, y) => diffs.push(Difference {
+                        path: path.clone(),
+                        left: x,
+                        right: y,
+                    }),
+                }
+                path.truncate(prefix_len);
+            }
+        }
+        _ => diffs.push(Difference {
+            path: path.clone(),
+            left: Some(left),
+            right: Some(right),
+        }),
+    }
+}
+
+/// Like `assert_eq!` for `Value`s, but only prints the paths that differ
+#[macro_export]
+macro_rules! assert_json_eq {
+    ($left:expr, $right:expr $(,)?) => {{
+        let (left, right) = (&$left, &$right);
+        let diffs = $crate::json::diff(left, right);
+        if !diffs.is_empty() {
+            let lines: Vec<String> = diffs.iter().map(|d| d.to_string()).collect();
+            panic!("json values differ:\n{}", lines.join("\n"));
+        }
+    }};
+}
+
+#[cfg(test)]
+mod test {
+    use super::diff;
+    use crate::json::parse_json;
+
+    #[test]
+    fn test_diff_paths() {
+        let left =
+            parse_json(br#"{"a": 1, "b": [1, 2, 3], "c": {"d": "x"}, "e/f": true}"#).unwrap();
+        let right = parse_json(br#"{"a": 1, "b": [1, 5], "c": {"d": "y", "g": null}}"#).unwrap();
+        let lines: Vec<String> = diff(&left, &right).iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "/b/1: 2 != 5",
+                "/b/2: 3 != <missing>",
+                "/c/d: \"x\" != \"y\"",
+                "/c/g: <missing> != null",
+                "/e~1f: true != <missing>",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_root() {
+        let (left, right) = (parse_json(b"1").unwrap(), parse_json(b"[1]").unwrap());
+        let diffs = diff(&left, &right);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].to_string(), "<root>: 1 != [1]");
+        assert!(diff(&right, &right).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "/a: 1 != 2")]
+    fn test_assert_json_eq() {
+        crate::assert_json_eq!(
+            parse_json(br#"{"a": 1}"#).unwrap(),
+            parse_json(br#"{"a": 2}"#).unwrap()
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::{borrow::Cow, ops::ControlFlow};
+
+use super::{
+    check_depth, parse_false, parse_key, parse_null, parse_number, parse_str, parse_true, Cursor,
+    Error, Value,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum Event<'a> {
+    StartObject,
+    Key(Cow<'a, str>),
+    EndObject,
+    StartArray,
+    EndArray,
+    Str(Cow<'a, str>),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Null,
+}
+
+/// Receives parsing events in document order.
+/// Returning `ControlFlow::Break` stops the parser early, in which case the
+/// rest of the document is not validated.
+pub trait Visitor<'a> {
+    fn visit(&mut self, event: Event<'a>) -> ControlFlow<()>;
+}
+
+impl<'a, F> Visitor<'a> for F
+where
+    F: FnMut(Event<'a>) -> ControlFlow<()>,
+{
+    fn visit(&mut self, event: Event<'a>) -> ControlFlow<()> {
+        self(event)
+    }
+}
+
+pub fn parse_events<'a, V: Visitor<'a>>(buf: &'a [u8], visitor: &mut V) -> Result<(), Error> {
+    let mut cursor = Cursor::default();
+    let _ = walk_value(buf, &mut cursor, 0, visitor)?;
+    Ok(())
+}
+
+macro_rules! emit {
+    ($visitor:expr, $event:expr) => {
+        if $visitor.visit($event).is_break() {
+            return Ok(ControlFlow::Break(()));
+        }
+    };
+}
+
+fn walk_value<'a, V: Visitor<'a>>(
+    buf: &'a [u8],
+    cursor: &mut Cursor,
+    depth: usize,
+    visitor: &mut V,
+) -> Result<ControlFlow<()>, Error> {
+    let event = match cursor.next_token(buf) {
+        b'"' => Event::Str(parse_str(buf, cursor)?),
+        b'-' | b'0'..=b'9' => match parse_number(buf, cursor)? {
+            Value::Int(v) => Event::Int(v),
+            Value::Float(v) => Event::Float(v),
+            _ => unreachable!(),
+        },
+        b'n' => {
+            parse_null(buf, cursor)?;
+            Event::Null
+        }
+        b't' => Event::Bool(parse_true(buf, cursor)?),
+        b'f' => Event::Bool(parse_false(buf, cursor)?),
+        b'{' => return walk_object(buf, cursor, depth, visitor),
+        b'[' => return walk_array(buf, cursor, depth, visitor),
+        0 => {
+            return Err(Error {
+                pos: cursor.pos,
+                msg: "Unexpected message end",
+            })
+        }
+        _ => {
+            return Err(Error {
+                pos: cursor.pos,
+                msg: "Unexpected token while parsing message",
+            })
+        }
+    };
+    Ok(visitor.visit(event))
+}
+
+fn walk_array<'a, V: Visitor<'a>>(
+    buf: &'a [u8],
+    cursor: &mut Cursor,
+    depth: usize,
+    visitor: &mut V,
+) -> Result<ControlFlow<()>, Error> {
+    check_depth(depth, cursor)?;
+    cursor.advance();
+    emit!(visitor, Event::StartArray);
+    // A value must follow each comma, only the empty array ends right away
+    if cursor.next_token(buf) != b']' {
+        loop {
+            if walk_value(buf, cursor, depth + 1, visitor)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+            match cursor.next_token(buf) {
+                b',' => cursor.advance(),
+                b']' => break,
+                _ => {
+                    return Err(Error {
+                        pos: cursor.pos,
+                        msg: "Unexpected token when parsing array",
+                    })
+                }
+            }
+        }
+    }
+    cursor.advance();
+    Ok(visitor.visit(Event::EndArray))
+}
+
+fn walk_object<'a, V: Visitor<'a>>(
+    buf: &'a [u8],
+    cursor: &mut Cursor,
+    depth: usize,
+    visitor: &mut V,
+) -> Result<ControlFlow<()>, Error> {
+    check_depth(depth, cursor)?;
+    cursor.advance();
+    emit!(visitor, Event::StartObject);
+    if cursor.next_token(buf) != b'}' {
+        loop {
+            let key = parse_key(buf, cursor)?;
+            emit!(visitor, Event::Key(key));
+            if walk_value(buf, cursor, depth + 1, visitor)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+            match cursor.next_token(buf) {
+                b',' => cursor.advance(),
+                b'}' => break,
+                _ => {
+                    return Err(Error {
+                        pos: cursor.pos,
+                        msg: "Unexpected token when parsing object",
+                    })
+                }
+            }
+        }
+    }
+    cursor.advance();
+    Ok(visitor.visit(Event::EndObject))
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::ControlFlow;
+
+    use super::{parse_events, Event};
+
+    #[test]
+    fn test_events_order() {
+        let mut events = Vec::new();
+        parse_events(
+            b"{\"a\": [1, 2.5, \"x\"], \"b\": {\"c\": null}, \"d\": true}",
+            &mut |e| {
+                events.push(e);
+                ControlFlow::Continue(())
+            },
+        )
+        .expect("parsing failed");
+        assert_eq!(
+            events,
+            vec![
+                Event::StartObject,
+                Event::Key("a".into()),
+                Event::StartArray,
+                Event::Int(1),
+                Event::Float(2.5),
+                Event::Str("x".into()),
+                Event::EndArray,
+                Event::Key("b".into()),
+                Event::StartObject,
+                Event::Key("c".into()),
+                Event::Null,
+                Event::EndObject,
+                Event::Key("d".into()),
+                Event::Bool(true),
+                Event::EndObject,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_events_stop_early() {
+        let mut seen = 0;
+        // The trailing garbage is never reached
+        parse_events(b"[1, 2, 3, }", &mut |e| {
+            if let Event::Int(_) = e {
+                seen += 1;
+            }
+            if seen == 2 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .expect("parsing failed");
+        assert_eq!(seen, 2);
+    }
+
+    #[test]
+    fn test_events_errors() {
+        let inputs = [
+            b"{".as_ref(),
+            b"[,]",
+            b"{\"a\" 1}",
+            b"[1 2]",
+            b"[1,]",
+            b"{\"a\":1,}",
+        ];
+        for input in inputs {
+            parse_events(input, &mut |_| ControlFlow::Continue(())).unwrap_err();
+        }
+
+        // Nesting is bounded rather than overflowing the stack
+        let deep = "[".repeat(1_000_000);
+        let err = parse_events(deep.as_bytes(), &mut |_| ControlFlow::Continue(())).unwrap_err();
+        assert_eq!(err.msg, "Maximum nesting depth exceeded");
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    sync::{OnceLock, RwLock},
+};
+
+use super::{array_into_vec, Value};
+
+/// Shares a single allocation between identical object keys across messages.
+/// Interned keys live for the rest of the program, so there is only the
+/// `global` interner, and only up to `limit` distinct keys shorter than
+/// `MAX_KEY_LEN` are interned, the others are allocated as usual. A client
+/// sending random keys can't grow it unboundedly.
+#[derive(Debug)]
+pub struct KeyInterner {
+    keys: RwLock<HashSet<&'static str>>,
+    limit: usize,
+}
+
+pub const MAX_KEY_LEN: usize = 64;
+
+impl KeyInterner {
+    fn new(limit: usize) -> Self {
+        Self {
+            keys: RwLock::new(HashSet::new()),
+            limit,
+        }
+    }
+
+    /// Process wide interner, shared by all connections
+    pub fn global() -> &'static KeyInterner {
+        static GLOBAL: OnceLock<KeyInterner> = OnceLock::new();
+        GLOBAL.get_or_init(|| KeyInterner::new(1024))
+    }
+
+    pub fn intern(&self, key: &str) -> Option<&'static str> {
+        if let Some(k) = self.keys.read().unwrap_or_else(|e| e.into_inner()).get(key) {
+            return Some(k);
+        }
+        if key.len() > MAX_KEY_LEN {
+            return None;
+        }
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(k) = keys.get(key) {
+            return Some(k);
+        }
+        if keys.len() >= self.limit {
+            return None;
+        }
+        let k: &'static str = Box::leak(key.into());
+        keys.insert(k);
+        Some(k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(super) fn key(&self, key: Cow<'_, str>) -> Cow<'static, str> {
+        match self.intern(&key) {
+            Some(k) => Cow::Borrowed(k),
+            None => Cow::Owned(key.into_owned()),
+        }
+    }
+}
+
+impl<'a> Value<'a> {
+    /// Like `into_owned`, but object keys are taken from `interner`
+    pub fn into_owned_interned(self, interner: &KeyInterner) -> Value<'static> {
+        match self {
+            Value::Array(v) => Value::Array(
+                array_into_vec(v)
+                    .into_iter()
+                    .map(|v| v.into_owned_interned(interner))
+                    .collect(),
+            ),
+            Value::Object(v) => Value::Object(
+                v.into_iter()
+                    .map(|(k, v)| (interner.key(k), v.into_owned_interned(interner)))
+                    .collect(),
+            ),
+            v => v.into_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use super::{KeyInterner, MAX_KEY_LEN};
+    use crate::json::parse_json;
+
+    #[test]
+    fn test_intern_shares_keys() {
+        let interner = KeyInterner::new(16);
+        let a = interner.intern("method").unwrap();
+        let b = interner.intern(&String::from("method")).unwrap();
+        assert!(std::ptr::eq(a, b));
+        assert_eq!(interner.len(), 1);
+        assert!(interner.intern(&"k".repeat(MAX_KEY_LEN + 1)).is_none());
+    }
+
+    #[test]
+    fn test_intern_limit() {
+        let interner = KeyInterner::new(2);
+        interner.intern("a").unwrap();
+        interner.intern("b").unwrap();
+        assert!(interner.intern("c").is_none());
+        assert!(interner.intern("a").is_some());
+    }
+
+    #[test]
+    fn test_into_owned_interned() {
+        let interner = KeyInterner::new(16);
+        let val = parse_json(br#"[{"method": 1}, {"method": {"id": 2}}]"#).unwrap();
+        let owned = val.clone().into_owned_interned(&interner);
+        assert_eq!(owned, val);
+        for elem in owned.array().unwrap() {
+            let (key, _) = elem.object().unwrap().iter().next().unwrap();
+            assert!(matches!(key, Cow::Borrowed(_)));
+        }
+        assert_eq!(interner.len(), 2);
+    }
+}
@@ -0,0 +1,472 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    slice, vec,
+};
+
+#[cfg(not(feature = "btree-map"))]
+use std::collections::hash_map as large;
+#[cfg(feature = "btree-map")]
+use std::collections::{btree_map as large, BTreeMap};
+
+use super::Value;
+
+/// Objects with more entries than this are moved to a `LargeMap`
+pub const SPILL_LEN: usize = 8;
+
+/// Backing of objects too big for a vector. A `HashMap` by default, or a
+/// `BTreeMap` with the `btree-map` feature for a deterministic iteration order,
+/// sorted by key.
+#[cfg(not(feature = "btree-map"))]
+type LargeMap<'a> = HashMap<Cow<'a, str>, Value<'a>>;
+#[cfg(feature = "btree-map")]
+type LargeMap<'a> = BTreeMap<Cow<'a, str>, Value<'a>>;
+
+/// Entries of a reused `Map` returned by `reuse_slot` so far
+#[derive(Debug, Default)]
+pub(super) struct Visited {
+    len: usize,
+    keys: HashSet<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Repr<'a> {
+    Small(Vec<(Cow<'a, str>, Value<'a>)>),
+    Large(LargeMap<'a>),
+}
+
+/// Storage behind `Value::Object`.
+/// Most protocol messages have a handful of keys, so up to `SPILL_LEN` entries
+/// are kept in insertion order in a vector searched linearly, which is cheaper
+/// than hashing every key.
+#[derive(Debug, Clone)]
+pub struct Map<'a> {
+    repr: Repr<'a>,
+}
+
+impl Default for Map<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Map<'a> {
+    pub fn new() -> Self {
+        Self {
+            repr: Repr::Small(Vec::new()),
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            repr: if capacity <= SPILL_LEN {
+                Repr::Small(Vec::with_capacity(capacity))
+            } else {
+                #[cfg(not(feature = "btree-map"))]
+                let m = HashMap::with_capacity(capacity);
+                #[cfg(feature = "btree-map")]
+                let m = BTreeMap::new();
+                Repr::Large(m)
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.repr {
+            Repr::Small(v) => v.len(),
+            Repr::Large(m) => m.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get<Q: AsRef<str> + ?Sized>(&self, key: &Q) -> Option<&Value<'a>> {
+        let key = key.as_ref();
+        match &self.repr {
+            Repr::Small(v) => v.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            Repr::Large(m) => m.get(key),
+        }
+    }
+
+    pub fn get_mut<Q: AsRef<str> + ?Sized>(&mut self, key: &Q) -> Option<&mut Value<'a>> {
+        let key = key.as_ref();
+        match &mut self.repr {
+            Repr::Small(v) => v.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v),
+            Repr::Large(m) => m.get_mut(key),
+        }
+    }
+
+    pub fn contains_key<Q: AsRef<str> + ?Sized>(&self, key: &Q) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns the previous value stored under `key`
+    pub fn insert<K: Into<Cow<'a, str>>>(&mut self, key: K, val: Value<'a>) -> Option<Value<'a>> {
+        let key = key.into();
+        match &mut self.repr {
+            Repr::Small(v) => {
+                if let Some((_, old)) = v.iter_mut().find(|(k, _)| *k == key) {
+                    return Some(std::mem::replace(old, val));
+                }
+                if v.len() < SPILL_LEN {
+                    v.push((key, val));
+                    return None;
+                }
+                let mut m: LargeMap = v.drain(..).collect();
+                m.insert(key, val);
+                self.repr = Repr::Large(m);
+                None
+            }
+            Repr::Large(m) => m.insert(key, val),
+        }
+    }
+
+    pub fn entry<K: Into<Cow<'a, str>>>(&mut self, key: K) -> Entry<'_, 'a> {
+        let key = key.into();
+        if let Repr::Small(v) = &self.repr {
+            return match v.iter().position(|(k, _)| *k == key) {
+                Some(idx) => match &mut self.repr {
+                    Repr::Small(v) => Entry::Occupied(&mut v[idx].1),
+                    Repr::Large(_) => unreachable!(),
+                },
+                None => Entry::Vacant(VacantEntry(Vacant::Small(self, key))),
+            };
+        }
+        match &mut self.repr {
+            Repr::Large(m) => match m.entry(key) {
+                large::Entry::Occupied(e) => Entry::Occupied(e.into_mut()),
+                large::Entry::Vacant(e) => Entry::Vacant(VacantEntry(Vacant::Large(e))),
+            },
+            Repr::Small(_) => unreachable!(),
+        }
+    }
+
+    pub fn remove<Q: AsRef<str> + ?Sized>(&mut self, key: &Q) -> Option<Value<'a>> {
+        let key = key.as_ref();
+        match &mut self.repr {
+            Repr::Small(v) => {
+                let idx = v.iter().position(|(k, _)| k == key)?;
+                Some(v.remove(idx).1)
+            }
+            Repr::Large(m) => m.remove(key),
+        }
+    }
+
+    /// Removes every entry, keeping the allocated capacity
+    pub fn clear(&mut self) {
+        match &mut self.repr {
+            Repr::Small(v) => v.clear(),
+            Repr::Large(m) => m.clear(),
+        }
+    }
+
+    /// Slot under `key` for `parse_into`, inserting `Null` if it's missing.
+    /// The first `visited.len` entries of a small map are the ones already
+    /// returned, the rest are left over from the previous message. Large maps
+    /// keep the returned keys in `visited.keys` instead.
+    pub(super) fn reuse_slot(&mut self, key: &str, visited: &mut Visited) -> &mut Value<'a> {
+        if let Repr::Small(v) = &mut self.repr {
+            if v.len() == SPILL_LEN && v.iter().all(|(k, _)| k != key) {
+                // Leftovers must not end up mixed in the LargeMap
+                v.truncate(visited.len);
+                visited.keys.extend(v.iter().map(|(k, _)| k.to_string()));
+                let m = v.drain(..).collect();
+                self.repr = Repr::Large(m);
+            }
+        }
+        match &mut self.repr {
+            Repr::Small(v) => {
+                let idx = match v.iter().position(|(k, _)| k == key) {
+                    Some(idx) if idx < visited.len => return &mut v[idx].1,
+                    Some(idx) => idx,
+                    None => {
+                        v.push((Cow::Owned(key.to_owned()), Value::Null(())));
+                        v.len() - 1
+                    }
+                };
+                v.swap(idx, visited.len);
+                visited.len += 1;
+                &mut v[visited.len - 1].1
+            }
+            Repr::Large(m) => {
+                if !visited.keys.contains(key) {
+                    visited.keys.insert(key.to_owned());
+                }
+                m.entry(Cow::Owned(key.to_owned()))
+                    .or_insert(Value::Null(()))
+            }
+        }
+    }
+
+    /// Drops the entries `reuse_slot` didn't return
+    pub(super) fn drop_unvisited(&mut self, visited: &Visited) {
+        match &mut self.repr {
+            Repr::Small(v) => v.truncate(visited.len),
+            Repr::Large(m) => m.retain(|k, _| visited.keys.contains(k.as_ref())),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, 'a> {
+        match &self.repr {
+            Repr::Small(v) => Iter::Small(v.iter()),
+            Repr::Large(m) => Iter::Large(m.iter()),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, 'a> {
+        match &mut self.repr {
+            Repr::Small(v) => IterMut::Small(v.iter_mut()),
+            Repr::Large(m) => IterMut::Large(m.iter_mut()),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Cow<'a, str>> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value<'a>> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+pub enum Entry<'m, 'a> {
+    Occupied(&'m mut Value<'a>),
+    Vacant(VacantEntry<'m, 'a>),
+}
+
+impl<'m, 'a> Entry<'m, 'a> {
+    pub fn or_insert(self, default: Value<'a>) -> &'m mut Value<'a> {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> Value<'a>>(self, default: F) -> &'m mut Value<'a> {
+        match self {
+            Entry::Occupied(v) => v,
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    pub fn and_modify<F: FnOnce(&mut Value<'a>)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(v) => {
+                f(v);
+                Entry::Occupied(v)
+            }
+            vacant => vacant,
+        }
+    }
+}
+
+pub struct VacantEntry<'m, 'a>(Vacant<'m, 'a>);
+
+enum Vacant<'m, 'a> {
+    Small(&'m mut Map<'a>, Cow<'a, str>),
+    Large(large::VacantEntry<'m, Cow<'a, str>, Value<'a>>),
+}
+
+impl<'m, 'a> VacantEntry<'m, 'a> {
+    pub fn key(&self) -> &str {
+        match &self.0 {
+            Vacant::Small(_, k) => k,
+            Vacant::Large(e) => e.key(),
+        }
+    }
+
+    pub fn insert(self, val: Value<'a>) -> &'m mut Value<'a> {
+        match self.0 {
+            Vacant::Small(map, key) if map.len() < SPILL_LEN => match &mut map.repr {
+                Repr::Small(v) => {
+                    v.push((key, val));
+                    &mut v.last_mut().unwrap().1
+                }
+                Repr::Large(_) => unreachable!(),
+            },
+            Vacant::Small(map, key) => {
+                map.insert(key.clone(), val);
+                map.get_mut(&key).unwrap()
+            }
+            Vacant::Large(e) => e.insert(val),
+        }
+    }
+}
+
+pub enum Iter<'m, 'a> {
+    Small(slice::Iter<'m, (Cow<'a, str>, Value<'a>)>),
+    Large(large::Iter<'m, Cow<'a, str>, Value<'a>>),
+}
+
+impl<'m, 'a> Iterator for Iter<'m, 'a> {
+    type Item = (&'m Cow<'a, str>, &'m Value<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Small(it) => it.next().map(|(k, v)| (k, v)),
+            Iter::Large(it) => it.next(),
+        }
+    }
+}
+
+pub enum IterMut<'m, 'a> {
+    Small(slice::IterMut<'m, (Cow<'a, str>, Value<'a>)>),
+    Large(large::IterMut<'m, Cow<'a, str>, Value<'a>>),
+}
+
+impl<'m, 'a> Iterator for IterMut<'m, 'a> {
+    type Item = (&'m Cow<'a, str>, &'m mut Value<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            IterMut::Small(it) => it.next().map(|(k, v)| (&*k, v)),
+            IterMut::Large(it) => it.next(),
+        }
+    }
+}
+
+pub enum IntoIter<'a> {
+    Small(vec::IntoIter<(Cow<'a, str>, Value<'a>)>),
+    Large(large::IntoIter<Cow<'a, str>, Value<'a>>),
+}
+
+impl<'a> Iterator for IntoIter<'a> {
+    type Item = (Cow<'a, str>, Value<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            IntoIter::Small(it) => it.next(),
+            IntoIter::Large(it) => it.next(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for Map<'a> {
+    type Item = (Cow<'a, str>, Value<'a>);
+    type IntoIter = IntoIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        match self.repr {
+            Repr::Small(v) => IntoIter::Small(v.into_iter()),
+            Repr::Large(m) => IntoIter::Large(m.into_iter()),
+        }
+    }
+}
+
+impl<'m, 'a> IntoIterator for &'m Map<'a> {
+    type Item = (&'m Cow<'a, str>, &'m Value<'a>);
+    type IntoIter = Iter<'m, 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K: Into<Cow<'a, str>>> FromIterator<(K, Value<'a>)> for Map<'a> {
+    fn from_iter<T: IntoIterator<Item = (K, Value<'a>)>>(iter: T) -> Self {
+        let iter = iter.into_iter();
+        let mut map = Map::with_capacity(iter.size_hint().0);
+        for (k, v) in iter {
+            map.insert(k, v);
+        }
+        map
+    }
+}
+
+impl<'a> From<HashMap<Cow<'a, str>, Value<'a>>> for Map<'a> {
+    fn from(m: HashMap<Cow<'a, str>, Value<'a>>) -> Self {
+        m.into_iter().collect()
+    }
+}
+
+/// Equality ignores insertion order and representation
+impl PartialEq for Map<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl Eq for Map<'_> {}
+
+#[cfg(test)]
+mod test {
+    use super::{Entry, Map, Repr, SPILL_LEN};
+    use crate::json::Value;
+
+    #[test]
+    fn test_map_spills() {
+        let mut map = Map::new();
+        for i in 0..SPILL_LEN {
+            assert!(map.insert(i.to_string(), Value::Int(i as i64)).is_none());
+        }
+        assert!(matches!(map.repr, Repr::Small(_)));
+        assert_eq!(map.insert("0", Value::Null(())), Some(Value::Int(0)));
+        assert!(matches!(map.repr, Repr::Small(_)));
+        map.insert("extra", Value::Null(()));
+        assert!(matches!(map.repr, Repr::Large(_)));
+        assert_eq!(map.len(), SPILL_LEN + 1);
+        assert_eq!(map.get("1"), Some(&Value::Int(1)));
+        assert_eq!(map.remove("extra"), Some(Value::Null(())));
+        assert!(!map.contains_key("extra"));
+    }
+
+    #[test]
+    fn test_map_entry() {
+        let mut map = Map::new();
+        for word in "a b a c a b".split(' ').chain((0..SPILL_LEN).map(|_| "x")) {
+            let count = map.entry(word).or_insert(Value::Int(0));
+            *count = Value::Int(count.int().unwrap() + 1);
+        }
+        assert_eq!(map.get("a"), Some(&Value::Int(3)));
+        assert_eq!(map.get("x"), Some(&Value::Int(SPILL_LEN as i64)));
+
+        for i in 0..SPILL_LEN * 2 {
+            map.entry(i.to_string())
+                .and_modify(|_| panic!("entry shouldn't exist"))
+                .or_insert_with(|| Value::Int(i as i64));
+        }
+        assert!(matches!(map.repr, Repr::Large(_)));
+        assert_eq!(map.len(), SPILL_LEN * 2 + 4);
+        assert_eq!(map.get("12"), Some(&Value::Int(12)));
+        map.entry("a").and_modify(|v| *v = Value::Null(()));
+        assert_eq!(map.get("a"), Some(&Value::Null(())));
+        match map.entry("missing") {
+            Entry::Vacant(e) => assert_eq!(e.key(), "missing"),
+            Entry::Occupied(_) => panic!("entry shouldn't exist"),
+        }
+    }
+
+    #[cfg(feature = "btree-map")]
+    #[test]
+    fn test_large_map_sorted() {
+        let map: Map = (0..SPILL_LEN * 2)
+            .rev()
+            .map(|i| (format!("{:02}", i), Value::Int(0)))
+            .collect();
+        let keys: Vec<_> = map.keys().collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+    }
+
+    #[test]
+    fn test_map_order_and_equality() {
+        let small: Map = [("b", Value::Int(1)), ("a", Value::Int(2))]
+            .into_iter()
+            .collect();
+        let keys: Vec<_> = small.keys().map(|k| k.as_ref()).collect();
+        assert_eq!(keys, vec!["b", "a"]);
+
+        let mut large: Map = (0..SPILL_LEN * 2)
+            .map(|i| (i.to_string(), Value::Int(0)))
+            .collect();
+        for i in 2..SPILL_LEN * 2 {
+            large.remove(&i.to_string());
+        }
+        large.insert("a", Value::Int(2));
+        large.insert("b", Value::Int(1));
+        large.remove("0");
+        large.remove("1");
+        assert!(matches!(large.repr, Repr::Large(_)));
+        assert_eq!(small, large);
+    }
+}
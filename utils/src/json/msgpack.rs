@@ -0,0 +1,345 @@
+//! MessagePack encoding of `Value`.
+//! Extension types aren't supported and integers must fit an `i64`.
+
+use std::{borrow::Cow, str};
+
+use super::{Error, Map, Value, DEFAULT_MAX_DEPTH};
+
+pub fn encode(val: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_into(val, &mut buf);
+    buf
+}
+
+pub fn encode_into(val: &Value, buf: &mut Vec<u8>) {
+    match val {
+        Value::Int(v) => encode_int(*v, buf),
+        Value::Float(v) => {
+            buf.push(0xcb);
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+        Value::Bool(false) => buf.push(0xc2),
+        Value::Bool(true) => buf.push(0xc3),
+        Value::Null(()) => buf.push(0xc0),
+        Value::String(v) => encode_str(v, buf),
+        Value::Bytes(v) => {
+            match v.len() {
+                0..=0xFF => buf.extend_from_slice(&[0xc4, v.len() as u8]),
+                0x100..=0xFFFF => {
+                    buf.push(0xc5);
+                    buf.extend_from_slice(&(v.len() as u16).to_be_bytes());
+                }
+                _ => {
+                    buf.push(0xc6);
+                    buf.extend_from_slice(&(v.len() as u32).to_be_bytes());
+                }
+            }
+            buf.extend_from_slice(v);
+        }
+        Value::Array(v) => {
+            write_container_head(v.len(), 0x90, 0xdc, buf);
+            for e in v {
+                encode_into(e, buf);
+            }
+        }
+        Value::Object(v) => {
+            write_container_head(v.len(), 0x80, 0xde, buf);
+            for (k, e) in v {
+                encode_str(k, buf);
+                encode_into(e, buf);
+            }
+        }
+        Value::Raw(v) => match v.parse() {
+            Ok(v) => encode_into(&v, buf),
+            Err(_) => encode_str(v.get(), buf),
+        },
+    }
+}
+
+/// Uses the smallest representation
+fn encode_int(v: i64, buf: &mut Vec<u8>) {
+    match v {
+        0..=0x7F => buf.push(v as u8),
+        -32..=-1 => buf.push(v as i8 as u8),
+        0x80..=0xFF => buf.extend_from_slice(&[0xcc, v as u8]),
+        0x100..=0xFFFF => {
+            buf.push(0xcd);
+            buf.extend_from_slice(&(v as u16).to_be_bytes());
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            buf.push(0xce);
+            buf.extend_from_slice(&(v as u32).to_be_bytes());
+        }
+        0x1_0000_0000.. => {
+            buf.push(0xcf);
+            buf.extend_from_slice(&(v as u64).to_be_bytes());
+        }
+        -0x80..=-33 => buf.extend_from_slice(&[0xd0, v as i8 as u8]),
+        -0x8000..=-0x81 => {
+            buf.push(0xd1);
+            buf.extend_from_slice(&(v as i16).to_be_bytes());
+        }
+        -0x8000_0000..=-0x8001 => {
+            buf.push(0xd2);
+            buf.extend_from_slice(&(v as i32).to_be_bytes());
+        }
+        _ => {
+            buf.push(0xd3);
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+}
+
+fn encode_str(s: &str, buf: &mut Vec<u8>) {
+    match s.len() {
+        0..=31 => buf.push(0xa0 | s.len() as u8),
+        32..=0xFF => buf.extend_from_slice(&[0xd9, s.len() as u8]),
+        0x100..=0xFFFF => {
+            buf.push(0xda);
+            buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+        }
+        _ => {
+            buf.push(0xdb);
+            buf.extend_from_slice(&(s.len() as u32).to_be_bytes());
+        }
+    }
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn write_container_head(len: usize, fix: u8, long: u8, buf: &mut Vec<u8>) {
+    match len {
+        0..=15 => buf.push(fix | len as u8),
+        16..=0xFFFF => {
+            buf.push(long);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            buf.push(long + 1);
+            buf.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+}
+
+/// Decodes exactly one object, strings are borrowed from `buf`
+pub fn decode(buf: &[u8]) -> Result<Value<'_>, Error> {
+    let mut decoder = Decoder { buf, pos: 0 };
+    let val = decoder.value(0)?;
+    if decoder.pos != buf.len() {
+        return Err(decoder.err("Trailing data after MessagePack object"));
+    }
+    Ok(val)
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn err(&self, msg: &'static str) -> Error {
+        Error { msg, pos: self.pos }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| self.err("Unexpected data end while decoding MessagePack"))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    /// Reads a big endian length of `size` bytes
+    fn len(&mut self, size: usize) -> Result<usize, Error> {
+        let len = match size {
+            1 => self.take(1)?[0] as usize,
+            2 => u16::from_be_bytes(self.take_array()?) as usize,
+            _ => u32::from_be_bytes(self.take_array()?) as usize,
+        };
+        // Every element takes at least a byte, which bounds preallocations
+        if len > self.buf.len() - self.pos {
+            return Err(self.err("MessagePack length larger than the input"));
+        }
+        Ok(len)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value<'a>, Error> {
+        let marker = self.take(1)?[0];
+        Ok(match marker {
+            0x00..=0x7f => Value::Int(marker as i64),
+            0xe0..=0xff => Value::Int(marker as i8 as i64),
+            0xc0 => Value::Null(()),
+            0xc2 => Value::Bool(false),
+            0xc3 => Value::Bool(true),
+            0xcc => Value::Int(self.take(1)?[0] as i64),
+            0xcd => Value::Int(u16::from_be_bytes(self.take_array()?) as i64),
+            0xce => Value::Int(u32::from_be_bytes(self.take_array()?) as i64),
+            0xcf => Value::Int(
+                i64::try_from(u64::from_be_bytes(self.take_array()?))
+                    .map_err(|_| self.err("MessagePack integer doesn't fit an i64"))?,
+            ),
+            0xd0 => Value::Int(self.take(1)?[0] as i8 as i64),
+            0xd1 => Value::Int(i16::from_be_bytes(self.take_array()?) as i64),
+            0xd2 => Value::Int(i32::from_be_bytes(self.take_array()?) as i64),
+            0xd3 => Value::Int(i64::from_be_bytes(self.take_array()?)),
+            0xca => Value::Float(f32::from_be_bytes(self.take_array()?) as f64),
+            0xcb => Value::Float(f64::from_be_bytes(self.take_array()?)),
+            0xa0..=0xbf => self.str((marker & 0x1f) as usize)?,
+            0xd9 => {
+                let len = self.len(1)?;
+                self.str(len)?
+            }
+            0xda => {
+                let len = self.len(2)?;
+                self.str(len)?
+            }
+            0xdb => {
+                let len = self.len(4)?;
+                self.str(len)?
+            }
+            0xc4..=0xc6 => {
+                let len = self.len(1 << (marker - 0xc4))?;
+                Value::Bytes(Cow::Borrowed(self.take(len)?))
+            }
+            0x90..=0x9f => self.array((marker & 0xf) as usize, depth)?,
+            0xdc => {
+                let len = self.len(2)?;
+                self.array(len, depth)?
+            }
+            0xdd => {
+                let len = self.len(4)?;
+                self.array(len, depth)?
+            }
+            0x80..=0x8f => self.map((marker & 0xf) as usize, depth)?,
+            0xde => {
+                let len = self.len(2)?;
+                self.map(len, depth)?
+            }
+            0xdf => {
+                let len = self.len(4)?;
+                self.map(len, depth)?
+            }
+            _ => return Err(self.err("Unsupported MessagePack type")),
+        })
+    }
+
+    fn str(&mut self, len: usize) -> Result<Value<'a>, Error> {
+        let text = str::from_utf8(self.take(len)?)
+            .map_err(|_| self.err("MessagePack string wasn't utf8 encoded"))?;
+        Ok(Value::String(Cow::Borrowed(text)))
+    }
+
+    fn array(&mut self, len: usize, depth: usize) -> Result<Value<'a>, Error> {
+        self.check_depth(depth)?;
+        let mut array = Vec::with_capacity(len.min(self.buf.len() - self.pos));
+        for _ in 0..len {
+            array.push(self.value(depth + 1)?);
+        }
+        Ok(Value::from(array))
+    }
+
+    fn map(&mut self, len: usize, depth: usize) -> Result<Value<'a>, Error> {
+        self.check_depth(depth)?;
+        let mut obj = Map::with_capacity(len.min(self.buf.len() - self.pos));
+        for _ in 0..len {
+            let key = match self.value(depth + 1)? {
+                Value::String(k) => k,
+                _ => return Err(self.err("MessagePack map key isn't a string")),
+            };
+            obj.insert(key, self.value(depth + 1)?);
+        }
+        Ok(Value::Object(obj))
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<(), Error> {
+        if depth >= DEFAULT_MAX_DEPTH {
+            return Err(self.err("Maximum nesting depth exceeded"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode};
+    use crate::json::{from_str, Value};
+
+    #[test]
+    fn test_msgpack_round_trip() {
+        let val = from_str(r#"{"method": "put", "job": {"pri": 1.5, "ok": true, "none": null}}"#)
+            .unwrap();
+        let mut val = val.into_owned();
+        let ints = [
+            0,
+            127,
+            128,
+            255,
+            256,
+            65536,
+            4294967296,
+            i64::MAX,
+            -1,
+            -32,
+            -33,
+            -128,
+            -129,
+            -32769,
+            -2147483649,
+            i64::MIN,
+        ];
+        if let Value::Object(obj) = &mut val {
+            obj.insert("bytes", Value::Bytes(vec![0xFF, 0].into()));
+            obj.insert(
+                "ints",
+                ints.into_iter().map(Value::Int).collect::<Vec<_>>().into(),
+            );
+            obj.insert("long", Value::from("x".repeat(300)));
+        }
+        assert_eq!(decode(&encode(&val)).unwrap(), val);
+    }
+
+    #[test]
+    fn test_msgpack_encoding() {
+        assert_eq!(encode(&Value::Int(-1)), [0xff]);
+        assert_eq!(encode(&Value::Int(200)), [0xcc, 200]);
+        assert_eq!(encode(&Value::Int(-200)), [0xd1, 0xff, 0x38]);
+        assert_eq!(encode(&Value::from("ab")), [0xa2, b'a', b'b']);
+        assert_eq!(
+            encode(
+                &[("a", Value::from(vec![Value::Bool(true)]))]
+                    .into_iter()
+                    .collect()
+            ),
+            [0x81, 0xa1, b'a', 0x91, 0xc3]
+        );
+        assert_eq!(
+            decode(&[0xca, 0x3f, 0xc0, 0, 0]).unwrap(),
+            Value::Float(1.5)
+        );
+    }
+
+    #[test]
+    fn test_msgpack_decode_errors() {
+        let inputs: &[&[u8]] = &[
+            &[],
+            &[0xcd, 0x01],
+            &[0xa2, b'a'],
+            &[0x81, 0x01, 0x01],
+            &[0xcf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            &[0xdd, 0xff, 0xff, 0xff, 0xff],
+            &[0xd4, 0x01, 0x01],
+            &[0x01, 0x01],
+            &[0xa2, 0xff, 0xfe],
+        ];
+        for input in inputs {
+            decode(input).unwrap_err();
+        }
+        decode(&[0x91; 200]).unwrap_err();
+    }
+}
@@ -0,0 +1,43 @@
+use super::DEFAULT_MAX_DEPTH;
+
+/// What to do when an object has the same key more than once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateKeys {
+    /// The last value wins
+    #[default]
+    Last,
+    /// The first value wins
+    First,
+    /// Fail the parse
+    Reject,
+}
+
+/// Settings accepted by `parse_json_with`. The default is what `parse_json`
+/// does: strict JSON, utf8 strings, `DEFAULT_MAX_DEPTH` and no size limit.
+#[derive(Debug, Clone)]
+pub struct ParserOptions {
+    /// Nesting of arrays and objects
+    pub max_depth: usize,
+    /// Inputs longer than this are rejected before parsing
+    pub max_size: usize,
+    pub duplicate_keys: DuplicateKeys,
+    /// Accept the JSON5 extensions, see `parse_json5`
+    pub json5: bool,
+    /// Keep strings that aren't utf8 as `Value::Bytes`, see `parse_json_bytes`
+    pub allow_bytes: bool,
+    /// Decode unpaired surrogates to U+FFFD, see `parse_json_lossy`
+    pub lossy_surrogates: bool,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_size: usize::MAX,
+            duplicate_keys: DuplicateKeys::Last,
+            json5: false,
+            allow_bytes: false,
+            lossy_surrogates: false,
+        }
+    }
+}
@@ -0,0 +1,451 @@
+//! JSON Patch (RFC 6902) parsing, application and generation.
+//! Paths are JSON Pointers (RFC 6901).
+
+use std::{borrow::Cow, fmt};
+
+use super::{edit_array, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation<'a> {
+    Add { path: String, value: Value<'a> },
+    Remove { path: String },
+    Replace { path: String, value: Value<'a> },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value<'a> },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    Malformed(&'static str),
+    InvalidPointer(String),
+    PathNotFound(String),
+    TestFailed(String),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Malformed(msg) => write!(f, "malformed patch: {}", msg),
+            PatchError::InvalidPointer(p) => write!(f, "invalid pointer {:?}", p),
+            PatchError::PathNotFound(p) => write!(f, "path not found {:?}", p),
+            PatchError::TestFailed(p) => write!(f, "test failed at {:?}", p),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+pub fn escape_token(token: &str) -> Cow<'_, str> {
+    if token.contains(['~', '/']) {
+        Cow::Owned(token.replace('~', "~0").replace('/', "~1"))
+    } else {
+        Cow::Borrowed(token)
+    }
+}
+
+fn parse_pointer(path: &str) -> Result<Vec<String>, PatchError> {
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rest = path
+        .strip_prefix('/')
+        .ok_or_else(|| PatchError::InvalidPointer(path.to_owned()))?;
+    Ok(rest
+        .split('/')
+        .map(|t| t.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+fn parse_index(token: &str, len: usize, path: &str) -> Result<usize, PatchError> {
+    let valid = !token.is_empty()
+        && token.bytes().all(|c| c.is_ascii_digit())
+        && (token == "0" || !token.starts_with('0'));
+    match token.parse::<usize>() {
+        Ok(idx) if valid && idx < len => Ok(idx),
+        _ => Err(PatchError::PathNotFound(path.to_owned())),
+    }
+}
+
+fn get<'v, 'a>(doc: &'v Value<'a>, path: &str) -> Result<&'v Value<'a>, PatchError> {
+    let mut cur = doc;
+    for token in parse_pointer(path)? {
+        cur = match cur {
+            Value::Object(o) => o.get(token.as_str()),
+            Value::Array(a) => a.get(parse_index(&token, a.len(), path)?),
+            _ => None,
+        }
+        .ok_or_else(|| PatchError::PathNotFound(path.to_owned()))?;
+    }
+    Ok(cur)
+}
+
+fn get_mut<'v, 'a>(
+    doc: &'v mut Value<'a>,
+    tokens: &[String],
+    path: &str,
+) -> Result<&'v mut Value<'a>, PatchError> {
+    let mut cur = doc;
+    for token in tokens {
+        cur = match cur {
+            Value::Object(o) => o.get_mut(token.as_str()),
+            Value::Array(a) => {
+                let idx = parse_index(token, a.len(), path)?;
+                a.get_mut(idx)
+            }
+            _ => None,
+        }
+        .ok_or_else(|| PatchError::PathNotFound(path.to_owned()))?;
+    }
+    Ok(cur)
+}
+
+fn add<'a>(doc: &mut Value<'a>, path: &str, value: Value<'a>) -> Result<(), PatchError> {
+    let tokens = parse_pointer(path)?;
+    let Some((last, parent)) = tokens.split_last() else {
+        *doc = value;
+        return Ok(());
+    };
+    match get_mut(doc, parent, path)? {
+        Value::Object(o) => {
+            o.insert(Cow::Owned(last.clone()), value);
+        }
+        Value::Array(a) if last == "-" => edit_array(a, |a| a.push(value)),
+        Value::Array(a) => {
+            let idx = parse_index(last, a.len() + 1, path)?;
+            edit_array(a, |a| a.insert(idx, value));
+        }
+        _ => return Err(PatchError::PathNotFound(path.to_owned())),
+    }
+    Ok(())
+}
+
+fn remove<'a>(doc: &mut Value<'a>, path: &str) -> Result<Value<'a>, PatchError> {
+    let tokens = parse_pointer(path)?;
+    let Some((last, parent)) = tokens.split_last() else {
+        return Err(PatchError::InvalidPointer(path.to_owned()));
+    };
+    match get_mut(doc, parent, path)? {
+        Value::Object(o) => o
+            .remove(last.as_str())
+            .ok_or_else(|| PatchError::PathNotFound(path.to_owned())),
+        Value::Array(a) => {
+            let idx = parse_index(last, a.len(), path)?;
+            Ok(edit_array(a, |a| a.remove(idx)))
+        }
+        _ => Err(PatchError::PathNotFound(path.to_owned())),
+    }
+}
+
+fn apply_one<'a>(doc: &mut Value<'a>, op: &Operation<'a>) -> Result<(), PatchError> {
+    match op {
+        Operation::Add { path, value } => add(doc, path, value.clone()),
+        Operation::Remove { path } => remove(doc, path).map(|_| ()),
+        Operation::Replace { path, value } => {
+            let tokens = parse_pointer(path)?;
+            *get_mut(doc, &tokens, path)? = value.clone();
+            Ok(())
+        }
+        Operation::Move { from, path } => {
+            if path.starts_with(from.as_str()) && path[from.len()..].starts_with('/') {
+                return Err(PatchError::Malformed("can't move a value into itself"));
+            }
+            let value = remove(doc, from)?;
+            add(doc, path, value)
+        }
+        Operation::Copy { from, path } => {
+            let value = get(doc, from)?.clone();
+            add(doc, path, value)
+        }
+        Operation::Test { path, value } => {
+            if get(doc, path)? == value {
+                Ok(())
+            } else {
+                Err(PatchError::TestFailed(path.clone()))
+            }
+        }
+    }
+}
+
+/// Applies all operations, leaving `doc` untouched if any of them fails
+pub fn apply<'a>(doc: &mut Value<'a>, ops: &[Operation<'a>]) -> Result<(), PatchError> {
+    let mut patched = doc.clone();
+    for op in ops {
+        apply_one(&mut patched, op)?;
+    }
+    *doc = patched;
+    Ok(())
+}
+
+pub fn parse_patch<'a>(val: &Value<'a>) -> Result<Vec<Operation<'a>>, PatchError> {
+    let Value::Array(ops) = val else {
+        return Err(PatchError::Malformed("patch is not an array"));
+    };
+    ops.iter()
+        .map(|op| {
+            let field = |name, err| {
+                op.get_str(name)
+                    .map(str::to_owned)
+                    .ok_or(PatchError::Malformed(err))
+            };
+            let value = || {
+                op.get_path("value")
+                    .cloned()
+                    .ok_or(PatchError::Malformed("missing value"))
+            };
+            let path = field("path", "missing path")?;
+            Ok(match field("op", "missing op")?.as_str() {
+                "add" => Operation::Add {
+                    path,
+                    value: value()?,
+                },
+                "remove" => Operation::Remove { path },
+                "replace" => Operation::Replace {
+                    path,
+                    value: value()?,
+                },
+                "move" => Operation::Move {
+                    from: field("from", "missing from")?,
+                    path,
+                },
+                "copy" => Operation::Copy {
+                    from: field("from", "missing from")?,
+                    path,
+                },
+                "test" => Operation::Test {
+                    path,
+                    value: value()?,
+                },
+                _ => return Err(PatchError::Malformed("unknown op")),
+            })
+        })
+        .collect()
+}
+
+impl<'a> Operation<'a> {
+    pub fn to_value(&self) -> Value<'a> {
+        let (op, path) = match self {
+            Operation::Add { path, .. } => ("add", path),
+            Operation::Remove { path } => ("remove", path),
+            Operation::Replace { path, .. } => ("replace", path),
+            Operation::Move { path, .. } => ("move", path),
+            Operation::Copy { path, .. } => ("copy", path),
+            Operation::Test { path, .. } => ("test", path),
+        };
+        let mut fields = vec![("op", Value::from(op)), ("path", path.clone().into())];
+        match self {
+            Operation::Add { value, .. }
+            | Operation::Replace { value, .. }
+            | Operation::Test { value, .. } => fields.push(("value", value.clone())),
+            Operation::Move { from, .. } | Operation::Copy { from, .. } => {
+                fields.push(("from", from.clone().into()))
+            }
+            Operation::Remove { .. } => {}
+        }
+        fields.into_iter().collect()
+    }
+}
+
+pub fn patch_to_value<'a>(ops: &[Operation<'a>]) -> Value<'a> {
+    Value::Array(ops.iter().map(Operation::to_value).collect())
+}
+
+/// Builds a patch turning `from` into `to`
+pub fn generate<'a>(from: &Value<'a>, to: &Value<'a>) -> Vec<Operation<'a>> {
+    let mut ops = Vec::new();
+    generate_at(from, to, &mut String::new(), &mut ops);
+    ops
+}
+
+fn generate_at<'a>(
+    from: &Value<'a>,
+    to: &Value<'a>,
+    path: &mut String,
+    ops: &mut Vec<Operation<'a>>,
+) {
+    if from == to {
+        return;
+    }
+    let prefix_len = path.len();
+    match (from, to) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys: Vec<_> = a
+                .keys()
+                .chain(b.keys().filter(|k| !a.contains_key(*k)))
+                .collect();
+            keys.sort();
+            for key in keys {
+                path.push('/');
+                path.push_str(&escape_token(key));
+                match (a.get(key), b.get(key)) {
+                    (Some(x), Some(y)) => generate_at(x, y, path, ops),
+                    (Some(_), None) => ops.push(Operation::Remove { path: path.clone() }),
+                    (None, Some(y)) => ops.push(Operation::Add {
+                        path: path.clone(),
+                        value: y.clone(),
+                    }),
+                    (None, None) => unreachable!(),
+                }
+                path.truncate(prefix_len);
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            for (i, (x, y)) in a.iter().zip(b).enumerate() {
+                path.push_str(&format!("/{}", i));
+                generate_at(x, y, path, ops);
+                path.truncate(prefix_len);
+            }
+            for i in (b.len()..a.len()).rev() {
+                ops.push(Operation::Remove {
+                    path: format!("{}/{}", path, i),
+                });
+            }
+            for (i, y) in b.iter().enumerate().skip(a.len()) {
+                ops.push(Operation::Add {
+                    path: format!("{}/{}", path, i),
+                    value: y.clone(),
+                });
+            }
+        }
+        _ => ops.push(Operation::Replace {
+            path: path.clone(),
+            value: to.clone(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply, generate, parse_patch, patch_to_value, Operation, PatchError};
+    use crate::json::{parse_json, Value};
+
+    fn patched(doc: &str, patch: &str) -> Result<Value<'static>, PatchError> {
+        let mut doc = parse_json(doc.as_bytes()).unwrap().into_owned();
+        let patch = parse_json(patch.as_bytes()).unwrap().into_owned();
+        apply(&mut doc, &parse_patch(&patch)?)?;
+        Ok(doc)
+    }
+
+    fn json(s: &str) -> Value<'_> {
+        parse_json(s.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_apply_operations() {
+        let cases = [
+            (
+                r#"{"foo": "bar"}"#,
+                r#"[{"op": "add", "path": "/baz", "value": "qux"}]"#,
+                r#"{"foo": "bar", "baz": "qux"}"#,
+            ),
+            (
+                r#"{"foo": ["bar", "baz"]}"#,
+                r#"[{"op": "add", "path": "/foo/1", "value": "qux"}]"#,
+                r#"{"foo": ["bar", "qux", "baz"]}"#,
+            ),
+            (
+                r#"{"foo": ["bar"]}"#,
+                r#"[{"op": "add", "path": "/foo/-", "value": 1}]"#,
+                r#"{"foo": ["bar", 1]}"#,
+            ),
+            (
+                r#"{"baz": "qux", "foo": "bar"}"#,
+                r#"[{"op": "remove", "path": "/baz"}]"#,
+                r#"{"foo": "bar"}"#,
+            ),
+            (
+                r#"{"baz": "qux"}"#,
+                r#"[{"op": "replace", "path": "/baz", "value": "boo"}]"#,
+                r#"{"baz": "boo"}"#,
+            ),
+            (
+                r#"{"foo": {"bar": "baz", "waldo": "fred"}, "qux": {"corge": "grault"}}"#,
+                r#"[{"op": "move", "from": "/foo/waldo", "path": "/qux/thud"}]"#,
+                r#"{"foo": {"bar": "baz"}, "qux": {"corge": "grault", "thud": "fred"}}"#,
+            ),
+            (
+                r#"{"a": [1, 2]}"#,
+                r#"[{"op": "copy", "from": "/a/0", "path": "/b"}, {"op": "test", "path": "/b", "value": 1}]"#,
+                r#"{"a": [1, 2], "b": 1}"#,
+            ),
+            (
+                r#"{"a/b": 1, "m~n": 2}"#,
+                r#"[{"op": "remove", "path": "/a~1b"}, {"op": "remove", "path": "/m~0n"}]"#,
+                r#"{}"#,
+            ),
+            (
+                r#"1"#,
+                r#"[{"op": "replace", "path": "", "value": 2}]"#,
+                r#"2"#,
+            ),
+        ];
+        for (doc, patch, expected) in cases {
+            assert_eq!(
+                patched(doc, patch).expect(patch),
+                json(expected),
+                "{}",
+                patch
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let cases = [
+            (r#"{}"#, r#"[{"op": "remove", "path": "/a"}]"#),
+            (r#"[1]"#, r#"[{"op": "add", "path": "/2", "value": 1}]"#),
+            (r#"[1]"#, r#"[{"op": "remove", "path": "/01"}]"#),
+            (
+                r#"{"a": 1}"#,
+                r#"[{"op": "test", "path": "/a", "value": 2}]"#,
+            ),
+            (
+                r#"{"a": {}}"#,
+                r#"[{"op": "move", "from": "/a", "path": "/a/b"}]"#,
+            ),
+            (r#"{}"#, r#"[{"op": "add", "path": "a", "value": 1}]"#),
+            (r#"{}"#, r#"[{"op": "frobnicate", "path": "/a"}]"#),
+            (r#"{}"#, r#"{"op": "add"}"#),
+        ];
+        for (doc, patch) in cases {
+            patched(doc, patch).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_apply_is_atomic() {
+        let mut doc = json(r#"{"a": 1}"#);
+        let ops = [
+            Operation::Add {
+                path: "/b".into(),
+                value: Value::Int(2),
+            },
+            Operation::Remove { path: "/c".into() },
+        ];
+        apply(&mut doc, &ops).unwrap_err();
+        assert_eq!(doc, json(r#"{"a": 1}"#));
+    }
+
+    #[test]
+    fn test_generate_roundtrip() {
+        let cases = [
+            (
+                r#"{"a": 1, "b": [1, 2, 3], "c": {"d": null}}"#,
+                r#"{"a": 2, "b": [1, 4], "e/f": true}"#,
+            ),
+            (r#"[1, 2]"#, r#"[1, 2, {"x": []}, 3]"#),
+            (r#"{"a": 1}"#, r#"[1]"#),
+            (r#"{"a": 1}"#, r#"{"a": 1}"#),
+        ];
+        for (from, to) in cases {
+            let (from, to) = (json(from), json(to));
+            let ops = generate(&from, &to);
+            // Make sure the patch survives serialization too
+            let ops = parse_patch(&patch_to_value(&ops)).unwrap();
+            let mut doc = from.clone();
+            apply(&mut doc, &ops).unwrap();
+            assert_eq!(doc, to);
+        }
+        assert!(generate(&json("[1]"), &json("[1]")).is_empty());
+    }
+}
@@ -0,0 +1,42 @@
+use std::{borrow::Cow, fmt};
+
+use super::{parse_json, skip_value, Cursor, Error, Value};
+
+/// Validated JSON text, kept verbatim instead of being parsed into a `Value`.
+/// Two raw values are equal if their text is, whatever the formatting.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawValue<'a>(pub(super) Cow<'a, str>);
+
+impl<'a> RawValue<'a> {
+    /// Checks that `text` holds exactly one JSON value
+    pub fn from_json(text: &'a str) -> Result<Self, Error> {
+        let buf = text.as_bytes();
+        let mut cursor = Cursor::default();
+        skip_value(buf, &mut cursor)?;
+        if cursor.next_token(buf) != 0 || cursor.pos != buf.len() {
+            return Err(Error {
+                pos: cursor.pos,
+                msg: "Trailing data after raw value",
+            });
+        }
+        Ok(RawValue(Cow::Borrowed(text.trim())))
+    }
+
+    pub fn get(&self) -> &str {
+        &self.0
+    }
+
+    pub fn parse(&self) -> Result<Value<'_>, Error> {
+        parse_json(self.0.as_bytes())
+    }
+
+    pub fn into_owned(self) -> RawValue<'static> {
+        RawValue(Cow::Owned(self.0.into_owned()))
+    }
+}
+
+impl fmt::Display for RawValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
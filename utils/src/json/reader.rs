@@ -0,0 +1,254 @@
+use std::{
+    fmt,
+    io::{self, BufRead},
+};
+
+use super::{parse_json, parse_json_with, Error, KeyInterner, ParserOptions, Value};
+
+/// Longest document read by `parse_json_from_reader`, past which it errors
+/// with `InvalidData` rather than buffering more
+pub const MAX_DOCUMENT_LEN: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    Json(Error),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "io error: {}", e),
+            ReadError::Json(e) => write!(f, "json error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+impl From<Error> for ReadError {
+    fn from(e: Error) -> Self {
+        ReadError::Json(e)
+    }
+}
+
+/// Tracks just enough of the JSON grammar to find where a document ends
+#[derive(Debug, Default)]
+struct Boundary {
+    depth: usize,
+    started: bool,
+    in_str: bool,
+    escape: bool,
+    scalar: bool,
+}
+
+impl Boundary {
+    /// Returns the number of bytes of `chunk` belonging to the document if it ends in it
+    fn feed(&mut self, chunk: &[u8]) -> Option<usize> {
+        for (i, &c) in chunk.iter().enumerate() {
+            if self.in_str {
+                if self.escape {
+                    self.escape = false;
+                } else if c == b'\\' {
+                    self.escape = true;
+                } else if c == b'"' {
+                    self.in_str = false;
+                    if self.depth == 0 {
+                        return Some(i + 1);
+                    }
+                }
+                continue;
+            }
+            if self.scalar {
+                if c.is_ascii_alphanumeric() || matches!(c, b'.' | b'-' | b'+') {
+                    continue;
+                }
+                return Some(i);
+            }
+            match c {
+                b' ' | b'\n' | b'\t' | b'\r' => {}
+                b'"' => {
+                    self.in_str = true;
+                    self.started = true;
+                }
+                b'{' | b'[' => {
+                    self.depth += 1;
+                    self.started = true;
+                }
+                b'}' | b']' => {
+                    self.started = true;
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        return Some(i + 1);
+                    }
+                }
+                _ if self.depth == 0 => {
+                    self.scalar = true;
+                    self.started = true;
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// Reads and parses exactly one JSON document from `reader`.
+/// Bytes following the document are left in the reader's buffer, so this can
+/// be called repeatedly on a stream of concatenated documents.
+/// Returns `None` if the reader reached EOF before a document started.
+pub fn parse_json_from_reader<R: BufRead>(
+    reader: &mut R,
+) -> Result<Option<Value<'static>>, ReadError> {
+    match read_document(reader, MAX_DOCUMENT_LEN)? {
+        Some(doc) => Ok(Some(parse_json(&doc)?.into_owned())),
+        None => Ok(None),
+    }
+}
+
+/// Like `parse_json_from_reader`, with documents longer than `opts.max_size`
+/// rejected instead of `MAX_DOCUMENT_LEN`
+pub fn parse_json_from_reader_with<R: BufRead>(
+    reader: &mut R,
+    opts: &ParserOptions,
+) -> Result<Option<Value<'static>>, ReadError> {
+    match read_document(reader, opts.max_size)? {
+        Some(doc) => Ok(Some(parse_json_with(&doc, opts)?.into_owned())),
+        None => Ok(None),
+    }
+}
+
+/// Like `parse_json_from_reader`, with object keys taken from `interner`
+pub fn parse_json_from_reader_interned<R: BufRead>(
+    reader: &mut R,
+    interner: &KeyInterner,
+) -> Result<Option<Value<'static>>, ReadError> {
+    match read_document(reader, MAX_DOCUMENT_LEN)? {
+        Some(doc) => Ok(Some(parse_json(&doc)?.into_owned_interned(interner))),
+        None => Ok(None),
+    }
+}
+
+fn read_document<R: BufRead>(reader: &mut R, max_len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut doc = Vec::new();
+    let mut boundary = Boundary::default();
+    loop {
+        let chunk = match reader.fill_buf() {
+            Ok(chunk) => chunk,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if chunk.is_empty() {
+            break;
+        }
+        let (used, done) = match boundary.feed(chunk) {
+            Some(end) => (end, true),
+            None => (chunk.len(), false),
+        };
+        if used > max_len - doc.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "document too long",
+            ));
+        }
+        doc.extend_from_slice(&chunk[..used]);
+        reader.consume(used);
+        if done {
+            break;
+        }
+    }
+    Ok(boundary.started.then_some(doc))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        borrow::Cow,
+        io::{self, BufReader, Read},
+    };
+
+    use super::{
+        parse_json_from_reader, parse_json_from_reader_interned, parse_json_from_reader_with,
+        ReadError, MAX_DOCUMENT_LEN,
+    };
+    use crate::json::{KeyInterner, ParserOptions, Value};
+
+    #[test]
+    fn test_read_concatenated_documents() {
+        let input = b"{\"a\": [1, \"]\"]}[2]\n\"x\\\"y\" 12 true\n  ";
+        // A tiny buffer forces documents to be split across reads
+        let mut reader = BufReader::with_capacity(3, &input[..]);
+        let mut docs = Vec::new();
+        while let Some(doc) = parse_json_from_reader(&mut reader).expect("parsing failed") {
+            docs.push(doc);
+        }
+        assert_eq!(
+            docs,
+            vec![
+                Value::Object(
+                    [(
+                        "a",
+                        Value::from(vec![Value::Int(1), Value::String("]".into())])
+                    )]
+                    .into_iter()
+                    .collect()
+                ),
+                Value::from(vec![Value::Int(2)]),
+                Value::String("x\"y".into()),
+                Value::Int(12),
+                Value::Bool(true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_leaves_trailing_bytes() {
+        let mut reader = &b"[1, 2] rest"[..];
+        parse_json_from_reader(&mut reader).unwrap().unwrap();
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, " rest");
+    }
+
+    #[test]
+    fn test_read_interned() {
+        let interner = KeyInterner::global();
+        let mut reader = &b"{\"id\": 1} {\"id\": 2}"[..];
+        while let Some(doc) = parse_json_from_reader_interned(&mut reader, interner).unwrap() {
+            let (key, _) = doc.object().unwrap().iter().next().unwrap();
+            assert!(matches!(key, Cow::Borrowed("id")));
+        }
+    }
+
+    #[test]
+    fn test_read_truncated_document() {
+        let mut reader = &b"{\"a\": [1"[..];
+        parse_json_from_reader(&mut reader).unwrap_err();
+    }
+
+    #[test]
+    fn test_read_too_long_document() {
+        let opts = ParserOptions {
+            max_size: 8,
+            ..Default::default()
+        };
+        let mut reader = &b"[1, 2]  [1, 2, 3, 4]"[..];
+        let doc = parse_json_from_reader_with(&mut reader, &opts).unwrap();
+        assert_eq!(doc.unwrap().array().unwrap().len(), 2);
+        match parse_json_from_reader_with(&mut reader, &opts) {
+            Err(ReadError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            res => panic!("unexpected {:?}", res),
+        }
+
+        // Unterminated input isn't buffered forever either
+        let unterminated = format!("\"{}", "x".repeat(MAX_DOCUMENT_LEN));
+        let mut reader = BufReader::new(unterminated.as_bytes());
+        assert!(parse_json_from_reader(&mut reader).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! Fast paths for the byte scanning loops of the parser.
+//! SSE2 is used when the CPU supports it, otherwise the portable versions work
+//! a word at a time where possible.
+
+fn is_whitespace(c: u8) -> bool {
+    matches!(c, b' ' | b'\n' | b'\t' | b'\r')
+}
+
+/// Index of the first `"` or `\` in `buf` at or after `from`
+pub fn find_quote_or_backslash(buf: &[u8], from: usize) -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("sse2") {
+        // SAFETY: sse2 support was just checked
+        return unsafe { sse2::find_quote_or_backslash(buf, from) };
+    }
+    find_quote_or_backslash_portable(buf, from)
+}
+
+/// Index of the first non whitespace byte in `buf` at or after `from`, or `buf.len()`
+pub fn skip_whitespace(buf: &[u8], from: usize) -> usize {
+    // Most tokens are separated by at most a space, don't bother with vectors then
+    match buf.get(from..from + 2) {
+        Some([a, b]) if !is_whitespace(*a) || !is_whitespace(*b) => {
+            return if is_whitespace(*a) { from + 1 } else { from };
+        }
+        _ => {}
+    }
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("sse2") {
+        // SAFETY: sse2 support was just checked
+        return unsafe { sse2::skip_whitespace(buf, from) };
+    }
+    skip_whitespace_portable(buf, from)
+}
+
+const LO: u64 = 0x0101_0101_0101_0101;
+const HI: u64 = 0x8080_8080_8080_8080;
+
+/// High bit set in each byte of `word` equal to `byte`. Bits above the first
+/// match may be wrong, which is fine since only the lowest one is used.
+fn match_byte(word: u64, byte: u8) -> u64 {
+    let v = word ^ (LO * byte as u64);
+    v.wrapping_sub(LO) & !v & HI
+}
+
+pub fn find_quote_or_backslash_portable(buf: &[u8], from: usize) -> Option<usize> {
+    let mut pos = from;
+    while let Some(chunk) = buf.get(pos..pos + 8) {
+        let word = u64::from_le_bytes(chunk.try_into().unwrap());
+        let found = match_byte(word, b'"') | match_byte(word, b'\\');
+        if found != 0 {
+            return Some(pos + found.trailing_zeros() as usize / 8);
+        }
+        pos += 8;
+    }
+    buf.get(pos..)?
+        .iter()
+        .position(|&c| c == b'"' || c == b'\\')
+        .map(|i| pos + i)
+}
+
+pub fn skip_whitespace_portable(buf: &[u8], from: usize) -> usize {
+    match buf.get(from..) {
+        Some(rest) => from + rest.iter().take_while(|&&c| is_whitespace(c)).count(),
+        None => buf.len(),
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use std::arch::x86_64::{
+        __m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_or_si128, _mm_set1_epi8,
+    };
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn find_quote_or_backslash(buf: &[u8], from: usize) -> Option<usize> {
+        let quote = _mm_set1_epi8(b'"' as i8);
+        let backslash = _mm_set1_epi8(b'\\' as i8);
+        let mut pos = from;
+        while pos + 16 <= buf.len() {
+            let chunk = _mm_loadu_si128(buf.as_ptr().add(pos) as *const __m128i);
+            let found = _mm_or_si128(
+                _mm_cmpeq_epi8(chunk, quote),
+                _mm_cmpeq_epi8(chunk, backslash),
+            );
+            let mask = _mm_movemask_epi8(found);
+            if mask != 0 {
+                return Some(pos + mask.trailing_zeros() as usize);
+            }
+            pos += 16;
+        }
+        super::find_quote_or_backslash_portable(buf, pos)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn skip_whitespace(buf: &[u8], from: usize) -> usize {
+        let space = _mm_set1_epi8(b' ' as i8);
+        let newline = _mm_set1_epi8(b'\n' as i8);
+        let tab = _mm_set1_epi8(b'\t' as i8);
+        let carriage_return = _mm_set1_epi8(b'\r' as i8);
+        let mut pos = from;
+        while pos + 16 <= buf.len() {
+            let chunk = _mm_loadu_si128(buf.as_ptr().add(pos) as *const __m128i);
+            let ws = _mm_or_si128(
+                _mm_or_si128(_mm_cmpeq_epi8(chunk, space), _mm_cmpeq_epi8(chunk, newline)),
+                _mm_or_si128(
+                    _mm_cmpeq_epi8(chunk, tab),
+                    _mm_cmpeq_epi8(chunk, carriage_return),
+                ),
+            );
+            let not_ws = !_mm_movemask_epi8(ws) & 0xFFFF;
+            if not_ws != 0 {
+                return pos + not_ws.trailing_zeros() as usize;
+            }
+            pos += 16;
+        }
+        super::skip_whitespace_portable(buf, pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        find_quote_or_backslash, find_quote_or_backslash_portable, skip_whitespace,
+        skip_whitespace_portable,
+    };
+
+    #[test]
+    fn test_find_quote_or_backslash() {
+        for len in 0..40 {
+            for special in [b'"', b'\\'] {
+                for at in 0..len {
+                    let mut buf = vec![b'a'; len];
+                    buf[at] = special;
+                    // Bytes that only differ from the targets by a bit or a borrow
+                    if at > 0 {
+                        buf[at - 1] = b'"' + 1;
+                    }
+                    for from in 0..=at {
+                        assert_eq!(find_quote_or_backslash(&buf, from), Some(at));
+                        assert_eq!(find_quote_or_backslash_portable(&buf, from), Some(at));
+                    }
+                    assert_eq!(find_quote_or_backslash(&buf, at + 1), None);
+                }
+            }
+            assert_eq!(find_quote_or_backslash(&vec![0xFF; len], 0), None);
+        }
+        assert_eq!(find_quote_or_backslash(b"ab", 5), None);
+    }
+
+    #[test]
+    fn test_skip_whitespace() {
+        for len in 0..40 {
+            let mut buf: Vec<u8> = b" \n\t\r".iter().cycle().take(len).copied().collect();
+            assert_eq!(skip_whitespace(&buf, 0), len);
+            assert_eq!(skip_whitespace_portable(&buf, 0), len);
+            buf.push(b'{');
+            for from in 0..=len {
+                assert_eq!(skip_whitespace(&buf, from), len);
+                assert_eq!(skip_whitespace_portable(&buf, from), len);
+            }
+        }
+        assert_eq!(skip_whitespace(b"  ", 5), 2);
+    }
+}
@@ -0,0 +1,257 @@
+use std::fmt;
+
+use super::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    String,
+    Bytes,
+    Int,
+    Float,
+    /// Either an int or a float
+    Number,
+    Bool,
+    Null,
+    Array,
+    Object,
+    Raw,
+    Any,
+}
+
+impl Kind {
+    fn matches(self, val: &Value) -> bool {
+        matches!(
+            (self, val),
+            (Kind::String, Value::String(_))
+                | (Kind::Bytes, Value::Bytes(_))
+                | (Kind::Int, Value::Int(_))
+                | (Kind::Float, Value::Float(_))
+                | (Kind::Number, Value::Int(_) | Value::Float(_))
+                | (Kind::Bool, Value::Bool(_))
+                | (Kind::Null, Value::Null(()))
+                | (Kind::Array, Value::Array(_))
+                | (Kind::Object, Value::Object(_))
+                | (Kind::Raw, Value::Raw(_))
+                | (Kind::Any, _)
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// Dot separated path of the offending field
+    pub path: String,
+    pub msg: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.msg)
+    }
+}
+
+#[derive(Debug)]
+struct Field {
+    name: &'static str,
+    kind: Kind,
+    required: bool,
+    range: Option<(f64, f64)>,
+    allowed: Option<&'static [&'static str]>,
+    nested: Option<Schema>,
+}
+
+/// Declarative description of an object. Constraints like `range` and `one_of`
+/// apply to the last declared field.
+#[derive(Debug, Default)]
+pub struct Schema {
+    fields: Vec<Field>,
+    deny_unknown: bool,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn field(mut self, name: &'static str, kind: Kind, required: bool) -> Self {
+        self.fields.push(Field {
+            name,
+            kind,
+            required,
+            range: None,
+            allowed: None,
+            nested: None,
+        });
+        self
+    }
+
+    fn last(&mut self) -> &mut Field {
+        self.fields
+            .last_mut()
+            .expect("constraint declared before any field")
+    }
+
+    pub fn required(self, name: &'static str, kind: Kind) -> Self {
+        self.field(name, kind, true)
+    }
+
+    pub fn optional(self, name: &'static str, kind: Kind) -> Self {
+        self.field(name, kind, false)
+    }
+
+    /// Inclusive numeric bounds
+    pub fn range(mut self, min: f64, max: f64) -> Self {
+        self.last().range = Some((min, max));
+        self
+    }
+
+    pub fn one_of(mut self, allowed: &'static [&'static str]) -> Self {
+        self.last().allowed = Some(allowed);
+        self
+    }
+
+    pub fn nested(mut self, schema: Schema) -> Self {
+        self.last().nested = Some(schema);
+        self
+    }
+
+    /// Reject keys that weren't declared
+    pub fn deny_unknown(mut self) -> Self {
+        self.deny_unknown = true;
+        self
+    }
+
+    /// Returns every violation found rather than stopping at the first one
+    pub fn validate(&self, val: &Value) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        self.validate_at(val, "", &mut violations);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn validate_at(&self, val: &Value, prefix: &str, violations: &mut Vec<Violation>) {
+        let Value::Object(obj) = val else {
+            let path = if prefix.is_empty() { "<root>" } else { prefix };
+            return push(violations, path.to_owned(), "expected an object".to_owned());
+        };
+        let path_of = |name: &str| {
+            if prefix.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{}.{}", prefix, name)
+            }
+        };
+        if self.deny_unknown {
+            let mut unknown: Vec<_> = obj
+                .keys()
+                .filter(|k| self.fields.iter().all(|f| f.name != k.as_ref()))
+                .collect();
+            unknown.sort();
+            for key in unknown {
+                push(violations, path_of(key), "unknown field".to_owned());
+            }
+        }
+        for field in &self.fields {
+            let path = path_of(field.name);
+            let Some(val) = obj.get(field.name) else {
+                if field.required {
+                    push(violations, path, "missing required field".to_owned());
+                }
+                continue;
+            };
+            if !field.kind.matches(val) {
+                push(violations, path, format!("expected {:?}", field.kind));
+                continue;
+            }
+            if let Some((min, max)) = field.range {
+                let n = match val {
+                    Value::Int(v) => Some(*v as f64),
+                    Value::Float(v) => Some(*v),
+                    _ => None,
+                };
+                match n {
+                    Some(n) if (min..=max).contains(&n) => {}
+                    _ => push(
+                        violations,
+                        path.clone(),
+                        format!("not in range {}..={}", min, max),
+                    ),
+                }
+            }
+            if let Some(allowed) = field.allowed {
+                match val {
+                    Value::String(s) if allowed.contains(&s.as_ref()) => {}
+                    _ => push(
+                        violations,
+                        path.clone(),
+                        format!("not one of {:?}", allowed),
+                    ),
+                }
+            }
+            if let Some(nested) = &field.nested {
+                nested.validate_at(val, &path, violations);
+            }
+        }
+    }
+}
+
+fn push(violations: &mut Vec<Violation>, path: String, msg: String) {
+    violations.push(Violation { path, msg })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Kind, Schema};
+    use crate::json::parse_json;
+
+    fn schema() -> Schema {
+        Schema::new()
+            .required("method", Kind::String)
+            .one_of(&["isPrime"])
+            .required("number", Kind::Number)
+            .range(0.0, 100.0)
+            .optional("opts", Kind::Object)
+            .nested(Schema::new().required("verbose", Kind::Bool).deny_unknown())
+    }
+
+    #[test]
+    fn test_valid() {
+        let val = parse_json(br#"{"method": "isPrime", "number": 7.5, "extra": 1}"#).unwrap();
+        schema().validate(&val).unwrap();
+        let val = parse_json(br#"{"method": "isPrime", "number": 7, "opts": {"verbose": true}}"#)
+            .unwrap();
+        schema().validate(&val).unwrap();
+    }
+
+    #[test]
+    fn test_all_violations_reported() {
+        let val =
+            parse_json(br#"{"method": "isComposite", "number": 700, "opts": {"v": 1}}"#).unwrap();
+        let violations: Vec<String> = schema()
+            .validate(&val)
+            .unwrap_err()
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        assert_eq!(
+            violations,
+            vec![
+                "method: not one of [\"isPrime\"]",
+                "number: not in range 0..=100",
+                "opts.v: unknown field",
+                "opts.verbose: missing required field",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wrong_types() {
+        let val = parse_json(br#"{"method": 1, "number": "7"}"#).unwrap();
+        assert_eq!(schema().validate(&val).unwrap_err().len(), 2);
+        let val = parse_json(b"[]").unwrap();
+        assert_eq!(schema().validate(&val).unwrap_err()[0].path, "<root>");
+    }
+}
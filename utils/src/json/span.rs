@@ -0,0 +1,63 @@
+use std::{borrow::Cow, collections::HashMap, fmt, ops::Range};
+
+/// Byte range of a value in the parsed buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// Mirrors the structure of a parsed `Value`, see `parse_json_spanned`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpanTree<'a> {
+    Leaf(Span),
+    Array(Span, Vec<SpanTree<'a>>),
+    Object(Span, HashMap<Cow<'a, str>, SpanTree<'a>>),
+}
+
+impl<'a> SpanTree<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            SpanTree::Leaf(span) | SpanTree::Array(span, _) | SpanTree::Object(span, _) => *span,
+        }
+    }
+
+    pub(super) fn set_end(&mut self, end: usize) {
+        match self {
+            SpanTree::Leaf(span) | SpanTree::Array(span, _) | SpanTree::Object(span, _) => {
+                span.end = end
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&SpanTree<'a>> {
+        match self {
+            SpanTree::Object(_, children) => children.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn index(&self, idx: usize) -> Option<&SpanTree<'a>> {
+        match self {
+            SpanTree::Array(_, children) => children.get(idx),
+            _ => None,
+        }
+    }
+
+    /// Same traversal as `Value::get_path`
+    pub fn get_path(&self, path: &str) -> Option<&SpanTree<'a>> {
+        path.split('.').try_fold(self, |tree, key| tree.get(key))
+    }
+}
@@ -0,0 +1,157 @@
+use std::io::{self, Write};
+
+use super::{serialize_json, serialize_str, Value};
+
+#[derive(Debug)]
+enum Frame {
+    Array { first: bool },
+    Object { first: bool, has_key: bool },
+}
+
+/// Writes a JSON document incrementally, so big arrays and objects can be
+/// produced without building the whole `Value` first.
+#[derive(Debug)]
+pub struct JsonWriter<W: Write> {
+    out: W,
+    stack: Vec<Frame>,
+    scratch: Vec<u8>,
+}
+
+fn misuse(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            stack: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Writes the separator expected before a value at the current position
+    fn before_value(&mut self) -> io::Result<()> {
+        match self.stack.last_mut() {
+            None => Ok(()),
+            Some(Frame::Array { first }) => {
+                if !*first {
+                    self.out.write_all(b", ")?;
+                }
+                *first = false;
+                Ok(())
+            }
+            Some(Frame::Object { has_key, .. }) => {
+                if !*has_key {
+                    return Err(misuse("object value written without a key"));
+                }
+                *has_key = false;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn begin_array(&mut self) -> io::Result<()> {
+        self.before_value()?;
+        self.out.write_all(b"[")?;
+        self.stack.push(Frame::Array { first: true });
+        Ok(())
+    }
+
+    pub fn begin_object(&mut self) -> io::Result<()> {
+        self.before_value()?;
+        self.out.write_all(b"{")?;
+        self.stack.push(Frame::Object {
+            first: true,
+            has_key: false,
+        });
+        Ok(())
+    }
+
+    pub fn key(&mut self, key: &str) -> io::Result<()> {
+        let Some(Frame::Object { first, has_key }) = self.stack.last_mut() else {
+            return Err(misuse("key written outside of an object"));
+        };
+        if *has_key {
+            return Err(misuse("key written twice"));
+        }
+        self.scratch.clear();
+        if !*first {
+            self.scratch.extend_from_slice(b", ");
+        }
+        serialize_str(key, &mut self.scratch);
+        self.scratch.extend_from_slice(b": ");
+        *first = false;
+        *has_key = true;
+        self.out.write_all(&self.scratch)
+    }
+
+    pub fn value(&mut self, val: &Value) -> io::Result<()> {
+        self.before_value()?;
+        self.scratch.clear();
+        serialize_json(val, &mut self.scratch);
+        self.out.write_all(&self.scratch)
+    }
+
+    /// Closes the innermost array or object
+    pub fn end(&mut self) -> io::Result<()> {
+        match self.stack.pop() {
+            Some(Frame::Array { .. }) => self.out.write_all(b"]"),
+            Some(Frame::Object { has_key: false, .. }) => self.out.write_all(b"}"),
+            Some(Frame::Object { has_key: true, .. }) => Err(misuse("key written without a value")),
+            None => Err(misuse("no open array or object")),
+        }
+    }
+
+    /// Flushes and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.stack.is_empty() {
+            return Err(misuse("unclosed array or object"));
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::JsonWriter;
+    use crate::json::{parse_json, Value};
+
+    #[test]
+    fn test_write_nested() {
+        let mut w = JsonWriter::new(Vec::new());
+        w.begin_object().unwrap();
+        w.key("jobs").unwrap();
+        w.begin_array().unwrap();
+        for i in 0..3 {
+            w.value(&Value::Int(i)).unwrap();
+        }
+        w.begin_object().unwrap();
+        w.end().unwrap();
+        w.end().unwrap();
+        w.key("status").unwrap();
+        w.value(&"ok".into()).unwrap();
+        w.end().unwrap();
+        let out = w.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "{\"jobs\": [0, 1, 2, {}], \"status\": \"ok\"}"
+        );
+        parse_json(&out).unwrap();
+    }
+
+    #[test]
+    fn test_write_misuse() {
+        let mut w = JsonWriter::new(Vec::new());
+        w.begin_object().unwrap();
+        w.value(&Value::Int(1)).unwrap_err();
+        w.key("a").unwrap();
+        w.key("b").unwrap_err();
+
+        let mut w = JsonWriter::new(Vec::new());
+        w.begin_array().unwrap();
+        w.key("a").unwrap_err();
+        w.finish().unwrap_err();
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::Hash,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::hub::{Hub, SlowConsumer, Subscriber};
+
+/// Changes queued for a watcher before the oldest ones are dropped
+pub const WATCH_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+struct Slot<V> {
+    /// `None` once removed, the revision is kept so it keeps increasing
+    value: Option<V>,
+    revision: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change<K, V> {
+    pub key: K,
+    /// `None` if the key was removed
+    pub value: Option<V>,
+    pub revision: u64,
+}
+
+/// Concurrent map counting the revisions of each key, starting at 1 for the
+/// first insert. Watchers are notified of every change.
+#[derive(Debug)]
+pub struct Store<K, V> {
+    map: RwLock<HashMap<K, Slot<V>>>,
+    watchers: Hub<Change<K, V>>,
+}
+
+impl<K, V> Store<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Self {
+            map: RwLock::default(),
+            watchers: Hub::new(WATCH_CAPACITY, SlowConsumer::DropOldest),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<K, Slot<V>>> {
+        self.map.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<K, Slot<V>>> {
+        self.map.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.read().get(key)?.value.clone()
+    }
+
+    /// Value and revision of a key
+    pub fn get_versioned<Q>(&self, key: &Q) -> Option<(V, u64)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let map = self.read();
+        let slot = map.get(key)?;
+        Some((slot.value.clone()?, slot.revision))
+    }
+
+    /// Revision of a key, 0 if it was never set
+    pub fn revision<Q>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.read().get(key).map_or(0, |s| s.revision)
+    }
+
+    /// Sets a key, returning its new revision
+    pub fn insert(&self, key: K, value: V) -> u64 {
+        self.set(&mut self.write(), key, Some(value))
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let mut map = self.write();
+        let old = map.get(key)?.value.clone()?;
+        self.set(&mut map, key.clone(), None);
+        Some(old)
+    }
+
+    /// Sets a key only if its revision is still `revision`, 0 for a key
+    /// never set. Returns the new revision, or the current one on conflict.
+    pub fn compare_and_set(&self, key: K, revision: u64, value: V) -> Result<u64, u64> {
+        let mut map = self.write();
+        let current = map.get(&key).map_or(0, |s| s.revision);
+        if current != revision {
+            return Err(current);
+        }
+        Ok(self.set(&mut map, key, Some(value)))
+    }
+
+    fn set(&self, map: &mut HashMap<K, Slot<V>>, key: K, value: Option<V>) -> u64 {
+        let slot = map.entry(key.clone()).or_insert(Slot {
+            value: None,
+            revision: 0,
+        });
+        slot.revision += 1;
+        slot.value = value.clone();
+        let revision = slot.revision;
+        // Published with the lock held so watchers see the changes in order
+        if self.watchers.subscribers() > 0 {
+            self.watchers.publish(Change {
+                key,
+                value,
+                revision,
+            });
+        }
+        revision
+    }
+
+    /// Number of keys set
+    pub fn len(&self) -> usize {
+        self.read().values().filter(|s| s.value.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Receives the changes made from now on
+    pub fn watch(&self) -> Subscriber<Change<K, V>> {
+        self.watchers.subscribe()
+    }
+}
+
+impl<K, V> Default for Store<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Change, Store};
+
+    #[test]
+    fn test_revisions() {
+        let store = Store::new();
+        assert_eq!(store.get("a"), None);
+        assert_eq!(store.revision("a"), 0);
+        assert_eq!(store.insert("a".to_owned(), 1), 1);
+        assert_eq!(store.insert("a".to_owned(), 2), 2);
+        assert_eq!(store.get_versioned("a"), Some((2, 2)));
+        assert_eq!(store.compare_and_set("a".to_owned(), 1, 3), Err(2));
+        assert_eq!(store.compare_and_set("a".to_owned(), 2, 3), Ok(3));
+        assert_eq!(store.compare_and_set("b".to_owned(), 0, 10), Ok(1));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.remove(&"a".to_owned()), Some(3));
+        assert_eq!(store.remove(&"a".to_owned()), None);
+        assert_eq!(store.get("a"), None);
+        assert_eq!(store.revision("a"), 4);
+        assert_eq!(store.insert("a".to_owned(), 5), 5);
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_watch() {
+        let store = Store::new();
+        store.insert("before", 0);
+        let watcher = store.watch();
+        store.insert("k", 1);
+        store.remove(&"k");
+        let changes: Vec<_> = std::iter::from_fn(|| watcher.try_recv()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                Change {
+                    key: "k",
+                    value: Some(1),
+                    revision: 1
+                },
+                Change {
+                    key: "k",
+                    value: None,
+                    revision: 2
+                },
+            ]
+        );
+    }
+}
@@ -1,5 +1,54 @@
+pub mod bufpool;
+pub mod cancel;
+pub mod channel;
+pub mod checksum;
+pub mod client;
+pub mod clock;
+pub mod codec;
+pub mod deadline;
+pub mod dns;
+pub mod framing;
+pub mod heartbeat;
+pub mod hexdump;
+pub mod http;
+pub mod hub;
+pub mod id;
+pub mod intern;
+pub mod isl;
+pub mod kv;
 pub mod logging;
+pub mod lrcp;
+pub mod lru;
+pub mod memory;
+pub mod metrics;
+pub mod mux;
+pub mod packet;
+pub mod policy;
+pub mod protocol;
+pub mod proxy;
+pub mod ratelimit;
+#[cfg(unix)]
+pub mod reactor;
+pub mod retransmit;
+pub mod retry;
+pub mod room;
+pub mod scheduler;
 pub mod server;
+pub mod session;
+pub mod slab;
+pub mod speed;
+pub mod task;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod timer;
+pub mod timeseries;
+pub mod tokenize;
+pub mod udp;
+pub mod vcs;
+pub mod websocket;
+pub mod wire;
+pub mod workqueue;
 pub mod json;
 
+pub use bufpool::BufPool;
 pub use server::Server;
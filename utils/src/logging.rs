@@ -1,21 +1,1008 @@
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::hash_map::RandomState,
+    env, fmt,
+    hash::{BuildHasher, Hasher},
+    io::{self, IsTerminal},
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Mutex, Once, OnceLock, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::json::{self, Map, RawValue, Value};
+
+mod history;
+mod rotate;
+
+pub use history::{recent, set_history, Entry, DEFAULT_HISTORY_CAPACITY, DEFAULT_HISTORY_LEVEL};
+pub use rotate::RotatingFile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub const ALL: [Level; 5] = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    /// Case insensitive
+    pub fn from_name(name: &str) -> Option<Level> {
+        Level::ALL
+            .into_iter()
+            .find(|l| l.as_str().eq_ignore_ascii_case(name.trim()))
+    }
+
+    fn from_u8(l: u8) -> Option<Level> {
+        Level::ALL.into_iter().find(|&level| level as u8 == l)
+    }
+}
+
+impl Level {
+    /// ANSI escape sequence used for the level tag
+    fn color(self) -> &'static str {
+        match self {
+            Level::Error => "\x1b[31m",
+            Level::Warn => "\x1b[33m",
+            Level::Info => "\x1b[32m",
+            Level::Debug => "\x1b[34m",
+            Level::Trace => "\x1b[2m",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub const DEFAULT_LEVEL: Level = Level::Info;
+
+/// 0 until read from the environment
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(0);
+
+/// Per module levels, overriding the max level
+static TARGETS: RwLock<Vec<(String, Level)>> = RwLock::new(Vec::new());
+static HAS_TARGETS: AtomicBool = AtomicBool::new(false);
+static FILTERS_FROM_ENV: Once = Once::new();
+
+/// Most verbose level logged, taken from the `LOG_LEVEL` env var, or a
+/// bare level in `LOG`, unless `set_max_level` was called
+pub fn max_level() -> Level {
+    if let Some(level) = Level::from_u8(MAX_LEVEL.load(Ordering::Relaxed)) {
+        return level;
+    }
+    FILTERS_FROM_ENV.call_once(filters_from_env);
+    Level::from_u8(MAX_LEVEL.load(Ordering::Relaxed)).unwrap()
+}
+
+fn filters_from_env() {
+    let mut level = match env::var("LOG_LEVEL") {
+        Ok(name) => Level::from_name(&name).unwrap_or_else(|| {
+            eprintln!("unknown LOG_LEVEL {:?}, using {}", name, DEFAULT_LEVEL);
+            DEFAULT_LEVEL
+        }),
+        Err(_) => DEFAULT_LEVEL,
+    };
+    if let Ok(spec) = env::var("LOG") {
+        match Filters::parse(&spec) {
+            Ok(filters) => {
+                level = filters.default.unwrap_or(level);
+                set_targets(filters.targets);
+            }
+            Err(e) => eprintln!("ignoring LOG: {}", e),
+        }
+    }
+    // Don't override a concurrent set_max_level
+    let _ = MAX_LEVEL.compare_exchange(0, level as u8, Ordering::Relaxed, Ordering::Relaxed);
+}
+
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+fn set_targets(targets: Vec<(String, Level)>) {
+    HAS_TARGETS.store(!targets.is_empty(), Ordering::Relaxed);
+    *TARGETS.write().unwrap_or_else(|e| e.into_inner()) = targets;
+}
+
+#[derive(Debug)]
+pub struct FilterError {
+    pub directive: String,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid log filter directive {:?}", self.directive)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+/// Parsed form of a `LOG` spec like `info,utils::server=debug,p06=trace`.
+/// A bare level sets the max level, `prefix=level` sets the level of the
+/// modules whose path starts with `prefix`, the longest prefix winning.
+#[derive(Debug, Default, PartialEq)]
+pub struct Filters {
+    pub default: Option<Level>,
+    pub targets: Vec<(String, Level)>,
+}
+
+impl Filters {
+    pub fn parse(spec: &str) -> Result<Self, FilterError> {
+        let mut filters = Filters::default();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let err = || FilterError {
+                directive: directive.to_owned(),
+            };
+            match directive.split_once('=') {
+                Some((prefix, level)) => {
+                    let level = Level::from_name(level).ok_or_else(err)?;
+                    filters.targets.push((prefix.trim().to_owned(), level));
+                }
+                None => filters.default = Some(Level::from_name(directive).ok_or_else(err)?),
+            }
+        }
+        // Longest prefixes first, so the first match is the most specific
+        filters.targets.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        Ok(filters)
+    }
+}
+
+/// Replaces the max level and the module levels, as the `LOG` env var does
+pub fn set_filters(spec: &str) -> Result<(), FilterError> {
+    let filters = Filters::parse(spec)?;
+    FILTERS_FROM_ENV.call_once(filters_from_env);
+    if let Some(level) = filters.default {
+        set_max_level(level);
+    }
+    set_targets(filters.targets);
+    Ok(())
+}
+
+/// Most verbose level logged for records of `module`
+pub fn level_for(module: &str) -> Level {
+    let default = max_level();
+    if !HAS_TARGETS.load(Ordering::Relaxed) {
+        return default;
+    }
+    let targets = TARGETS.read().unwrap_or_else(|e| e.into_inner());
+    targets
+        .iter()
+        .find(|(prefix, _)| module.starts_with(prefix.as_str()))
+        .map_or(default, |(_, level)| *level)
+}
+
+/// Whether records of `module` at `level` are written or kept in the history
+pub fn enabled(level: Level, module: &str) -> bool {
+    level <= level_for(module) || history::wants(level)
+}
+
+/// When a record was emitted: UTC wall clock time, to line up with other
+/// machines' logs, and the monotonic time since the first record, which
+/// doesn't jump when the clock is adjusted
+#[derive(Debug, Clone, Copy)]
+pub struct Timestamp {
+    pub wall: SystemTime,
+    pub uptime: Duration,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        static START: OnceLock<Instant> = OnceLock::new();
+        Self {
+            wall: SystemTime::now(),
+            uptime: START.get_or_init(Instant::now).elapsed(),
+        }
+    }
+}
+
+impl Timestamp {
+    /// `2022-10-03T18:04:05.123Z`
+    pub fn utc(&self) -> String {
+        let since_epoch = self.wall.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs();
+        let (year, month, day) = civil_from_days((secs / 86400) as i64);
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year,
+            month,
+            day,
+            secs / 3600 % 24,
+            secs / 60 % 60,
+            secs % 60,
+            since_epoch.subsec_millis(),
+        )
+    }
+}
+
+/// `2022-10-03T18:04:05.123Z +12.345s`
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} +{}.{:03}s",
+            self.utc(),
+            self.uptime.as_secs(),
+            self.uptime.subsec_millis(),
+        )
+    }
+}
+
+/// Gregorian date of a number of days since 1970-01-01, from
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Value of a `key = value` field, `key = %value` uses `Display`
+#[derive(Clone, Copy)]
+pub enum FieldValue<'a> {
+    Debug(&'a dyn fmt::Debug),
+    Display(&'a dyn fmt::Display),
+}
+
+#[derive(Clone, Copy)]
+pub struct Field<'a> {
+    pub key: &'static str,
+    pub value: FieldValue<'a>,
+}
+
+impl fmt::Display for Field<'_> {
+    /// `key=value`, with the value quoted if it contains spaces, quotes or `=`
+    /// so that lines can be split on whitespace
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value {
+            FieldValue::Debug(v) => write!(f, "{}={:?}", self.key, v),
+            FieldValue::Display(v) => {
+                let v = v.to_string();
+                if v.is_empty() || v.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+                    write!(f, "{}={:?}", self.key, v)
+                } else {
+                    write!(f, "{}={}", self.key, v)
+                }
+            }
+        }
+    }
+}
+
+impl Field<'_> {
+    /// Debug output that happens to be valid JSON, like numbers, is kept as
+    /// is, anything else becomes a string
+    fn to_json(self) -> Value<'static> {
+        match self.value {
+            FieldValue::Debug(v) => {
+                let text = format!("{:?}", v);
+                match RawValue::from_json(&text) {
+                    Ok(raw) => Value::Raw(raw.into_owned()),
+                    Err(_) => Value::String(Cow::Owned(text)),
+                }
+            }
+            FieldValue::Display(v) => Value::String(Cow::Owned(v.to_string())),
+        }
+    }
+}
+
+/// Connection the current thread is handling, attached to its records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    pub conn_id: u64,
+    pub peer: SocketAddr,
+}
+
+thread_local! {
+    static CONTEXT: Cell<Option<Context>> = const { Cell::new(None) };
+}
+
+pub fn context() -> Option<Context> {
+    CONTEXT.with(|c| c.get())
+}
+
+/// Tags records of the current thread with `ctx` until the guard is dropped.
+/// `Server` does it for every connection.
+pub fn set_context(ctx: Context) -> ContextGuard {
+    ContextGuard {
+        previous: CONTEXT.with(|c| c.replace(Some(ctx))),
+    }
+}
+
+#[must_use = "the context is removed when the guard is dropped"]
+pub struct ContextGuard {
+    previous: Option<Context>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        CONTEXT.with(|c| c.set(self.previous));
+    }
+}
+
+pub struct Record<'a> {
+    /// `None` for `log!`, which isn't filtered
+    pub level: Option<Level>,
+    /// Module path of the call site, or target of the log crate records
+    pub module: &'a str,
+    pub timestamp: Timestamp,
+    pub context: Option<Context>,
+    pub args: fmt::Arguments<'a>,
+    pub fields: &'a [Field<'a>],
+}
+
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_text(f, false)
+    }
+}
+
+/// See `Record::colored`
+pub struct Colored<'r, 'a>(&'r Record<'a>);
+
+impl fmt::Display for Colored<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt_text(f, true)
+    }
+}
+
+impl Record<'_> {
+    /// Text format with the level tag in color, for terminals
+    pub fn colored(&self) -> Colored<'_, '_> {
+        Colored(self)
+    }
+
+    fn fmt_text(&self, f: &mut fmt::Formatter<'_>, color: bool) -> fmt::Result {
+        write!(f, "{} ", self.timestamp)?;
+        match self.level {
+            Some(level) if color => write!(f, "{}{}\x1b[0m ", level.color(), level)?,
+            Some(level) => write!(f, "{} ", level)?,
+            None => {}
+        }
+        if !self.module.is_empty() {
+            write!(f, "{} ", self.module)?;
+        }
+        if let Some(ctx) = self.context {
+            write!(f, "[#{} {}] ", ctx.conn_id, ctx.peer)?;
+        }
+        if self.level.is_some() || !self.module.is_empty() || self.context.is_some() {
+            write!(f, "- ")?;
+        }
+        write!(f, "{}", self.args)?;
+        for field in self.fields {
+            write!(f, " {}", field)?;
+        }
+        Ok(())
+    }
+
+    /// A single line JSON object, for log processors
+    pub fn to_json(&self) -> String {
+        let mut obj = Map::new();
+        obj.insert("ts", Value::String(Cow::Owned(self.timestamp.utc())));
+        obj.insert(
+            "uptime_ms",
+            Value::Int(self.timestamp.uptime.as_millis() as i64),
+        );
+        if let Some(level) = self.level {
+            obj.insert("level", Value::from(level.as_str()));
+        }
+        if !self.module.is_empty() {
+            obj.insert("module", Value::from(self.module));
+        }
+        if let Some(ctx) = self.context {
+            obj.insert("conn", Value::Int(ctx.conn_id as i64));
+            obj.insert("peer", Value::String(Cow::Owned(ctx.peer.to_string())));
+        }
+        obj.insert("msg", Value::String(Cow::Owned(self.args.to_string())));
+        if !self.fields.is_empty() {
+            let fields = self.fields.iter().map(|f| (f.key, f.to_json())).collect();
+            obj.insert("fields", Value::Object(fields));
+        }
+        json::to_string(&Value::Object(obj))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text = 1,
+    Json,
+}
+
+/// 0 until read from the environment
+static FORMAT: AtomicU8 = AtomicU8::new(0);
+
+/// Taken from the `LOG_FORMAT` env var, `text` or `json`, unless `set_format`
+/// was called
+pub fn format() -> Format {
+    let from_u8 = |f| {
+        [Format::Text, Format::Json]
+            .into_iter()
+            .find(|&x| x as u8 == f)
+    };
+    if let Some(format) = from_u8(FORMAT.load(Ordering::Relaxed)) {
+        return format;
+    }
+    let format = match env::var("LOG_FORMAT") {
+        Ok(f) if f.eq_ignore_ascii_case("json") => Format::Json,
+        _ => Format::Text,
+    };
+    let _ = FORMAT.compare_exchange(0, format as u8, Ordering::Relaxed, Ordering::Relaxed);
+    from_u8(FORMAT.load(Ordering::Relaxed)).unwrap()
+}
+
+pub fn set_format(format: Format) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+/// Defaults of the `LOG_FILE_MAX_SIZE` and `LOG_FILE_COUNT` env vars
+pub const DEFAULT_FILE_MAX_SIZE: u64 = 16 << 20;
+pub const DEFAULT_FILE_COUNT: usize = 4;
+
+static FILE: Mutex<Option<RotatingFile>> = Mutex::new(None);
+static FILE_FROM_ENV: Once = Once::new();
+
+/// Also writes records to `path`, rotated when it reaches `max_size` bytes,
+/// keeping `max_files` old files. Replaces the file set by the `LOG_FILE`
+/// env var, or a previous call.
+pub fn log_to_file<P: AsRef<Path>>(path: P, max_size: u64, max_files: usize) -> io::Result<()> {
+    // Don't let the env var override this later
+    FILE_FROM_ENV.call_once(|| {});
+    let file = RotatingFile::open(path, max_size, max_files)?;
+    *FILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    Ok(())
+}
+
+fn file_from_env() {
+    let Ok(path) = env::var("LOG_FILE") else {
+        return;
+    };
+    let max_size = env::var("LOG_FILE_MAX_SIZE")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_FILE_MAX_SIZE);
+    let count = env::var("LOG_FILE_COUNT")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_FILE_COUNT);
+    match RotatingFile::open(&path, max_size, count) {
+        Ok(file) => *FILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(file),
+        Err(e) => eprintln!("opening LOG_FILE {}: {}", path, e),
+    }
+}
+
+/// 0 until detected
+static COLOR: AtomicU8 = AtomicU8::new(0);
+
+/// Whether text records written to stderr are colored: when stderr is a
+/// terminal and the `NO_COLOR` env var isn't set, unless `set_color` was called
+pub fn color() -> bool {
+    match COLOR.load(Ordering::Relaxed) {
+        0 => {
+            let color =
+                io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty());
+            let _ =
+                COLOR.compare_exchange(0, color as u8 + 1, Ordering::Relaxed, Ordering::Relaxed);
+            COLOR.load(Ordering::Relaxed) == 2
+        }
+        c => c == 2,
+    }
+}
+
+pub fn set_color(color: bool) {
+    COLOR.store(color as u8 + 1, Ordering::Relaxed);
+}
+
+#[doc(hidden)]
+pub fn write(level: Option<Level>, module: &str, args: fmt::Arguments, fields: &[Field]) {
+    let record = Record {
+        level,
+        module,
+        timestamp: Timestamp::now(),
+        context: context(),
+        args,
+        fields,
+    };
+    if level.is_none_or(history::wants) {
+        history::push(&record);
+    }
+    if level.is_some_and(|l| l > level_for(module)) {
+        return;
+    }
+    let line = match format() {
+        Format::Text => record.to_string(),
+        Format::Json => record.to_json(),
+    };
+    if format() == Format::Text && color() {
+        eprintln!("{}", record.colored());
+    } else {
+        eprintln!("{}", line);
+    }
+    FILE_FROM_ENV.call_once(file_from_env);
+    if let Some(file) = FILE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        if let Err(e) = file.write_line(&line) {
+            eprintln!("writing log file: {}", e);
+        }
+    }
+}
+
+/// Sends records of the `log` crate to `write`, for dependencies using it
+#[cfg(feature = "log")]
+struct LogFacade;
+
+#[cfg(feature = "log")]
+impl LogFacade {
+    fn level(level: log::Level) -> Level {
+        match level {
+            log::Level::Error => Level::Error,
+            log::Level::Warn => Level::Warn,
+            log::Level::Info => Level::Info,
+            log::Level::Debug => Level::Debug,
+            log::Level::Trace => Level::Trace,
+        }
+    }
+}
+
+#[cfg(feature = "log")]
+impl log::Log for LogFacade {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        enabled(Self::level(metadata.level()), metadata.target())
+    }
+
+    fn log(&self, record: &log::Record) {
+        let level = Self::level(record.level());
+        if enabled(level, record.target()) {
+            write(Some(level), record.target(), *record.args(), &[]);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the adapter forwarding the `log` crate's records to this module's
+/// output. Their filtering follows `max_level` at the time of the call.
+#[cfg(feature = "log")]
+pub fn init_log_facade() -> Result<(), log::SetLoggerError> {
+    static FACADE: LogFacade = LogFacade;
+    log::set_logger(&FACADE)?;
+    log::set_max_level(match max_level() {
+        Level::Error => log::LevelFilter::Error,
+        Level::Warn => log::LevelFilter::Warn,
+        Level::Info => log::LevelFilter::Info,
+        Level::Debug => log::LevelFilter::Debug,
+        Level::Trace => log::LevelFilter::Trace,
+    });
+    Ok(())
+}
+
+/// Hits a throttled call site may log per second
+pub const DEFAULT_THROTTLE: u32 = 10;
+
+/// Per call site state of the throttled macros
+#[derive(Debug)]
+pub struct Throttle {
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Debug)]
+struct ThrottleState {
+    window_start: Option<Instant>,
+    logged: u32,
+    suppressed: u64,
+}
+
+impl Throttle {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(ThrottleState {
+                window_start: None,
+                logged: 0,
+                suppressed: 0,
+            }),
+        }
+    }
+
+    /// Whether a hit at `now` may be logged, with the number of hits
+    /// suppressed since the last one that was
+    pub fn check(&self, per_sec: u32, now: Instant) -> Option<u64> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.window_start {
+            Some(start) if now.duration_since(start) < Duration::from_secs(1) => {}
+            _ => {
+                state.window_start = Some(now);
+                state.logged = 0;
+            }
+        }
+        if state.logged >= per_sec {
+            state.suppressed += 1;
+            return None;
+        }
+        state.logged += 1;
+        Some(std::mem::take(&mut state.suppressed))
+    }
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+thread_local! {
+    static SAMPLE_RNG: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+/// Randomly true for a `rate` fraction of the calls
+pub fn sample(rate: f64) -> bool {
+    SAMPLE_RNG.with(|rng| {
+        // xorshift64*
+        let mut x = rng.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        rng.set(x);
+        let r = (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64;
+        r < rate
+    })
+}
+
+/// Like `log_at!`, but only a `rate` fraction (0.0 to 1.0) of the hits at the
+/// call site are logged
+#[macro_export]
+macro_rules! log_at_sampled {
+    ($level:expr, $rate:expr, $($arg:tt)*) => {{
+        let level = $level;
+        if $crate::logging::enabled(level, module_path!()) && $crate::logging::sample($rate) {
+            $crate::log_at!(level, $($arg)*)
+        }
+    }};
+}
+
+/// `log_debug!` for a `rate` fraction of the hits, for per message logs in
+/// hot loops: `log_sampled!(0.01, "echoed"; len = n)`
+#[macro_export]
+macro_rules! log_sampled {
+    ($rate:expr, $($arg:tt)*) => {
+        $crate::log_at_sampled!($crate::logging::Level::Debug, $rate, $($arg)*)
+    };
+}
+
+/// Like `log_at!`, but the call site logs at most `per_sec` times per second.
+/// The next record logged after some were dropped is preceded by a
+/// "suppressed N duplicates" line.
+#[macro_export]
+macro_rules! log_at_throttled {
+    ($level:expr, $per_sec:expr, $($arg:tt)*) => {{
+        static THROTTLE: $crate::logging::Throttle = $crate::logging::Throttle::new();
+        let level = $level;
+        if $crate::logging::enabled(level, module_path!()) {
+            match THROTTLE.check($per_sec, std::time::Instant::now()) {
+                Some(0) => $crate::log_at!(level, $($arg)*),
+                Some(suppressed) => {
+                    $crate::log_at!(
+                        level,
+                        "suppressed {} duplicates", suppressed;
+                        at = %concat!(file!(), ":", line!())
+                    );
+                    $crate::log_at!(level, $($arg)*)
+                }
+                None => {}
+            }
+        }
+    }};
+}
+
+/// `log_err!` limited to `DEFAULT_THROTTLE` records per second, for errors
+/// a misbehaving peer can trigger in a loop
+#[macro_export]
+macro_rules! log_err_throttled {
+    ($($arg:tt)*) => {
+        $crate::log_at_throttled!(
+            $crate::logging::Level::Error,
+            $crate::logging::DEFAULT_THROTTLE,
+            $($arg)*
+        )
+    };
+}
+
+/// Always printed, without a level
 #[macro_export]
 macro_rules! log {
-    ($fmt_str:expr $(, $arg:expr)* $(,)?) => {
-        eprintln!($fmt_str $(, $arg)*)
+    ($fmt_str:expr $(, $arg:expr)* $(,)? $(; $($fields:tt)*)?) => {
+        $crate::logging::write(
+            None,
+            module_path!(),
+            format_args!($fmt_str $(, $arg)*),
+            &$crate::__log_fields!([] $($($fields)*)?),
+        )
+    };
+}
+
+/// Logs at a runtime level. Like the other macros, the format arguments can be
+/// followed by `;` and `key = value` fields, formatted with `Debug`, or
+/// `key = %value` for `Display`:
+/// `log_at!(Level::Info, "ticket sent"; road = road, plate = %plate)`
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $fmt_str:expr $(, $arg:expr)* $(,)? $(; $($fields:tt)*)?) => {{
+        let level = $level;
+        if $crate::logging::enabled(level, module_path!()) {
+            $crate::logging::write(
+                Some(level),
+                module_path!(),
+                format_args!($fmt_str $(, $arg)*),
+                &$crate::__log_fields!([] $($($fields)*)?),
+            )
+        }
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_fields {
+    ([$($acc:expr,)*]) => {
+        [$($acc,)*]
+    };
+    ([$($acc:expr,)*] $key:ident = % $val:expr $(, $($rest:tt)*)?) => {
+        $crate::__log_fields!(
+            [$($acc,)* $crate::logging::Field {
+                key: stringify!($key),
+                value: $crate::logging::FieldValue::Display(&$val),
+            },]
+            $($($rest)*)?
+        )
+    };
+    ([$($acc:expr,)*] $key:ident = $val:expr $(, $($rest:tt)*)?) => {
+        $crate::__log_fields!(
+            [$($acc,)* $crate::logging::Field {
+                key: stringify!($key),
+                value: $crate::logging::FieldValue::Debug(&$val),
+            },]
+            $($($rest)*)?
+        )
     };
 }
 
 #[macro_export]
 macro_rules! log_err {
-    ($fmt_str:expr $(, $arg:expr)* $(,)?) => {
-        $crate::log!(concat!("ERROR - ", $fmt_str) $(, $arg)*)
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::logging::Level::Error, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::logging::Level::Warn, $($arg)*)
     };
 }
 
 #[macro_export]
 macro_rules! log_info {
-    ($fmt_str:expr $(, $arg:expr)* $(,)?) => {
-        $crate::log!(concat!("INFO - ", $fmt_str) $(, $arg)*)
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::logging::Level::Info, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::logging::Level::Debug, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::logging::Level::Trace, $($arg)*)
+    };
+}
+
+/// `log_info!` with the time elapsed since the `start` Instant as an
+/// `elapsed` field: `log_elapsed!(start, "request done"; id = id)`
+#[macro_export]
+macro_rules! log_elapsed {
+    ($start:expr, $fmt_str:expr $(, $arg:expr)* $(,)? $(; $($fields:tt)*)?) => {
+        $crate::log_info!(
+            $fmt_str $(, $arg)*;
+            elapsed = $start.elapsed() $(, $($fields)*)?
+        )
     };
 }
 
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{
+        civil_from_days, context, sample, set_context, Context, Field, FieldValue, Filters, Level,
+        Record, Timestamp,
+    };
+
+    #[test]
+    fn test_level_names() {
+        assert_eq!(Level::from_name("debug"), Some(Level::Debug));
+        assert_eq!(Level::from_name("Trace"), Some(Level::Trace));
+        assert!(Level::Debug < Level::Trace);
+        assert_eq!(Level::from_name(" WARN "), Some(Level::Warn));
+        assert_eq!(Level::from_name("verbose"), None);
+        assert!(Level::Error < Level::Info);
+        assert_eq!(Level::Info.to_string(), "INFO");
+    }
+
+    #[test]
+    fn test_record_fields() {
+        let fields =
+            crate::__log_fields!([] road = 12u16, plate = %"UN1X", note = %"a b", who = "x",);
+        let record = Record {
+            level: Some(Level::Info),
+            module: "p06_speed_daemon",
+            timestamp: Timestamp {
+                wall: UNIX_EPOCH,
+                uptime: Duration::ZERO,
+            },
+            context: None,
+            args: format_args!("ticket {}", "sent"),
+            fields: &fields,
+        };
+        assert_eq!(
+            record.to_string(),
+            r#"1970-01-01T00:00:00.000Z +0.000s INFO p06_speed_daemon - ticket sent road=12 plate=UN1X note="a b" who="x""#
+        );
+        assert_eq!(
+            record.colored().to_string(),
+            "1970-01-01T00:00:00.000Z +0.000s \x1b[32mINFO\x1b[0m p06_speed_daemon - ticket sent road=12 plate=UN1X note=\"a b\" who=\"x\""
+        );
+        let empty = Field {
+            key: "k",
+            value: FieldValue::Display(&""),
+        };
+        assert_eq!(empty.to_string(), "k=\"\"");
+        assert_eq!(
+            record.to_json(),
+            r#"{"ts": "1970-01-01T00:00:00.000Z", "uptime_ms": 0, "level": "INFO", "module": "p06_speed_daemon", "msg": "ticket sent", "fields": {"road": 12, "plate": "UN1X", "note": "a b", "who": "x"}}"#
+        );
+        // Only checks that every form of the macros compiles
+        crate::log_info!("plain");
+        crate::log_info!("args {} {}", 1, 2,);
+        crate::log_err!("args {}", 1; a = 1);
+        crate::log!("fields"; a = 1, b = %2);
+    }
+
+    #[test]
+    fn test_filters() {
+        let filters = Filters::parse("info, utils=warn,utils::server=debug,p06=trace").unwrap();
+        assert_eq!(filters.default, Some(Level::Info));
+        assert_eq!(
+            filters.targets,
+            vec![
+                ("utils::server".to_owned(), Level::Debug),
+                ("utils".to_owned(), Level::Warn),
+                ("p06".to_owned(), Level::Trace),
+            ]
+        );
+        assert_eq!(Filters::parse("").unwrap(), Filters::default());
+        let err = Filters::parse("utils=loud").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"invalid log filter directive "utils=loud""#
+        );
+        Filters::parse("verbose").unwrap_err();
+    }
+
+    #[test]
+    fn test_context() {
+        let ctx = Context {
+            conn_id: 3,
+            peer: "127.0.0.1:5000".parse().unwrap(),
+        };
+        assert_eq!(context(), None);
+        {
+            let _guard = set_context(ctx);
+            let inner = Context { conn_id: 4, ..ctx };
+            drop(set_context(inner));
+            assert_eq!(context(), Some(ctx));
+            let record = Record {
+                level: None,
+                module: "",
+                timestamp: Timestamp {
+                    wall: UNIX_EPOCH,
+                    uptime: Duration::ZERO,
+                },
+                context: context(),
+                args: format_args!("hello"),
+                fields: &[],
+            };
+            assert_eq!(
+                record.to_string(),
+                "1970-01-01T00:00:00.000Z +0.000s [#3 127.0.0.1:5000] - hello"
+            );
+            assert_eq!(
+                record.to_json(),
+                r#"{"ts": "1970-01-01T00:00:00.000Z", "uptime_ms": 0, "conn": 3, "peer": "127.0.0.1:5000", "msg": "hello"}"#
+            );
+        }
+        assert_eq!(context(), None);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_facade() {
+        use super::{init_log_facade, LogFacade};
+
+        assert_eq!(LogFacade::level(log::Level::Trace), Level::Trace);
+        assert_eq!(LogFacade::level(log::Level::Warn), Level::Warn);
+        init_log_facade().unwrap();
+        assert!(log::logger().enabled(&log::Metadata::builder().level(log::Level::Error).build()));
+        log::error!("forwarded");
+    }
+
+    #[test]
+    fn test_throttle() {
+        use std::time::Instant;
+
+        use super::Throttle;
+
+        let throttle = Throttle::new();
+        let start = Instant::now();
+        assert_eq!(throttle.check(2, start), Some(0));
+        assert_eq!(throttle.check(2, start), Some(0));
+        assert_eq!(throttle.check(2, start), None);
+        assert_eq!(throttle.check(2, start + Duration::from_millis(999)), None);
+        assert_eq!(throttle.check(2, start + Duration::from_secs(1)), Some(2));
+        assert_eq!(throttle.check(2, start + Duration::from_secs(1)), Some(0));
+        for _ in 0..3 {
+            crate::log_err_throttled!("compiles {}", 1; a = 1);
+        }
+    }
+
+    #[test]
+    fn test_sample() {
+        assert!(!(0..1000).any(|_| sample(0.0)));
+        assert!((0..1000).all(|_| sample(1.0)));
+        let hits = (0..10000).filter(|_| sample(0.1)).count();
+        assert!((800..1200).contains(&hits), "{}", hits);
+    }
+
+    #[test]
+    fn test_timestamp_format() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        let ts = Timestamp {
+            wall: UNIX_EPOCH + Duration::from_millis(1_664_820_245_123),
+            uptime: Duration::from_millis(12_345),
+        };
+        assert_eq!(ts.to_string(), "2022-10-03T18:04:05.123Z +12.345s");
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Mutex,
+    },
+};
+
+use super::{Context, Level, Record, Timestamp, DEFAULT_LEVEL};
+
+pub const DEFAULT_HISTORY_CAPACITY: usize = 1024;
+/// The level written by default, as any record the history wants has to be
+/// formatted even if it isn't written
+pub const DEFAULT_HISTORY_LEVEL: Level = DEFAULT_LEVEL;
+
+/// A record kept by the history, already formatted as text
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub level: Option<Level>,
+    pub timestamp: Timestamp,
+    pub context: Option<Context>,
+    pub text: String,
+}
+
+struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+static HISTORY: Mutex<History> = Mutex::new(History {
+    entries: VecDeque::new(),
+    capacity: DEFAULT_HISTORY_CAPACITY,
+});
+static HISTORY_LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_HISTORY_LEVEL as u8);
+
+/// The history keeps records up to `level` even if they're too verbose to be
+/// written, so they can be looked at after the fact. A capacity of 0 disables it.
+pub fn set_history(capacity: usize, level: Level) {
+    let mut history = HISTORY.lock().unwrap_or_else(|e| e.into_inner());
+    history.capacity = capacity;
+    while history.entries.len() > capacity {
+        history.entries.pop_front();
+    }
+    // Below every level when disabled, so that no record is formatted for it
+    let level = if capacity == 0 { 0 } else { level as u8 };
+    HISTORY_LEVEL.store(level, Ordering::Relaxed);
+}
+
+pub(super) fn wants(level: Level) -> bool {
+    level as u8 <= HISTORY_LEVEL.load(Ordering::Relaxed)
+}
+
+pub(super) fn push(record: &Record) {
+    let text = record.to_string();
+    let mut history = HISTORY.lock().unwrap_or_else(|e| e.into_inner());
+    if history.capacity == 0 {
+        return;
+    }
+    if history.entries.len() == history.capacity {
+        history.entries.pop_front();
+    }
+    history.entries.push_back(Entry {
+        level: record.level,
+        timestamp: record.timestamp,
+        context: record.context,
+        text,
+    });
+}
+
+/// Records in the history, oldest first, only of connection `conn_id` if set
+pub fn recent(conn_id: Option<u64>) -> Vec<Entry> {
+    let history = HISTORY.lock().unwrap_or_else(|e| e.into_inner());
+    history
+        .entries
+        .iter()
+        .filter(|e| conn_id.is_none() || e.context.map(|c| c.conn_id) == conn_id)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{recent, set_history, wants, DEFAULT_HISTORY_CAPACITY, DEFAULT_HISTORY_LEVEL};
+    use crate::logging::{set_context, Context, Level};
+
+    #[test]
+    fn test_history_per_connection() {
+        assert!(wants(Level::Info) && !wants(Level::Debug));
+        set_history(DEFAULT_HISTORY_CAPACITY, Level::Debug);
+        // Other tests log concurrently, so only look at these connections
+        let ids = [u64::MAX - 1, u64::MAX];
+        for id in ids {
+            let _ctx = set_context(Context {
+                conn_id: id,
+                peer: "127.0.0.1:1".parse().unwrap(),
+            });
+            crate::log_debug!("kept even if not written"; id = id);
+            crate::log_trace!("too verbose for the history");
+        }
+        let entries = recent(Some(ids[0]));
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0]
+            .text
+            .ends_with("kept even if not written id=18446744073709551614"));
+        let all = recent(None);
+        assert!(
+            all.iter()
+                .filter(|e| e.context.is_some_and(|c| ids.contains(&c.conn_id)))
+                .count()
+                == 2
+        );
+
+        set_history(0, Level::Debug);
+        assert!(!wants(Level::Error));
+        set_history(DEFAULT_HISTORY_CAPACITY, DEFAULT_HISTORY_LEVEL);
+    }
+}
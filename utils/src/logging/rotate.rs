@@ -0,0 +1,103 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Appends lines to `path`. When it would grow past `max_size` it's renamed to
+/// `path.1`, shifting the previous `path.1` to `path.2` and so on, keeping at
+/// most `max_files` old files.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open<P: AsRef<Path>>(path: P, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        // A line longer than max_size still gets a file to itself
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                match fs::rename(self.rotated(n), self.rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use super::RotatingFile;
+
+    #[test]
+    fn test_rotation() {
+        let dir = env::temp_dir().join(format!("utils-rotate-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.log");
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in ["aaaa", "bbbb", "cccc", "dddd", "eeee", "a line too long"] {
+            file.write_line(line).unwrap();
+        }
+        let read = |name: &str| fs::read_to_string(dir.join(name)).ok();
+        assert_eq!(read("server.log").as_deref(), Some("a line too long\n"));
+        assert_eq!(read("server.log.1").as_deref(), Some("eeee\n"));
+        assert_eq!(read("server.log.2").as_deref(), Some("cccc\ndddd\n"));
+        assert_eq!(read("server.log.3"), None);
+
+        // Appends to what's already there
+        drop(file);
+        let mut file = RotatingFile::open(&path, 100, 2).unwrap();
+        file.write_line("more").unwrap();
+        assert_eq!(
+            read("server.log").as_deref(),
+            Some("a line too long\nmore\n")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
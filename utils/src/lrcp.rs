@@ -0,0 +1,616 @@
+//! Line Reversal Control Protocol: reliable byte streams over UDP, with
+//! cumulative acks and retransmissions
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{self, Clock},
+    log_debug, log_err_throttled,
+};
+
+/// Datagrams must be shorter than this
+pub const MAX_MESSAGE: usize = 1000;
+/// Numeric fields must be smaller than this
+pub const MAX_NUMBER: u32 = 1 << 31;
+
+#[derive(Debug, PartialEq)]
+pub enum Message<'a> {
+    Connect {
+        session: u32,
+    },
+    /// `data` is unescaped
+    Data {
+        session: u32,
+        pos: u32,
+        data: Cow<'a, [u8]>,
+    },
+    Ack {
+        session: u32,
+        len: u32,
+    },
+    Close {
+        session: u32,
+    },
+}
+
+impl<'a> Message<'a> {
+    /// `None` for invalid messages, which are to be ignored
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        let inner = buf.strip_prefix(b"/")?.strip_suffix(b"/")?;
+        let mut fields = split_unescaped(inner);
+        let kind = fields.next()?;
+        let session = parse_number(fields.next()?)?;
+        let msg = match kind {
+            b"connect" => Message::Connect { session },
+            b"close" => Message::Close { session },
+            b"ack" => Message::Ack {
+                session,
+                len: parse_number(fields.next()?)?,
+            },
+            b"data" => Message::Data {
+                session,
+                pos: parse_number(fields.next()?)?,
+                data: unescape(fields.next()?)?,
+            },
+            _ => return None,
+        };
+        match fields.next() {
+            Some(_) => None,
+            None => Some(msg),
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        use std::io::Write;
+        let _ = match self {
+            Message::Connect { session } => write!(buf, "/connect/{}/", session),
+            Message::Close { session } => write!(buf, "/close/{}/", session),
+            Message::Ack { session, len } => write!(buf, "/ack/{}/{}/", session, len),
+            Message::Data { session, pos, data } => {
+                let _ = write!(buf, "/data/{}/{}/", session, pos);
+                escape(data, buf);
+                write!(buf, "/")
+            }
+        };
+    }
+}
+
+/// Splits on slashes which aren't escaped
+fn split_unescaped(buf: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut escaped = false;
+    buf.split(move |&b| {
+        let split = b == b'/' && !escaped;
+        escaped = b == b'\\' && !escaped;
+        split
+    })
+}
+
+fn parse_number(field: &[u8]) -> Option<u32> {
+    if field.is_empty() || field.len() > 10 || !field.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let n: u64 = std::str::from_utf8(field).ok()?.parse().ok()?;
+    (n < MAX_NUMBER as u64).then_some(n as u32)
+}
+
+fn unescape(data: &[u8]) -> Option<Cow<'_, [u8]>> {
+    if !data.contains(&b'\\') {
+        return Some(Cow::Borrowed(data));
+    }
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'\\' => match bytes.next()? {
+                c @ (b'\\' | b'/') => out.push(*c),
+                _ => return None,
+            },
+            b => out.push(b),
+        }
+    }
+    Some(Cow::Owned(out))
+}
+
+fn escape(data: &[u8], buf: &mut Vec<u8>) {
+    for &b in data {
+        if b == b'\\' || b == b'/' {
+            buf.push(b'\\');
+        }
+        buf.push(b);
+    }
+}
+
+/// Length of the longest prefix of `data` whose data message fits in a datagram
+fn chunk_len(session: u32, pos: u32, data: &[u8]) -> usize {
+    let header = format!("/data/{}/{}/", session, pos).len() + 1;
+    let mut size = header;
+    for (i, &b) in data.iter().enumerate() {
+        size += if b == b'\\' || b == b'/' { 2 } else { 1 };
+        if size >= MAX_MESSAGE {
+            return i;
+        }
+    }
+    data.len()
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Delay before unacked data is sent again
+    pub retransmit: Duration,
+    /// Sessions whose data isn't acked for this long are closed
+    pub expiry: Duration,
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            retransmit: Duration::from_secs(3),
+            expiry: Duration::from_secs(60),
+            clock: clock::system(),
+        }
+    }
+}
+
+struct Session {
+    peer: SocketAddr,
+    received: VecDeque<u8>,
+    /// Bytes received in order since the start of the session
+    recv_len: u32,
+    /// Bytes sent but not acked yet, starting at `acked`
+    unacked: Vec<u8>,
+    acked: u32,
+    /// Last time the peer acked something, or there was nothing to ack
+    last_progress: Instant,
+    last_send: Instant,
+    closed: bool,
+}
+
+impl Session {
+    fn sent_len(&self) -> u32 {
+        self.acked + self.unacked.len() as u32
+    }
+}
+
+#[derive(Default)]
+struct State {
+    sessions: HashMap<u32, Session>,
+    pending: VecDeque<u32>,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    config: Config,
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send(&self, msg: &Message, peer: SocketAddr) {
+        let mut buf = Vec::new();
+        msg.encode(&mut buf);
+        if let Err(e) = self.socket.send_to(&buf, peer) {
+            log_debug!("sending lrcp message: {}", e; peer = peer);
+        }
+    }
+
+    /// Sends data messages for the bytes of `session` from `from`
+    fn send_data(&self, id: u32, session: &mut Session, from: u32) {
+        let mut pos = from;
+        while pos < session.sent_len() {
+            let data = &session.unacked[(pos - session.acked) as usize..];
+            let len = chunk_len(id, pos, data);
+            let msg = Message::Data {
+                session: id,
+                pos,
+                data: Cow::Borrowed(&data[..len]),
+            };
+            self.send(&msg, session.peer);
+            pos += len as u32;
+        }
+        session.last_send = self.config.clock.now();
+    }
+
+    fn handle(&self, msg: Message, peer: SocketAddr) {
+        let mut state = self.lock();
+        let State { sessions, pending } = &mut *state;
+        let id = match msg {
+            Message::Connect { session } => session,
+            Message::Data { session, .. } => session,
+            Message::Ack { session, .. } => session,
+            Message::Close { session } => session,
+        };
+        if let Message::Connect { .. } = msg {
+            sessions.entry(id).or_insert_with(|| {
+                pending.push_back(id);
+                let now = self.config.clock.now();
+                Session {
+                    peer,
+                    received: VecDeque::new(),
+                    recv_len: 0,
+                    unacked: Vec::new(),
+                    acked: 0,
+                    last_progress: now,
+                    last_send: now,
+                    closed: false,
+                }
+            });
+        }
+        let session = match sessions.get_mut(&id) {
+            Some(session) if !session.closed => session,
+            _ => return self.send(&Message::Close { session: id }, peer),
+        };
+        session.peer = peer;
+        match msg {
+            Message::Connect { .. } => {
+                self.send(
+                    &Message::Ack {
+                        session: id,
+                        len: session.recv_len,
+                    },
+                    peer,
+                );
+            }
+            Message::Data { pos, data, .. } => {
+                let end = pos as u64 + data.len() as u64;
+                if pos <= session.recv_len && end > session.recv_len as u64 {
+                    if end >= MAX_NUMBER as u64 {
+                        return self.close(id, session);
+                    }
+                    let new = &data[(session.recv_len - pos) as usize..];
+                    session.received.extend(new);
+                    session.recv_len = end as u32;
+                    self.cond.notify_all();
+                }
+                self.send(
+                    &Message::Ack {
+                        session: id,
+                        len: session.recv_len,
+                    },
+                    peer,
+                );
+            }
+            Message::Ack { len, .. } => {
+                if len <= session.acked {
+                    return;
+                }
+                if len > session.sent_len() {
+                    // The peer is misbehaving
+                    return self.close(id, session);
+                }
+                session.unacked.drain(..(len - session.acked) as usize);
+                session.acked = len;
+                session.last_progress = self.config.clock.now();
+                if len < session.sent_len() {
+                    self.send_data(id, session, len);
+                }
+            }
+            Message::Close { .. } => self.close(id, session),
+        }
+    }
+
+    fn close(&self, id: u32, session: &mut Session) {
+        session.closed = true;
+        self.send(&Message::Close { session: id }, session.peer);
+        self.cond.notify_all();
+    }
+
+    fn retransmit(&self, now: Instant) {
+        let mut state = self.lock();
+        for (&id, session) in state.sessions.iter_mut() {
+            if session.closed || session.unacked.is_empty() {
+                continue;
+            }
+            if now.duration_since(session.last_progress) >= self.config.expiry {
+                self.close(id, session);
+            } else if now.duration_since(session.last_send) >= self.config.retransmit {
+                self.send_data(id, session, session.acked);
+            }
+        }
+    }
+}
+
+fn serve(shared: Arc<Shared>) {
+    let mut buf = [0; MAX_MESSAGE];
+    // Stops once the listener and all the streams are dropped
+    while Arc::strong_count(&shared) > 1 {
+        match shared.socket.recv_from(&mut buf) {
+            Ok((n, peer)) if n < MAX_MESSAGE => {
+                if let Some(msg) = Message::parse(&buf[..n]) {
+                    shared.handle(msg, peer);
+                }
+            }
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(e) => log_err_throttled!("receiving lrcp message: {}", e),
+        }
+        shared.retransmit(shared.config.clock.now());
+    }
+}
+
+/// Accepts LRCP sessions, handled by a background thread
+pub struct Listener {
+    shared: Arc<Shared>,
+}
+
+impl Listener {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::bind_with(addr, Config::default())
+    }
+
+    pub fn bind_with<A: ToSocketAddrs>(addr: A, config: Config) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        let tick =
+            (config.retransmit / 10).clamp(Duration::from_millis(1), Duration::from_millis(100));
+        socket.set_read_timeout(Some(tick))?;
+        let shared = Arc::new(Shared {
+            socket,
+            config,
+            state: Mutex::default(),
+            cond: Condvar::new(),
+        });
+        let background = shared.clone();
+        thread::spawn(move || serve(background));
+        Ok(Self { shared })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.shared.socket.local_addr()
+    }
+
+    /// Waits for a peer to open a session
+    pub fn accept(&self) -> Stream {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(session) = state.pending.pop_front() {
+                let peer = state.sessions[&session].peer;
+                return Stream {
+                    shared: self.shared.clone(),
+                    session,
+                    peer,
+                };
+            }
+            state = self
+                .shared
+                .cond
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    pub fn incoming(&self) -> impl Iterator<Item = Stream> + '_ {
+        std::iter::repeat_with(|| self.accept())
+    }
+}
+
+/// Byte stream of an LRCP session. Reads return 0 once the session is closed,
+/// and dropping the stream closes it.
+pub struct Stream {
+    shared: Arc<Shared>,
+    session: u32,
+    peer: SocketAddr,
+}
+
+impl Stream {
+    pub fn session(&self) -> u32 {
+        self.session
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    pub fn close(&self) {
+        let mut state = self.shared.lock();
+        if let Some(session) = state.sessions.get_mut(&self.session) {
+            if !session.closed {
+                self.shared.close(self.session, session);
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.shared.lock();
+        loop {
+            let Some(session) = state.sessions.get_mut(&self.session) else {
+                return Ok(0);
+            };
+            if !session.received.is_empty() {
+                let n = session.received.read(buf)?;
+                return Ok(n);
+            }
+            if session.closed {
+                return Ok(0);
+            }
+            state = self
+                .shared
+                .cond
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.shared.lock();
+        let session = match state.sessions.get_mut(&self.session) {
+            Some(session) if !session.closed => session,
+            _ => return Err(io::ErrorKind::BrokenPipe.into()),
+        };
+        if session.sent_len() as u64 + buf.len() as u64 >= MAX_NUMBER as u64 {
+            return Err(io::Error::other("lrcp session length limit reached"));
+        }
+        if session.unacked.is_empty() {
+            session.last_progress = self.shared.config.clock.now();
+        }
+        let from = session.sent_len();
+        session.unacked.extend_from_slice(buf);
+        self.shared.send_data(self.session, session, from);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.close();
+        self.shared.lock().sessions.remove(&self.session);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        borrow::Cow,
+        io::{BufRead, BufReader, Write},
+        net::UdpSocket,
+        time::Duration,
+    };
+
+    use super::{chunk_len, Config, Listener, Message, MAX_MESSAGE};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Message::parse(b"/connect/12345/"),
+            Some(Message::Connect { session: 12345 })
+        );
+        assert_eq!(
+            Message::parse(br"/data/1/0/a\/b\\c/"),
+            Some(Message::Data {
+                session: 1,
+                pos: 0,
+                data: Cow::Owned(br"a/b\c".to_vec())
+            })
+        );
+        assert_eq!(
+            Message::parse(b"/ack/1/2147483647/"),
+            Some(Message::Ack {
+                session: 1,
+                len: 2147483647
+            })
+        );
+        let invalid = [
+            &b"/connect/1"[..],
+            b"connect/1/",
+            b"/connect/1/2/",
+            b"/ack/1/2147483648/",
+            b"/ack/1/-1/",
+            b"/ack/1//",
+            b"/data/1/0/a/b/",
+            b"/data/1/0/a\\b/",
+            b"/unknown/1/",
+            b"/close/",
+        ];
+        for msg in invalid {
+            assert_eq!(
+                Message::parse(msg),
+                None,
+                "{}",
+                String::from_utf8_lossy(msg)
+            );
+        }
+        let msg = Message::Data {
+            session: 7,
+            pos: 3,
+            data: Cow::Borrowed(br"x/\y"),
+        };
+        let mut buf = Vec::new();
+        msg.encode(&mut buf);
+        assert_eq!(buf, br"/data/7/3/x\/\\y/");
+        assert_eq!(Message::parse(&buf), Some(msg));
+    }
+
+    #[test]
+    fn test_chunk_len() {
+        let data = vec![b'/'; 2000];
+        let len = chunk_len(1, 0, &data);
+        let mut buf = Vec::new();
+        Message::Data {
+            session: 1,
+            pos: 0,
+            data: Cow::Borrowed(&data[..len]),
+        }
+        .encode(&mut buf);
+        assert!(buf.len() < MAX_MESSAGE && buf.len() >= MAX_MESSAGE - 2);
+        assert_eq!(chunk_len(1, 0, b"short"), 5);
+    }
+
+    fn recv(client: &UdpSocket) -> String {
+        let mut buf = [0; MAX_MESSAGE];
+        let n = client.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..n].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_session() {
+        let config = Config {
+            retransmit: Duration::from_millis(100),
+            expiry: Duration::from_secs(5),
+            ..Config::default()
+        };
+        let listener = Listener::bind_with("127.0.0.1:0", config).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.connect(listener.local_addr().unwrap()).unwrap();
+
+        client.send(b"/data/5/0/early/").unwrap();
+        assert_eq!(recv(&client), "/close/5/");
+        client.send(b"/connect/5/").unwrap();
+        assert_eq!(recv(&client), "/ack/5/0/");
+        let stream = listener.accept();
+        assert_eq!(stream.session(), 5);
+
+        client.send(br"/data/5/0/hello\/wor/").unwrap();
+        assert_eq!(recv(&client), "/ack/5/9/");
+        // Out of order, then overlapping
+        client.send(b"/data/5/20/x/").unwrap();
+        assert_eq!(recv(&client), "/ack/5/9/");
+        client.send(b"/data/5/6/world\n/").unwrap();
+        assert_eq!(recv(&client), "/ack/5/12/");
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "hello/world\n");
+        let mut stream = reader.into_inner();
+        stream.write_all(b"dlrow/olleh\n").unwrap();
+        assert_eq!(recv(&client), concat!(r"/data/5/0/dlrow\/olleh", "\n/"));
+        // Not acked, so sent again
+        assert_eq!(recv(&client), concat!(r"/data/5/0/dlrow\/olleh", "\n/"));
+        client.send(b"/ack/5/5/").unwrap();
+        assert_eq!(recv(&client), concat!(r"/data/5/5/\/olleh", "\n/"));
+        client.send(b"/ack/5/12/").unwrap();
+
+        client.send(b"/close/5/").unwrap();
+        assert_eq!(recv(&client), "/close/5/");
+        line.clear();
+        let mut reader = BufReader::new(stream);
+        assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+    }
+}
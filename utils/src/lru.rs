@@ -0,0 +1,225 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::{
+    metrics::{self, Counter},
+    slab::Slab,
+};
+
+#[derive(Debug)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    weight: usize,
+    /// More recently used
+    prev: Option<usize>,
+    /// Less recently used
+    next: Option<usize>,
+}
+
+/// Cache dropping the least recently used entries once the total weight of
+/// the entries goes over its capacity. Entries weigh 1 unless given a weigher,
+/// to bound the bytes used for example.
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    index: HashMap<K, usize>,
+    nodes: Slab<Node<K, V>>,
+    /// Most recently used
+    head: Option<usize>,
+    tail: Option<usize>,
+    capacity: usize,
+    weight: usize,
+    weigher: fn(&K, &V) -> usize,
+    hits: u64,
+    misses: u64,
+    /// Hits and misses counters of the metrics registry
+    counters: Option<(&'static Counter, &'static Counter)>,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    /// Cache of up to `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        Self::with_weigher(capacity, |_, _| 1)
+    }
+
+    /// Cache of entries weighing up to `capacity` in total
+    pub fn with_weigher(capacity: usize, weigher: fn(&K, &V) -> usize) -> Self {
+        Self {
+            index: HashMap::new(),
+            nodes: Slab::new(),
+            head: None,
+            tail: None,
+            capacity,
+            weight: 0,
+            weigher,
+            hits: 0,
+            misses: 0,
+            counters: None,
+        }
+    }
+
+    /// Also counts hits and misses in the `{name}_hits` and `{name}_misses`
+    /// counters of the metrics registry
+    pub fn with_metrics(mut self, name: &str) -> Self {
+        self.counters = Some((
+            metrics::counter(&format!("{}_hits", name)),
+            metrics::counter(&format!("{}_misses", name)),
+        ));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Total weight of the entries
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.nodes[i].prev, self.nodes[i].next);
+        match prev {
+            Some(p) => self.nodes[p].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(n) => self.nodes[n].prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.nodes[i].prev = None;
+        self.nodes[i].next = self.head;
+        match self.head {
+            Some(h) => self.nodes[h].prev = Some(i),
+            None => self.tail = Some(i),
+        }
+        self.head = Some(i);
+    }
+
+    /// Value of `key`, marking it as the most recently used
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.get_mut(key).map(|v| &*v)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let found = self.index.get(key).copied();
+        let (local, registry) = match found {
+            Some(_) => (&mut self.hits, self.counters.map(|c| c.0)),
+            None => (&mut self.misses, self.counters.map(|c| c.1)),
+        };
+        *local += 1;
+        if let Some(counter) = registry {
+            counter.inc();
+        }
+        let i = found?;
+        self.unlink(i);
+        self.push_front(i);
+        Some(&mut self.nodes[i].value)
+    }
+
+    /// Value of `key`, without counting as a use
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.index.get(key).map(|&i| &self.nodes[i].value)
+    }
+
+    /// Inserts or replaces the value of `key`, returning the previous one,
+    /// then evicts entries until the cache is within capacity. A value heavier
+    /// than the capacity is not kept.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old = self.remove(&key);
+        let weight = (self.weigher)(&key, &value);
+        let i = self.nodes.insert(Node {
+            key: key.clone(),
+            value,
+            weight,
+            prev: None,
+            next: None,
+        });
+        self.index.insert(key, i);
+        self.push_front(i);
+        self.weight += weight;
+        while self.weight > self.capacity {
+            let Some(tail) = self.tail else { break };
+            let key = self.nodes[tail].key.clone();
+            self.remove(&key);
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let i = self.index.remove(key)?;
+        self.unlink(i);
+        let node = self.nodes.remove(i)?;
+        self.weight -= node.weight;
+        Some(node.value)
+    }
+
+    pub fn clear(&mut self) {
+        self.index.clear();
+        self.nodes.clear();
+        self.head = None;
+        self.tail = None;
+        self.weight = 0;
+    }
+
+    /// Entries from the most to the least recently used
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        let mut next = self.head;
+        std::iter::from_fn(move || {
+            let node = &self.nodes[next?];
+            next = node.next;
+            Some((&node.key, &node.value))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LruCache;
+    use crate::metrics;
+
+    #[test]
+    fn test_evicts_least_recent() {
+        let mut cache = LruCache::new(2).with_metrics("test_lru");
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.get(&1), Some(&"a"));
+        cache.insert(3, "c");
+        assert_eq!(cache.peek(&2), None);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.iter().collect::<Vec<_>>(), [(&3, &"c"), (&1, &"a")]);
+        assert_eq!(cache.insert(1, "A"), Some("a"));
+        cache.insert(4, "d");
+        assert_eq!(cache.iter().map(|(k, _)| *k).collect::<Vec<_>>(), [4, 1]);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(metrics::counter("test_lru_hits").get(), 1);
+        assert_eq!(metrics::counter("test_lru_misses").get(), 1);
+    }
+
+    #[test]
+    fn test_weigher() {
+        let mut cache: LruCache<u32, Vec<u8>> = LruCache::with_weigher(10, |_, v| v.len());
+        cache.insert(1, vec![0; 4]);
+        cache.insert(2, vec![0; 4]);
+        cache.insert(3, vec![0; 4]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.weight(), 8);
+        cache.insert(4, vec![0; 11]);
+        assert!(cache.is_empty());
+        assert_eq!(cache.remove(&4), None);
+    }
+}
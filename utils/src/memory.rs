@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{cancel::CancelToken, id::IdGen, log_info, metrics::Gauge};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverBudget {
+    /// The connection would use more than its own limit
+    Connection,
+    /// The global budget is used up, and the connection was the biggest
+    /// user or nothing could be shed
+    Global,
+}
+
+impl fmt::Display for OverBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverBudget::Connection => write!(f, "connection memory budget exceeded"),
+            OverBudget::Global => write!(f, "global memory budget exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for OverBudget {}
+
+#[derive(Debug)]
+struct Usage {
+    used: usize,
+    shed: bool,
+    cancel: Option<CancelToken>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    accounts: HashMap<u64, Usage>,
+    total: usize,
+}
+
+#[derive(Debug)]
+struct Inner {
+    per_conn: usize,
+    global: usize,
+    state: Mutex<State>,
+    ids: IdGen,
+    gauge: Option<&'static Gauge>,
+}
+
+/// Bytes buffered by connections: read buffers, pending writes or any state
+/// they make the server keep. Each connection has a limit, and when the
+/// global budget is reached the connections using the most are shed to make
+/// room.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    inner: Arc<Inner>,
+}
+
+impl Budget {
+    pub fn new(per_conn: usize, global: usize) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                per_conn,
+                global,
+                state: Mutex::default(),
+                ids: IdGen::new(),
+                gauge: None,
+            }),
+        }
+    }
+
+    /// Budget exporting the bytes used as the `{name}_bytes` gauge
+    pub fn with_metrics(per_conn: usize, global: usize, name: &str) -> Self {
+        let mut budget = Self::new(per_conn, global);
+        Arc::get_mut(&mut budget.inner).unwrap().gauge =
+            Some(crate::metrics::gauge(&format!("{}_bytes", name)));
+        budget
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.inner.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Account of a new connection, whose `cancel` token is cancelled if
+    /// it's shed
+    pub fn account(&self, cancel: Option<CancelToken>) -> Account {
+        let id = self.inner.ids.next_id();
+        let usage = Usage {
+            used: 0,
+            shed: false,
+            cancel,
+        };
+        self.lock().accounts.insert(id, usage);
+        Account {
+            id,
+            budget: self.clone(),
+        }
+    }
+
+    /// Bytes used by all the connections not shed
+    pub fn total(&self) -> usize {
+        self.lock().total
+    }
+
+    fn set_total(&self, state: &mut State, total: usize) {
+        state.total = total;
+        if let Some(gauge) = self.inner.gauge {
+            gauge.set(total as i64);
+        }
+    }
+}
+
+/// Share of a connection in a `Budget`, released when dropped
+#[derive(Debug)]
+pub struct Account {
+    id: u64,
+    budget: Budget,
+}
+
+impl Account {
+    /// Accounts for `n` more bytes, shedding the biggest users if the global
+    /// budget is reached. Errors if this connection is over its limit or is
+    /// the one shed, the bytes aren't accounted for then.
+    pub fn reserve(&self, n: usize) -> Result<(), OverBudget> {
+        let inner = &self.budget.inner;
+        let mut state = self.budget.lock();
+        let state = &mut *state;
+        let own = &state.accounts[&self.id];
+        if own.shed {
+            return Err(OverBudget::Global);
+        }
+        if own.used + n > inner.per_conn {
+            return Err(OverBudget::Connection);
+        }
+        let mut total = state.total;
+        while total + n > inner.global {
+            let victim = state
+                .accounts
+                .iter_mut()
+                .filter(|(_, u)| !u.shed)
+                .max_by_key(|(_, u)| u.used);
+            let (&id, usage) = match victim {
+                Some(victim) if victim.1.used > 0 => victim,
+                _ => return Err(OverBudget::Global),
+            };
+            log_info!("Shedding connection over the memory budget"; used = usage.used);
+            usage.shed = true;
+            if let Some(token) = &usage.cancel {
+                token.cancel();
+            }
+            // Counted as freed, as the connection should close soon
+            total -= std::mem::take(&mut usage.used);
+            if id == self.id {
+                self.budget.set_total(state, total);
+                return Err(OverBudget::Global);
+            }
+        }
+        state.accounts.get_mut(&self.id).unwrap().used += n;
+        self.budget.set_total(state, total + n);
+        Ok(())
+    }
+
+    /// Stops accounting for `n` bytes, at most those reserved
+    pub fn release(&self, n: usize) {
+        let mut state = self.budget.lock();
+        let usage = state.accounts.get_mut(&self.id).unwrap();
+        let n = n.min(usage.used);
+        usage.used -= n;
+        let total = state.total - n;
+        self.budget.set_total(&mut state, total);
+    }
+
+    pub fn used(&self) -> usize {
+        self.budget.lock().accounts[&self.id].used
+    }
+
+    /// Whether the connection was shed, it should close
+    pub fn is_shed(&self) -> bool {
+        self.budget.lock().accounts[&self.id].shed
+    }
+}
+
+impl Drop for Account {
+    fn drop(&mut self) {
+        let mut state = self.budget.lock();
+        if let Some(usage) = state.accounts.remove(&self.id) {
+            let total = state.total - usage.used;
+            self.budget.set_total(&mut state, total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Budget, OverBudget};
+    use crate::cancel::CancelToken;
+
+    #[test]
+    fn test_budget() {
+        let budget = Budget::new(100, 150);
+        let token = CancelToken::new();
+        let hog = budget.account(Some(token.clone()));
+        let small = budget.account(None);
+        assert_eq!(hog.reserve(101), Err(OverBudget::Connection));
+        hog.reserve(90).unwrap();
+        small.reserve(40).unwrap();
+        assert_eq!(budget.total(), 130);
+
+        // The biggest user makes room
+        small.reserve(30).unwrap();
+        assert!(hog.is_shed() && token.is_cancelled());
+        assert_eq!(hog.reserve(1), Err(OverBudget::Global));
+        assert_eq!(budget.total(), 70);
+        small.release(20);
+        assert_eq!(small.used(), 50);
+
+        // Shedding itself
+        let other = budget.account(None);
+        other.reserve(10).unwrap();
+        assert_eq!(small.reserve(95), Err(OverBudget::Connection));
+        let big = budget.account(None);
+        big.reserve(80).unwrap();
+        assert_eq!(big.reserve(15), Err(OverBudget::Global));
+        assert!(big.is_shed());
+        assert_eq!(budget.total(), 60);
+        drop(small);
+        assert_eq!(budget.total(), 10);
+    }
+}
@@ -0,0 +1,433 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write as _},
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock,
+    },
+    time::Duration,
+};
+
+/// Duration of connection handlers, in microseconds
+pub static CONN_DURATION_US: Histogram = Histogram::new();
+/// Bytes read plus bytes written per connection
+pub static CONN_BYTES: Histogram = Histogram::new();
+/// Connections being handled
+pub static CONNS_OPEN: Gauge = Gauge::new();
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1)
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub const fn new() -> Self {
+        Self(AtomicI64::new(0))
+    }
+
+    pub fn set(&self, v: i64) {
+        self.0.store(v, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.add(1)
+    }
+
+    pub fn dec(&self) {
+        self.add(-1)
+    }
+
+    pub fn add(&self, n: i64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Metric {
+    Counter(&'static Counter),
+    Gauge(&'static Gauge),
+    Histogram(&'static Histogram),
+}
+
+impl Metric {
+    fn kind(&self) -> &'static str {
+        match self {
+            Metric::Counter(_) => "counter",
+            Metric::Gauge(_) => "gauge",
+            Metric::Histogram(_) => "histogram",
+        }
+    }
+
+    pub fn value(&self) -> Value {
+        match self {
+            Metric::Counter(c) => Value::Counter(c.get()),
+            Metric::Gauge(g) => Value::Gauge(g.get()),
+            Metric::Histogram(h) => Value::Histogram {
+                count: h.count(),
+                sum: h.sum(),
+                max: h.max(),
+                quantiles: QUANTILES.map(|q| (q, h.quantile(q))),
+            },
+        }
+    }
+}
+
+const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Counter(u64),
+    Gauge(i64),
+    Histogram {
+        count: u64,
+        sum: u64,
+        max: u64,
+        quantiles: [(f64, u64); 3],
+    },
+}
+
+fn registry() -> MutexGuard<'static, BTreeMap<String, Metric>> {
+    static REGISTRY: OnceLock<Mutex<BTreeMap<String, Metric>>> = OnceLock::new();
+    REGISTRY
+        .get_or_init(|| {
+            Mutex::new(BTreeMap::from([
+                (
+                    "conn_duration_us".into(),
+                    Metric::Histogram(&CONN_DURATION_US),
+                ),
+                ("conn_bytes".into(), Metric::Histogram(&CONN_BYTES)),
+                ("conns_open".into(), Metric::Gauge(&CONNS_OPEN)),
+            ]))
+        })
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Registers a static metric under `name`.
+/// Panics if the name is taken or isn't made of `[a-zA-Z0-9_]`.
+pub fn register(name: &str, metric: Metric) {
+    assert!(valid_name(name), "invalid metric name {:?}", name);
+    let mut registry = registry();
+    if let Some(existing) = registry.get(name) {
+        panic!(
+            "metric {} already registered as a {}",
+            name,
+            existing.kind()
+        );
+    }
+    registry.insert(name.to_owned(), metric);
+}
+
+macro_rules! get_or_register {
+    ($name:ident, $ty:ident) => {
+        /// Metric registered under `name`, created on first use.
+        /// Panics if it is registered as another kind of metric.
+        pub fn $name(name: &str) -> &'static $ty {
+            let mut registry = registry();
+            match registry.get(name) {
+                Some(Metric::$ty(m)) => m,
+                Some(other) => panic!("metric {} is a {}", name, other.kind()),
+                None => {
+                    assert!(valid_name(name), "invalid metric name {:?}", name);
+                    let m: &'static $ty = Box::leak(Box::default());
+                    registry.insert(name.to_owned(), Metric::$ty(m));
+                    m
+                }
+            }
+        }
+    };
+}
+
+get_or_register!(counter, Counter);
+get_or_register!(gauge, Gauge);
+get_or_register!(histogram, Histogram);
+
+/// Values of all the metrics, sorted by name
+pub fn snapshot() -> Vec<(String, Value)> {
+    let registry = registry();
+    registry
+        .iter()
+        .map(|(n, m)| (n.clone(), m.value()))
+        .collect()
+}
+
+/// Metrics in the Prometheus text format, histograms as summaries
+pub fn render() -> String {
+    let mut out = String::new();
+    for (name, value) in snapshot() {
+        let _ = match value {
+            Value::Counter(v) => writeln!(out, "# TYPE {0} counter\n{0} {1}", name, v),
+            Value::Gauge(v) => writeln!(out, "# TYPE {0} gauge\n{0} {1}", name, v),
+            Value::Histogram {
+                count,
+                sum,
+                max,
+                quantiles,
+            } => {
+                let _ = writeln!(out, "# TYPE {} summary", name);
+                for (q, v) in quantiles {
+                    let _ = writeln!(out, "{}{{quantile=\"{}\"}} {}", name, q, v);
+                }
+                writeln!(
+                    out,
+                    "{0}_sum {1}\n{0}_count {2}\n# TYPE {0}_max gauge\n{0}_max {3}",
+                    name, sum, count, max
+                )
+            }
+        };
+    }
+    out
+}
+
+/// Lock free histogram with power of two buckets, so quantiles are
+/// approximated to the next power of two, capped by the max
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: [AtomicU64; 65],
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; 65],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, v: u64) {
+        self.buckets[(u64::BITS - v.leading_zeros()) as usize].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(v, Ordering::Relaxed);
+        self.max.fetch_max(v, Ordering::Relaxed);
+    }
+
+    pub fn record_duration(&self, d: Duration) {
+        self.record(d.as_micros().try_into().unwrap_or(u64::MAX))
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum.load(Ordering::Relaxed)
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    /// Upper bound of the value under which a `q` fraction of the samples are
+    pub fn quantile(&self, q: f64) -> u64 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                let upper = if i == 64 { u64::MAX } else { (1 << i) - 1 };
+                return upper.min(self.max());
+            }
+        }
+        self.max()
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "count={} p50={} p90={} p99={} max={}",
+            self.count(),
+            self.quantile(0.5),
+            self.quantile(0.9),
+            self.quantile(0.99),
+            self.max()
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Counts {
+    read: AtomicU64,
+    written: AtomicU64,
+}
+
+impl Counts {
+    pub fn read(&self) -> u64 {
+        self.read.load(Ordering::Relaxed)
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+}
+
+/// Stream wrapper counting the bytes going through it. Clones share the counts.
+#[derive(Debug)]
+pub struct Counted<S> {
+    inner: S,
+    counts: Arc<Counts>,
+}
+
+impl<S> Counted<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            counts: Arc::default(),
+        }
+    }
+
+    pub fn counts(&self) -> Arc<Counts> {
+        self.counts.clone()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl Counted<TcpStream> {
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            inner: self.inner.try_clone()?,
+            counts: self.counts.clone(),
+        })
+    }
+}
+
+impl<S: Read> Read for Counted<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.counts.read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<S: Write> Write for Counted<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.counts.written.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use super::{counter, gauge, histogram, render, snapshot, Counted, Histogram, Value};
+
+    #[test]
+    fn test_histogram_quantiles() {
+        let h = Histogram::new();
+        assert_eq!(h.quantile(0.5), 0);
+        for v in 1..=100 {
+            h.record(v);
+        }
+        assert_eq!(h.count(), 100);
+        assert_eq!(h.sum(), 5050);
+        assert_eq!(h.quantile(0.5), 63);
+        assert_eq!(h.quantile(0.99), 100);
+        assert_eq!(h.quantile(0.0), 1);
+        h.record(u64::MAX);
+        assert_eq!(h.quantile(1.0), u64::MAX);
+    }
+
+    #[test]
+    fn test_counted() {
+        let mut s = Counted::new(&b"hello"[..]);
+        let mut buf = Vec::new();
+        s.read_to_end(&mut buf).unwrap();
+        assert_eq!(s.counts().read(), 5);
+
+        let mut s = Counted::new(Vec::new());
+        s.write_all(b"abc").unwrap();
+        write!(s, "{}", 12).unwrap();
+        assert_eq!(s.counts().written(), 5);
+        assert_eq!(s.into_inner(), b"abc12");
+    }
+
+    #[test]
+    fn test_registry() {
+        counter("test_requests").add(2);
+        counter("test_requests").inc();
+        gauge("test_sessions").set(-4);
+        histogram("test_latency").record(5);
+        let snapshot = snapshot();
+        let value = |name| {
+            snapshot
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.clone())
+        };
+        assert_eq!(value("test_requests"), Some(Value::Counter(3)));
+        assert_eq!(value("test_sessions"), Some(Value::Gauge(-4)));
+        assert!(value("conn_bytes").is_some());
+
+        let text = render();
+        assert!(text.contains("# TYPE test_requests counter\ntest_requests 3\n"));
+        assert!(text.contains("test_latency{quantile=\"0.99\"} 5\n"));
+        assert!(text.contains("test_latency_count 1\n"));
+        assert!(std::panic::catch_unwind(|| gauge("test_requests")).is_err());
+    }
+}
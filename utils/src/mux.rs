@@ -0,0 +1,360 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, BufReader, Read, Write},
+    net::Shutdown,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+};
+
+use crate::{
+    log_debug,
+    proxy::Duplex,
+    wire::{Reader, Writer},
+};
+
+/// Largest payload of a frame
+pub const MAX_FRAME: u32 = 16384;
+/// Most channels open at once, past which the peer opening more closes the
+/// connection
+pub const MAX_CHANNELS: usize = 1024;
+
+const DATA: u8 = 0;
+/// Payload is a u32 of bytes the receiver read, which can be sent again
+const CREDIT: u8 = 1;
+const CLOSE: u8 = 2;
+
+#[derive(Debug, Default)]
+struct Chan {
+    incoming: VecDeque<u8>,
+    /// Bytes which can be sent before the peer reads some
+    credit: u32,
+    /// Bytes read and not returned as credit yet
+    unacked: u32,
+    remote_closed: bool,
+    /// Dropped here, and kept until the peer closes it too
+    local_closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    channels: HashMap<u32, Chan>,
+    dead: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    cond: Condvar,
+    writer: Mutex<Box<dyn Write + Send>>,
+    window: u32,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send(&self, id: u32, kind: u8, payload: &[u8]) -> io::Result<()> {
+        let mut w = Writer::new(Vec::with_capacity(9 + payload.len()));
+        w.write_u32(id)?;
+        w.write_u8(kind)?;
+        w.write_u32(payload.len() as u32)?;
+        w.write_bytes(payload)?;
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        writer.write_all(w.get_ref())
+    }
+}
+
+fn chan(state: &mut State, id: u32, window: u32) -> &mut Chan {
+    state.channels.entry(id).or_insert_with(|| Chan {
+        credit: window,
+        ..Chan::default()
+    })
+}
+
+/// Logical channels over a single connection, framed as a u32 channel id, a
+/// u8 kind and a u32 length prefixed payload. Each channel has a window of
+/// bytes sent and not read yet by the other side, which must use the same.
+/// Channels exist on both sides once either uses their id.
+pub struct Mux {
+    shared: Arc<Shared>,
+    shutdown: Box<dyn Fn() + Send>,
+}
+
+impl Mux {
+    pub fn new<S: Duplex + 'static>(stream: S, window: u32) -> io::Result<Self> {
+        assert!(window > 0);
+        let reader = stream.try_clone()?;
+        let shared = Arc::new(Shared {
+            state: Mutex::default(),
+            cond: Condvar::new(),
+            writer: Mutex::new(Box::new(stream.try_clone()?)),
+            window,
+        });
+        let background = shared.clone();
+        thread::spawn(move || {
+            if let Err(e) = demux(reader, &background) {
+                log_debug!("multiplexed connection closed: {}", e);
+            }
+            background.lock().dead = true;
+            background.cond.notify_all();
+        });
+        Ok(Self {
+            shared,
+            shutdown: Box::new(move || drop(stream.shutdown(Shutdown::Both))),
+        })
+    }
+
+    /// Channel `id`, with a single handle per id at a time. Closed on its side
+    /// when dropped.
+    pub fn channel(&self, id: u32) -> Channel {
+        let mut state = self.shared.lock();
+        if chan(&mut state, id, self.shared.window).local_closed {
+            // Reused before the peer closed it
+            state.channels.remove(&id);
+            chan(&mut state, id, self.shared.window);
+        }
+        drop(state);
+        Channel {
+            id,
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for Mux {
+    /// Closes the connection, and all the channels with it
+    fn drop(&mut self) {
+        (self.shutdown)();
+    }
+}
+
+/// Channel `id` for a frame from the peer, which can't open too many
+fn remote_chan(state: &mut State, id: u32, window: u32) -> io::Result<&mut Chan> {
+    if state.channels.len() >= MAX_CHANNELS && !state.channels.contains_key(&id) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "peer opened too many channels",
+        ));
+    }
+    Ok(chan(state, id, window))
+}
+
+fn demux<R: Read>(reader: R, shared: &Shared) -> io::Result<()> {
+    let mut r = Reader::new(BufReader::new(reader));
+    loop {
+        let id = r.read_u32()?;
+        let kind = r.read_u8()?;
+        let len = r.read_u32()?;
+        if len > MAX_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"));
+        }
+        let payload = r.read_bytes(len as usize)?;
+        let mut state = shared.lock();
+        match kind {
+            DATA => {
+                // Data opens the channel if needed
+                let c = remote_chan(&mut state, id, shared.window)?;
+                if c.local_closed {
+                    continue;
+                }
+                if c.incoming.len() + payload.len() > shared.window as usize {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "peer sent more than the window",
+                    ));
+                }
+                c.incoming.extend(payload);
+            }
+            CREDIT => {
+                let credit = payload.try_into().map(u32::from_be_bytes).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid credit frame")
+                })?;
+                if let Some(c) = state.channels.get_mut(&id) {
+                    c.credit = c.credit.saturating_add(credit);
+                }
+            }
+            CLOSE => {
+                let c = remote_chan(&mut state, id, shared.window)?;
+                match c.local_closed {
+                    true => drop(state.channels.remove(&id)),
+                    false => c.remote_closed = true,
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown frame kind {}", kind),
+                ))
+            }
+        }
+        shared.cond.notify_all();
+    }
+}
+
+/// Logical channel of a `Mux`. Reads end once the other side dropped its
+/// handle, and writes then fail. Writes wait for the other side to read when
+/// the window is full.
+pub struct Channel {
+    id: u32,
+    shared: Arc<Shared>,
+}
+
+impl Channel {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Read for Channel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let window = self.shared.window;
+        let mut state = self.shared.lock();
+        let (n, credit) = loop {
+            let dead = state.dead;
+            let c = chan(&mut state, self.id, window);
+            if !c.incoming.is_empty() {
+                let n = buf.len().min(c.incoming.len());
+                for (dst, src) in buf.iter_mut().zip(c.incoming.drain(..n)) {
+                    *dst = src;
+                }
+                c.unacked += n as u32;
+                // Credit is returned in batches rather than for every read
+                let credit = match c.unacked >= window.div_ceil(2) {
+                    true => std::mem::take(&mut c.unacked),
+                    false => 0,
+                };
+                break (n, credit);
+            }
+            if c.remote_closed || dead {
+                return Ok(0);
+            }
+            state = self
+                .shared
+                .cond
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        };
+        drop(state);
+        if credit > 0 {
+            self.shared.send(self.id, CREDIT, &credit.to_be_bytes())?;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Channel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let window = self.shared.window;
+        let mut state = self.shared.lock();
+        let n = loop {
+            if state.dead {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let c = chan(&mut state, self.id, window);
+            if c.remote_closed {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            if c.credit > 0 {
+                let n = buf.len().min(c.credit.min(MAX_FRAME) as usize);
+                c.credit -= n as u32;
+                break n;
+            }
+            state = self
+                .shared
+                .cond
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        };
+        drop(state);
+        self.shared.send(self.id, DATA, &buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        let c = chan(&mut state, self.id, self.shared.window);
+        match c.remote_closed {
+            true => drop(state.channels.remove(&self.id)),
+            false => c.local_closed = true,
+        }
+        drop(state);
+        let _ = self.shared.send(self.id, CLOSE, &[]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use super::{Mux, MAX_CHANNELS};
+
+    #[test]
+    fn test_channels() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let a = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let b = listener.accept().unwrap().0;
+        let (a, b) = (Mux::new(a, 8).unwrap(), Mux::new(b, 8).unwrap());
+
+        let mut bulk = a.channel(1);
+        let sending = thread::spawn(move || {
+            // Waits on the window until the other side reads
+            bulk.write_all(&[7; 100]).unwrap();
+        });
+        a.channel(2).write_all(b"two").unwrap();
+        let mut received = String::new();
+        b.channel(2).read_to_string(&mut received).unwrap();
+        assert_eq!(received, "two");
+
+        let mut received = Vec::new();
+        b.channel(1).read_to_end(&mut received).unwrap();
+        assert_eq!(received, [7; 100]);
+        sending.join().unwrap();
+
+        // Channels end with the connection
+        // Writes fail once the peer dropped its side, even with a full window
+        drop(b.channel(4));
+        assert_eq!(
+            a.channel(4).write_all(&[1; 100]).unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+
+        let mut other = b.channel(3);
+        drop(a);
+        assert_eq!(other.read(&mut [0; 8]).unwrap(), 0);
+        assert!(other.write_all(b"gone").is_err());
+    }
+
+    #[test]
+    fn test_channel_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mux = Mux::new(listener.accept().unwrap().0, 8).unwrap();
+        let mut waiting = mux.channel(u32::MAX);
+        for id in 0..MAX_CHANNELS as u32 {
+            let mut frame = id.to_be_bytes().to_vec();
+            frame.extend_from_slice(&[2, 0, 0, 0, 0]);
+            // Fails once the connection is closed
+            if peer.write_all(&frame).is_err() {
+                break;
+            }
+        }
+        // The connection is dropped, ending the reads
+        assert_eq!(waiting.read(&mut [0; 8]).unwrap(), 0);
+    }
+}
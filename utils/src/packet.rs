@@ -0,0 +1,226 @@
+use std::{
+    io::{self, Read, Write},
+    marker::PhantomData,
+};
+
+use crate::{
+    codec::{BytesBuf, Codec},
+    wire::{Reader, Writer},
+};
+
+/// Field of a packet defined with `define_packets!`
+pub trait Field: Sized {
+    fn read<R: Read>(r: &mut Reader<R>) -> io::Result<Self>;
+    fn write<W: Write>(&self, w: &mut Writer<W>) -> io::Result<()>;
+}
+
+macro_rules! int_field {
+    ($ty:ty, $read:ident, $write:ident) => {
+        impl Field for $ty {
+            fn read<R: Read>(r: &mut Reader<R>) -> io::Result<Self> {
+                r.$read()
+            }
+
+            fn write<W: Write>(&self, w: &mut Writer<W>) -> io::Result<()> {
+                w.$write(*self)
+            }
+        }
+    };
+}
+
+int_field!(u8, read_u8, write_u8);
+int_field!(u16, read_u16, write_u16);
+int_field!(u32, read_u32, write_u32);
+int_field!(i32, read_i32, write_i32);
+int_field!(u64, read_u64, write_u64);
+
+/// String prefixed by its length in a u8
+pub type LenStr = String;
+
+impl Field for String {
+    fn read<R: Read>(r: &mut Reader<R>) -> io::Result<Self> {
+        r.read_str()
+    }
+
+    fn write<W: Write>(&self, w: &mut Writer<W>) -> io::Result<()> {
+        w.write_str(self)
+    }
+}
+
+/// Array prefixed by its length in a u8
+impl<T: Field> Field for Vec<T> {
+    fn read<R: Read>(r: &mut Reader<R>) -> io::Result<Self> {
+        let len = r.read_u8()?;
+        (0..len).map(|_| T::read(r)).collect()
+    }
+
+    fn write<W: Write>(&self, w: &mut Writer<W>) -> io::Result<()> {
+        let len = u8::try_from(self.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "array longer than 255 items")
+        })?;
+        w.write_u8(len)?;
+        self.iter().try_for_each(|item| item.write(w))
+    }
+}
+
+/// Messages made of a u8 tag and big endian fields, see `define_packets!`
+pub trait Packet: Sized {
+    fn read_from<R: Read>(r: &mut Reader<R>) -> io::Result<Self>;
+    fn write_to<W: Write>(&self, w: &mut Writer<W>) -> io::Result<()>;
+}
+
+/// Defines an enum of packets, and their encoding as a u8 tag followed by the
+/// fields in order. Fields are integers, `LenStr` or `Vec`s of fields.
+/// The variants are declared as `Plate = 0x20 { plate: LenStr, timestamp: u32 }`.
+#[macro_export]
+macro_rules! define_packets {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($variant:ident = $tag:literal { $($field:ident: $ty:ty),* $(,)? }),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($variant { $($field: $ty),* }),*
+        }
+
+        impl $name {
+            #[allow(dead_code)]
+            pub fn tag(&self) -> u8 {
+                match self {
+                    $($name::$variant { .. } => $tag),*
+                }
+            }
+        }
+
+        impl $crate::packet::Packet for $name {
+            fn read_from<R: ::std::io::Read>(
+                r: &mut $crate::wire::Reader<R>,
+            ) -> ::std::io::Result<Self> {
+                match r.read_u8()? {
+                    $($tag => Ok($name::$variant {
+                        $($field: $crate::packet::Field::read(r)?),*
+                    }),)*
+                    tag => Err(::std::io::Error::new(
+                        ::std::io::ErrorKind::InvalidData,
+                        format!("unknown packet type 0x{:02x}", tag),
+                    )),
+                }
+            }
+
+            fn write_to<W: ::std::io::Write>(
+                &self,
+                w: &mut $crate::wire::Writer<W>,
+            ) -> ::std::io::Result<()> {
+                match self {
+                    $($name::$variant { $($field),* } => {
+                        w.write_u8($tag)?;
+                        $($crate::packet::Field::write($field, w)?;)*
+                    })*
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+/// Codec receiving `In` packets and sending `Out` ones. Encoding panics on
+/// strings or arrays too long for their prefix.
+#[derive(Debug)]
+pub struct PacketCodec<In, Out> {
+    _packets: PhantomData<fn(Out) -> In>,
+}
+
+impl<In, Out> PacketCodec<In, Out> {
+    pub fn new() -> Self {
+        Self {
+            _packets: PhantomData,
+        }
+    }
+}
+
+impl<In, Out> Default for PacketCodec<In, Out> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<In: Packet, Out: Packet> Codec for PacketCodec<In, Out> {
+    type In = In;
+    type Out = Out;
+
+    fn decode(&mut self, buf: &mut BytesBuf) -> io::Result<Option<In>> {
+        let mut r = Reader::new(&buf[..]);
+        match In::read_from(&mut r) {
+            Ok(packet) => {
+                let len = buf.len() - r.get_ref().len();
+                buf.advance(len);
+                Ok(Some(packet))
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn encode(&mut self, packet: Out, buf: &mut Vec<u8>) {
+        packet
+            .write_to(&mut Writer::new(buf))
+            .expect("packet fields too long to encode");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::{LenStr, PacketCodec};
+    use crate::codec::{BytesBuf, Codec};
+
+    crate::define_packets! {
+        #[derive(Debug, Clone, PartialEq)]
+        enum Message {
+            Plate = 0x20 { plate: LenStr, timestamp: u32 },
+            IAmDispatcher = 0x81 { roads: Vec<u16> },
+            Heartbeat = 0x41 {},
+        }
+    }
+
+    #[test]
+    fn test_packets() {
+        let messages = [
+            Message::Plate {
+                plate: "UN1X".to_owned(),
+                timestamp: 1000,
+            },
+            Message::IAmDispatcher {
+                roads: vec![66, 368, 5000],
+            },
+            Message::Heartbeat {},
+        ];
+        let mut codec = PacketCodec::<Message, Message>::new();
+        let mut bytes = Vec::new();
+        for m in messages.clone() {
+            codec.encode(m, &mut bytes);
+        }
+        assert_eq!(
+            bytes,
+            b"\x20\x04UN1X\x00\x00\x03\xe8\x81\x03\x00\x42\x01\x70\x13\x88\x41"
+        );
+        assert_eq!(messages[1].tag(), 0x81);
+
+        // Fed a byte at a time
+        let mut buf = BytesBuf::new();
+        let mut decoded = Vec::new();
+        for b in bytes {
+            buf.extend_from_slice(&[b]);
+            decoded.extend(codec.decode(&mut buf).unwrap());
+        }
+        assert_eq!(decoded, messages);
+        assert!(buf.is_empty());
+
+        buf.extend_from_slice(b"\x99");
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
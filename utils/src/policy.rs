@@ -0,0 +1,182 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Cull,
+    Conserve,
+}
+
+/// Range of population wanted for a species, both ends included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    pub min: u32,
+    pub max: u32,
+}
+
+/// Request to send to the authority
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Create { species: String, action: Action },
+    Delete { id: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictingCounts(pub String);
+
+impl fmt::Display for ConflictingCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "conflicting counts for {}", self.0)
+    }
+}
+
+impl std::error::Error for ConflictingCounts {}
+
+#[derive(Debug, Default)]
+struct Slot {
+    /// Policy in place, without id while its creation is outstanding
+    policy: Option<(Action, Option<u32>)>,
+    wanted: Option<Action>,
+}
+
+/// Policies of a site, reconciled with the populations observed. Policies
+/// are created one at a time per species: changes wanted while a creation is
+/// outstanding are issued once its id is known, by `created`. Commands are
+/// given in species order.
+#[derive(Debug)]
+pub struct Reconciler {
+    targets: BTreeMap<String, Target>,
+    slots: BTreeMap<String, Slot>,
+}
+
+impl Reconciler {
+    pub fn new<I: IntoIterator<Item = (String, Target)>>(targets: I) -> Self {
+        Self {
+            targets: targets.into_iter().collect(),
+            slots: BTreeMap::new(),
+        }
+    }
+
+    /// Commands to issue for the populations counted during a visit. Species
+    /// without a target are ignored, and those with a target but not counted
+    /// are absent.
+    pub fn observe(&mut self, counts: &[(String, u32)]) -> Result<Vec<Command>, ConflictingCounts> {
+        let mut seen = HashMap::new();
+        for (species, count) in counts {
+            if *seen.entry(species.as_str()).or_insert(*count) != *count {
+                return Err(ConflictingCounts(species.clone()));
+            }
+        }
+        let mut commands = Vec::new();
+        for (species, target) in &self.targets {
+            let count = seen.get(species.as_str()).copied().unwrap_or(0);
+            let wanted = if count < target.min {
+                Some(Action::Conserve)
+            } else if count > target.max {
+                Some(Action::Cull)
+            } else {
+                None
+            };
+            let slot = self.slots.entry(species.clone()).or_default();
+            slot.wanted = wanted;
+            sync(species, slot, &mut commands);
+        }
+        Ok(commands)
+    }
+
+    /// Records the id of the policy created for `species`, returning the
+    /// commands which were waiting on it
+    pub fn created(&mut self, species: &str, id: u32) -> Vec<Command> {
+        let mut commands = Vec::new();
+        if let Some(slot) = self.slots.get_mut(species) {
+            if let Some((_, pending @ None)) = &mut slot.policy {
+                *pending = Some(id);
+                sync(species, slot, &mut commands);
+            }
+        }
+        commands
+    }
+
+    /// Policies in place with their ids, by species
+    pub fn policies(&self) -> impl Iterator<Item = (&str, Action, u32)> {
+        self.slots
+            .iter()
+            .filter_map(|(species, slot)| match slot.policy {
+                Some((action, Some(id))) => Some((species.as_str(), action, id)),
+                _ => None,
+            })
+    }
+}
+
+fn sync(species: &str, slot: &mut Slot, commands: &mut Vec<Command>) {
+    match slot.policy {
+        Some((action, _)) if Some(action) == slot.wanted => return,
+        // Deleted once created
+        Some((_, None)) => return,
+        Some((_, Some(id))) => {
+            commands.push(Command::Delete { id });
+            slot.policy = None;
+        }
+        None => {}
+    }
+    if let Some(action) = slot.wanted {
+        commands.push(Command::Create {
+            species: species.to_owned(),
+            action,
+        });
+        slot.policy = Some((action, None));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Action, Command, ConflictingCounts, Reconciler, Target};
+
+    fn counts(c: &[(&str, u32)]) -> Vec<(String, u32)> {
+        c.iter().map(|&(s, n)| (s.to_owned(), n)).collect()
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let mut site = Reconciler::new([("dog".to_owned(), Target { min: 2, max: 5 })]);
+        let create = |action| Command::Create {
+            species: "dog".to_owned(),
+            action,
+        };
+
+        // Not counted, so absent
+        assert_eq!(
+            site.observe(&counts(&[("cat", 9)])).unwrap(),
+            [create(Action::Conserve)]
+        );
+        // Still outstanding
+        assert_eq!(site.observe(&counts(&[("dog", 1)])).unwrap(), []);
+        assert_eq!(site.created("dog", 7), []);
+        assert_eq!(site.observe(&counts(&[("dog", 1)])).unwrap(), []);
+        assert_eq!(
+            site.policies().collect::<Vec<_>>(),
+            [("dog", Action::Conserve, 7)]
+        );
+
+        assert_eq!(
+            site.observe(&counts(&[("dog", 6)])).unwrap(),
+            [Command::Delete { id: 7 }, create(Action::Cull)]
+        );
+        // Back in range before the creation is done
+        assert_eq!(site.observe(&counts(&[("dog", 3)])).unwrap(), []);
+        assert_eq!(site.created("dog", 8), [Command::Delete { id: 8 }]);
+        assert_eq!(site.policies().count(), 0);
+        assert_eq!(site.observe(&counts(&[("dog", 3)])).unwrap(), []);
+
+        assert_eq!(
+            site.observe(&counts(&[("dog", 3), ("dog", 4)])),
+            Err(ConflictingCounts("dog".to_owned()))
+        );
+        assert_eq!(
+            site.observe(&counts(&[("dog", 3), ("dog", 3)])).unwrap(),
+            []
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+use crate::deadline::Timeouts;
+
+/// Protocol logic without IO: bytes are fed in and events come out, bytes to
+/// send and timers are polled for. Drivers do the IO, so that the same logic
+/// runs in any server mode and in tests without sockets.
+pub trait Protocol {
+    type Event;
+
+    /// Bytes received from the peer. Errors close the connection.
+    fn feed(&mut self, bytes: &[u8], now: Instant) -> io::Result<()>;
+
+    /// The peer closed its side
+    fn feed_eof(&mut self, _now: Instant) {}
+
+    fn poll_event(&mut self) -> Option<Self::Event>;
+
+    /// Appends the bytes to send to `buf`
+    fn poll_transmit(&mut self, buf: &mut Vec<u8>);
+
+    /// When `handle_timeout` should be called next
+    fn poll_timeout(&self) -> Option<Instant> {
+        None
+    }
+
+    fn handle_timeout(&mut self, _now: Instant) {}
+
+    /// Whether the connection should be closed, once the bytes to send are
+    /// sent
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+/// Runs `protocol` over a blocking stream until it's closed or the peer
+/// closed its side, handing the events to `on_event`, which can act on the
+/// protocol in turn
+pub fn drive<S, P, F>(mut stream: S, protocol: &mut P, mut on_event: F) -> io::Result<()>
+where
+    S: Read + Write + Timeouts,
+    P: Protocol,
+    F: FnMut(&mut P, P::Event) -> io::Result<()>,
+{
+    let mut buf = vec![0; 4096];
+    let mut out = Vec::new();
+    let mut eof = false;
+    loop {
+        while let Some(event) = protocol.poll_event() {
+            on_event(protocol, event)?;
+        }
+        out.clear();
+        protocol.poll_transmit(&mut out);
+        if !out.is_empty() {
+            stream.write_all(&out)?;
+            continue;
+        }
+        if eof || protocol.is_closed() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let timeout = protocol
+            .poll_timeout()
+            .map(|t| t.saturating_duration_since(now));
+        if timeout == Some(Duration::ZERO) {
+            protocol.handle_timeout(now);
+            continue;
+        }
+        stream.set_read_timeout(timeout)?;
+        match stream.read(&mut buf) {
+            Ok(0) => {
+                protocol.feed_eof(Instant::now());
+                eof = true;
+            }
+            Ok(n) => protocol.feed(&buf[..n], Instant::now())?,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                protocol.handle_timeout(Instant::now())
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{drive, Protocol};
+
+    /// Lines in, closing after being idle for `idle`
+    struct Lines {
+        buf: Vec<u8>,
+        out: Vec<u8>,
+        idle: Duration,
+        last_seen: Instant,
+        closed: bool,
+    }
+
+    impl Protocol for Lines {
+        type Event = Vec<u8>;
+
+        fn feed(&mut self, bytes: &[u8], now: Instant) -> io::Result<()> {
+            self.buf.extend_from_slice(bytes);
+            self.last_seen = now;
+            Ok(())
+        }
+
+        fn poll_event(&mut self) -> Option<Vec<u8>> {
+            let i = self.buf.iter().position(|&b| b == b'\n')?;
+            let mut line: Vec<u8> = self.buf.drain(..=i).collect();
+            line.pop();
+            Some(line)
+        }
+
+        fn poll_transmit(&mut self, buf: &mut Vec<u8>) {
+            buf.append(&mut self.out);
+        }
+
+        fn poll_timeout(&self) -> Option<Instant> {
+            Some(self.last_seen + self.idle)
+        }
+
+        fn handle_timeout(&mut self, now: Instant) {
+            if now >= self.last_seen + self.idle {
+                self.out.extend_from_slice(b"idle\n");
+                self.closed = true;
+            }
+        }
+
+        fn is_closed(&self) -> bool {
+            self.closed
+        }
+    }
+
+    #[test]
+    fn test_drive() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let driving = thread::spawn(move || {
+            let mut lines = Lines {
+                buf: Vec::new(),
+                out: Vec::new(),
+                idle: Duration::from_millis(50),
+                last_seen: Instant::now(),
+                closed: false,
+            };
+            drive(server, &mut lines, |p, mut line| {
+                line.reverse();
+                p.out.extend_from_slice(&line);
+                p.out.push(b'\n');
+                Ok(())
+            })
+        });
+        client.write_all(b"hello\nwor").unwrap();
+        client.write_all(b"ld\n").unwrap();
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "olleh\ndlrow\nidle\n");
+        driving.join().unwrap().unwrap();
+    }
+}
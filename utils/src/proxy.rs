@@ -0,0 +1,251 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    thread,
+};
+
+use crate::{
+    framing::{write_frame, FrameReader, LineReader, Prefix},
+    metrics::Counted,
+};
+
+/// Longest line forwarded by `Hook::Lines`
+pub const MAX_LINE: usize = 1 << 20;
+
+/// Stream which can be read and written from two threads and half-closed
+pub trait Duplex: Read + Write + Send + Sized {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+}
+
+impl Duplex for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+}
+
+impl Duplex for Counted<TcpStream> {
+    fn try_clone(&self) -> io::Result<Self> {
+        Counted::try_clone(self)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.get_ref().shutdown(how)
+    }
+}
+
+/// Message transform, returning `None` drops the message
+pub type Transform = Box<dyn FnMut(Vec<u8>) -> Option<Vec<u8>> + Send>;
+
+/// How one direction of a proxy is forwarded
+#[derive(Default)]
+pub enum Hook {
+    /// Bytes copied as they come
+    #[default]
+    Raw,
+    /// Newline terminated lines, given to the transform without the newline.
+    /// A last line without a newline isn't forwarded.
+    Lines(Transform),
+    /// Length prefixed frames of at most the given length, given to the
+    /// transform without the prefix. A longer frame closes the proxy.
+    Frames(Prefix, usize, Transform),
+}
+
+#[derive(Default)]
+pub struct Hooks {
+    pub a_to_b: Hook,
+    pub b_to_a: Hook,
+}
+
+/// Copies `a` to `b` and `b` to `a` concurrently until both directions end.
+/// The end of one direction is propagated by shutting down the writing half
+/// of its destination, and an error in one direction closes both.
+/// Returns the bytes written to `b` and to `a`.
+pub fn pump<A: Duplex, B: Duplex>(a: A, b: B, hooks: Hooks) -> io::Result<(u64, u64)> {
+    let (a2, b2) = (a.try_clone()?, b.try_clone()?);
+    thread::scope(|s| {
+        let forward = s.spawn(|| copy(a, b, hooks.a_to_b));
+        let backward = copy(b2, a2, hooks.b_to_a);
+        let forward = forward
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e));
+        Ok((forward?, backward?))
+    })
+}
+
+/// Copies `from` to `to` until the end of `from`, then shuts down the writing
+/// half of `to` so its peer sees the end too, rather than waiting for `to` to
+/// be dropped
+pub fn copy_and_shutdown<R: Read, W: Duplex>(from: &mut R, to: &mut W) -> io::Result<u64> {
+    let written = io::copy(from, to)?;
+    to.shutdown(Shutdown::Write)?;
+    Ok(written)
+}
+
+/// Sends back everything received as it comes, and closes the writing half
+/// once the peer closed its own. Returns the bytes echoed.
+pub fn echo<S: Duplex>(mut stream: S) -> io::Result<u64> {
+    let mut reader = stream.try_clone()?;
+    copy_and_shutdown(&mut reader, &mut stream)
+}
+
+fn copy<R: Duplex, W: Duplex>(mut from: R, mut to: W, hook: Hook) -> io::Result<u64> {
+    // Kept to shut the stream down while the reader owns it
+    let from_handle = from.try_clone()?;
+    let res = match hook {
+        Hook::Raw => io::copy(&mut from, &mut to),
+        Hook::Lines(mut transform) => {
+            let mut reader = LineReader::new(from, MAX_LINE);
+            let mut written = 0;
+            loop {
+                let line = match reader.read_line() {
+                    Ok(Some(line)) => line.to_vec(),
+                    Ok(None) => break Ok(written),
+                    Err(e) => break Err(e),
+                };
+                if let Some(mut line) = transform(line) {
+                    line.push(b'\n');
+                    written += line.len() as u64;
+                    if let Err(e) = to.write_all(&line) {
+                        break Err(e);
+                    }
+                }
+            }
+        }
+        Hook::Frames(prefix, max_len, mut transform) => {
+            let mut reader = FrameReader::new(from, prefix, max_len);
+            let mut written = 0;
+            loop {
+                let frame = match reader.read_frame() {
+                    Ok(Some(frame)) => frame.to_vec(),
+                    Ok(None) => break Ok(written),
+                    Err(e) => break Err(e),
+                };
+                if let Some(frame) = transform(frame) {
+                    written += (prefix.size() + frame.len()) as u64;
+                    if let Err(e) = write_frame(&mut to, prefix, &frame) {
+                        break Err(e);
+                    }
+                }
+            }
+        }
+    };
+    match res {
+        Ok(_) => {
+            let _ = to.shutdown(Shutdown::Write);
+        }
+        Err(_) => {
+            // Unblocks the other direction
+            let _ = from_handle.shutdown(Shutdown::Both);
+            let _ = to.shutdown(Shutdown::Both);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Read, Write},
+        net::{Shutdown, TcpListener, TcpStream},
+        thread,
+    };
+
+    use super::{echo, pump, Hook, Hooks};
+    use crate::framing::{write_frame, FrameReader, Prefix};
+
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+
+    #[test]
+    fn test_pump_lines() {
+        let (mut client, a) = socket_pair();
+        let (b, mut server) = socket_pair();
+        let hooks = Hooks {
+            a_to_b: Hook::Lines(Box::new(|line| {
+                (line != b"drop").then(|| line.to_ascii_uppercase())
+            })),
+            b_to_a: Hook::Raw,
+        };
+        let proxy = thread::spawn(move || pump(a, b, hooks).unwrap());
+
+        client
+            .write_all(b"hello\ndrop\nworld\nunterminated")
+            .unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"HELLO\nWORLD\n");
+
+        // The other direction still works after the half close
+        server.write_all(b"raw bytes").unwrap();
+        server.shutdown(Shutdown::Write).unwrap();
+        received.clear();
+        client.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"raw bytes");
+        assert_eq!(proxy.join().unwrap(), (12, 9));
+    }
+
+    #[test]
+    fn test_pump_frames() {
+        let (mut client, a) = socket_pair();
+        let (b, server) = socket_pair();
+        let hooks = Hooks {
+            a_to_b: Hook::Frames(
+                Prefix::U16,
+                100,
+                Box::new(|mut frame| {
+                    frame.reverse();
+                    Some(frame)
+                }),
+            ),
+            ..Hooks::default()
+        };
+        let proxy = thread::spawn(move || pump(a, b, hooks).unwrap());
+        write_frame(&mut client, Prefix::U16, b"abc").unwrap();
+        write_frame(&mut client, Prefix::U16, b"").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut reader = FrameReader::new(server, Prefix::U16, 100);
+        assert_eq!(reader.read_frame().unwrap(), Some(&b"cba"[..]));
+        assert_eq!(reader.read_frame().unwrap(), Some(&b""[..]));
+        assert_eq!(reader.read_frame().unwrap(), None);
+        drop(reader);
+        assert_eq!(proxy.join().unwrap(), (7, 0));
+
+        // A frame over the limit isn't buffered, whatever its prefix says
+        let (mut client, a) = socket_pair();
+        let (b, mut server) = socket_pair();
+        let hooks = Hooks {
+            a_to_b: Hook::Frames(Prefix::U32, 16, Box::new(Some)),
+            ..Hooks::default()
+        };
+        let proxy = thread::spawn(move || pump(a, b, hooks));
+        client.write_all(&[0xff; 4]).unwrap();
+        let err = proxy.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(server.read(&mut [0; 4]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_echo() {
+        let (mut client, server) = socket_pair();
+        let echoing = thread::spawn(move || echo(server).unwrap());
+        client.write_all(b"hello").unwrap();
+        let mut buf = [0; 5];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        client.write_all(b" world").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b" world");
+        assert_eq!(echoing.join().unwrap(), 11);
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    io::{self, Read, Write},
+    sync::{Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct State {
+    tokens: f64,
+    last: Instant,
+}
+
+/// Refills `rate` tokens per second, up to `capacity`, which is the largest
+/// burst allowed. Starts full.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<State>,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, capacity: u32) -> Self {
+        assert!(rate > 0.0 && capacity > 0);
+        Self {
+            rate,
+            capacity: capacity as f64,
+            state: Mutex::new(State {
+                tokens: capacity as f64,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    fn refill(&self, now: Instant) -> MutexGuard<'_, State> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        // The clock is monotonic, but `now` can be older than the last call
+        let elapsed = now.saturating_duration_since(state.last).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.capacity);
+        state.last = state.last.max(now);
+        state
+    }
+
+    pub fn try_acquire(&self, n: u32) -> bool {
+        self.try_acquire_at(n, Instant::now()).is_ok()
+    }
+
+    /// Takes `n` tokens if available at `now`, otherwise returns how long
+    /// until they are
+    pub fn try_acquire_at(&self, n: u32, now: Instant) -> Result<(), Duration> {
+        assert!(
+            n as f64 <= self.capacity,
+            "acquiring more than the capacity"
+        );
+        let mut state = self.refill(now);
+        if state.tokens >= n as f64 {
+            state.tokens -= n as f64;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (n as f64 - state.tokens) / self.rate,
+            ))
+        }
+    }
+
+    /// Waits until `n` tokens are available and takes them
+    pub fn acquire(&self, n: u32) {
+        while let Err(wait) = self.try_acquire_at(n, Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Whether the bucket refilled completely at `now`, so it can be dropped
+    /// and made again without changing anything
+    pub fn is_full_at(&self, now: Instant) -> bool {
+        self.refill(now).tokens >= self.capacity
+    }
+}
+
+/// Writer pacing writes to `rate` bytes per second, after a burst of `burst`
+/// bytes. Reads go through untouched.
+#[derive(Debug)]
+pub struct Shaped<W> {
+    inner: W,
+    bucket: TokenBucket,
+    burst: u32,
+}
+
+impl<W> Shaped<W> {
+    pub fn new(inner: W, rate: f64, burst: u32) -> Self {
+        Self {
+            inner,
+            bucket: TokenBucket::new(rate, burst),
+            burst,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Shaped<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.write(buf);
+        }
+        let n = buf.len().min(self.burst as usize);
+        self.bucket.acquire(n as u32);
+        self.inner.write(&buf[..n])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Shaped<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Write,
+        time::{Duration, Instant},
+    };
+
+    use super::{Shaped, TokenBucket};
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = TokenBucket::new(10.0, 3);
+        let start = Instant::now();
+        for _ in 0..3 {
+            bucket.try_acquire_at(1, start).unwrap();
+        }
+        let wait = bucket.try_acquire_at(1, start).unwrap_err();
+        assert!(wait <= Duration::from_millis(100) && wait > Duration::from_millis(90));
+        assert!(bucket
+            .try_acquire_at(2, start + Duration::from_millis(150))
+            .is_err());
+        bucket
+            .try_acquire_at(1, start + Duration::from_millis(150))
+            .unwrap();
+        // Stays capped at the capacity
+        assert!(bucket.is_full_at(start + Duration::from_secs(10)));
+        bucket
+            .try_acquire_at(3, start + Duration::from_secs(10))
+            .unwrap();
+        assert!(!bucket.is_full_at(start + Duration::from_secs(10)));
+
+        let bucket = TokenBucket::new(200.0, 1);
+        let start = Instant::now();
+        for _ in 0..3 {
+            bucket.acquire(1);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(9));
+    }
+
+    #[test]
+    fn test_shaped() {
+        let mut shaped = Shaped::new(Vec::new(), 1000.0, 10);
+        let start = Instant::now();
+        // The burst goes through, the other 20 bytes take 20ms
+        shaped.write_all(&[0; 30]).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(19));
+        assert_eq!(shaped.into_inner().len(), 30);
+    }
+}
@@ -0,0 +1,469 @@
+use std::{
+    cell::RefCell,
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, RawFd},
+        raw::{c_int, c_short},
+    },
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    protocol::Protocol,
+    slab::Slab,
+    timer::{TimerId, Wheel},
+};
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+#[cfg(target_os = "linux")]
+type Nfds = std::os::raw::c_ulong;
+#[cfg(not(target_os = "linux"))]
+type Nfds = std::os::raw::c_uint;
+
+const POLLIN: c_short = 0x1;
+const POLLOUT: c_short = 0x4;
+const POLLERR: c_short = 0x8;
+const POLLHUP: c_short = 0x10;
+const POLLNVAL: c_short = 0x20;
+
+extern "C" {
+    fn poll(fds: *mut PollFd, nfds: Nfds, timeout: c_int) -> c_int;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interest {
+    Read,
+    Write,
+    Both,
+}
+
+/// Readiness of a source. Errors and hang ups make it both readable and
+/// writable, so that the next read or write reports them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ready {
+    pub readable: bool,
+    pub writable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Token(usize);
+
+type Handler = Box<dyn FnMut(&mut Reactor, Token, Ready)>;
+type Callback = Box<dyn FnOnce(&mut Reactor)>;
+
+struct Source {
+    fd: RawFd,
+    interest: Interest,
+    /// Taken out while it runs
+    handler: Option<Handler>,
+    /// Tells apart the sources reusing a token
+    generation: u64,
+}
+
+/// Single threaded event loop waiting on sockets and timers in the same
+/// `poll(2)` call, then running the handlers of the ready sockets and the
+/// expired timers. Sockets must be set non-blocking by their owners, which
+/// deregister them before closing them.
+pub struct Reactor {
+    sources: Slab<Source>,
+    timers: Wheel<Callback>,
+    generation: u64,
+}
+
+impl Reactor {
+    /// Reactor with timers of a precision of `tick`
+    pub fn new(tick: Duration) -> Self {
+        Self {
+            sources: Slab::new(),
+            timers: Wheel::new(tick, 512, Instant::now()),
+            generation: 0,
+        }
+    }
+
+    /// Calls `handler` whenever `source` is ready for `interest`
+    pub fn register<S, F>(&mut self, source: &S, interest: Interest, handler: F) -> Token
+    where
+        S: AsRawFd,
+        F: FnMut(&mut Reactor, Token, Ready) + 'static,
+    {
+        self.generation += 1;
+        Token(self.sources.insert(Source {
+            fd: source.as_raw_fd(),
+            interest,
+            handler: Some(Box::new(handler)),
+            generation: self.generation,
+        }))
+    }
+
+    /// Changes what the source is waited for, such as writability when a
+    /// write would block
+    pub fn reregister(&mut self, token: Token, interest: Interest) {
+        if let Some(source) = self.sources.get_mut(token.0) {
+            source.interest = interest;
+        }
+    }
+
+    /// Returns false if the token wasn't registered
+    pub fn deregister(&mut self, token: Token) -> bool {
+        self.sources.remove(token.0).is_some()
+    }
+
+    /// Runs `f` after `delay`, rounded up to the next tick
+    pub fn after<F: FnOnce(&mut Reactor) + 'static>(&mut self, delay: Duration, f: F) -> TimerId {
+        self.timers.insert(Instant::now() + delay, Box::new(f))
+    }
+
+    /// Returns false if the timer already ran or was cancelled
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        self.timers.cancel(id).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty() && self.timers.is_empty()
+    }
+
+    /// Runs until no source nor timer is left
+    pub fn run(&mut self) -> io::Result<()> {
+        while !self.is_empty() {
+            self.run_once(None)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for sources or timers for at most `timeout`, forever if `None`
+    /// and no timer is set, and runs their handlers
+    pub fn run_once(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        let next_timer = self
+            .timers
+            .next_deadline()
+            .map(|d| d.saturating_duration_since(Instant::now()));
+        let wait = match (timeout, next_timer) {
+            (Some(t), Some(n)) => Some(t.min(n)),
+            (t, n) => t.or(n),
+        };
+        let mut fds: Vec<_> = self
+            .sources
+            .iter()
+            .map(|(_, s)| PollFd {
+                fd: s.fd,
+                events: match s.interest {
+                    Interest::Read => POLLIN,
+                    Interest::Write => POLLOUT,
+                    Interest::Both => POLLIN | POLLOUT,
+                },
+                revents: 0,
+            })
+            .collect();
+        let keys: Vec<_> = self
+            .sources
+            .iter()
+            .map(|(key, s)| (key, s.generation))
+            .collect();
+        let timeout_ms = match wait {
+            Some(wait) => wait.as_nanos().div_ceil(1_000_000).min(c_int::MAX as u128) as c_int,
+            None => -1,
+        };
+        // Safety: fds is a valid array of nfds pollfd structs
+        let res = unsafe { poll(fds.as_mut_ptr(), fds.len() as Nfds, timeout_ms) };
+        if res < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+            fds.iter_mut().for_each(|fd| fd.revents = 0);
+        }
+
+        for (fd, (key, generation)) in fds.iter().zip(keys) {
+            if fd.revents == 0 {
+                continue;
+            }
+            let failed = fd.revents & (POLLERR | POLLHUP | POLLNVAL) != 0;
+            let ready = Ready {
+                readable: failed || fd.revents & POLLIN != 0,
+                writable: failed || fd.revents & POLLOUT != 0,
+            };
+            // Deregistered by an earlier handler, maybe with its token reused
+            let handler = match self.sources.get_mut(key) {
+                Some(s) if s.generation == generation => s.handler.take(),
+                _ => None,
+            };
+            if let Some(mut handler) = handler {
+                handler(self, Token(key), ready);
+                match self.sources.get_mut(key) {
+                    Some(s) if s.generation == generation => s.handler = Some(handler),
+                    _ => {}
+                }
+            }
+        }
+
+        for (_, callback) in self.timers.advance(Instant::now()) {
+            callback(self);
+        }
+        Ok(())
+    }
+}
+
+struct Driven<S, P, F> {
+    stream: S,
+    protocol: P,
+    on_event: F,
+    out: Vec<u8>,
+    eof: bool,
+    token: Token,
+    timer: Option<(TimerId, Instant)>,
+    on_close: Option<Box<dyn FnOnce(io::Result<()>)>>,
+}
+
+impl<S, P, F> Driven<S, P, F>
+where
+    S: Read + Write,
+    P: Protocol,
+    F: FnMut(&mut P, P::Event) -> io::Result<()>,
+{
+    /// Returns whether the connection is done
+    fn step(&mut self, readable: bool) -> io::Result<bool> {
+        let mut buf = [0; 4096];
+        while readable && !self.eof && !self.protocol.is_closed() {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.protocol.feed_eof(Instant::now());
+                    self.eof = true;
+                }
+                Ok(n) => self.protocol.feed(&buf[..n], Instant::now())?,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        while let Some(event) = self.protocol.poll_event() {
+            (self.on_event)(&mut self.protocol, event)?;
+        }
+        self.protocol.poll_transmit(&mut self.out);
+        while !self.out.is_empty() {
+            match self.stream.write(&self.out) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => drop(self.out.drain(..n)),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(self.out.is_empty() && (self.eof || self.protocol.is_closed()))
+    }
+}
+
+fn process<S, P, F>(reactor: &mut Reactor, driven: &Rc<RefCell<Driven<S, P, F>>>, readable: bool)
+where
+    S: Read + Write + 'static,
+    P: Protocol + 'static,
+    F: FnMut(&mut P, P::Event) -> io::Result<()> + 'static,
+{
+    let mut d = driven.borrow_mut();
+    let res = d.step(readable);
+    if let Ok(false) = res {
+        let interest = match (d.out.is_empty(), d.eof || d.protocol.is_closed()) {
+            (true, _) => Interest::Read,
+            (false, true) => Interest::Write,
+            (false, false) => Interest::Both,
+        };
+        reactor.reregister(d.token, interest);
+        let deadline = d.protocol.poll_timeout();
+        if d.timer.map(|(_, at)| at) != deadline {
+            if let Some((id, _)) = d.timer.take() {
+                reactor.cancel(id);
+            }
+            if let Some(at) = deadline {
+                let driven = driven.clone();
+                let delay = at.saturating_duration_since(Instant::now());
+                let id = reactor.after(delay, move |reactor| {
+                    let mut d = driven.borrow_mut();
+                    d.timer = None;
+                    d.protocol.handle_timeout(Instant::now());
+                    drop(d);
+                    process(reactor, &driven, false);
+                });
+                d.timer = Some((id, at));
+            }
+        }
+        return;
+    }
+    reactor.deregister(d.token);
+    if let Some((id, _)) = d.timer.take() {
+        reactor.cancel(id);
+    }
+    let on_close = d.on_close.take();
+    drop(d);
+    if let Some(on_close) = on_close {
+        on_close(res.map(drop));
+    }
+}
+
+/// Runs `protocol` over the non-blocking `stream` from `reactor`, like
+/// `protocol::drive` does over a blocking one. Its timeouts are reactor
+/// timers, and `on_close` gets the outcome once the connection is done.
+pub fn drive<S, P, F, C>(
+    reactor: &mut Reactor,
+    stream: S,
+    protocol: P,
+    on_event: F,
+    on_close: C,
+) -> Token
+where
+    S: Read + Write + AsRawFd + 'static,
+    P: Protocol + 'static,
+    F: FnMut(&mut P, P::Event) -> io::Result<()> + 'static,
+    C: FnOnce(io::Result<()>) + 'static,
+{
+    let fd = stream.as_raw_fd();
+    let driven = Rc::new(RefCell::new(Driven {
+        stream,
+        protocol,
+        on_event,
+        out: Vec::new(),
+        eof: false,
+        token: Token(0),
+        timer: None,
+        on_close: Some(Box::new(on_close)),
+    }));
+    let d = driven.clone();
+    let token = reactor.register(&fd, Interest::Read, move |reactor, _, ready| {
+        process(reactor, &d, ready.readable)
+    });
+    driven.borrow_mut().token = token;
+    // Sends what the protocol starts with, and sets its first timer
+    process(reactor, &driven, false);
+    token
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::RefCell,
+        io::{self, Read, Write},
+        os::{fd::AsRawFd, unix::net::UnixStream},
+        rc::Rc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{drive, Interest, Reactor};
+    use crate::protocol::Protocol;
+
+    #[test]
+    fn test_sockets_and_timers() {
+        let mut reactor = Reactor::new(Duration::from_millis(1));
+        let (mut a, mut b) = UnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let r = received.clone();
+        let token = reactor.register(
+            &a.as_raw_fd(),
+            Interest::Read,
+            move |reactor, token, ready| {
+                assert!(ready.readable);
+                let mut buf = [0; 16];
+                loop {
+                    match a.read(&mut buf) {
+                        Ok(0) => {
+                            reactor.deregister(token);
+                            return;
+                        }
+                        Ok(n) => r.borrow_mut().extend_from_slice(&buf[..n]),
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                        Err(e) => panic!("{}", e),
+                    }
+                }
+            },
+        );
+        let start = Instant::now();
+        reactor.after(Duration::from_millis(20), move |reactor| {
+            b.write_all(b"hi").unwrap();
+            // Timers can set timers
+            reactor.after(Duration::from_millis(10), move |_| drop(b));
+        });
+        let cancelled = reactor.after(Duration::from_millis(5), |_| panic!("cancelled timer ran"));
+        assert!(reactor.cancel(cancelled));
+
+        reactor.run().unwrap();
+        assert_eq!(*received.borrow(), b"hi");
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert!(!reactor.deregister(token));
+    }
+
+    /// Echoes uppercased, saying bye after being idle for 50ms
+    struct Shout {
+        out: Vec<u8>,
+        last_seen: Instant,
+        closed: bool,
+    }
+
+    impl Protocol for Shout {
+        type Event = ();
+
+        fn feed(&mut self, bytes: &[u8], now: Instant) -> io::Result<()> {
+            self.out.extend(bytes.to_ascii_uppercase());
+            self.last_seen = now;
+            Ok(())
+        }
+
+        fn poll_event(&mut self) -> Option<()> {
+            None
+        }
+
+        fn poll_transmit(&mut self, buf: &mut Vec<u8>) {
+            buf.append(&mut self.out);
+        }
+
+        fn poll_timeout(&self) -> Option<Instant> {
+            (!self.closed).then(|| self.last_seen + Duration::from_millis(50))
+        }
+
+        fn handle_timeout(&mut self, now: Instant) {
+            if now >= self.last_seen + Duration::from_millis(50) {
+                self.out.extend_from_slice(b"bye");
+                self.closed = true;
+            }
+        }
+
+        fn is_closed(&self) -> bool {
+            self.closed
+        }
+    }
+
+    #[test]
+    fn test_drive() {
+        let mut reactor = Reactor::new(Duration::from_millis(1));
+        let (a, mut b) = UnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let shout = Shout {
+            out: b"hi ".to_vec(),
+            last_seen: Instant::now(),
+            closed: false,
+        };
+        let closed = Rc::new(RefCell::new(None));
+        let c = closed.clone();
+        drive(
+            &mut reactor,
+            a,
+            shout,
+            |_, ()| Ok(()),
+            move |res| *c.borrow_mut() = Some(res.is_ok()),
+        );
+        let client = thread::spawn(move || {
+            b.write_all(b"hello ").unwrap();
+            let mut received = String::new();
+            b.read_to_string(&mut received).unwrap();
+            received
+        });
+        reactor.run().unwrap();
+        assert_eq!(client.join().unwrap(), "hi HELLO bye");
+        assert_eq!(*closed.borrow(), Some(true));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    /// Delay before the first retransmission
+    pub initial: Duration,
+    /// Cap of the delay, which doubles after each retransmission
+    pub max: Duration,
+    /// Messages not acked this long after they were first sent are given up
+    pub give_up: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(8),
+            give_up: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pending<M> {
+    msg: M,
+    first_sent: Instant,
+    next_send: Instant,
+    delay: Duration,
+    attempts: u32,
+}
+
+/// Messages sent on an unreliable transport, kept until acked. Keys identify
+/// what an ack refers to: a sequence number, or the end offset of the data of
+/// the message for cumulative acks. The queue does no IO, `poll` calls back
+/// for the messages to send again.
+#[derive(Debug)]
+pub struct RetransmitQueue<K, M> {
+    pending: BTreeMap<K, Pending<M>>,
+    backoff: Backoff,
+}
+
+impl<K: Ord + Clone, M> RetransmitQueue<K, M> {
+    pub fn new(backoff: Backoff) -> Self {
+        Self {
+            pending: BTreeMap::new(),
+            backoff,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Tracks a message which was just sent for the first time
+    pub fn push(&mut self, key: K, msg: M, now: Instant) {
+        self.pending.insert(
+            key,
+            Pending {
+                msg,
+                first_sent: now,
+                next_send: now + self.backoff.initial,
+                delay: self.backoff.initial,
+                attempts: 1,
+            },
+        );
+    }
+
+    pub fn ack(&mut self, key: &K) -> Option<M> {
+        self.pending.remove(key).map(|p| p.msg)
+    }
+
+    /// Acks all the messages with a key up to `key` included, returning how
+    /// many were pending
+    pub fn ack_up_to(&mut self, key: &K) -> usize {
+        let rest = match self.pending.iter().find(|(k, _)| *k > key) {
+            Some((k, _)) => {
+                let k = k.clone();
+                self.pending.split_off(&k)
+            }
+            None => BTreeMap::new(),
+        };
+        let acked = self.pending.len();
+        self.pending = rest;
+        acked
+    }
+
+    /// Calls `resend` with the messages due for retransmission at `now`, and
+    /// removes and returns the ones given up on
+    pub fn poll<F>(&mut self, now: Instant, mut resend: F) -> Vec<(K, M)>
+    where
+        F: FnMut(&K, &M),
+    {
+        let mut expired = Vec::new();
+        for (key, p) in self.pending.iter_mut() {
+            if now.duration_since(p.first_sent) >= self.backoff.give_up {
+                expired.push(key.clone());
+            } else if now >= p.next_send {
+                resend(key, &p.msg);
+                p.attempts += 1;
+                p.delay = (p.delay * 2).min(self.backoff.max);
+                p.next_send = now + p.delay;
+            }
+        }
+        expired
+            .into_iter()
+            .filter_map(|k| self.pending.remove(&k).map(|p| (k, p.msg)))
+            .collect()
+    }
+
+    /// Next time `poll` has something to do
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .map(|p| p.next_send.min(p.first_sent + self.backoff.give_up))
+            .min()
+    }
+
+    /// Times a pending message was sent
+    pub fn attempts(&self, key: &K) -> Option<u32> {
+        self.pending.get(key).map(|p| p.attempts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{Backoff, RetransmitQueue};
+
+    #[test]
+    fn test_backoff() {
+        let ms = Duration::from_millis;
+        let mut queue = RetransmitQueue::new(Backoff {
+            initial: ms(100),
+            max: ms(300),
+            give_up: ms(1000),
+        });
+        let start = Instant::now();
+        queue.push(1, "a", start);
+        queue.push(2, "b", start + ms(50));
+        assert_eq!(queue.next_deadline(), Some(start + ms(100)));
+
+        let mut resent = Vec::new();
+        let mut poll = |queue: &mut RetransmitQueue<u32, &'static str>, at| {
+            let expired = queue.poll(start + ms(at), |_, m| resent.push((at, *m)));
+            expired.into_iter().map(|(_, m)| m).collect::<Vec<_>>()
+        };
+        for at in [99, 100, 150, 299, 300, 599, 600, 900, 999] {
+            assert!(poll(&mut queue, at).is_empty());
+        }
+        assert_eq!(poll(&mut queue, 1000), vec!["a"]);
+        assert_eq!(
+            resent,
+            vec![
+                (100, "a"),
+                (150, "b"),
+                (300, "a"),
+                (599, "b"),
+                (600, "a"),
+                (900, "a"),
+                (900, "b"),
+            ]
+        );
+        assert_eq!(queue.attempts(&2), Some(4));
+        assert_eq!(queue.ack(&2), Some("b"));
+        assert!(queue.is_empty());
+        assert_eq!(queue.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_cumulative_ack() {
+        let mut queue = RetransmitQueue::new(Backoff::default());
+        let now = Instant::now();
+        // Keyed by the end offset of their data
+        for end in [10, 20, 30] {
+            queue.push(end, (), now);
+        }
+        assert_eq!(queue.ack_up_to(&5), 0);
+        assert_eq!(queue.ack_up_to(&25), 2);
+        assert_eq!(queue.ack_up_to(&30), 1);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io, thread,
+    time::Duration,
+};
+
+use crate::log_debug;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    pub attempts: u32,
+    /// Delay after the first failed attempt, doubling after each one
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Errors worth another attempt, the others are returned right away
+    pub retryable: fn(&io::Error) -> bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            retryable: is_transient,
+        }
+    }
+}
+
+/// Errors which may not happen again: timeouts, refused or reset connections
+pub fn is_transient(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    matches!(
+        e.kind(),
+        ConnectionRefused
+            | ConnectionReset
+            | ConnectionAborted
+            | NotConnected
+            | AddrNotAvailable
+            | BrokenPipe
+            | TimedOut
+            | WouldBlock
+            | Interrupted
+    )
+}
+
+/// Random delay between half of `delay` and `delay`, so clients failing
+/// together don't retry together
+pub fn jitter(delay: Duration) -> Duration {
+    let r = RandomState::new().build_hasher().finish();
+    delay / 2 + delay.mul_f64((r >> 11) as f64 / (1u64 << 53) as f64 / 2.0)
+}
+
+/// Runs `op` until it succeeds, fails with an error which isn't retryable or
+/// `policy.attempts` are made, sleeping with jittered exponential backoff
+/// between attempts. `op` is given the attempt number, from 1.
+pub fn with_backoff<T, F>(policy: &Policy, mut op: F) -> io::Result<T>
+where
+    F: FnMut(u32) -> io::Result<T>,
+{
+    let mut backoff = policy.initial_backoff;
+    let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, "no attempts");
+    for attempt in 1..=policy.attempts {
+        if attempt > 1 {
+            thread::sleep(jitter(backoff));
+            backoff = (backoff * 2).min(policy.max_backoff);
+        }
+        match op(attempt) {
+            Ok(v) => return Ok(v),
+            Err(e) if (policy.retryable)(&e) => {
+                log_debug!("attempt failed: {}", e; attempt = attempt);
+                last_err = e;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_err)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, time::Duration};
+
+    use super::{jitter, with_backoff, Policy};
+
+    #[test]
+    fn test_with_backoff() {
+        let policy = Policy {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            ..Policy::default()
+        };
+        let mut calls = 0;
+        let res = with_backoff(&policy, |attempt| {
+            calls += 1;
+            match attempt {
+                3 => Ok(attempt),
+                _ => Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+            }
+        });
+        assert_eq!(res.unwrap(), 3);
+        assert_eq!(calls, 3);
+
+        calls = 0;
+        let res: io::Result<()> = with_backoff(&policy, |_| {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::TimedOut))
+        });
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert_eq!(calls, 3);
+
+        // Not retried
+        calls = 0;
+        let res: io::Result<()> = with_backoff(&policy, |_| {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        });
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let delay = Duration::from_millis(100);
+        for _ in 0..100 {
+            let d = jitter(delay);
+            assert!(d >= delay / 2 && d <= delay);
+        }
+    }
+}
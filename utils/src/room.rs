@@ -0,0 +1,227 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::hub::{Hub, SlowConsumer, Subscriber};
+
+/// Names accepted by `Room::join`
+#[derive(Debug, Clone, Copy)]
+pub struct NameRules {
+    pub min_len: usize,
+    pub max_len: usize,
+    pub is_allowed: fn(char) -> bool,
+}
+
+impl Default for NameRules {
+    fn default() -> Self {
+        Self {
+            min_len: 1,
+            max_len: 16,
+            is_allowed: |c| c.is_ascii_alphanumeric(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JoinError {
+    TooShort,
+    TooLong,
+    InvalidChar(char),
+    Taken,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::TooShort => write!(f, "name too short"),
+            JoinError::TooLong => write!(f, "name too long"),
+            JoinError::InvalidChar(c) => write!(f, "invalid character {:?} in name", c),
+            JoinError::Taken => write!(f, "name already taken"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+/// What members of a room receive, displayed as in the budget chat protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Joined(Arc<str>),
+    Left(Arc<str>),
+    Message { from: Arc<str>, text: Arc<str> },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Joined(name) => write!(f, "* {} has entered the room", name),
+            Event::Left(name) => write!(f, "* {} has left the room", name),
+            Event::Message { from, text } => write!(f, "[{}] {}", from, text),
+        }
+    }
+}
+
+/// Chat room whose members each have a unique name, and receive the messages
+/// of the others
+#[derive(Debug, Clone)]
+pub struct Room {
+    rules: NameRules,
+    names: Arc<Mutex<BTreeSet<Arc<str>>>>,
+    hub: Hub<Event>,
+}
+
+impl Room {
+    /// Room queueing at most `capacity` events per member, the members not
+    /// keeping up are disconnected
+    pub fn new(rules: NameRules, capacity: usize) -> Self {
+        Self {
+            rules,
+            names: Arc::default(),
+            hub: Hub::new(capacity, SlowConsumer::Disconnect),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeSet<Arc<str>>> {
+        self.names.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn validate(&self, name: &str) -> Result<(), JoinError> {
+        let len = name.chars().count();
+        if len < self.rules.min_len {
+            return Err(JoinError::TooShort);
+        }
+        if len > self.rules.max_len {
+            return Err(JoinError::TooLong);
+        }
+        match name.chars().find(|&c| !(self.rules.is_allowed)(c)) {
+            Some(c) => Err(JoinError::InvalidChar(c)),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds a member, announced to the others. It leaves when dropped.
+    pub fn join(&self, name: &str) -> Result<Member, JoinError> {
+        self.validate(name)?;
+        let mut names = self.lock();
+        if names.contains(name) {
+            return Err(JoinError::Taken);
+        }
+        // Under the lock, so that the others are exactly those which were
+        // told about this member
+        let others = names.iter().map(|n| n.to_string()).collect();
+        let name: Arc<str> = name.into();
+        names.insert(name.clone());
+        let sub = self.hub.subscribe();
+        let id = sub.id();
+        self.hub
+            .publish_filtered(Event::Joined(name.clone()), |to| to != id);
+        Ok(Member {
+            name,
+            others,
+            sub,
+            room: self.clone(),
+        })
+    }
+
+    /// Names of the members, sorted
+    pub fn members(&self) -> Vec<String> {
+        self.lock().iter().map(|n| n.to_string()).collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Member {
+    name: Arc<str>,
+    others: Vec<String>,
+    sub: Subscriber<Event>,
+    room: Room,
+}
+
+impl Member {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Members already there when this one joined, sorted
+    pub fn others(&self) -> &[String] {
+        &self.others
+    }
+
+    /// Sends `text` to the other members, returning how many got it
+    pub fn send(&self, text: &str) -> usize {
+        let event = Event::Message {
+            from: self.name.clone(),
+            text: text.into(),
+        };
+        let id = self.sub.id();
+        self.room.hub.publish_filtered(event, |to| to != id)
+    }
+
+    /// Next event, `None` if disconnected for not keeping up
+    pub fn recv(&self) -> Option<Event> {
+        self.sub.recv()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Event> {
+        self.sub.recv_timeout(timeout)
+    }
+
+    pub fn try_recv(&self) -> Option<Event> {
+        self.sub.try_recv()
+    }
+}
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        let mut names = self.room.lock();
+        names.remove(&self.name);
+        let id = self.sub.id();
+        self.room
+            .hub
+            .publish_filtered(Event::Left(self.name.clone()), |to| to != id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, JoinError, NameRules, Room};
+
+    #[test]
+    fn test_names() {
+        let room = Room::new(NameRules::default(), 8);
+        assert_eq!(room.join("").unwrap_err(), JoinError::TooShort);
+        assert_eq!(room.join(&"a".repeat(17)).unwrap_err(), JoinError::TooLong);
+        assert_eq!(
+            room.join("bob smith").unwrap_err(),
+            JoinError::InvalidChar(' ')
+        );
+        let bob = room.join("bob").unwrap();
+        assert_eq!(room.join("bob").unwrap_err(), JoinError::Taken);
+        drop(bob);
+        room.join("bob").unwrap();
+    }
+
+    #[test]
+    fn test_presence_and_messages() {
+        let room = Room::new(NameRules::default(), 8);
+        let alice = room.join("alice").unwrap();
+        assert!(alice.others().is_empty());
+        let bob = room.join("bob").unwrap();
+        assert_eq!(bob.others(), ["alice"]);
+        assert_eq!(room.members(), ["alice", "bob"]);
+        assert_eq!(
+            alice.try_recv().unwrap().to_string(),
+            "* bob has entered the room"
+        );
+        // Not to the sender
+        assert_eq!(bob.send("hi"), 1);
+        assert_eq!(bob.try_recv(), None);
+        assert_eq!(alice.try_recv().unwrap().to_string(), "[bob] hi");
+        drop(bob);
+        assert_eq!(alice.try_recv(), Some(Event::Left("bob".into())));
+        assert_eq!(room.members(), ["alice"]);
+    }
+}
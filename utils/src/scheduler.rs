@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::workqueue::{Checkout, Client, JobId, WorkQueue};
+
+/// Jobs in named queues, which clients take from a set of queues, best
+/// priority first
+#[derive(Debug)]
+pub struct Scheduler<T> {
+    queue: WorkQueue<T>,
+}
+
+impl<T> Clone for Scheduler<T> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T> Default for Scheduler<T> {
+    fn default() -> Self {
+        Self {
+            queue: WorkQueue::default(),
+        }
+    }
+}
+
+impl<T: Clone> Scheduler<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&self, queue: &str, priority: u64, item: T) -> JobId {
+        self.queue.put_in(queue, priority, item)
+    }
+
+    pub fn delete(&self, id: JobId) -> bool {
+        self.queue.delete(id)
+    }
+
+    /// Ready and checked out jobs of each queue holding any
+    pub fn depths(&self) -> HashMap<String, (usize, usize)> {
+        self.queue.depths()
+    }
+
+    pub fn client(&self) -> SchedulerClient<T> {
+        SchedulerClient {
+            client: self.queue.client(),
+        }
+    }
+}
+
+/// Worker of a `Scheduler`, its jobs are put back when dropped
+#[derive(Debug)]
+pub struct SchedulerClient<T: Clone> {
+    client: Client<T>,
+}
+
+impl<T: Clone> SchedulerClient<T> {
+    /// Best job of `queues`, without waiting
+    pub fn try_get(&self, queues: &[&str]) -> Option<Checkout<T>> {
+        self.client.try_get_from(Some(queues))
+    }
+
+    pub fn get(&self, queues: &[&str]) -> Checkout<T> {
+        self.client.get_from(Some(queues), None).unwrap()
+    }
+
+    pub fn get_timeout(&self, queues: &[&str], timeout: Duration) -> Option<Checkout<T>> {
+        self.client
+            .get_from(Some(queues), Some(Instant::now() + timeout))
+    }
+
+    pub fn abort(&self, id: JobId) -> bool {
+        self.client.abort(id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    use super::Scheduler;
+
+    #[test]
+    fn test_queues() {
+        let scheduler = Scheduler::new();
+        scheduler.put("a", 1, "a1");
+        scheduler.put("b", 5, "b5");
+        scheduler.put("c", 9, "c9");
+        scheduler.put("a", 7, "a7");
+        let client = scheduler.client();
+        assert_eq!(client.try_get(&["a", "b"]).unwrap().item, "a7");
+        let job = client.try_get(&["a", "b"]).unwrap();
+        assert_eq!((job.queue.as_str(), job.item), ("b", "b5"));
+        assert_eq!(client.try_get(&["b", "missing"]), None);
+
+        let depths = scheduler.depths();
+        assert_eq!(depths["a"], (1, 1));
+        assert_eq!(depths["b"], (0, 1));
+        assert_eq!(depths["c"], (1, 0));
+        drop(client);
+        assert_eq!(scheduler.depths()["a"], (2, 0));
+    }
+
+    #[test]
+    fn test_wait_on_queue_set() {
+        let scheduler = Scheduler::new();
+        let client = scheduler.client();
+        let waiter = thread::spawn(move || (client.get(&["wanted"]).item, client));
+        thread::sleep(Duration::from_millis(10));
+        scheduler.put("other", 1, 1);
+        scheduler.put("wanted", 1, 2);
+        // Keeps the client, and so its job, until the end
+        let (item, _client) = waiter.join().unwrap();
+        assert_eq!(item, 2);
+        let client = scheduler.client();
+        assert_eq!(
+            client.get_timeout(&["wanted"], Duration::from_millis(1)),
+            None
+        );
+    }
+}
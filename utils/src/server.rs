@@ -1,50 +1,239 @@
 use std::{
+    cell::RefCell,
+    collections::HashMap,
     error::Error,
-    io,
-    net::{SocketAddr, TcpListener, TcpStream},
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    panic::AssertUnwindSafe,
+    sync::Mutex,
     thread,
+    time::Instant,
 };
 
-use crate::{log_err, log_info};
+use crate::{
+    bufpool::BufPool,
+    cancel::CancelToken,
+    codec::{Codec, Framed},
+    framing::LineReader,
+    id::IdGen,
+    log_at_throttled, log_debug, log_elapsed, log_err, log_err_throttled, log_info,
+    logging::{self, Context, Level, DEFAULT_THROTTLE},
+    metrics::{self, Counted},
+    proxy::MAX_LINE,
+    ratelimit::TokenBucket,
+};
+
+/// Connection handed to the handlers, counting the bytes exchanged
+pub type Conn = Counted<TcpStream>;
+
+type ConnHandler = dyn Fn(Conn) -> Result<(), Box<dyn Error>> + Sync;
+
+/// Answer to a line of `Server::lines`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// Sends the line, a newline is added
+    Line(String),
+    /// Sends nothing
+    Nothing,
+    /// Sends the line if any, then closes the connection
+    Close(Option<String>),
+}
+
+thread_local! {
+    static CLEANUPS: RefCell<Vec<Box<dyn FnOnce()>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs `f` once the connection handled by the current thread is done with,
+/// whether the handler returned, failed or panicked. Cleanups run in reverse
+/// order of registration, on the handler thread.
+pub fn on_disconnect<F: FnOnce() + 'static>(f: F) {
+    CLEANUPS.with(|c| c.borrow_mut().push(Box::new(f)));
+}
+
+/// Runs the cleanups registered on the current thread. `Server` calls it
+/// after each handler, other loops handling connections should too.
+pub fn run_cleanups() {
+    while let Some(f) = CLEANUPS.with(|c| c.borrow_mut().pop()) {
+        if let Err(e) = std::panic::catch_unwind(AssertUnwindSafe(f)) {
+            log_err!("connection cleanup panicked: {:?}", e);
+        }
+    }
+}
 
 pub struct Server {
-    conn_handler: Box<dyn Fn(TcpStream) -> Result<(), Box<dyn Error>> + Sync>,
+    conn_handler: Box<ConnHandler>,
+    per_ip: Option<PerIp>,
+    cancel: Option<CancelToken>,
+}
+
+struct PerIp {
+    rate: f64,
+    burst: u32,
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl PerIp {
+    fn allow(&self, ip: IpAddr) -> bool {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= 4096 {
+            // Full buckets are the same as new ones
+            let now = Instant::now();
+            buckets.retain(|_, b| !b.is_full_at(now));
+        }
+        buckets
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::new(self.rate, self.burst))
+            .try_acquire(1)
+    }
 }
 
 impl Server {
     pub fn new<F>(handler: F) -> io::Result<Self>
     where
-        F: Fn(TcpStream) -> Result<(), Box<dyn Error>> + Sync + 'static,
+        F: Fn(Conn) -> Result<(), Box<dyn Error>> + Sync + 'static,
     {
         Ok(Self {
             conn_handler: Box::new(handler),
+            per_ip: None,
+            cancel: None,
+        })
+    }
+
+    /// Closes connections from IPs opening more than `rate` per second, after
+    /// a burst of `burst` connections
+    pub fn limit_per_ip(mut self, rate: f64, burst: u32) -> Self {
+        self.per_ip = Some(PerIp {
+            rate,
+            burst,
+            buckets: Mutex::default(),
+        });
+        self
+    }
+
+    /// Stops accepting connections once `token` is cancelled, `listen` then
+    /// returns when the connections being handled are closed. Handlers
+    /// should watch a child of the token to close theirs.
+    pub fn with_cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Server whose handlers receive and send messages of the codecs made by
+    /// `make_codec` rather than bytes
+    pub fn framed<C, M, F>(make_codec: M, handler: F) -> io::Result<Self>
+    where
+        C: Codec,
+        M: Fn() -> C + Sync + 'static,
+        F: Fn(&mut Framed<Conn, C>) -> Result<(), Box<dyn Error>> + Sync + 'static,
+    {
+        Self::new(move |conn| handler(&mut Framed::new(conn, make_codec())))
+    }
+
+    /// Server answering every newline terminated line with the reply of `f`.
+    /// Lines which aren't UTF-8 are given to `f` lossily converted, and lines
+    /// longer than `max_len` close the connection. Replies are buffered until
+    /// no whole line is left to answer.
+    pub fn lines<F>(max_len: usize, f: F) -> io::Result<Self>
+    where
+        F: Fn(&str) -> Reply + Sync + 'static,
+    {
+        Self::new(move |mut conn| {
+            let mut reader = LineReader::new(conn.try_clone()?, max_len);
+            let mut out = BufPool::global().get(1024);
+            while let Some(line) = reader.read_line()? {
+                let (reply, close) = match f(&String::from_utf8_lossy(line)) {
+                    Reply::Line(reply) => (Some(reply), false),
+                    Reply::Nothing => (None, false),
+                    Reply::Close(reply) => (reply, true),
+                };
+                if let Some(reply) = reply {
+                    out.extend_from_slice(reply.as_bytes());
+                    out.push(b'\n');
+                }
+                if close {
+                    break;
+                }
+                if !reader.buffered().contains(&b'\n') {
+                    conn.write_all(&out)?;
+                    out.clear();
+                }
+            }
+            conn.write_all(&out)?;
+            Ok(())
         })
     }
 
     pub fn listen(&self, addr: SocketAddr) -> io::Result<()> {
+        self.listen_on(TcpListener::bind(addr)?)
+    }
+
+    /// Serves the connections of an already bound listener
+    pub fn listen_on(&self, listener: TcpListener) -> io::Result<()> {
+        let conn_ids = IdGen::new();
         thread::scope(|s| {
-            let listener = TcpListener::bind(addr)?;
-            log_info!("Listening on {}", addr);
+            log_info!("Listening on {}", listener.local_addr()?);
+            if let Some(token) = &self.cancel {
+                // Wakes the accept call up
+                let mut wake = listener.local_addr()?;
+                if wake.ip().is_unspecified() {
+                    wake.set_ip(match wake {
+                        SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                        SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+                    });
+                }
+                token.on_cancel(move || drop(TcpStream::connect(wake)));
+            }
             for incoming in listener.incoming() {
+                if self.cancel.as_ref().is_some_and(|t| t.is_cancelled()) {
+                    log_info!("Shutting down");
+                    break;
+                }
                 s.spawn(|| {
                     let conn = match incoming {
                         Ok(conn) => conn,
-                        Err(e) => return log_err!("accepting connection: {}", e),
+                        Err(e) => return log_err_throttled!("accepting connection: {}", e),
                     };
                     let peer = match conn.peer_addr() {
                         Ok(peer) => peer,
                         Err(e) => return log_err!("getting peer address: {}", e),
                     };
-                    eprintln!("Handling connection from {peer}");
-                    match std::panic::catch_unwind(|| (self.conn_handler)(conn)) {
-                        Ok(Ok(())) => {
-                            log_info!("Connection from {} closed", peer)
+                    if let Some(per_ip) = &self.per_ip {
+                        if !per_ip.allow(peer.ip()) {
+                            return log_at_throttled!(
+                                Level::Warn,
+                                DEFAULT_THROTTLE,
+                                "Connection rate limit reached";
+                                peer = peer
+                            );
                         }
-                        Ok(Err(e)) => log_err!("handling connection from {}: {}", peer, e),
-                        Err(e) => {
-                            log_err!("handling for connection from {} panicked: {}", peer, e);
-                            return;
+                    }
+                    let conn_id = conn_ids.next_id();
+                    let _ctx = logging::set_context(Context { conn_id, peer });
+                    log_debug!("Handling connection");
+                    let start = Instant::now();
+                    let conn = Counted::new(conn);
+                    let counts = conn.counts();
+                    metrics::CONNS_OPEN.inc();
+                    // The peer is part of the logging context from here on
+                    let res =
+                        std::panic::catch_unwind(AssertUnwindSafe(|| (self.conn_handler)(conn)));
+                    run_cleanups();
+                    metrics::CONNS_OPEN.dec();
+                    let (read, written) = (counts.read(), counts.written());
+                    metrics::CONN_DURATION_US.record_duration(start.elapsed());
+                    metrics::CONN_BYTES.record(read + written);
+                    match res {
+                        Ok(Ok(())) => {
+                            log_elapsed!(start, "Connection closed"; read = read, written = written)
                         }
+                        Ok(Err(e)) => log_err!(
+                            "handling connection: {}", e;
+                            elapsed = start.elapsed(), read = read, written = written
+                        ),
+                        Err(e) => log_err!(
+                            "handling connection panicked: {:?}", e;
+                            elapsed = start.elapsed(), read = read, written = written
+                        ),
                     };
                 });
             }
@@ -52,3 +241,112 @@ impl Server {
         })
     }
 }
+
+/// Serves a line protocol on `addr`, see `Server::lines`
+pub fn serve_lines<F>(addr: SocketAddr, f: F) -> io::Result<()>
+where
+    F: Fn(&str) -> Reply + Sync + 'static,
+{
+    Server::lines(MAX_LINE, f)?.listen(addr)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        sync::mpsc,
+        thread::{self, JoinHandle},
+    };
+
+    use super::{on_disconnect, Reply, Server};
+    use crate::cancel::CancelToken;
+
+    /// Runs the server made by `make` on a free port until `token` is
+    /// cancelled
+    fn serve<F>(token: &CancelToken, make: F) -> (SocketAddr, JoinHandle<io::Result<()>>)
+    where
+        F: FnOnce() -> Server + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let token = token.clone();
+        let listening = thread::spawn(move || make().with_cancel(token).listen_on(listener));
+        (addr, listening)
+    }
+
+    #[test]
+    fn test_cancel() {
+        let token = CancelToken::new();
+        let handlers = token.child();
+        let (addr, listening) = serve(&token, move || {
+            Server::new(move |mut conn| {
+                conn.write_all(b"hi")?;
+                handlers.wait();
+                Ok(())
+            })
+            .unwrap()
+        });
+        let mut conn = TcpStream::connect(addr).unwrap();
+        let mut buf = [0; 2];
+        conn.read_exact(&mut buf).unwrap();
+        token.cancel();
+        listening.join().unwrap().unwrap();
+        // The handler returned on cancellation
+        assert_eq!(conn.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_cleanups() {
+        let (cleaned_tx, cleaned) = mpsc::channel();
+        let token = CancelToken::new();
+        let (addr, listening) = serve(&token, move || {
+            Server::new(move |mut conn| {
+                let mut buf = [0; 1];
+                conn.read_exact(&mut buf)?;
+                let (first, second) = (cleaned_tx.clone(), cleaned_tx.clone());
+                on_disconnect(move || first.send("first").unwrap());
+                on_disconnect(move || second.send("second").unwrap());
+                match buf[0] {
+                    b'p' => panic!("handler panicked"),
+                    b'e' => Err("handler failed".into()),
+                    _ => Ok(()),
+                }
+            })
+            .unwrap()
+        });
+        for byte in b"pe." {
+            let mut conn = TcpStream::connect(addr).unwrap();
+            conn.write_all(&[*byte]).unwrap();
+            assert_eq!(cleaned.recv().unwrap(), "second");
+            assert_eq!(cleaned.recv().unwrap(), "first");
+        }
+        token.cancel();
+        listening.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_lines() {
+        let token = CancelToken::new();
+        let (addr, listening) = serve(&token, || {
+            Server::lines(16, |line| match line {
+                "quiet" => Reply::Nothing,
+                "bye" => Reply::Close(Some("bye".to_owned())),
+                _ => Reply::Line(line.to_uppercase()),
+            })
+            .unwrap()
+        });
+        let mut conn = TcpStream::connect(addr).unwrap();
+        conn.write_all(b"hello\nquiet\n\xffworld\nbye\n").unwrap();
+        let mut received = String::new();
+        conn.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "HELLO\n\u{FFFD}WORLD\nbye\n");
+
+        let mut conn = TcpStream::connect(addr).unwrap();
+        conn.write_all(b"much too long of a line\n").unwrap();
+        // Closed, possibly reset as the line wasn't read whole
+        assert!(!matches!(conn.read(&mut [0; 16]), Ok(n) if n > 0));
+        token.cancel();
+        listening.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hash, Hasher},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::timer::{Timer, TimerHandle};
+
+#[derive(Debug)]
+struct Entry<S> {
+    state: S,
+    last_touch: Instant,
+}
+
+/// Sessions expiring when not touched for `ttl`. Looking a session up
+/// through `with` touches it.
+#[derive(Debug)]
+pub struct SessionMap<K, S> {
+    sessions: Mutex<HashMap<K, Entry<S>>>,
+    ttl: Duration,
+}
+
+impl<K: Hash + Eq + Clone, S> SessionMap<K, S> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            sessions: Mutex::default(),
+            ttl,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<K, Entry<S>>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Creates a session, unless one exists for `key`, in which case `state`
+    /// is given back
+    pub fn create(&self, key: K, state: S) -> Result<(), S> {
+        let mut sessions = self.lock();
+        let now = Instant::now();
+        match sessions.get(&key) {
+            Some(entry) if now.duration_since(entry.last_touch) < self.ttl => Err(state),
+            _ => {
+                sessions.insert(
+                    key,
+                    Entry {
+                        state,
+                        last_touch: now,
+                    },
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Resets the expiry of a session, false if there is none
+    pub fn touch(&self, key: &K) -> bool {
+        self.with(key, |_| ()).is_some()
+    }
+
+    /// Runs `f` on a live session, touching it
+    pub fn with<R, F: FnOnce(&mut S) -> R>(&self, key: &K, f: F) -> Option<R> {
+        let mut sessions = self.lock();
+        let now = Instant::now();
+        let entry = sessions.get_mut(key)?;
+        if now.duration_since(entry.last_touch) >= self.ttl {
+            return None;
+        }
+        entry.last_touch = now;
+        Some(f(&mut entry.state))
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        let sessions = self.lock();
+        sessions
+            .get(key)
+            .is_some_and(|e| e.last_touch.elapsed() < self.ttl)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<S> {
+        self.lock().remove(key).map(|e| e.state)
+    }
+
+    /// Sessions, live or expired but not swept yet
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes and returns the sessions expired at `now`
+    pub fn expire(&self, now: Instant) -> Vec<(K, S)> {
+        let mut sessions = self.lock();
+        let expired: Vec<K> = sessions
+            .iter()
+            .filter(|(_, e)| now.saturating_duration_since(e.last_touch) >= self.ttl)
+            .map(|(k, _)| k.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|k| sessions.remove(&k).map(|e| (k, e.state)))
+            .collect()
+    }
+}
+
+impl<K, S> SessionMap<K, S>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+    S: Send + 'static,
+{
+    /// Expires sessions every `interval` from `timer`, handing them to
+    /// `on_expire`. Stops when the map is dropped or the handle cancelled.
+    pub fn start_sweeper<F>(
+        self: &Arc<Self>,
+        timer: &Timer,
+        interval: Duration,
+        mut on_expire: F,
+    ) -> TimerHandle
+    where
+        F: FnMut(K, S) + Send + 'static,
+    {
+        let sessions = Arc::downgrade(self);
+        timer.every(interval, move || {
+            if let Some(sessions) = sessions.upgrade() {
+                for (key, state) in sessions.expire(Instant::now()) {
+                    on_expire(key, state);
+                }
+            }
+        })
+    }
+}
+
+impl<S> SessionMap<u64, S> {
+    /// Creates a session under a new random token
+    pub fn create_token(&self, state: S) -> u64 {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let token = loop {
+            let token = RandomState::new().build_hasher().finish();
+            if !sessions.contains_key(&token) {
+                break token;
+            }
+        };
+        sessions.insert(
+            token,
+            Entry {
+                state,
+                last_touch: Instant::now(),
+            },
+        );
+        token
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{mpsc, Arc},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::SessionMap;
+    use crate::timer::Timer;
+
+    #[test]
+    fn test_lifecycle() {
+        let sessions = SessionMap::new(Duration::from_secs(60));
+        sessions.create(1, "a").unwrap();
+        assert_eq!(sessions.create(1, "b"), Err("b"));
+        assert_eq!(sessions.with(&1, |s| *s = "c"), Some(()));
+        assert_eq!(sessions.with(&2, |s| *s), None);
+        assert!(sessions.touch(&1));
+        assert!(!sessions.touch(&2));
+        let token = sessions.create_token("token");
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.contains(&token));
+
+        assert!(sessions.expire(Instant::now()).is_empty());
+        let mut expired = sessions.expire(Instant::now() + Duration::from_secs(60));
+        expired.sort();
+        let mut expected = vec![(1, "c"), (token, "token")];
+        expected.sort();
+        assert_eq!(expired, expected);
+        assert!(sessions.is_empty());
+        assert_eq!(sessions.remove(&1), None);
+    }
+
+    #[test]
+    fn test_sweeper() {
+        let timer = Timer::new(Duration::from_millis(1));
+        let sessions = Arc::new(SessionMap::new(Duration::from_millis(20)));
+        sessions.create(1, "short").unwrap();
+        let (tx, rx) = mpsc::channel();
+        let _sweeper = sessions.start_sweeper(&timer, Duration::from_millis(5), move |k, s| {
+            tx.send((k, s)).unwrap();
+        });
+        // Touched sessions live on
+        sessions.create(2, "touched").unwrap();
+        for _ in 0..6 {
+            thread::sleep(Duration::from_millis(5));
+            sessions.touch(&2);
+        }
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            (1, "short")
+        );
+        assert!(sessions.contains(&2));
+        assert_eq!(sessions.with(&1, |_| ()), None);
+    }
+}
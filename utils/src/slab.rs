@@ -0,0 +1,182 @@
+use std::{
+    mem,
+    ops::{Index, IndexMut},
+};
+
+#[derive(Debug, Clone)]
+enum Entry<T> {
+    Occupied(T),
+    /// Next vacant entry
+    Vacant(Option<usize>),
+}
+
+/// Values stored at stable keys, reused once removed. Inserting and removing
+/// are O(1), without hashing.
+#[derive(Debug, Clone)]
+pub struct Slab<T> {
+    entries: Vec<Entry<T>>,
+    /// Most recently vacated entry
+    free: Option<usize>,
+    len: usize,
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            free: None,
+            len: 0,
+        }
+    }
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            ..Self::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Key the next insert returns
+    pub fn vacant_key(&self) -> usize {
+        self.free.unwrap_or(self.entries.len())
+    }
+
+    pub fn insert(&mut self, value: T) -> usize {
+        self.len += 1;
+        match self.free {
+            Some(key) => {
+                let entry = mem::replace(&mut self.entries[key], Entry::Occupied(value));
+                match entry {
+                    Entry::Vacant(next) => self.free = next,
+                    Entry::Occupied(_) => unreachable!("free list points to an occupied entry"),
+                }
+                key
+            }
+            None => {
+                self.entries.push(Entry::Occupied(value));
+                self.entries.len() - 1
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: usize) -> Option<T> {
+        let entry = self.entries.get_mut(key)?;
+        if let Entry::Vacant(_) = entry {
+            return None;
+        }
+        self.len -= 1;
+        let entry = mem::replace(entry, Entry::Vacant(self.free));
+        self.free = Some(key);
+        match entry {
+            Entry::Occupied(value) => Some(value),
+            Entry::Vacant(_) => unreachable!(),
+        }
+    }
+
+    pub fn get(&self, key: usize) -> Option<&T> {
+        match self.entries.get(key)? {
+            Entry::Occupied(value) => Some(value),
+            Entry::Vacant(_) => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut T> {
+        match self.entries.get_mut(key)? {
+            Entry::Occupied(value) => Some(value),
+            Entry::Vacant(_) => None,
+        }
+    }
+
+    pub fn contains(&self, key: usize) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.free = None;
+        self.len = 0;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(k, e)| match e {
+                Entry::Occupied(value) => Some((k, value)),
+                Entry::Vacant(_) => None,
+            })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> {
+        self.entries
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(k, e)| match e {
+                Entry::Occupied(value) => Some((k, value)),
+                Entry::Vacant(_) => None,
+            })
+    }
+}
+
+impl<T> Index<usize> for Slab<T> {
+    type Output = T;
+
+    fn index(&self, key: usize) -> &T {
+        self.get(key).expect("no value at slab key")
+    }
+}
+
+impl<T> IndexMut<usize> for Slab<T> {
+    fn index_mut(&mut self, key: usize) -> &mut T {
+        self.get_mut(key).expect("no value at slab key")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Slab;
+
+    #[test]
+    fn test_reuse_keys() {
+        let mut slab = Slab::new();
+        let a = slab.insert("a");
+        let b = slab.insert("b");
+        let c = slab.insert("c");
+        assert_eq!((a, b, c), (0, 1, 2));
+        assert_eq!(slab.remove(b), Some("b"));
+        assert_eq!(slab.remove(b), None);
+        assert_eq!(slab.remove(a), Some("a"));
+        assert_eq!(slab.len(), 1);
+        assert_eq!(slab.vacant_key(), a);
+        assert_eq!(slab.insert("d"), a);
+        assert_eq!(slab.insert("e"), b);
+        assert_eq!(slab.insert("f"), 3);
+        slab[c] = "C";
+        assert_eq!(
+            slab.iter().collect::<Vec<_>>(),
+            [(0, &"d"), (1, &"e"), (2, &"C"), (3, &"f")]
+        );
+        for (_, v) in slab.iter_mut() {
+            *v = "x";
+        }
+        assert_eq!(slab.get(3), Some(&"x"));
+        assert_eq!(slab.get(4), None);
+        slab.clear();
+        assert!(slab.is_empty());
+        assert_eq!(slab.insert("g"), 0);
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Bound,
+};
+
+/// Ticket for driving above the limit between two observations, with
+/// `timestamp1 < timestamp2` and the speed in hundredths of mph
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ticket {
+    pub plate: String,
+    pub road: u16,
+    pub mile1: u16,
+    pub timestamp1: u32,
+    pub mile2: u16,
+    pub timestamp2: u32,
+    pub speed: u16,
+}
+
+impl Ticket {
+    /// Days covered by the ticket
+    pub fn days(&self) -> std::ops::RangeInclusive<u32> {
+        day(self.timestamp1)..=day(self.timestamp2)
+    }
+}
+
+pub fn day(timestamp: u32) -> u32 {
+    timestamp / 86400
+}
+
+/// Plate observations by road cameras, giving the tickets for the average
+/// speed between consecutive observations of a car on a road. A car gets at
+/// most one ticket per day, later tickets on a day already ticketed are
+/// dropped.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    /// Miles of each plate on each road by timestamp
+    observations: HashMap<(String, u16), BTreeMap<u32, u16>>,
+    ticketed: HashMap<String, HashSet<u32>>,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an observation, returning the tickets it causes. Observations
+    /// can come out of order, they are compared with the ones just before and
+    /// after them.
+    pub fn observe(
+        &mut self,
+        plate: &str,
+        road: u16,
+        limit: u16,
+        mile: u16,
+        timestamp: u32,
+    ) -> Vec<Ticket> {
+        let seen = self
+            .observations
+            .entry((plate.to_owned(), road))
+            .or_default();
+        if seen.contains_key(&timestamp) {
+            return Vec::new();
+        }
+        seen.insert(timestamp, mile);
+        let before = seen.range(..timestamp).next_back();
+        let after = seen
+            .range((Bound::Excluded(timestamp), Bound::Unbounded))
+            .next();
+        let candidates: Vec<_> = [
+            before.map(|(&t, &m)| ((m, t), (mile, timestamp))),
+            after.map(|(&t, &m)| ((mile, timestamp), (m, t))),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|((mile1, timestamp1), (mile2, timestamp2))| {
+            let speed = speed(mile1, timestamp1, mile2, timestamp2, limit)?;
+            Some(Ticket {
+                plate: plate.to_owned(),
+                road,
+                mile1,
+                timestamp1,
+                mile2,
+                timestamp2,
+                speed,
+            })
+        })
+        .collect();
+        candidates.into_iter().filter(|t| self.claim(t)).collect()
+    }
+
+    /// Marks the days of a ticket, false if one of them already had one
+    fn claim(&mut self, ticket: &Ticket) -> bool {
+        let days = self.ticketed.entry(ticket.plate.clone()).or_default();
+        if ticket.days().any(|d| days.contains(&d)) {
+            return false;
+        }
+        days.extend(ticket.days());
+        true
+    }
+
+    pub fn is_ticketed(&self, plate: &str, day: u32) -> bool {
+        self.ticketed.get(plate).is_some_and(|d| d.contains(&day))
+    }
+}
+
+/// Average speed in hundredths of mph if it is at least half a mph over the
+/// limit
+fn speed(mile1: u16, timestamp1: u32, mile2: u16, timestamp2: u32, limit: u16) -> Option<u16> {
+    let distance = mile1.abs_diff(mile2) as u64;
+    let elapsed = (timestamp2 - timestamp1) as u64;
+    if elapsed == 0 || distance * 3600 * 2 < (limit as u64 * 2 + 1) * elapsed {
+        return None;
+    }
+    Some((distance * 360_000 / elapsed).min(u16::MAX as u64) as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Tracker;
+
+    #[test]
+    fn test_tickets() {
+        let mut tracker = Tracker::new();
+        // Example of the protohackers problem, a mile in 45 seconds
+        assert!(tracker.observe("UN1X", 123, 60, 8, 0).is_empty());
+        let tickets = tracker.observe("UN1X", 123, 60, 9, 45);
+        assert_eq!(tickets.len(), 1);
+        assert_eq!(
+            (
+                tickets[0].mile1,
+                tickets[0].timestamp1,
+                tickets[0].mile2,
+                tickets[0].timestamp2
+            ),
+            (8, 0, 9, 45)
+        );
+        assert_eq!(tickets[0].speed, 8000);
+        // Same day
+        assert!(tracker.observe("UN1X", 123, 60, 10, 90).is_empty());
+        assert!(tracker.is_ticketed("UN1X", 0));
+
+        // Under limit + 0.5, then out of order observations
+        assert!(tracker.observe("RE05BKG", 1, 60, 0, 0).is_empty());
+        assert!(tracker.observe("RE05BKG", 1, 60, 1, 119).is_empty());
+        let tickets = tracker.observe("RE05BKG", 1, 60, 100, 86400 * 2);
+        assert!(tickets.is_empty());
+        let tickets = tracker.observe("RE05BKG", 1, 60, 50, 86400 * 2 - 60);
+        assert_eq!(tickets.len(), 1);
+        assert_eq!((tickets[0].mile1, tickets[0].mile2), (50, 100));
+        assert_eq!(tickets[0].speed, u16::MAX);
+
+        // Last timestamp, with nothing after it
+        assert!(tracker.observe("LATE", 1, 60, 0, 0).is_empty());
+        assert!(tracker.observe("LATE", 1, 60, 1, u32::MAX).is_empty());
+        let tickets = tracker.observe("LATE", 1, 60, 100, u32::MAX - 60);
+        assert_eq!(
+            (tickets[0].timestamp1, tickets[0].timestamp2),
+            (u32::MAX - 60, u32::MAX)
+        );
+    }
+
+    #[test]
+    fn test_one_ticket_per_day() {
+        let mut tracker = Tracker::new();
+        tracker.observe("CAR", 1, 50, 0, 86400 - 60);
+        // Ticket over two days
+        assert_eq!(tracker.observe("CAR", 1, 50, 2, 86400 + 60).len(), 1);
+        assert!(tracker.is_ticketed("CAR", 0) && tracker.is_ticketed("CAR", 1));
+        // Another road, on the second day
+        tracker.observe("CAR", 2, 50, 0, 86400 + 1000);
+        assert!(tracker.observe("CAR", 2, 50, 10, 86400 + 1060).is_empty());
+        tracker.observe("CAR", 2, 50, 0, 86400 * 2);
+        assert_eq!(tracker.observe("CAR", 2, 50, 10, 86400 * 2 + 60).len(), 1);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    any::Any,
+    error::Error,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    thread::{self, JoinHandle},
+};
+
+use crate::{cancel::CancelToken, log_err, logging};
+
+pub type TaskError = Box<dyn Error + Send + Sync>;
+
+/// Helper threads of a handler. The group is cancelled when one of them fails
+/// or the group is dropped, and dropping it waits for all of them, so none
+/// outlives the handler. Tasks blocked on IO should register an `on_cancel`
+/// callback on their token shutting the socket down.
+#[derive(Debug, Default)]
+pub struct TaskGroup {
+    token: CancelToken,
+    tasks: Vec<JoinHandle<Result<(), TaskError>>>,
+}
+
+impl TaskGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Group cancelled with `parent`
+    pub fn child_of(parent: &CancelToken) -> Self {
+        Self {
+            token: parent.child(),
+            tasks: Vec::new(),
+        }
+    }
+
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+
+    pub fn cancel(&self) {
+        self.token.cancel()
+    }
+
+    /// Runs `f` on a new thread, with the logging context of the caller
+    pub fn spawn<F>(&mut self, f: F)
+    where
+        F: FnOnce(CancelToken) -> Result<(), TaskError> + Send + 'static,
+    {
+        let token = self.token.clone();
+        let ctx = logging::context();
+        self.tasks.push(thread::spawn(move || {
+            let _ctx = ctx.map(logging::set_context);
+            let res = panic::catch_unwind(AssertUnwindSafe(|| f(token.clone())))
+                .unwrap_or_else(|e| Err(panicked(e)));
+            if res.is_err() {
+                token.cancel();
+            }
+            res
+        }));
+    }
+
+    /// Waits for all the tasks, returning the error of the first one spawned
+    /// which failed
+    pub fn join(mut self) -> Result<(), TaskError> {
+        self.join_all()
+    }
+
+    fn join_all(&mut self) -> Result<(), TaskError> {
+        let mut res = Ok(());
+        for task in mem::take(&mut self.tasks) {
+            let task_res = task.join().unwrap_or_else(|e| Err(panicked(e)));
+            if res.is_ok() {
+                res = task_res;
+            }
+        }
+        res
+    }
+}
+
+fn panicked(e: Box<dyn Any + Send>) -> TaskError {
+    let msg = match (e.downcast_ref::<&str>(), e.downcast_ref::<String>()) {
+        (Some(msg), _) => msg.to_string(),
+        (_, Some(msg)) => msg.clone(),
+        _ => "unknown payload".to_owned(),
+    };
+    format!("task panicked: {}", msg).into()
+}
+
+impl Drop for TaskGroup {
+    fn drop(&mut self) {
+        if self.tasks.is_empty() {
+            return;
+        }
+        self.token.cancel();
+        if let Err(e) = self.join_all() {
+            log_err!("helper task: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::TaskGroup;
+    use crate::cancel::CancelToken;
+
+    #[test]
+    fn test_drop_cancels_and_joins() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let parent = CancelToken::new();
+        let mut group = TaskGroup::child_of(&parent);
+        for _ in 0..3 {
+            let stopped = stopped.clone();
+            group.spawn(move |token| {
+                token.wait();
+                stopped.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            });
+        }
+        drop(group);
+        assert_eq!(stopped.load(Ordering::SeqCst), 3);
+        assert!(!parent.is_cancelled());
+    }
+
+    #[test]
+    fn test_failure_cancels_group() {
+        let mut group = TaskGroup::new();
+        group.spawn(|token| {
+            assert!(token.wait_timeout(Duration::from_secs(10)));
+            Ok(())
+        });
+        group.spawn(|_| Err("writer failed".into()));
+        assert_eq!(group.join().unwrap_err().to_string(), "writer failed");
+
+        let mut group = TaskGroup::new();
+        group.spawn(|_| panic!("oops"));
+        assert_eq!(group.join().unwrap_err().to_string(), "task panicked: oops");
+    }
+}
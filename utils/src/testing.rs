@@ -0,0 +1,360 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    codec::{BytesBuf, Codec},
+    json::arbitrary::Rng,
+    packet::Packet,
+    retry::{self, with_backoff},
+    wire::Writer,
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DuplexOptions {
+    /// Delay before written bytes can be read
+    pub latency: Duration,
+    /// Max bytes returned by a read, unlimited if 0, to exercise the handling
+    /// of short reads
+    pub max_read: usize,
+}
+
+#[derive(Debug, Default)]
+struct PipeState {
+    /// Written chunks and when they can be read
+    chunks: VecDeque<(Instant, Vec<u8>)>,
+    /// Bytes of the front chunk already read
+    offset: usize,
+    write_closed: bool,
+    read_closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    cond: Condvar,
+}
+
+impl Pipe {
+    fn lock(&self) -> MutexGuard<'_, PipeState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn close_write(&self) {
+        self.lock().write_closed = true;
+        self.cond.notify_all();
+    }
+
+    fn close_read(&self) {
+        self.lock().read_closed = true;
+    }
+}
+
+/// End of an in-memory connection. Dropping it closes the connection, the
+/// peer then reads EOF and its writes fail with `BrokenPipe`.
+#[derive(Debug)]
+pub struct MemStream {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    options: DuplexOptions,
+}
+
+/// Result of `f` once it succeeds, retrying quickly for about a second, for
+/// things taking a moment to be ready such as a server thread listening
+pub fn eventually<T, F: FnMut() -> io::Result<T>>(mut f: F) -> T {
+    let policy = retry::Policy {
+        attempts: 50,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(50),
+        retryable: |_| true,
+    };
+    with_backoff(&policy, |_| f()).unwrap()
+}
+
+/// Pair of connected in-memory streams
+pub fn duplex() -> (MemStream, MemStream) {
+    duplex_with(DuplexOptions::default())
+}
+
+pub fn duplex_with(options: DuplexOptions) -> (MemStream, MemStream) {
+    let (a, b) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+    (
+        MemStream {
+            incoming: a.clone(),
+            outgoing: b.clone(),
+            options,
+        },
+        MemStream {
+            incoming: b,
+            outgoing: a,
+            options,
+        },
+    )
+}
+
+impl MemStream {
+    /// Closes the writing half, like `TcpStream::shutdown(Shutdown::Write)`
+    pub fn shutdown_write(&self) {
+        self.outgoing.close_write();
+    }
+}
+
+impl Read for MemStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.incoming;
+        let mut state = pipe.lock();
+        loop {
+            let now = Instant::now();
+            match state.chunks.front() {
+                Some(&(ready, _)) if ready <= now => break,
+                Some(&(ready, _)) => {
+                    state = pipe
+                        .cond
+                        .wait_timeout(state, ready - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None if state.write_closed => return Ok(0),
+                None => state = pipe.cond.wait(state).unwrap_or_else(|e| e.into_inner()),
+            }
+        }
+        let offset = state.offset;
+        let chunk = &state.chunks.front().unwrap().1[offset..];
+        let mut n = chunk.len().min(buf.len());
+        if self.options.max_read > 0 {
+            n = n.min(self.options.max_read);
+        }
+        buf[..n].copy_from_slice(&chunk[..n]);
+        if n == chunk.len() {
+            state.chunks.pop_front();
+            state.offset = 0;
+        } else {
+            state.offset += n;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MemStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.outgoing.lock();
+        if state.read_closed || state.write_closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        if !buf.is_empty() {
+            let ready = Instant::now() + self.options.latency;
+            state.chunks.push_back((ready, buf.to_vec()));
+            self.outgoing.cond.notify_all();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MemStream {
+    fn drop(&mut self) {
+        self.outgoing.close_write();
+        self.incoming.close_read();
+    }
+}
+
+/// Input for a decoder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Case {
+    /// Whole packet
+    Valid(Vec<u8>),
+    /// Strict prefix of a packet
+    Truncated(Vec<u8>),
+    /// Packet with random changes, which may or may not be valid
+    Mutated(Vec<u8>),
+}
+
+/// Encodings of the `samples`, all their truncations, and `mutations`
+/// mutated copies of each, reproducible from `seed`
+pub fn packet_corpus<P: Packet>(samples: &[P], mutations: usize, seed: u64) -> Vec<Case> {
+    let mut rng = Rng::new(seed);
+    let mut cases = Vec::new();
+    for sample in samples {
+        let mut w = Writer::new(Vec::new());
+        sample.write_to(&mut w).unwrap();
+        let bytes = w.into_inner();
+        cases.extend((0..bytes.len()).map(|len| Case::Truncated(bytes[..len].to_vec())));
+        for _ in 0..mutations {
+            cases.push(Case::Mutated(mutate(&bytes, &mut rng)));
+        }
+        cases.push(Case::Valid(bytes));
+    }
+    cases
+}
+
+fn mutate(bytes: &[u8], rng: &mut Rng) -> Vec<u8> {
+    let mut bytes = bytes.to_vec();
+    let i = rng.below(bytes.len() as u64 + 1) as usize;
+    match rng.below(5) {
+        0 if i < bytes.len() => bytes[i] ^= 1 << rng.below(8),
+        // Lengths and counts as large as they go
+        1 if i < bytes.len() => bytes[i] = 0xff,
+        2 if i < bytes.len() => bytes[i] = rng.next_u64() as u8,
+        3 => bytes.insert(i, rng.next_u64() as u8),
+        _ => bytes.extend((0..rng.below(8)).map(|_| rng.next_u64() as u8)),
+    }
+    bytes
+}
+
+/// Decodes every case with a new codec from `make_codec`, panicking with the
+/// case if the decoder panics, fails on a valid packet, doesn't consume it
+/// whole, or doesn't wait for more bytes on a truncated one. Mutated cases
+/// only have to be decoded or rejected with an error.
+pub fn check_decoder<C: Codec, F: FnMut() -> C>(mut make_codec: F, cases: &[Case]) {
+    for case in cases {
+        let (Case::Valid(bytes) | Case::Truncated(bytes) | Case::Mutated(bytes)) = case;
+        let mut codec = make_codec();
+        let mut buf = BytesBuf::new();
+        buf.extend_from_slice(bytes);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            // Decodes until done, with a bound as each message consumes bytes
+            let mut results = Vec::new();
+            for _ in 0..=bytes.len() {
+                match codec.decode(&mut buf) {
+                    Ok(Some(_)) => results.push(true),
+                    Ok(None) => break,
+                    Err(_) => {
+                        results.push(false);
+                        break;
+                    }
+                }
+            }
+            (results, buf.len())
+        }));
+        let (results, left) = match res {
+            Ok(res) => res,
+            Err(_) => panic!("decoder panicked on {:?}", case),
+        };
+        let ok = match case {
+            Case::Valid(_) => results == [true] && left == 0,
+            Case::Truncated(_) => results.is_empty(),
+            Case::Mutated(_) => true,
+        };
+        assert!(ok, "decoder mishandled {:?}", case);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, BufRead, BufReader, Read, Write},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{check_decoder, duplex, duplex_with, packet_corpus, Case, DuplexOptions};
+    use crate::packet::{LenStr, PacketCodec};
+
+    #[test]
+    fn test_echo() {
+        let (mut client, server) = duplex();
+        let echo = thread::spawn(move || {
+            let mut reader = BufReader::new(server);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                reader
+                    .get_mut()
+                    .write_all(line.to_uppercase().as_bytes())
+                    .unwrap();
+                line.clear();
+            }
+        });
+        client.write_all(b"hello\nworld\n").unwrap();
+        client.shutdown_write();
+        let mut out = String::new();
+        client.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "HELLO\nWORLD\n");
+        echo.join().unwrap();
+        let err = client.write_all(b"late").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_latency_and_short_reads() {
+        let (mut a, mut b) = duplex_with(DuplexOptions {
+            latency: Duration::from_millis(20),
+            max_read: 2,
+        });
+        let start = Instant::now();
+        a.write_all(b"hello").unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(b.read(&mut buf).unwrap(), 2);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(&buf[..2], b"he");
+        drop(a);
+        let mut rest = Vec::new();
+        b.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"llo");
+    }
+
+    crate::define_packets! {
+        enum Message {
+            Error = 0x10 { msg: LenStr },
+            Ticket = 0x21 { plate: LenStr, road: u16, days: Vec<u32> },
+        }
+    }
+
+    #[test]
+    fn test_packet_corpus() {
+        let samples = [
+            Message::Error {
+                msg: "bad".to_owned(),
+            },
+            Message::Ticket {
+                plate: "UN1X".to_owned(),
+                road: 66,
+                days: vec![1, 2],
+            },
+        ];
+        let cases = packet_corpus(&samples, 100, 1);
+        assert_eq!(cases, packet_corpus(&samples, 100, 1));
+        assert_eq!(
+            cases.iter().filter(|c| matches!(c, Case::Valid(_))).count(),
+            2
+        );
+        // 5 and 17 bytes long
+        assert_eq!(
+            cases
+                .iter()
+                .filter(|c| matches!(c, Case::Truncated(_)))
+                .count(),
+            22
+        );
+        check_decoder(PacketCodec::<Message, Message>::new, &cases);
+    }
+
+    #[test]
+    #[should_panic(expected = "decoder panicked")]
+    fn test_check_decoder_panics() {
+        check_decoder(PanickingCodec::default, &[Case::Mutated(vec![0xff])]);
+    }
+
+    #[derive(Default)]
+    struct PanickingCodec;
+
+    impl crate::codec::Codec for PanickingCodec {
+        type In = ();
+        type Out = ();
+
+        fn decode(&mut self, _: &mut crate::codec::BytesBuf) -> std::io::Result<Option<()>> {
+            panic!("garbage")
+        }
+
+        fn encode(&mut self, _: (), _: &mut Vec<u8>) {}
+    }
+}
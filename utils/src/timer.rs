@@ -0,0 +1,341 @@
+use std::{
+    collections::HashMap,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{self, Clock},
+    log_err,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+struct Slot<T> {
+    id: TimerId,
+    tick: u64,
+    item: T,
+}
+
+/// Hashed timing wheel: timers are rounded up to the next tick, and inserting
+/// or cancelling one is O(1). Driven by calling `advance`, from the `Timer`
+/// thread or any other loop.
+pub struct Wheel<T> {
+    tick: Duration,
+    start: Instant,
+    /// Ticks already expired
+    current: u64,
+    slots: Vec<Vec<Slot<T>>>,
+    /// Slot of each timer
+    index: HashMap<TimerId, usize>,
+    next_id: u64,
+}
+
+impl<T> Wheel<T> {
+    pub fn new(tick: Duration, slots: usize, start: Instant) -> Self {
+        assert!(!tick.is_zero() && slots > 0);
+        Self {
+            tick,
+            start,
+            current: 0,
+            slots: (0..slots).map(|_| Vec::new()).collect(),
+            index: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn tick(&self) -> Duration {
+        self.tick
+    }
+
+    /// Schedules `item` to be returned by the first `advance` at or after
+    /// `deadline`, rounded up to the next tick
+    pub fn insert(&mut self, deadline: Instant, item: T) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.insert_with_id(id, deadline, item);
+        id
+    }
+
+    fn insert_with_id(&mut self, id: TimerId, deadline: Instant, item: T) {
+        let since_start = deadline.saturating_duration_since(self.start);
+        let tick = since_start.as_nanos().div_ceil(self.tick.as_nanos()) as u64;
+        let tick = tick.max(self.current + 1);
+        let slot = (tick % self.slots.len() as u64) as usize;
+        self.slots[slot].push(Slot { id, tick, item });
+        self.index.insert(id, slot);
+    }
+
+    /// When the earliest timer expires, rounded up to its tick
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let tick = self.slots.iter().flatten().map(|s| s.tick).min()?;
+        let nanos = (tick as u128 * self.tick.as_nanos()).min(u64::MAX as u128);
+        Some(self.start + Duration::from_nanos(nanos as u64))
+    }
+
+    /// Removes a timer, returning its item if it hadn't expired yet
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        let slot = &mut self.slots[self.index.remove(&id)?];
+        let i = slot.iter().position(|s| s.id == id)?;
+        Some(slot.swap_remove(i).item)
+    }
+
+    /// Removes and returns the timers expired at `now`, in deadline order
+    pub fn advance(&mut self, now: Instant) -> Vec<(TimerId, T)> {
+        let target =
+            (now.saturating_duration_since(self.start).as_nanos() / self.tick.as_nanos()) as u64;
+        let mut expired = Vec::new();
+        if target <= self.current {
+            return Vec::new();
+        }
+        // Each slot only has to be visited once
+        let n = self.slots.len() as u64;
+        let visits = (target - self.current).min(n);
+        for tick in target - visits + 1..=target {
+            let slot = &mut self.slots[(tick % n) as usize];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].tick <= target {
+                    let s = slot.swap_remove(i);
+                    self.index.remove(&s.id);
+                    expired.push((s.tick, s.id, s.item));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.current = target;
+        expired.sort_by_key(|&(tick, id, _)| (tick, id.0));
+        expired
+            .into_iter()
+            .map(|(_, id, item)| (id, item))
+            .collect()
+    }
+}
+
+enum Task {
+    Once(Box<dyn FnOnce() + Send>),
+    Every(Duration, Arc<Mutex<dyn FnMut() + Send>>),
+}
+
+struct State {
+    wheel: Wheel<Task>,
+    shutdown: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    cond: Condvar,
+    clock: Arc<dyn Clock>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Runs callbacks after a delay or periodically, on a dedicated thread.
+/// Callbacks should be short, as they delay the ones after them.
+pub struct Timer {
+    shared: Arc<Shared>,
+}
+
+impl Timer {
+    /// Timer with a precision of `tick`
+    pub fn new(tick: Duration) -> Self {
+        Self::with_clock(tick, clock::system())
+    }
+
+    /// Timer running callbacks by the time of `clock`, checked every real
+    /// `tick`
+    pub fn with_clock(tick: Duration, clock: Arc<dyn Clock>) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                wheel: Wheel::new(tick, 512, clock.now()),
+                shutdown: false,
+            }),
+            cond: Condvar::new(),
+            clock,
+        });
+        let background = shared.clone();
+        thread::spawn(move || run(&background));
+        Self { shared }
+    }
+
+    pub fn after<F>(&self, delay: Duration, f: F) -> TimerHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.schedule(delay, Task::Once(Box::new(f)))
+    }
+
+    /// Runs `f` every `period`, starting one period from now
+    pub fn every<F>(&self, period: Duration, f: F) -> TimerHandle
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.schedule(period, Task::Every(period, Arc::new(Mutex::new(f))))
+    }
+
+    fn schedule(&self, delay: Duration, task: Task) -> TimerHandle {
+        let at = self.shared.clock.now() + delay;
+        let id = self.shared.lock().wheel.insert(at, task);
+        self.shared.cond.notify_one();
+        TimerHandle {
+            id,
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.cond.notify_one();
+    }
+}
+
+pub struct TimerHandle {
+    id: TimerId,
+    shared: Weak<Shared>,
+}
+
+impl TimerHandle {
+    /// Stops the timer, returning false if it already ran or was cancelled.
+    /// A periodic callback currently running finishes.
+    pub fn cancel(&self) -> bool {
+        match self.shared.upgrade() {
+            Some(shared) => shared.lock().wheel.cancel(self.id).is_some(),
+            None => false,
+        }
+    }
+}
+
+fn run(shared: &Shared) {
+    let mut state = shared.lock();
+    loop {
+        if state.shutdown {
+            return;
+        }
+        state = if state.wheel.is_empty() {
+            shared.cond.wait(state).unwrap_or_else(|e| e.into_inner())
+        } else {
+            let tick = state.wheel.tick();
+            let res = shared.cond.wait_timeout(state, tick);
+            res.unwrap_or_else(|e| e.into_inner()).0
+        };
+        let now = shared.clock.now();
+        let expired = state.wheel.advance(now);
+        for (id, task) in &expired {
+            if let Task::Every(period, f) = task {
+                let task = Task::Every(*period, f.clone());
+                state.wheel.insert_with_id(*id, now + *period, task);
+            }
+        }
+        drop(state);
+        for (_, task) in expired {
+            let res = std::panic::catch_unwind(AssertUnwindSafe(|| match task {
+                Task::Once(f) => f(),
+                Task::Every(_, f) => (f.lock().unwrap_or_else(|e| e.into_inner()))(),
+            }));
+            if let Err(e) = res {
+                log_err!("timer callback panicked: {:?}", e);
+            }
+        }
+        state = shared.lock();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{Timer, Wheel};
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_wheel() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut wheel = Wheel::new(ms(10), 8, start);
+        wheel.insert(start + ms(25), "a");
+        let b = wheel.insert(start + ms(5), "b");
+        // More than a full turn of the wheel away
+        wheel.insert(start + ms(200), "c");
+        let d = wheel.insert(start + ms(30), "d");
+        wheel.insert(start, "now");
+        assert_eq!(wheel.len(), 5);
+        assert_eq!(wheel.cancel(d), Some("d"));
+        assert_eq!(wheel.cancel(d), None);
+        assert_eq!(wheel.next_deadline(), Some(start + ms(10)));
+
+        let items = |v: Vec<(_, &'static str)>| v.into_iter().map(|(_, i)| i).collect::<Vec<_>>();
+        assert_eq!(items(wheel.advance(start + ms(9))), Vec::<&str>::new());
+        assert_eq!(items(wheel.advance(start + ms(10))), vec!["b", "now"]);
+        assert_eq!(wheel.cancel(b), None);
+        assert_eq!(wheel.next_deadline(), Some(start + ms(30)));
+        assert_eq!(items(wheel.advance(start + ms(100))), vec!["a"]);
+        assert_eq!(wheel.next_deadline(), Some(start + ms(200)));
+        assert_eq!(items(wheel.advance(start + ms(1000))), vec!["c"]);
+        assert!(wheel.is_empty());
+        assert_eq!(wheel.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_timer() {
+        let timer = Timer::new(Duration::from_millis(1));
+        let (tx, rx) = mpsc::channel();
+        let start = Instant::now();
+        timer.after(Duration::from_millis(20), move || tx.send(()).unwrap());
+        let cancelled = timer.after(Duration::from_millis(10), || panic!("cancelled timer ran"));
+        assert!(cancelled.cancel());
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let count = Arc::new(AtomicU32::new(0));
+        let c = count.clone();
+        let every = timer.every(Duration::from_millis(5), move || {
+            c.fetch_add(1, Ordering::Relaxed);
+        });
+        while count.load(Ordering::Relaxed) < 3 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(every.cancel());
+        thread::sleep(Duration::from_millis(10));
+        let after_cancel = count.load(Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(count.load(Ordering::Relaxed), after_cancel);
+        assert!(!every.cancel());
+    }
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = Arc::new(MockClock::new());
+        let timer = Timer::with_clock(Duration::from_millis(1), clock.clone());
+        let (tx, rx) = mpsc::channel();
+        timer.after(Duration::from_secs(60), move || tx.send(()).unwrap());
+        clock.advance(Duration::from_secs(59));
+        assert!(rx.recv_timeout(Duration::from_millis(20)).is_err());
+        clock.advance(Duration::from_secs(1));
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+/// Prices sorted by timestamp, with the prefix sums answering range means in
+/// logarithmic time. Inserts move the later points and invalidate their sums,
+/// which are recomputed on the next query.
+#[derive(Debug, Clone)]
+pub struct PriceStore {
+    points: Vec<(i32, i32)>,
+    /// sums[i] is the total of the first i prices, valid up to sums.len()
+    sums: Vec<i64>,
+}
+
+impl Default for PriceStore {
+    fn default() -> Self {
+        Self {
+            points: Vec::new(),
+            sums: vec![0],
+        }
+    }
+}
+
+impl PriceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Adds a price, false if there is already one at `timestamp`, which is
+    /// kept
+    pub fn insert(&mut self, timestamp: i32, price: i32) -> bool {
+        match self.points.binary_search_by_key(&timestamp, |&(t, _)| t) {
+            Ok(_) => false,
+            Err(i) => {
+                self.points.insert(i, (timestamp, price));
+                self.sums.truncate(i + 1);
+                true
+            }
+        }
+    }
+
+    /// Mean of the prices from `min` to `max` included, rounded toward zero,
+    /// `None` if there are none
+    pub fn mean(&mut self, min: i32, max: i32) -> Option<i32> {
+        if min > max {
+            return None;
+        }
+        let start = self.points.partition_point(|&(t, _)| t < min);
+        let end = self.points.partition_point(|&(t, _)| t <= max);
+        if start == end {
+            return None;
+        }
+        for i in self.sums.len() - 1..end {
+            let sum = self.sums[i] + self.points[i].1 as i64;
+            self.sums.push(sum);
+        }
+        let total = self.sums[end] - self.sums[start];
+        Some((total / (end - start) as i64) as i32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PriceStore;
+
+    #[test]
+    fn test_means() {
+        // Example session of the protohackers problem
+        let mut store = PriceStore::new();
+        assert!(store.insert(12345, 101));
+        assert!(store.insert(12346, 102));
+        assert!(store.insert(12347, 100));
+        assert!(store.insert(40960, 5));
+        assert_eq!(store.mean(12288, 16384), Some(101));
+        assert!(!store.insert(12345, 0));
+
+        // Inserting before queried points invalidates their sums
+        assert!(store.insert(12000, 301));
+        assert_eq!(store.mean(12000, 12346), Some(168));
+        assert_eq!(store.mean(i32::MIN, i32::MAX), Some(121));
+        assert_eq!(store.mean(20000, 40000), None);
+        assert_eq!(store.mean(40960, 12000), None);
+        assert_eq!(store.len(), 5);
+    }
+
+    #[test]
+    fn test_no_overflow() {
+        let mut store = PriceStore::new();
+        for t in 0..1000 {
+            store.insert(t, i32::MAX);
+            store.insert(-t - 1, i32::MIN);
+        }
+        assert_eq!(store.mean(0, 999), Some(i32::MAX));
+        assert_eq!(store.mean(-1000, 999), Some(0));
+    }
+}
@@ -0,0 +1,205 @@
+use std::{borrow::Cow, error::Error, fmt};
+
+/// How a protocol splits its commands in tokens
+#[derive(Debug, Clone, Copy)]
+pub struct Rules {
+    pub is_separator: fn(char) -> bool,
+    /// Runs of separators end a single token, otherwise each one does and
+    /// empty tokens are kept
+    pub collapse: bool,
+    /// Tokens can be in double quotes, with backslash escapes
+    pub quotes: bool,
+    /// Characters allowed in tokens, quoted or not
+    pub is_allowed: fn(char) -> bool,
+    pub max_tokens: usize,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            is_separator: |c| c == ' ',
+            collapse: true,
+            quotes: false,
+            is_allowed: |c| c.is_ascii_graphic() || c == ' ',
+            max_tokens: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    /// Character at a byte offset of the line which the rules don't allow
+    Invalid {
+        pos: usize,
+        char: char,
+    },
+    /// Quote opened at a byte offset of the line and never closed
+    Unterminated {
+        pos: usize,
+    },
+    TooMany,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Invalid { pos, char } => {
+                write!(f, "invalid character {:?} at {}", char, pos)
+            }
+            TokenError::Unterminated { pos } => write!(f, "unterminated quote at {}", pos),
+            TokenError::TooMany => write!(f, "too many tokens"),
+        }
+    }
+}
+
+impl Error for TokenError {}
+
+/// Splits `line` in tokens. Tokens borrow from the line unless they contain
+/// escapes. An empty line has no tokens.
+pub fn tokenize<'a>(line: &'a str, rules: &Rules) -> Result<Vec<Cow<'a, str>>, TokenError> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < line.len() || (!rules.collapse && pos > 0) {
+        if rules.collapse {
+            pos += line[pos..]
+                .find(|c| !(rules.is_separator)(c))
+                .unwrap_or(line.len() - pos);
+            if pos == line.len() {
+                break;
+            }
+        }
+        if tokens.len() == rules.max_tokens {
+            return Err(TokenError::TooMany);
+        }
+        let (token, end) = read_token(line, pos, rules)?;
+        tokens.push(token);
+        match line[end..].chars().next() {
+            Some(sep) => pos = end + sep.len_utf8(),
+            None => break,
+        }
+    }
+    Ok(tokens)
+}
+
+/// Token starting at `pos`, and where it ends
+fn read_token<'a>(
+    line: &'a str,
+    pos: usize,
+    rules: &Rules,
+) -> Result<(Cow<'a, str>, usize), TokenError> {
+    let invalid = |i: usize, c| TokenError::Invalid {
+        pos: pos + i,
+        char: c,
+    };
+    let rest = &line[pos..];
+    if !(rules.quotes && rest.starts_with('"')) {
+        for (i, c) in rest.char_indices() {
+            if (rules.is_separator)(c) {
+                return Ok((Cow::Borrowed(&rest[..i]), pos + i));
+            }
+            if !(rules.is_allowed)(c) {
+                return Err(invalid(i, c));
+            }
+        }
+        return Ok((Cow::Borrowed(rest), line.len()));
+    }
+    let mut token = Cow::Borrowed("");
+    let mut from = 1;
+    let mut chars = rest.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        if !(rules.is_allowed)(c) {
+            return Err(invalid(i, c));
+        }
+        match c {
+            '"' => {
+                match &mut token {
+                    Cow::Borrowed(_) => token = Cow::Borrowed(&rest[from..i]),
+                    Cow::Owned(token) => token.push_str(&rest[from..i]),
+                }
+                return match chars.next() {
+                    Some((j, c)) if !(rules.is_separator)(c) => Err(invalid(j, c)),
+                    _ => Ok((token, pos + i + 1)),
+                };
+            }
+            '\\' => {
+                let Some((j, escaped)) = chars.next() else {
+                    break;
+                };
+                if !(rules.is_allowed)(escaped) {
+                    return Err(invalid(j, escaped));
+                }
+                token.to_mut().push_str(&rest[from..i]);
+                token.to_mut().push(escaped);
+                from = j + escaped.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    Err(TokenError::Unterminated { pos })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{tokenize, Rules, TokenError};
+
+    fn split(line: &str, rules: &Rules) -> Result<Vec<String>, TokenError> {
+        Ok(tokenize(line, rules)?
+            .into_iter()
+            .map(|t| t.into_owned())
+            .collect())
+    }
+
+    #[test]
+    fn test_split() {
+        let rules = Rules::default();
+        assert_eq!(
+            split("PUT /a/b  12 ", &rules).unwrap(),
+            ["PUT", "/a/b", "12"]
+        );
+        assert_eq!(split("  ", &rules).unwrap(), Vec::<String>::new());
+        assert_eq!(
+            split("a\tb", &rules),
+            Err(TokenError::Invalid { pos: 1, char: '\t' })
+        );
+        let whitespace = Rules {
+            is_separator: char::is_whitespace,
+            ..rules
+        };
+        assert_eq!(split("a\t b", &whitespace).unwrap(), ["a", "b"]);
+
+        let strict = Rules {
+            collapse: false,
+            max_tokens: 3,
+            ..rules
+        };
+        assert_eq!(split("a  b", &strict).unwrap(), ["a", "", "b"]);
+        assert_eq!(split("a ", &strict).unwrap(), ["a", ""]);
+        assert_eq!(split("", &strict).unwrap(), Vec::<String>::new());
+        assert_eq!(split("a b c d", &strict), Err(TokenError::TooMany));
+    }
+
+    #[test]
+    fn test_quotes() {
+        let rules = Rules {
+            quotes: true,
+            ..Rules::default()
+        };
+        let tokens = tokenize(r#"say "hello world" "a \"b\" \\" x"y"#, &rules).unwrap();
+        assert_eq!(tokens, ["say", "hello world", r#"a "b" \"#, r#"x"y"#]);
+        assert!(matches!(tokens[1], std::borrow::Cow::Borrowed(_)));
+        assert_eq!(split(r#""""#, &rules).unwrap(), [""]);
+        assert_eq!(
+            split(r#"a "open"#, &rules),
+            Err(TokenError::Unterminated { pos: 2 })
+        );
+        assert_eq!(
+            split(r#""a"b"#, &rules),
+            Err(TokenError::Invalid { pos: 3, char: 'b' })
+        );
+        // Quotes are literal without the option
+        assert_eq!(
+            split(r#""a b""#, &Rules::default()).unwrap(),
+            ["\"a", "b\""]
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    ops::ControlFlow,
+    time::{Duration, Instant},
+};
+
+/// Protocol run by a `Dispatcher`, with a state per session. Sessions are
+/// identified by a key taken from each datagram, the peer address or a
+/// session token.
+pub trait Handler {
+    type Key: Hash + Eq + Clone;
+    type State;
+
+    /// Key of the session the datagram belongs to, `None` to drop it
+    fn key(&mut self, datagram: &[u8], peer: SocketAddr) -> Option<Self::Key>;
+
+    /// State of a session receiving its first datagram, `None` to drop the
+    /// datagram without creating the session
+    fn open(&mut self, key: &Self::Key, datagram: &[u8], peer: SocketAddr) -> Option<Self::State>;
+
+    /// Handles a datagram, `Break` closes the session
+    fn handle(
+        &mut self,
+        socket: &UdpSocket,
+        key: &Self::Key,
+        state: &mut Self::State,
+        datagram: &[u8],
+        peer: SocketAddr,
+    ) -> ControlFlow<()>;
+
+    /// Called on sessions which didn't receive anything for the idle timeout
+    fn expired(&mut self, _key: Self::Key, _state: Self::State) {}
+}
+
+struct Session<S> {
+    state: S,
+    last_seen: Instant,
+}
+
+/// Receives datagrams and hands them to the handler along with their session
+pub struct Dispatcher<H: Handler> {
+    socket: UdpSocket,
+    idle_timeout: Duration,
+    sessions: HashMap<H::Key, Session<H::State>>,
+    last_sweep: Instant,
+    buf: Vec<u8>,
+}
+
+impl<H: Handler> Dispatcher<H> {
+    pub fn bind<A: ToSocketAddrs>(addr: A, idle_timeout: Duration) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        // Wake up regularly to expire sessions even if nothing is received
+        socket.set_read_timeout(Some(Self::sweep_interval(idle_timeout)))?;
+        Ok(Self {
+            socket,
+            idle_timeout,
+            sessions: HashMap::new(),
+            last_sweep: Instant::now(),
+            buf: vec![0; 65536],
+        })
+    }
+
+    fn sweep_interval(idle_timeout: Duration) -> Duration {
+        (idle_timeout / 4).max(Duration::from_millis(1))
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn sessions(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn run(&mut self, handler: &mut H) -> io::Result<()> {
+        loop {
+            self.step(handler)?;
+        }
+    }
+
+    /// Handles at most one datagram, waiting for it for a fraction of the
+    /// idle timeout, then expires the idle sessions
+    pub fn step(&mut self, handler: &mut H) -> io::Result<()> {
+        match self.socket.recv_from(&mut self.buf) {
+            Ok((n, peer)) => self.dispatch(handler, n, peer),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            // A previous send failing makes the next recv fail on some platforms
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+            Err(e) => return Err(e),
+        }
+        let now = Instant::now();
+        if now.duration_since(self.last_sweep) >= Self::sweep_interval(self.idle_timeout) {
+            self.last_sweep = now;
+            self.expire(handler, now);
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, handler: &mut H, n: usize, peer: SocketAddr) {
+        let datagram = &self.buf[..n];
+        let Some(key) = handler.key(datagram, peer) else {
+            return;
+        };
+        let session = match self.sessions.get_mut(&key) {
+            Some(session) => session,
+            None => match handler.open(&key, datagram, peer) {
+                Some(state) => self.sessions.entry(key.clone()).or_insert(Session {
+                    state,
+                    last_seen: Instant::now(),
+                }),
+                None => return,
+            },
+        };
+        session.last_seen = Instant::now();
+        if handler
+            .handle(&self.socket, &key, &mut session.state, datagram, peer)
+            .is_break()
+        {
+            self.sessions.remove(&key);
+        }
+    }
+
+    fn expire(&mut self, handler: &mut H, now: Instant) {
+        let idle: Vec<H::Key> = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| now.duration_since(s.last_seen) >= self.idle_timeout)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in idle {
+            if let Some(session) = self.sessions.remove(&key) {
+                handler.expired(key, session.state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{SocketAddr, UdpSocket},
+        ops::ControlFlow,
+        thread,
+        time::Duration,
+    };
+
+    use super::{Dispatcher, Handler};
+
+    /// Answers each datagram with the number of datagrams of the peer so far
+    #[derive(Default)]
+    struct Counter {
+        expired: Vec<SocketAddr>,
+    }
+
+    impl Handler for Counter {
+        type Key = SocketAddr;
+        type State = u32;
+
+        fn key(&mut self, datagram: &[u8], peer: SocketAddr) -> Option<SocketAddr> {
+            (datagram != b"drop").then_some(peer)
+        }
+
+        fn open(&mut self, _: &SocketAddr, _: &[u8], _: SocketAddr) -> Option<u32> {
+            Some(0)
+        }
+
+        fn handle(
+            &mut self,
+            socket: &UdpSocket,
+            _: &SocketAddr,
+            count: &mut u32,
+            datagram: &[u8],
+            peer: SocketAddr,
+        ) -> ControlFlow<()> {
+            *count += 1;
+            socket.send_to(count.to_string().as_bytes(), peer).unwrap();
+            if datagram == b"close" {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }
+
+        fn expired(&mut self, peer: SocketAddr, _: u32) {
+            self.expired.push(peer);
+        }
+    }
+
+    #[test]
+    fn test_sessions() {
+        let mut dispatcher = Dispatcher::bind("127.0.0.1:0", Duration::from_millis(100)).unwrap();
+        let addr = dispatcher.socket().local_addr().unwrap();
+        let mut handler = Counter::default();
+        let clients = [(); 2].map(|_| UdpSocket::bind("127.0.0.1:0").unwrap());
+        let mut buf = [0; 16];
+        let mut exchange = |dispatcher: &mut Dispatcher<Counter>, client: &UdpSocket, msg| {
+            client.send_to(msg, addr).unwrap();
+            dispatcher.step(&mut handler).unwrap();
+            let n = client.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..n].to_vec()).unwrap()
+        };
+        assert_eq!(exchange(&mut dispatcher, &clients[0], b"a"), "1");
+        assert_eq!(exchange(&mut dispatcher, &clients[0], b"b"), "2");
+        assert_eq!(exchange(&mut dispatcher, &clients[1], b"a"), "1");
+        assert_eq!(exchange(&mut dispatcher, &clients[0], b"close"), "3");
+        assert_eq!(dispatcher.sessions(), 1);
+        assert_eq!(exchange(&mut dispatcher, &clients[0], b"again"), "1");
+
+        clients[0].send_to(b"drop", addr).unwrap();
+        dispatcher.step(&mut handler).unwrap();
+        thread::sleep(Duration::from_millis(120));
+        dispatcher.step(&mut handler).unwrap();
+        assert_eq!(dispatcher.sessions(), 0);
+        assert_eq!(handler.expired.len(), 2);
+    }
+}
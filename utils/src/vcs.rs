@@ -0,0 +1,186 @@
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    InvalidPath,
+    /// Content which isn't printable text
+    InvalidContent,
+    NoSuchFile,
+    NoSuchRevision,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidPath => write!(f, "illegal file name"),
+            Error::InvalidContent => write!(f, "text files only"),
+            Error::NoSuchFile => write!(f, "no such file"),
+            Error::NoSuchRevision => write!(f, "no such revision"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Entry of a directory listing, displayed as in the code storage protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    /// File with its latest revision
+    File(String, u64),
+    Dir(String),
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::File(name, revision) => write!(f, "{} r{}", name, revision),
+            Entry::Dir(name) => write!(f, "{}/ DIR", name),
+        }
+    }
+}
+
+fn is_valid_dir(path: &str) -> bool {
+    path.starts_with('/')
+        && !path.contains("//")
+        && path
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"/._-".contains(&b))
+}
+
+fn is_valid_file(path: &str) -> bool {
+    is_valid_dir(path) && !path.ends_with('/')
+}
+
+fn is_text(data: &[u8]) -> bool {
+    data.iter()
+        .all(|&b| b.is_ascii_graphic() || b" \t\r\n".contains(&b))
+}
+
+/// Text files with numbered revisions from 1, in a tree of directories which
+/// exist as long as files are in them
+#[derive(Debug, Default)]
+pub struct Store {
+    files: BTreeMap<String, Vec<Arc<[u8]>>>,
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores a new revision of the file at `path` and returns its number,
+    /// or the number of the latest one if it has the same content
+    pub fn put(&mut self, path: &str, data: &[u8]) -> Result<u64, Error> {
+        if !is_valid_file(path) {
+            return Err(Error::InvalidPath);
+        }
+        if !is_text(data) {
+            return Err(Error::InvalidContent);
+        }
+        let revisions = self.files.entry(path.to_owned()).or_default();
+        if revisions.last().is_none_or(|last| **last != *data) {
+            revisions.push(data.into());
+        }
+        Ok(revisions.len() as u64)
+    }
+
+    /// Content of a revision of the file, the latest one if `None`
+    pub fn get(&self, path: &str, revision: Option<u64>) -> Result<Arc<[u8]>, Error> {
+        if !is_valid_file(path) {
+            return Err(Error::InvalidPath);
+        }
+        let revisions = self.files.get(path).ok_or(Error::NoSuchFile)?;
+        let revision = revision.unwrap_or(revisions.len() as u64);
+        match revision.checked_sub(1) {
+            Some(i) if i < revisions.len() as u64 => Ok(revisions[i as usize].clone()),
+            _ => Err(Error::NoSuchRevision),
+        }
+    }
+
+    /// Number of the latest revision of the file, if it exists
+    pub fn latest(&self, path: &str) -> Option<u64> {
+        self.files.get(path).map(|r| r.len() as u64)
+    }
+
+    /// Files and directories directly in `dir`, sorted by name. A name can
+    /// be both a file and a directory.
+    pub fn list(&self, dir: &str) -> Result<Vec<Entry>, Error> {
+        if !is_valid_dir(dir) {
+            return Err(Error::InvalidPath);
+        }
+        let prefix = match dir.ends_with('/') {
+            true => dir.to_owned(),
+            false => format!("{}/", dir),
+        };
+        let mut entries = Vec::new();
+        let in_dir = self.files.range(prefix.clone()..);
+        for (path, revisions) in in_dir.take_while(|(p, _)| p.starts_with(&prefix)) {
+            let entry = match path[prefix.len()..].split_once('/') {
+                Some((name, _)) => Entry::Dir(name.to_owned()),
+                None => Entry::File(path[prefix.len()..].to_owned(), revisions.len() as u64),
+            };
+            entries.push(entry);
+        }
+        // Stable, files stay before directories of the same name
+        entries.sort_by(|a, b| name(a).cmp(name(b)));
+        entries.dedup();
+        Ok(entries)
+    }
+}
+
+fn name(entry: &Entry) -> &str {
+    match entry {
+        Entry::File(name, _) | Entry::Dir(name) => name,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Entry, Error, Store};
+
+    #[test]
+    fn test_revisions() {
+        let mut store = Store::new();
+        assert_eq!(store.put("/a/b.txt", b"one\n"), Ok(1));
+        assert_eq!(store.put("/a/b.txt", b"two\n"), Ok(2));
+        // Same content as the latest revision
+        assert_eq!(store.put("/a/b.txt", b"two\n"), Ok(2));
+        assert_eq!(store.put("/a/b.txt", b"one\n"), Ok(3));
+        assert_eq!(&*store.get("/a/b.txt", Some(2)).unwrap(), b"two\n");
+        assert_eq!(&*store.get("/a/b.txt", None).unwrap(), b"one\n");
+        assert_eq!(store.get("/a/b.txt", Some(0)), Err(Error::NoSuchRevision));
+        assert_eq!(store.get("/a/b.txt", Some(4)), Err(Error::NoSuchRevision));
+        assert_eq!(store.get("/a/c.txt", None), Err(Error::NoSuchFile));
+        assert_eq!(store.latest("/a/b.txt"), Some(3));
+
+        for path in ["a.txt", "/a//b", "/a/", "/a b"] {
+            assert_eq!(store.put(path, b""), Err(Error::InvalidPath));
+        }
+        assert_eq!(store.put("/bin", b"\x00\x01"), Err(Error::InvalidContent));
+    }
+
+    #[test]
+    fn test_list() {
+        let mut store = Store::new();
+        store.put("/a/x", b"x").unwrap();
+        store.put("/a/y/z", b"z").unwrap();
+        store.put("/a/y/w", b"w").unwrap();
+        store.put("/a-b", b"").unwrap();
+        store.put("/a", b"1").unwrap();
+        store.put("/a", b"2").unwrap();
+        let list: Vec<_> = store
+            .list("/")
+            .unwrap()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(list, ["a r2", "a/ DIR", "a-b r1"]);
+        assert_eq!(
+            store.list("/a").unwrap(),
+            [Entry::File("x".to_owned(), 1), Entry::Dir("y".to_owned())]
+        );
+        assert_eq!(store.list("/a/"), store.list("/a"));
+        assert!(store.list("/none").unwrap().is_empty());
+        assert_eq!(store.list("a"), Err(Error::InvalidPath));
+    }
+}
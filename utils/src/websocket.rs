@@ -0,0 +1,531 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::{self, Read, Write},
+};
+
+use crate::codec::{BytesBuf, Codec, Framed};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(b: u8) -> Option<Self> {
+        Some(match b {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xa => Opcode::Pong,
+            _ => return None,
+        })
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Last frame of a message
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Self {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// RFC 6455 frames. Clients mask the frames they send, and servers require
+/// them to.
+#[derive(Debug, Clone)]
+pub struct FrameCodec {
+    pub max_len: usize,
+    client: bool,
+}
+
+impl FrameCodec {
+    pub fn server(max_len: usize) -> Self {
+        Self {
+            max_len,
+            client: false,
+        }
+    }
+
+    pub fn client(max_len: usize) -> Self {
+        Self {
+            max_len,
+            client: true,
+        }
+    }
+}
+
+impl Codec for FrameCodec {
+    type In = Frame;
+    type Out = Frame;
+
+    fn decode(&mut self, buf: &mut BytesBuf) -> io::Result<Option<Frame>> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let (b0, b1) = (buf[0], buf[1]);
+        if b0 & 0x70 != 0 {
+            return Err(invalid("reserved websocket frame bits set"));
+        }
+        let opcode =
+            Opcode::from_u8(b0 & 0x0f).ok_or_else(|| invalid("unknown websocket opcode"))?;
+        let fin = b0 & 0x80 != 0;
+        let masked = b1 & 0x80 != 0;
+        if masked == self.client {
+            return Err(invalid(
+                "websocket frame masking doesn't match the peer's role",
+            ));
+        }
+        let (len, mut pos) = match b1 & 0x7f {
+            126 if buf.len() >= 4 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+            127 if buf.len() >= 10 => (u64::from_be_bytes(buf[2..10].try_into().unwrap()), 10),
+            126 | 127 => return Ok(None),
+            len => (len as u64, 2),
+        };
+        if opcode.is_control() && (len > 125 || !fin) {
+            return Err(invalid("invalid websocket control frame"));
+        }
+        if len > self.max_len as u64 {
+            return Err(invalid("websocket frame too long"));
+        }
+        let len = len as usize;
+        let mask_len = if masked { 4 } else { 0 };
+        if buf.len() < pos + mask_len + len {
+            return Ok(None);
+        }
+        let mut mask = [0; 4];
+        if masked {
+            mask.copy_from_slice(&buf[pos..pos + 4]);
+            pos += 4;
+        }
+        buf.advance(pos);
+        let mut payload = buf.split_to(len);
+        if masked {
+            apply_mask(&mut payload, mask);
+        }
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+
+    fn encode(&mut self, frame: Frame, buf: &mut Vec<u8>) {
+        buf.push((frame.fin as u8) << 7 | frame.opcode.as_u8());
+        let mask_bit = (self.client as u8) << 7;
+        let len = frame.payload.len();
+        if len < 126 {
+            buf.push(mask_bit | len as u8);
+        } else if len <= u16::MAX as usize {
+            buf.push(mask_bit | 126);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            buf.push(mask_bit | 127);
+            buf.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+        let start = buf.len();
+        if self.client {
+            let mask = (RandomState::new().build_hasher().finish() as u32).to_be_bytes();
+            buf.extend_from_slice(&mask);
+            buf.extend_from_slice(&frame.payload);
+            apply_mask(&mut buf[start + 4..], mask);
+        } else {
+            buf.extend_from_slice(&frame.payload);
+        }
+    }
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
+/// Request of a client opening a websocket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handshake {
+    pub path: String,
+    /// Header names are lowercased
+    pub headers: Vec<(String, String)>,
+}
+
+impl Handshake {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+const MAX_HANDSHAKE: usize = 8192;
+
+/// Reads the HTTP upgrade request of a client, and answers it. Reads byte
+/// by byte, so nothing after the request is consumed. Answers 400 and errors
+/// with `InvalidData` if it isn't a websocket upgrade.
+pub fn handshake<S: Read + Write>(stream: &mut S) -> io::Result<Handshake> {
+    let mut request = Vec::new();
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() >= MAX_HANDSHAKE {
+            return Err(invalid("websocket handshake too long"));
+        }
+        let mut b = [0];
+        stream.read_exact(&mut b)?;
+        request.push(b[0]);
+    }
+    match parse_handshake(&request) {
+        Ok((handshake, key)) => {
+            write!(
+                stream,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&key)
+            )?;
+            Ok(handshake)
+        }
+        Err(e) => {
+            let _ = stream.write_all(
+                b"HTTP/1.1 400 Bad Request\r\nSec-WebSocket-Version: 13\r\nContent-Length: 0\r\n\r\n",
+            );
+            Err(e)
+        }
+    }
+}
+
+fn parse_handshake(request: &[u8]) -> io::Result<(Handshake, String)> {
+    let request = std::str::from_utf8(request).map_err(|_| invalid("handshake isn't UTF-8"))?;
+    let mut lines = request.split("\r\n");
+    let mut start = lines.next().unwrap_or_default().split(' ');
+    let (Some("GET"), Some(path), Some(_version)) = (start.next(), start.next(), start.next())
+    else {
+        return Err(invalid("websocket handshake isn't a GET request"));
+    };
+    let headers: Vec<(String, String)> = lines
+        .filter(|l| !l.is_empty())
+        .filter_map(|l| l.split_once(':'))
+        .map(|(n, v)| (n.trim().to_ascii_lowercase(), v.trim().to_owned()))
+        .collect();
+    let handshake = Handshake {
+        path: path.to_owned(),
+        headers,
+    };
+    let has_token = |name, token: &str| {
+        handshake
+            .header(name)
+            .is_some_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    };
+    if !has_token("upgrade", "websocket") || !has_token("connection", "upgrade") {
+        return Err(invalid("not a websocket upgrade request"));
+    }
+    if handshake.header("sec-websocket-version") != Some("13") {
+        return Err(invalid("unsupported websocket version"));
+    }
+    let key = handshake
+        .header("sec-websocket-key")
+        .ok_or_else(|| invalid("missing websocket key"))?
+        .to_owned();
+    Ok((handshake, key))
+}
+
+/// Value of the Sec-WebSocket-Accept header answering `key`
+pub fn accept_key(key: &str) -> String {
+    let mut data = key.as_bytes().to_vec();
+    data.extend_from_slice(b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11");
+    base64(&sha1(&data))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes(block[i * 4..i * 4 + 4].try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut out = [0; 20];
+    for (i, h) in h.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&h.to_be_bytes());
+    }
+    out
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Byte stream over the data frames of a websocket, so handlers written for
+/// TCP serve browsers too. Each write is sent as a message, pings are
+/// answered, and a close frame reads as the end of the stream.
+#[derive(Debug)]
+pub struct WsStream<S> {
+    framed: Framed<S, FrameCodec>,
+    opcode: Opcode,
+    pending: Vec<u8>,
+    pos: usize,
+    closed: bool,
+}
+
+/// Runs the handshake on a new connection
+pub fn accept<S: Read + Write>(
+    mut stream: S,
+    max_len: usize,
+) -> io::Result<(WsStream<S>, Handshake)> {
+    let handshake = handshake(&mut stream)?;
+    Ok((
+        WsStream::new(stream, FrameCodec::server(max_len)),
+        handshake,
+    ))
+}
+
+impl<S: Read + Write> WsStream<S> {
+    /// Stream over a websocket whose handshake is done
+    pub fn new(stream: S, codec: FrameCodec) -> Self {
+        Self {
+            framed: Framed::new(stream, codec),
+            opcode: Opcode::Binary,
+            pending: Vec::new(),
+            pos: 0,
+            closed: false,
+        }
+    }
+
+    /// Sends writes as text messages rather than binary ones
+    pub fn set_text(&mut self, text: bool) {
+        self.opcode = if text { Opcode::Text } else { Opcode::Binary };
+    }
+
+    /// Sends a close frame with `code`, if none was sent yet
+    pub fn close(&mut self, code: u16) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        self.framed
+            .send(Frame::new(Opcode::Close, code.to_be_bytes().to_vec()))
+    }
+
+    pub fn get_ref(&self) -> &S {
+        self.framed.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        self.framed.get_mut()
+    }
+}
+
+impl<S: Read + Write> Read for WsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.pending.len() {
+            let Some(frame) = self.framed.recv()? else {
+                return Ok(0);
+            };
+            match frame.opcode {
+                Opcode::Text | Opcode::Binary | Opcode::Continuation => {
+                    self.pending = frame.payload;
+                    self.pos = 0;
+                }
+                Opcode::Ping => self.framed.send(Frame::new(Opcode::Pong, frame.payload))?,
+                Opcode::Pong => {}
+                Opcode::Close => {
+                    // Echoes the status code
+                    let code = frame
+                        .payload
+                        .get(..2)
+                        .map_or(1000, |c| u16::from_be_bytes([c[0], c[1]]));
+                    self.close(code)?;
+                    return Ok(0);
+                }
+            }
+        }
+        let n = buf.len().min(self.pending.len() - self.pos);
+        buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> Write for WsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        self.framed.send(Frame::new(self.opcode, buf.to_vec()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.framed.get_mut().flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        thread,
+    };
+
+    use super::{accept, accept_key, base64, sha1, Frame, FrameCodec, Opcode, WsStream};
+    use crate::{
+        codec::{BytesBuf, Codec},
+        testing::duplex,
+    };
+
+    #[test]
+    fn test_handshake() {
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(&sha1(b"abc"))[..8], *"qZk+NkcG");
+        // Example of the RFC
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let (mut client, server) = duplex();
+        let echo = thread::spawn(move || {
+            let (ws, handshake) = accept(server, 1024).unwrap();
+            assert_eq!(handshake.path, "/chat");
+            let mut lines = BufReader::new(ws);
+            let mut line = String::new();
+            lines.read_line(&mut line).unwrap();
+            lines.get_mut().set_text(true);
+            lines
+                .get_mut()
+                .write_all(line.to_uppercase().as_bytes())
+                .unwrap();
+            // Until the close frame
+            assert_eq!(lines.read_line(&mut line).unwrap(), 0);
+        });
+        client
+            .write_all(
+                b"GET /chat HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\n\
+                  Connection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                  Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let mut ws = WsStream::new(client, FrameCodec::client(1024));
+        let mut response = [0; 129];
+        ws.get_mut().read_exact(&mut response).unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.ends_with(b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n"));
+
+        ws.write_all(b"hello\n").unwrap();
+        let mut reply = [0; 6];
+        ws.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"HELLO\n");
+        ws.close(1000).unwrap();
+        echo.join().unwrap();
+        assert_eq!(ws.read(&mut reply).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut client = FrameCodec::client(1 << 20);
+        let mut server = FrameCodec::server(1 << 20);
+        let mut buf = Vec::new();
+        for len in [0, 125, 126, 65535, 65536] {
+            client.encode(Frame::new(Opcode::Binary, vec![7; len]), &mut buf);
+        }
+        // Fed a byte at a time
+        let mut bytes = BytesBuf::new();
+        let mut frames = Vec::new();
+        for &b in &buf {
+            bytes.extend_from_slice(&[b]);
+            frames.extend(server.decode(&mut bytes).unwrap());
+        }
+        let lens: Vec<usize> = frames.iter().map(|f| f.payload.len()).collect();
+        assert_eq!(lens, [0, 125, 126, 65535, 65536]);
+        assert!(frames.iter().all(|f| f.payload.iter().all(|&b| b == 7)));
+
+        // Unmasked frame to a server, and a too long one
+        let mut bytes = BytesBuf::new();
+        bytes.extend_from_slice(b"\x82\x01a");
+        assert!(server.decode(&mut bytes).is_err());
+        let mut bytes = BytesBuf::new();
+        bytes.extend_from_slice(b"\x82\x81\0\0\0\0a");
+        assert!(FrameCodec::server(0).decode(&mut bytes).is_err());
+    }
+}
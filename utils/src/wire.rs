@@ -0,0 +1,268 @@
+use std::io::{self, Read, Write};
+
+/// Reads big endian integers and u8 length prefixed strings
+#[derive(Debug)]
+pub struct Reader<R> {
+    inner: R,
+}
+
+macro_rules! read_int {
+    ($name:ident, $ty:ty) => {
+        pub fn $name(&mut self) -> io::Result<$ty> {
+            let mut buf = [0; std::mem::size_of::<$ty>()];
+            self.read_exact(&mut buf, stringify!($ty))?;
+            Ok(<$ty>::from_be_bytes(buf))
+        }
+    };
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8], what: &str) -> io::Result<()> {
+        self.inner.read_exact(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("stream ended while reading {}", what),
+            ),
+            _ => e,
+        })
+    }
+
+    read_int!(read_u8, u8);
+    read_int!(read_u16, u16);
+    read_int!(read_u32, u32);
+    read_int!(read_i32, i32);
+    read_int!(read_u64, u64);
+
+    pub fn read_bytes(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        self.read_exact(&mut buf, "bytes")?;
+        Ok(buf)
+    }
+
+    /// LEB128 unsigned varint. Errors with `InvalidData` if it doesn't fit a
+    /// u64.
+    pub fn read_varint(&mut self) -> io::Result<u64> {
+        let mut buf = [0; MAX_VARINT_LEN];
+        for i in 0..MAX_VARINT_LEN {
+            self.read_exact(&mut buf[i..i + 1], "varint")?;
+            if let Some((v, _)) = decode_varint(&buf[..i + 1])? {
+                return Ok(v);
+            }
+        }
+        unreachable!("decode_varint rejects varints longer than the max")
+    }
+
+    /// Zigzag encoded signed varint
+    pub fn read_varint_signed(&mut self) -> io::Result<i64> {
+        self.read_varint().map(zigzag_decode)
+    }
+
+    /// String prefixed by its length in a u8. Errors with `InvalidData` if it
+    /// isn't UTF-8.
+    pub fn read_str(&mut self) -> io::Result<String> {
+        let len = self.read_u8()?;
+        let mut buf = vec![0; len as usize];
+        self.read_exact(&mut buf, "str")?;
+        String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// Writes big endian integers and u8 length prefixed strings. Wrap a
+/// `Vec<u8>` to build a message and send it with a single write.
+#[derive(Debug)]
+pub struct Writer<W> {
+    inner: W,
+}
+
+macro_rules! write_int {
+    ($name:ident, $ty:ty) => {
+        pub fn $name(&mut self, v: $ty) -> io::Result<()> {
+            self.inner.write_all(&v.to_be_bytes())
+        }
+    };
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    write_int!(write_u8, u8);
+    write_int!(write_u16, u16);
+    write_int!(write_u32, u32);
+    write_int!(write_i32, i32);
+    write_int!(write_u64, u64);
+
+    pub fn write_varint(&mut self, v: u64) -> io::Result<()> {
+        let mut buf = [0; MAX_VARINT_LEN];
+        let len = encode_varint(v, &mut buf);
+        self.inner.write_all(&buf[..len])
+    }
+
+    pub fn write_varint_signed(&mut self, v: i64) -> io::Result<()> {
+        self.write_varint(zigzag_encode(v))
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.inner.write_all(bytes)
+    }
+
+    /// Errors with `InvalidInput` if `s` is longer than 255 bytes
+    pub fn write_str(&mut self, s: &str) -> io::Result<()> {
+        let len = u8::try_from(s.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "str longer than 255 bytes")
+        })?;
+        self.write_u8(len)?;
+        self.inner.write_all(s.as_bytes())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+pub const MAX_VARINT_LEN: usize = 10;
+
+/// Writes `v` as a LEB128 varint, 7 bits per byte starting with the lowest
+/// ones, returning its length
+pub fn encode_varint(mut v: u64, buf: &mut [u8; MAX_VARINT_LEN]) -> usize {
+    let mut i = 0;
+    while v >= 0x80 {
+        buf[i] = v as u8 | 0x80;
+        v >>= 7;
+        i += 1;
+    }
+    buf[i] = v as u8;
+    i + 1
+}
+
+/// Varint at the start of `buf` and its length, or `None` if it is
+/// incomplete. Errors with `InvalidData` if it doesn't fit a u64.
+pub fn decode_varint(buf: &[u8]) -> io::Result<Option<(u64, usize)>> {
+    let mut v = 0;
+    for (i, &b) in buf.iter().enumerate().take(MAX_VARINT_LEN) {
+        if i == MAX_VARINT_LEN - 1 && b > 1 {
+            break;
+        }
+        v |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok(Some((v, i + 1)));
+        }
+    }
+    if buf.len() < MAX_VARINT_LEN {
+        return Ok(None);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "varint overflows u64",
+    ))
+}
+
+/// Maps signed integers to unsigned ones so small magnitudes have short
+/// varints: 0, -1, 1, -2... become 0, 1, 2, 3...
+pub fn zigzag_encode(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+pub fn zigzag_decode(v: u64) -> i64 {
+    (v >> 1) as i64 ^ -((v & 1) as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::{decode_varint, zigzag_decode, zigzag_encode, Reader, Writer};
+
+    #[test]
+    fn test_round_trip() {
+        let mut w = Writer::new(Vec::new());
+        w.write_u8(0x20).unwrap();
+        w.write_str("UN1X").unwrap();
+        w.write_u16(66).unwrap();
+        w.write_u32(123456).unwrap();
+        w.write_i32(-2).unwrap();
+        w.write_u64(u64::MAX - 1).unwrap();
+        w.write_bytes(b"xy").unwrap();
+        let buf = w.into_inner();
+        assert_eq!(&buf[..8], b"\x20\x04UN1X\x00\x42");
+
+        let mut r = Reader::new(&buf[..]);
+        assert_eq!(r.read_u8().unwrap(), 0x20);
+        assert_eq!(r.read_str().unwrap(), "UN1X");
+        assert_eq!(r.read_u16().unwrap(), 66);
+        assert_eq!(r.read_u32().unwrap(), 123456);
+        assert_eq!(r.read_i32().unwrap(), -2);
+        assert_eq!(r.read_u64().unwrap(), u64::MAX - 1);
+        assert_eq!(r.read_bytes(2).unwrap(), b"xy");
+        let err = r.read_u8().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(err.to_string(), "stream ended while reading u8");
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = Reader::new(&b"\x05abc"[..]).read_str().unwrap_err();
+        assert_eq!(err.to_string(), "stream ended while reading str");
+        let err = Reader::new(&b"\x02\xff\xfe"[..]).read_str().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = Reader::new(&b"\x01"[..]).read_u16().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let long = "a".repeat(256);
+        let err = Writer::new(Vec::new()).write_str(&long).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_varints() {
+        let values = [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX];
+        let mut w = Writer::new(Vec::new());
+        for v in values {
+            w.write_varint(v).unwrap();
+        }
+        w.write_varint_signed(-1).unwrap();
+        w.write_varint_signed(i64::MIN).unwrap();
+        let buf = w.into_inner();
+        assert_eq!(&buf[..6], b"\x00\x01\x7f\x80\x01\xac");
+        let mut r = Reader::new(&buf[..]);
+        for v in values {
+            assert_eq!(r.read_varint().unwrap(), v);
+        }
+        assert_eq!(r.read_varint_signed().unwrap(), -1);
+        assert_eq!(r.read_varint_signed().unwrap(), i64::MIN);
+
+        for v in [0, -1, 1, -2, i64::MAX, i64::MIN] {
+            assert_eq!(zigzag_decode(zigzag_encode(v)), v);
+        }
+        assert_eq!(zigzag_encode(-2), 3);
+        assert_eq!(decode_varint(b"\xac\x02rest").unwrap(), Some((300, 2)));
+        assert_eq!(decode_varint(b"\xac").unwrap(), None);
+        let overflow = [0xff; 9].into_iter().chain([0x02]).collect::<Vec<_>>();
+        assert!(decode_varint(&overflow).is_err());
+        assert!(Reader::new(&overflow[..]).read_varint().is_err());
+    }
+}
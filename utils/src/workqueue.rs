@@ -0,0 +1,358 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::id::IdGen;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JobId(pub u64);
+
+#[derive(Debug)]
+struct Job<T> {
+    queue: Arc<str>,
+    priority: u64,
+    item: T,
+    /// Client which checked the job out
+    owner: Option<u64>,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    /// Ready jobs of each queue by priority, then oldest first. Entries of
+    /// jobs deleted or checked out since are skipped when popped.
+    ready: HashMap<Arc<str>, BinaryHeap<(u64, Reverse<JobId>)>>,
+    jobs: HashMap<JobId, Job<T>>,
+}
+
+impl<T> State<T> {
+    fn requeue(&mut self, id: JobId) {
+        if let Some(job) = self.jobs.get_mut(&id) {
+            job.owner = None;
+            let heap = self.ready.entry(job.queue.clone()).or_default();
+            heap.push((job.priority, Reverse(id)));
+        }
+    }
+
+    /// Best ready job of a queue, dropping the stale entries on top
+    fn peek(&mut self, queue: &str) -> Option<(u64, Reverse<JobId>)> {
+        let heap = self.ready.get_mut(queue)?;
+        while let Some(&(priority, Reverse(id))) = heap.peek() {
+            if self.jobs.get(&id).is_some_and(|j| j.owner.is_none()) {
+                return Some((priority, Reverse(id)));
+            }
+            heap.pop();
+        }
+        self.ready.remove(queue);
+        None
+    }
+
+    /// Removes the entry returned by `peek`
+    fn pop(&mut self, queue: &str) {
+        if let Some(heap) = self.ready.get_mut(queue) {
+            heap.pop();
+            if heap.is_empty() {
+                self.ready.remove(queue);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    cond: Condvar,
+    job_ids: IdGen,
+    client_ids: IdGen,
+}
+
+/// Jobs handed out by priority to clients, which hold them until they delete
+/// or abort them. The jobs of a client which goes away are put back in the
+/// queue.
+/// Jobs can be spread over named queues, see `scheduler::Scheduler`.
+#[derive(Debug)]
+pub struct WorkQueue<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for WorkQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Default for WorkQueue<T> {
+    fn default() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    ready: HashMap::new(),
+                    jobs: HashMap::new(),
+                }),
+                cond: Condvar::new(),
+                job_ids: IdGen::new(),
+                client_ids: IdGen::new(),
+            }),
+        }
+    }
+}
+
+impl<T: Clone> WorkQueue<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds a job, jobs with higher priorities are handed out first
+    pub fn put(&self, priority: u64, item: T) -> JobId {
+        self.put_in("", priority, item)
+    }
+
+    /// Adds a job to a named queue
+    pub fn put_in(&self, queue: &str, priority: u64, item: T) -> JobId {
+        let mut state = self.lock();
+        let id = JobId(self.shared.job_ids.next_id());
+        let queue: Arc<str> = match state.ready.get_key_value(queue) {
+            Some((name, _)) => name.clone(),
+            None => queue.into(),
+        };
+        state.jobs.insert(
+            id,
+            Job {
+                queue: queue.clone(),
+                priority,
+                item,
+                owner: None,
+            },
+        );
+        state
+            .ready
+            .entry(queue)
+            .or_default()
+            .push((priority, Reverse(id)));
+        // Waiting clients may not all be interested in this queue
+        self.shared.cond.notify_all();
+        id
+    }
+
+    /// Deletes a job, ready or checked out
+    pub fn delete(&self, id: JobId) -> bool {
+        self.lock().jobs.remove(&id).is_some()
+    }
+
+    /// Number of jobs waiting to be handed out
+    pub fn ready(&self) -> usize {
+        let state = self.lock();
+        state.jobs.values().filter(|j| j.owner.is_none()).count()
+    }
+
+    /// Number of jobs checked out by clients
+    pub fn checked_out(&self) -> usize {
+        let state = self.lock();
+        state.jobs.values().filter(|j| j.owner.is_some()).count()
+    }
+
+    /// Ready and checked out jobs of each queue
+    pub fn depths(&self) -> HashMap<String, (usize, usize)> {
+        let state = self.lock();
+        let mut depths = HashMap::new();
+        for job in state.jobs.values() {
+            let (ready, checked_out) = depths.entry(job.queue.to_string()).or_insert((0, 0));
+            match job.owner {
+                None => *ready += 1,
+                Some(_) => *checked_out += 1,
+            }
+        }
+        depths
+    }
+
+    pub fn client(&self) -> Client<T> {
+        Client {
+            queue: self.clone(),
+            id: self.shared.client_ids.next_id(),
+        }
+    }
+}
+
+/// Checked out job
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkout<T> {
+    pub id: JobId,
+    pub queue: String,
+    pub priority: u64,
+    pub item: T,
+}
+
+/// Handle of a worker on a `WorkQueue`, putting its jobs back when dropped
+#[derive(Debug)]
+pub struct Client<T: Clone> {
+    queue: WorkQueue<T>,
+    id: u64,
+}
+
+impl<T: Clone> Client<T> {
+    /// Checks out the best job of `queues`, of all of them if `None`
+    fn take(&self, state: &mut State<T>, queues: Option<&[&str]>) -> Option<Checkout<T>> {
+        let names: Vec<Arc<str>> = match queues {
+            Some(queues) => queues.iter().map(|&q| q.into()).collect(),
+            None => state.ready.keys().cloned().collect(),
+        };
+        let ((priority, Reverse(id)), queue) = names
+            .iter()
+            .filter_map(|q| Some((state.peek(q)?, q)))
+            .max_by_key(|&(entry, _)| entry)?;
+        state.pop(queue);
+        let job = state.jobs.get_mut(&id).unwrap();
+        job.owner = Some(self.id);
+        Some(Checkout {
+            id,
+            queue: job.queue.to_string(),
+            priority,
+            item: job.item.clone(),
+        })
+    }
+
+    pub fn try_get(&self) -> Option<Checkout<T>> {
+        self.try_get_from(None)
+    }
+
+    /// Waits for a job
+    pub fn get(&self) -> Checkout<T> {
+        self.get_from(None, None).unwrap()
+    }
+
+    /// Waits for a job for at most `timeout`
+    pub fn get_timeout(&self, timeout: Duration) -> Option<Checkout<T>> {
+        self.get_from(None, Some(Instant::now() + timeout))
+    }
+
+    pub(crate) fn try_get_from(&self, queues: Option<&[&str]>) -> Option<Checkout<T>> {
+        self.take(&mut self.queue.lock(), queues)
+    }
+
+    /// Waits for a job of `queues` until `deadline`, forever if `None`
+    pub(crate) fn get_from(
+        &self,
+        queues: Option<&[&str]>,
+        deadline: Option<Instant>,
+    ) -> Option<Checkout<T>> {
+        let cond = &self.queue.shared.cond;
+        let mut state = self.queue.lock();
+        loop {
+            if let Some(job) = self.take(&mut state, queues) {
+                return Some(job);
+            }
+            state = match deadline {
+                None => cond.wait(state).unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    let res = cond.wait_timeout(state, deadline - now);
+                    res.unwrap_or_else(|e| e.into_inner()).0
+                }
+            };
+        }
+    }
+
+    /// Puts back a job checked out by this client
+    pub fn abort(&self, id: JobId) -> bool {
+        let mut state = self.queue.lock();
+        if state.jobs.get(&id).and_then(|j| j.owner) != Some(self.id) {
+            return false;
+        }
+        state.requeue(id);
+        self.queue.shared.cond.notify_all();
+        true
+    }
+}
+
+impl<T: Clone> Drop for Client<T> {
+    fn drop(&mut self) {
+        let mut state = self.queue.lock();
+        let owned: Vec<JobId> = state
+            .jobs
+            .iter()
+            .filter(|(_, j)| j.owner == Some(self.id))
+            .map(|(&id, _)| id)
+            .collect();
+        for &id in &owned {
+            state.requeue(id);
+        }
+        if !owned.is_empty() {
+            self.queue.shared.cond.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    use super::WorkQueue;
+
+    #[test]
+    fn test_priorities() {
+        let queue = WorkQueue::new();
+        queue.put(1, "low");
+        let high = queue.put(10, "high");
+        queue.put(10, "high second");
+        let client = queue.client();
+        let job = client.try_get().unwrap();
+        assert_eq!((job.id, job.item), (high, "high"));
+        assert_eq!(client.try_get().unwrap().item, "high second");
+        assert_eq!(queue.ready(), 1);
+        assert_eq!(queue.checked_out(), 2);
+
+        assert!(client.abort(high));
+        assert!(!client.abort(high));
+        assert!(!queue.client().abort(high));
+        assert!(queue.delete(high));
+        assert!(!queue.delete(high));
+        assert_eq!(client.try_get().unwrap().item, "low");
+        assert_eq!(client.try_get(), None);
+    }
+
+    #[test]
+    fn test_heap_entries_dropped() {
+        let queue = WorkQueue::new();
+        let id = queue.put(1, ());
+        let client = queue.client();
+        for _ in 0..100 {
+            client.try_get().unwrap();
+            client.abort(id);
+        }
+        assert_eq!(queue.lock().ready[""].len(), 1);
+        client.try_get().unwrap();
+        assert!(queue.lock().ready.is_empty());
+        queue.delete(id);
+        assert_eq!(client.try_get(), None);
+    }
+
+    #[test]
+    fn test_disconnect_requeues() {
+        let queue = WorkQueue::new();
+        let first = queue.client();
+        queue.put(5, 1);
+        queue.put(3, 2);
+        first.try_get().unwrap();
+        first.try_get().unwrap();
+        let second = queue.client();
+        assert_eq!(second.get_timeout(Duration::from_millis(1)), None);
+
+        let waiter = thread::spawn(move || second.get().item);
+        thread::sleep(Duration::from_millis(10));
+        drop(first);
+        assert_eq!(waiter.join().unwrap(), 1);
+        // The waiter went away with its job too
+        assert_eq!(queue.ready(), 2);
+    }
+}